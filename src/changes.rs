@@ -0,0 +1,370 @@
+use crate::transaction_tracker::TransactionId;
+use crate::types::TypeName;
+use crate::{Error, Result};
+use std::io::{Read, Write};
+
+// Changesets are serialized as:
+// * magic number: 4 bytes
+// * version: 1 byte
+// * transaction id: 8 bytes
+// * previous transaction id: 8 bytes
+// * flags: 1 byte. Bit 0 is set if a savepoint was restored
+// * number of tables: 4 bytes
+// * for each table:
+//   * name, key type, and value type: each as a 4 byte length, followed by the bytes
+//   * multimap: 1 byte
+// * number of changes: 8 bytes
+// * for each change:
+//   * table: 4 byte index into the tables
+//   * key: 4 byte length, followed by the bytes
+//   * old value, and new value: each as 1 byte, which is 1 if the value is present, followed by a
+//     4 byte length and the bytes
+//
+// All integers are little endian
+const MAGICNUMBER: [u8; 4] = *b"rdcs";
+const VERSION: u8 = 2;
+const RESTORED_SAVEPOINT: u8 = 1;
+
+/// The changes made by a committed [`crate::WriteTransaction`]
+///
+/// Delivered to the receivers returned by [`crate::Database::subscribe`]
+#[derive(Clone, Debug)]
+pub struct ChangeSet {
+    transaction_id: TransactionId,
+    previous_transaction_id: TransactionId,
+    restored_savepoint: bool,
+    tables: Vec<ChangedTable>,
+    changes: Vec<Change>,
+}
+
+impl ChangeSet {
+    pub(crate) fn new(transaction_id: TransactionId) -> Self {
+        Self {
+            transaction_id,
+            previous_transaction_id: TransactionId(0),
+            restored_savepoint: false,
+            tables: vec![],
+            changes: vec![],
+        }
+    }
+
+    // Records the types of a table which may be changed
+    pub(crate) fn record_table(
+        &mut self,
+        name: &str,
+        multimap: bool,
+        key_type: TypeName,
+        value_type: TypeName,
+    ) {
+        let table = ChangedTable::new(name, multimap, key_type, value_type);
+        if let Some(existing) = self.tables.iter_mut().find(|x| x.name == name) {
+            *existing = table;
+        } else {
+            self.tables.push(table);
+        }
+    }
+
+    pub(crate) fn tables(&self) -> &[ChangedTable] {
+        &self.tables
+    }
+
+    pub(crate) fn push(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    // Discards the changes recorded so far, which were reverted by restoring a savepoint
+    pub(crate) fn restore_savepoint(&mut self) {
+        self.changes.clear();
+        self.restored_savepoint = true;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.changes.is_empty() && !self.restored_savepoint
+    }
+
+    /// Returns the id of the transaction which made the changes. Ids increase with every
+    /// transaction
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id.0
+    }
+
+    /// Returns the id of the last transaction before this one which may have changed the database
+    ///
+    /// Every transaction which changes the database has a changeset, so a follower which did not
+    /// apply the changeset of this transaction has missed some changes
+    pub fn previous_transaction_id(&self) -> u64 {
+        self.previous_transaction_id.0
+    }
+
+    pub(crate) fn set_previous_transaction_id(&mut self, id: TransactionId) {
+        self.previous_transaction_id = id;
+    }
+
+    /// Returns `true` if the transaction restored a [`crate::Savepoint`]
+    ///
+    /// Restoring a savepoint may revert any number of earlier transactions, so the changes that it
+    /// made are not included. Only the changes made after the savepoint was restored are
+    pub fn restored_savepoint(&self) -> bool {
+        self.restored_savepoint
+    }
+
+    /// Returns the changes, in the order that they were made
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Serializes the changes to `writer`, so that they can be applied to a follower with
+    /// [`crate::Database::apply_changeset`]
+    ///
+    /// The format is versioned, and includes the types of the changed tables
+    pub fn write_to(&self, writer: &mut impl Write) -> Result {
+        let mut data = vec![];
+        data.extend_from_slice(&MAGICNUMBER);
+        data.push(VERSION);
+        data.extend_from_slice(&self.transaction_id.0.to_le_bytes());
+        data.extend_from_slice(&self.previous_transaction_id.0.to_le_bytes());
+        data.push(if self.restored_savepoint {
+            RESTORED_SAVEPOINT
+        } else {
+            0
+        });
+        data.extend_from_slice(&u32::try_from(self.tables.len()).unwrap().to_le_bytes());
+        for table in self.tables.iter() {
+            write_bytes(&mut data, table.name.as_bytes());
+            write_bytes(&mut data, &table.key_type.to_bytes());
+            write_bytes(&mut data, &table.value_type.to_bytes());
+            data.push(table.multimap.into());
+        }
+        data.extend_from_slice(&(self.changes.len() as u64).to_le_bytes());
+        for change in self.changes.iter() {
+            let table = self
+                .tables
+                .iter()
+                .position(|x| x.name == change.table)
+                .unwrap();
+            data.extend_from_slice(&u32::try_from(table).unwrap().to_le_bytes());
+            write_bytes(&mut data, &change.key);
+            for value in [&change.old_value, &change.new_value] {
+                if let Some(value) = value {
+                    data.push(1);
+                    write_bytes(&mut data, value);
+                } else {
+                    data.push(0);
+                }
+            }
+        }
+        writer.write_all(&data)?;
+
+        Ok(())
+    }
+
+    /// Deserializes changes which were serialized by [`ChangeSet::write_to`]
+    pub fn read_from(reader: &mut impl Read) -> Result<ChangeSet> {
+        let magic: [u8; 4] = read_array(reader)?;
+        if magic != MAGICNUMBER {
+            return Err(invalid("not a changeset"));
+        }
+        let [version] = read_array(reader)?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {version}")));
+        }
+        let transaction_id = TransactionId(u64::from_le_bytes(read_array(reader)?));
+        let previous_transaction_id = TransactionId(u64::from_le_bytes(read_array(reader)?));
+        if previous_transaction_id.0 >= transaction_id.0 {
+            return Err(invalid(
+                "previous transaction is not before the transaction",
+            ));
+        }
+        let [flags] = read_array(reader)?;
+        let mut result = ChangeSet::new(transaction_id);
+        result.previous_transaction_id = previous_transaction_id;
+        result.restored_savepoint = flags & RESTORED_SAVEPOINT != 0;
+
+        let num_tables = u32::from_le_bytes(read_array(reader)?);
+        for _ in 0..num_tables {
+            let name = String::from_utf8(read_bytes(reader)?)
+                .map_err(|_| invalid("table name is not valid UTF-8"))?;
+            let key_type = read_type_name(reader)?;
+            let value_type = read_type_name(reader)?;
+            let [multimap] = read_array(reader)?;
+            result.record_table(&name, multimap != 0, key_type, value_type);
+        }
+        if result.tables.len() != num_tables as usize {
+            return Err(invalid("duplicate table"));
+        }
+
+        let num_changes = u64::from_le_bytes(read_array(reader)?);
+        for _ in 0..num_changes {
+            let table = u32::from_le_bytes(read_array(reader)?);
+            let table = result
+                .tables
+                .get(table as usize)
+                .ok_or_else(|| invalid("unknown table"))?;
+            let key = read_bytes(reader)?;
+            let mut values = [None, None];
+            for value in values.iter_mut() {
+                let [present] = read_array(reader)?;
+                if present != 0 {
+                    *value = Some(read_bytes(reader)?);
+                }
+            }
+            let [old_value, new_value] = values;
+            result.changes.push(Change {
+                table: table.name.clone(),
+                multimap: table.multimap,
+                key,
+                old_value,
+                new_value,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+// The name and types of a table which is changed by a ChangeSet
+#[derive(Clone, Debug)]
+pub struct ChangedTable {
+    name: String,
+    multimap: bool,
+    key_type: TypeName,
+    value_type: TypeName,
+}
+
+impl ChangedTable {
+    pub(crate) fn new(
+        name: &str,
+        multimap: bool,
+        key_type: TypeName,
+        value_type: TypeName,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            multimap,
+            key_type,
+            value_type,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Checks that the table has the given type
+    pub(crate) fn check_type(
+        &self,
+        multimap: bool,
+        key_type: TypeName,
+        value_type: TypeName,
+    ) -> Result {
+        if self.multimap != multimap {
+            return Err(Error::TableTypeMismatch(format!(
+                "{:?} is not of type {}",
+                self.name,
+                if multimap { "Multimap" } else { "Normal" }
+            )));
+        }
+        if self.key_type != key_type || self.value_type != value_type {
+            return Err(Error::TableTypeMismatch(format!(
+                "{} is of type Table<{}, {}> not Table<{}, {}>",
+                self.name,
+                self.key_type.name(),
+                self.value_type.name(),
+                key_type.name(),
+                value_type.name()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidChangeSet(reason.to_string())
+}
+
+fn write_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    data.extend_from_slice(&u32::try_from(bytes.len()).unwrap().to_le_bytes());
+    data.extend_from_slice(bytes);
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut result = [0; N];
+    reader.read_exact(&mut result)?;
+    Ok(result)
+}
+
+fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let len = u32::from_le_bytes(read_array(reader)?);
+    // Read incrementally, rather than trusting the length to allocate a buffer
+    let mut result = vec![];
+    reader.take(len.into()).read_to_end(&mut result)?;
+    if result.len() != len as usize {
+        return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(result)
+}
+
+fn read_type_name(reader: &mut impl Read) -> Result<TypeName> {
+    let bytes = read_bytes(reader)?;
+    TypeName::try_from_bytes(&bytes).ok_or_else(|| invalid("invalid type name"))
+}
+
+/// A change to a single entry of a table
+///
+/// Keys and values are serialized, as by [`crate::RedbValue::as_bytes`], and can be decoded
+/// with [`crate::RedbValue::from_bytes`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    table: String,
+    multimap: bool,
+    key: Vec<u8>,
+    old_value: Option<Vec<u8>>,
+    new_value: Option<Vec<u8>>,
+}
+
+impl Change {
+    pub(crate) fn new(
+        table: &str,
+        multimap: bool,
+        key: &[u8],
+        old_value: Option<&[u8]>,
+        new_value: Option<&[u8]>,
+    ) -> Self {
+        Self {
+            table: table.to_string(),
+            multimap,
+            key: key.to_vec(),
+            old_value: old_value.map(|x| x.to_vec()),
+            new_value: new_value.map(|x| x.to_vec()),
+        }
+    }
+
+    /// Returns the name of the table which was changed
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns `true` if the table is a multimap table
+    ///
+    /// Each value of a multimap table is changed separately: inserting a value has no old value,
+    /// and removing one has no new value
+    pub fn is_multimap(&self) -> bool {
+        self.multimap
+    }
+
+    /// Returns the key of the entry which was changed
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Returns the value of the entry before the change, or `None` if it was inserted
+    pub fn old_value(&self) -> Option<&[u8]> {
+        self.old_value.as_deref()
+    }
+
+    /// Returns the value of the entry after the change, or `None` if it was removed
+    pub fn new_value(&self) -> Option<&[u8]> {
+        self.new_value.as_deref()
+    }
+}
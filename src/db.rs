@@ -1,25 +1,42 @@
-use crate::transaction_tracker::{SavepointId, TransactionId, TransactionTracker};
+use crate::changes::ChangeSet;
+use crate::index::{is_reserved_table_name, TableIndex};
+use crate::optimistic::{ConflictTracker, OptimisticTransaction};
+use crate::replication;
+use crate::transaction_tracker::{
+    Expiration, LiveReader, ReaderId, SavepointId, TransactionId, TransactionTracker,
+};
+use crate::transactions::read_persistent_savepoints;
 use crate::tree_store::{
-    AllPageNumbersBtreeIter, BtreeRangeIter, FreedTableKey, InternalTableDefinition, RawBtree,
-    TableType, TransactionalMemory, PAGE_SIZE,
+    tree_overflow_pages, AllPageNumbersBtreeIter, BtreeRangeIter, Compression, EncryptionKey,
+    FreedTableKey, InternalTableDefinition, PageNumber, RawBtree, StorageBackend, StorageSource,
+    TableTree, TableType, TransactionalMemory, PAGE_SIZE,
 };
 use crate::types::{RedbKey, RedbValue};
 use crate::Error;
-use crate::{ReadTransaction, Result, WriteTransaction};
+use crate::{
+    CacheStats, ReadTransaction, ReadTransactionInfo, ReplicatedTable, Result, Savepoint,
+    WriteTransaction,
+};
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::ErrorKind;
 use std::marker::PhantomData;
 use std::ops::RangeFull;
+use std::panic;
 use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
-use crate::multimap_table::parse_subtree_roots;
+use crate::multimap_table::{parse_subtree_roots, verify_tree_and_subtree_checksums};
 #[cfg(feature = "logging")]
 use log::{info, warn};
 
+type CacheStatsCallback = Arc<dyn Fn(&CacheStats) + Send + Sync>;
+
 struct AtomicTransactionId {
     inner: AtomicU64,
 }
@@ -45,6 +62,8 @@ impl AtomicTransactionId {
 /// that is stored or retreived from the table
 pub struct TableDefinition<'a, K: RedbKey + 'static, V: RedbValue + 'static> {
     name: &'a str,
+    compression: Option<Compression>,
+    indexes: &'a [&'a dyn TableIndex<K, V>],
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -54,23 +73,62 @@ impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> TableDefinition<'a, K, V>
     ///
     /// ## Invariant
     ///
-    /// `name` must not be empty.
+    /// `name` must not be empty, and must not contain a nul byte. Such names are reserved for the
+    /// tables which redb maintains, such as indexes
     pub const fn new(name: &'a str) -> Self {
+        assert!(!is_reserved_table_name(name));
+        Self::internal(name)
+    }
+
+    // Like new(), but allows a reserved name
+    pub(crate) const fn internal(name: &'a str) -> Self {
         assert!(!name.is_empty());
         // Custom alignment is not currently supported
         assert!(K::ALIGNMENT == 1);
         assert!(V::ALIGNMENT == 1);
         Self {
             name,
+            compression: None,
+            indexes: &[],
             _key_type: PhantomData,
             _value_type: PhantomData,
         }
     }
 
+    /// Compress the values stored in the table with `compression`
+    ///
+    /// The codec is stored with the table, and opening it with a different codec will return
+    /// [`Error::TableTypeMismatch`]
+    pub const fn with_compression(self, compression: Compression) -> Self {
+        Self {
+            compression: Some(compression),
+            ..self
+        }
+    }
+
+    /// Maintain the given secondary `indexes` of the table, each of which is stored in a hidden
+    /// multimap table
+    ///
+    /// The indexes are updated whenever the table is modified. An index which is added to a table
+    /// that already contains entries is built when the table is next opened for writing. If the
+    /// table is opened for writing without one of its indexes, that index is deleted, since it
+    /// would no longer be kept up to date, and is rebuilt the next time that it's used
+    pub const fn with_indexes(self, indexes: &'a [&'a dyn TableIndex<K, V>]) -> Self {
+        Self { indexes, ..self }
+    }
+
     /// Returns a reference of the `name` of the current `TableDefinition`.
     pub fn name(&self) -> &str {
         self.name
     }
+
+    pub(crate) fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    pub(crate) fn indexes(&self) -> &'a [&'a dyn TableIndex<K, V>] {
+        self.indexes
+    }
 }
 
 impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Clone for TableDefinition<'a, K, V> {
@@ -108,7 +166,19 @@ pub struct MultimapTableDefinition<'a, K: RedbKey + 'static, V: RedbKey + 'stati
 }
 
 impl<'a, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTableDefinition<'a, K, V> {
+    /// Construct a new multimap table with given `name`
+    ///
+    /// ## Invariant
+    ///
+    /// `name` must not be empty, and must not contain a nul byte. Such names are reserved for the
+    /// tables which redb maintains, such as indexes
     pub const fn new(name: &'a str) -> Self {
+        assert!(!is_reserved_table_name(name));
+        Self::internal(name)
+    }
+
+    // Like new(), but allows a reserved name
+    pub(crate) const fn internal(name: &'a str) -> Self {
         assert!(!name.is_empty());
         // Custom alignment is not currently supported
         assert!(K::ALIGNMENT == 1);
@@ -179,7 +249,15 @@ pub struct Database {
     mem: TransactionalMemory,
     next_transaction_id: AtomicTransactionId,
     transaction_tracker: Arc<Mutex<TransactionTracker>>,
-    pub(crate) live_write_transaction: Mutex<Option<TransactionId>>,
+    live_write_transaction: Mutex<Option<TransactionId>>,
+    live_write_transaction_available: Condvar,
+    subscribers: Mutex<Vec<Sender<ChangeSet>>>,
+    // The last write transaction which may have changed the database. Each changeset refers to it,
+    // so that followers can detect changesets which they missed
+    last_change: Mutex<TransactionId>,
+    conflict_tracker: Mutex<ConflictTracker>,
+    read_transaction_timeout: Option<Duration>,
+    cache_stats_callback: Option<CacheStatsCallback>,
 }
 
 impl Database {
@@ -233,17 +311,23 @@ impl Database {
             BtreeRangeIter::new::<RangeFull, &str>(.., Some(root), mem)?;
         for entry in iter {
             let definition = entry.value();
-            if let Some((table_root, table_checksum)) = definition.get_root() {
-                if !RawBtree::new(
-                    Some((table_root, table_checksum)),
+            let valid = match definition.get_type() {
+                TableType::Normal => RawBtree::new(
+                    definition.get_root(),
                     definition.get_fixed_key_size(),
                     definition.get_fixed_value_size(),
                     mem,
                 )
-                .verify_checksum()?
-                {
-                    return Ok(false);
-                }
+                .verify_checksum()?,
+                TableType::Multimap => verify_tree_and_subtree_checksums(
+                    definition.get_root(),
+                    definition.get_fixed_key_size(),
+                    definition.get_fixed_value_size(),
+                    mem,
+                )?,
+            };
+            if !valid {
+                return Ok(false);
             }
         }
 
@@ -252,29 +336,33 @@ impl Database {
 
     #[allow(clippy::too_many_arguments)]
     fn new(
-        file: File,
-        use_mmap: bool,
+        source: StorageSource,
         page_size: usize,
         region_size: Option<usize>,
         initial_size: Option<u64>,
         read_cache_size_bytes: usize,
         write_cache_size_bytes: usize,
         write_strategy: Option<WriteStrategy>,
+        encryption_key: Option<EncryptionKey>,
+        read_transaction_timeout: Option<Duration>,
+        cache_stats_callback: Option<CacheStatsCallback>,
     ) -> Result<Self> {
         #[cfg(feature = "logging")]
-        let file_path = format!("{:?}", &file);
+        let file_path = format!("{:?}", &source);
         #[cfg(feature = "logging")]
         info!("Opening database {:?}", &file_path);
         let mut mem = TransactionalMemory::new(
-            file,
-            use_mmap,
+            source,
             page_size,
             region_size,
             initial_size,
             read_cache_size_bytes,
             write_cache_size_bytes,
             write_strategy,
+            encryption_key,
         )?;
+        let transaction_tracker = Arc::new(Mutex::new(TransactionTracker::new()));
+        let mut savepoint_pages = HashSet::new();
         if mem.needs_repair()? {
             #[cfg(feature = "logging")]
             warn!("Database {:?} not shutdown cleanly. Repairing", &file_path);
@@ -291,70 +379,140 @@ impl Database {
                 .expect("Tried to repair an empty database");
 
             // Repair the allocator state
-            // All pages in the master table
-            let master_pages_iter = AllPageNumbersBtreeIter::new(root, None, None, &mem)?;
-            mem.mark_pages_allocated(master_pages_iter)?;
-
-            // Iterate over all other tables
-            let iter: BtreeRangeIter<&str, InternalTableDefinition> =
-                BtreeRangeIter::new::<RangeFull, &str>(.., Some(root), &mem)?;
-
-            // Chain all the other tables to the master table iter
-            for entry in iter {
-                let definition = entry.value();
-                if let Some((table_root, _)) = definition.get_root() {
-                    let table_pages_iter = AllPageNumbersBtreeIter::new(
-                        table_root,
-                        definition.get_fixed_key_size(),
-                        definition.get_fixed_value_size(),
+            let mut allocated_pages = HashSet::new();
+            Self::collect_table_pages(root, &mem, &mut allocated_pages)?;
+
+            // Persistent savepoints also reference pages which are no longer part of any table
+            let table_tree = TableTree::new(Some((root, root_checksum)), &mem, Default::default());
+            for (id, data) in read_persistent_savepoints(&table_tree, &mem)? {
+                let savepoint =
+                    Savepoint::from_bytes(SavepointId(id), &data, transaction_tracker.clone())?;
+                if let Some((savepoint_root, _)) = savepoint.get_root() {
+                    Self::collect_table_pages(savepoint_root, &mem, &mut savepoint_pages)?;
+                }
+                if let Some((freed_root, _)) = savepoint.get_freed_root() {
+                    savepoint_pages.extend(AllPageNumbersBtreeIter::new(
+                        freed_root,
+                        FreedTableKey::fixed_width(),
+                        None,
                         &mem,
-                    )?;
-                    mem.mark_pages_allocated(table_pages_iter)?;
-
-                    // Multimap tables may have additional subtrees in their values
-                    if definition.get_type() == TableType::Multimap {
-                        let table_pages_iter = AllPageNumbersBtreeIter::new(
-                            table_root,
-                            definition.get_fixed_key_size(),
-                            definition.get_fixed_value_size(),
-                            &mem,
-                        )?;
-                        for table_page in table_pages_iter {
-                            let page = mem.get_page(table_page)?;
-                            let mut subtree_roots = parse_subtree_roots(
-                                &page,
-                                definition.get_fixed_key_size(),
-                                definition.get_fixed_value_size(),
-                            );
-                            mem.mark_pages_allocated(subtree_roots.drain(..))?;
-                        }
-                    }
+                    )?);
                 }
             }
+            drop(table_tree);
+            savepoint_pages.retain(|page| !allocated_pages.contains(page));
+            mem.mark_pages_allocated(
+                allocated_pages
+                    .into_iter()
+                    .chain(savepoint_pages.iter().copied()),
+            )?;
 
             mem.end_repair()?;
 
             // Clear the freed table. We just rebuilt the allocator state by walking all the
             // reachable data pages, which implicitly frees the pages for the freed table
             let transaction_id = mem.get_last_committed_transaction_id()?.next();
-            mem.commit(
-                Some((root, root_checksum)),
-                None,
-                transaction_id,
-                false,
-                None,
-            )?;
+            mem.commit(Some((root, root_checksum)), None, transaction_id, false)?;
         }
 
-        mem.begin_writable()?;
-        let next_transaction_id = mem.get_last_committed_transaction_id()?.next();
+        if !mem.is_read_only() {
+            mem.begin_writable()?;
+        }
+        let last_committed_transaction_id = mem.get_last_committed_transaction_id()?;
+        let next_transaction_id = last_committed_transaction_id.next();
 
-        Ok(Database {
+        let db = Database {
             mem,
             next_transaction_id: AtomicTransactionId::new(next_transaction_id),
-            transaction_tracker: Arc::new(Mutex::new(TransactionTracker::new())),
+            transaction_tracker,
             live_write_transaction: Mutex::new(None),
-        })
+            live_write_transaction_available: Condvar::new(),
+            subscribers: Mutex::new(vec![]),
+            last_change: Mutex::new(last_committed_transaction_id),
+            conflict_tracker: Mutex::new(Default::default()),
+            read_transaction_timeout,
+            cache_stats_callback,
+        };
+
+        // Persistent savepoints prevent the pages that they reference from being freed
+        let table_tree = TableTree::new(db.mem.get_data_root(), &db.mem, Default::default());
+        for (id, data) in read_persistent_savepoints(&table_tree, &db.mem)? {
+            let savepoint =
+                Savepoint::from_bytes(SavepointId(id), &data, db.transaction_tracker())?;
+            db.transaction_tracker
+                .lock()
+                .unwrap()
+                .register_persistent_savepoint(&savepoint);
+        }
+        drop(table_tree);
+        // The pages which are only referenced by persistent savepoints were not in the freed table
+        // that repair cleared, so are freed once the savepoints no longer need them
+        if !savepoint_pages.is_empty() {
+            let txn = db.begin_write()?;
+            txn.free_on_commit(savepoint_pages);
+            txn.commit()?;
+        }
+
+        Ok(db)
+    }
+
+    // Collects the pages of the master table whose root is `root`, and of all the tables in it
+    fn collect_table_pages(
+        root: PageNumber,
+        mem: &TransactionalMemory,
+        pages: &mut HashSet<PageNumber>,
+    ) -> Result {
+        // All pages in the master table
+        pages.extend(AllPageNumbersBtreeIter::new(root, None, None, mem)?);
+
+        // Iterate over all other tables
+        let iter: BtreeRangeIter<&str, InternalTableDefinition> =
+            BtreeRangeIter::new::<RangeFull, &str>(.., Some(root), mem)?;
+
+        for entry in iter {
+            let definition = entry.value();
+            if let Some((table_root, _)) = definition.get_root() {
+                let table_pages_iter = AllPageNumbersBtreeIter::new(
+                    table_root,
+                    definition.get_fixed_key_size(),
+                    definition.get_fixed_value_size(),
+                    mem,
+                )?;
+                pages.extend(table_pages_iter);
+
+                // Multimap tables may have additional subtrees in their values
+                if definition.get_type() == TableType::Multimap {
+                    let table_pages_iter = AllPageNumbersBtreeIter::new(
+                        table_root,
+                        definition.get_fixed_key_size(),
+                        definition.get_fixed_value_size(),
+                        mem,
+                    )?;
+                    for table_page in table_pages_iter {
+                        let page = mem.get_page(table_page)?;
+                        let subtree_roots = parse_subtree_roots(
+                            &page,
+                            definition.get_fixed_key_size(),
+                            definition.get_fixed_value_size(),
+                        );
+                        pages.extend(subtree_roots);
+                    }
+                }
+
+                // Large values of normal tables may be stored in overflow pages
+                if definition.get_type() == TableType::Normal
+                    && definition.get_fixed_value_size().is_none()
+                {
+                    pages.extend(tree_overflow_pages(
+                        table_root,
+                        definition.get_fixed_key_size(),
+                        mem,
+                    )?);
+                }
+            }
+        }
+
+        Ok(())
     }
 
     // TODO: we could probably remove this method and pass this clone into the Transaction objects
@@ -370,6 +528,38 @@ impl Database {
         Ok(id)
     }
 
+    // Registers a reader of the latest commit, or of `savepoint` if one is given
+    fn allocate_reader(
+        &self,
+        savepoint: Option<&Savepoint>,
+        location: &'static panic::Location<'static>,
+    ) -> Result<(ReaderId, Expiration)> {
+        let mut guard = self.transaction_tracker.lock().unwrap();
+        let id = if let Some(savepoint) = savepoint {
+            if !guard.is_valid_savepoint(savepoint.get_id()) {
+                return Err(Error::InvalidSavepoint);
+            }
+            savepoint.get_transaction_id()
+        } else {
+            self.mem.get_last_committed_transaction_id()?
+        };
+        let started = Instant::now();
+        let deadline = self
+            .read_transaction_timeout
+            .map(|timeout| started + timeout);
+        let expiration = Expiration::new(deadline);
+        let reader = guard.register_reader(LiveReader {
+            transaction_id: id,
+            started,
+            location,
+            expiration: expiration.clone(),
+        });
+        #[cfg(feature = "logging")]
+        info!("Beginning read transaction id={:?}", id);
+
+        Ok((reader, expiration))
+    }
+
     pub(crate) fn allocate_savepoint(&self) -> Result<(SavepointId, TransactionId)> {
         let id = self
             .transaction_tracker
@@ -383,6 +573,41 @@ impl Database {
         self.next_transaction_id.next()
     }
 
+    // Waits for the live write transaction, if any, to complete, for at most `timeout` if one is
+    // given. Returns the lock on the live write transaction, or `None` if the timeout elapsed
+    pub(crate) fn wait_for_write_transaction(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Option<MutexGuard<'_, Option<TransactionId>>> {
+        let live_write_transaction = self.live_write_transaction.lock().unwrap();
+        let is_live = |live: &mut Option<TransactionId>| live.is_some();
+        match timeout {
+            None => Some(
+                self.live_write_transaction_available
+                    .wait_while(live_write_transaction, is_live)
+                    .unwrap(),
+            ),
+            Some(timeout) => {
+                let (live_write_transaction, _) = self
+                    .live_write_transaction_available
+                    .wait_timeout_while(live_write_transaction, timeout, is_live)
+                    .unwrap();
+                live_write_transaction
+                    .is_none()
+                    .then_some(live_write_transaction)
+            }
+        }
+    }
+
+    pub(crate) fn end_write_transaction(&self) {
+        *self.live_write_transaction.lock().unwrap() = None;
+        self.live_write_transaction_available.notify_all();
+    }
+
+    pub(crate) fn conflict_tracker(&self) -> &Mutex<ConflictTracker> {
+        &self.conflict_tracker
+    }
+
     /// Convenience method for [`Builder::new`]
     pub fn builder() -> Builder {
         Builder::new()
@@ -390,29 +615,72 @@ impl Database {
 
     /// Changes the write strategy of the database for future [`WriteTransaction`]s.
     ///
-    /// Calling this method will invalidate all existing [`crate::Savepoint`]s.
+    /// Calling this method will invalidate all existing [`crate::Savepoint`]s. Returns
+    /// [`Error::PersistentSavepointExists`] if there are persistent savepoints.
+    ///
+    /// If a write transaction is in progress, this method will block until it completes.
     ///
     /// Note: Changing to the [`WriteStrategy::Checksum`] strategy can take a long time, as checksums
     /// will need to be calculated for every entry in the database
     pub fn set_write_strategy(&self, strategy: WriteStrategy) -> Result {
-        let mut tracker = self.transaction_tracker.lock().unwrap();
-        tracker.invalidate_all_savepoints();
+        let mut txn = self.begin_write()?;
+        if txn.list_persistent_savepoints()?.next().is_some() {
+            return Err(Error::PersistentSavepointExists);
+        }
+        self.transaction_tracker
+            .lock()
+            .unwrap()
+            .invalidate_all_savepoints();
+
+        txn.change_write_strategy(strategy)?;
+        txn.commit()
+    }
 
-        let guard = self.live_write_transaction.lock().unwrap();
-        assert!(guard.is_none());
-        // TODO: implement switching to checksum strategy
-        assert!(matches!(strategy, WriteStrategy::TwoPhase));
+    /// Compacts the database file
+    ///
+    /// Pages are moved into free space towards the start of the file, which is then truncated.
+    /// Returns `true` if any pages were moved, or the file was truncated.
+    ///
+    /// Calling this method will invalidate all existing [`crate::Savepoint`]s. Returns
+    /// [`Error::PersistentSavepointExists`] if there are persistent savepoints, since the pages
+    /// that they reference can't be moved.
+    pub fn compact(&mut self) -> Result<bool> {
+        let txn = self.begin_write()?;
+        if txn.list_persistent_savepoints()?.next().is_some() {
+            return Err(Error::PersistentSavepointExists);
+        }
+        self.transaction_tracker
+            .lock()
+            .unwrap()
+            .invalidate_all_savepoints();
 
-        let id = self.increment_transaction_id();
-        let root_page = self.mem.get_data_root();
-        let freed_root = self.mem.get_freed_root();
-        self.mem
-            .commit(root_page, freed_root, id, false, Some(strategy.into()))?;
-        drop(guard);
+        // Commit once, to free any pages that are still pending from previous transactions
+        txn.commit()?;
 
-        drop(tracker);
+        let mut compacted = false;
+        loop {
+            let file_len = self.mem.get_file_len();
 
-        Ok(())
+            let mut txn = self.begin_write()?;
+            let relocated = txn.compact_pages()?;
+            txn.commit()?;
+            // The relocated pages are freed by the following commit, which then truncates the file
+            self.begin_write()?.commit()?;
+
+            if !relocated && self.mem.get_file_len() == file_len {
+                break;
+            }
+            compacted = true;
+        }
+
+        Ok(compacted)
+    }
+
+    /// Writes a copy of the database to a new file at `path`
+    ///
+    /// Convenience method for [`ReadTransaction::backup_to`]
+    pub fn backup(&self, path: impl AsRef<Path>) -> Result {
+        self.begin_read()?.backup_to(path)
     }
 
     /// Begins a write transaction
@@ -420,8 +688,56 @@ impl Database {
     /// Returns a [`WriteTransaction`] which may be used to read/write to the database. Only a single
     /// write may be in progress at a time. If a write is in progress, this function will block
     /// until it completes.
+    ///
+    /// Returns [`Error::DatabaseReadOnly`] if the database was opened with [`Builder::open_read_only`]
     pub fn begin_write(&self) -> Result<WriteTransaction> {
-        WriteTransaction::new(self)
+        if self.mem.is_read_only() {
+            return Err(Error::DatabaseReadOnly);
+        }
+        WriteTransaction::new(self, self.wait_for_write_transaction(None).unwrap())
+    }
+
+    /// Begins a write transaction, if no other write is in progress
+    ///
+    /// Like [`Database::begin_write`], but returns `None` instead of blocking
+    pub fn try_begin_write(&self) -> Result<Option<WriteTransaction<'_>>> {
+        self.begin_write_timeout(Duration::ZERO)
+    }
+
+    /// Begins a write transaction, waiting at most `timeout` for a write in progress to complete
+    ///
+    /// Like [`Database::begin_write`], but returns `None` if the timeout elapses
+    pub fn begin_write_timeout(&self, timeout: Duration) -> Result<Option<WriteTransaction<'_>>> {
+        if self.mem.is_read_only() {
+            return Err(Error::DatabaseReadOnly);
+        }
+        match self.wait_for_write_transaction(Some(timeout)) {
+            Some(live_write_transaction) => {
+                WriteTransaction::new(self, live_write_transaction).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Begins an optimistic write transaction
+    ///
+    /// Unlike [`Database::begin_write`], any number of optimistic transactions may be in progress
+    /// at the same time, alongside a [`WriteTransaction`], and beginning one never waits. Their
+    /// writes are buffered in memory, and applied by a regular write transaction when they commit,
+    /// so only reads and the buffering of writes run concurrently: commits are still serialized.
+    /// Each one is validated when it commits, and fails with [`Error::Conflict`] if another
+    /// transaction changed a key which it read or wrote.
+    ///
+    /// This suits writers which spend most of their time reading, and mostly access disjoint keys.
+    /// It does not speed up workloads which are limited by the time taken to commit.
+    ///
+    /// Optimistic transactions only support the entries of regular tables. See
+    /// [`OptimisticTransaction`] for their limitations.
+    pub fn begin_optimistic_write(&self) -> Result<OptimisticTransaction<'_>> {
+        if self.mem.is_read_only() {
+            return Err(Error::DatabaseReadOnly);
+        }
+        OptimisticTransaction::new(self)
     }
 
     /// Begins a read transaction
@@ -431,11 +747,148 @@ impl Database {
     ///
     /// Returns a [`ReadTransaction`] which may be used to read from the database. Read transactions
     /// may exist concurrently with writes
+    #[track_caller]
     pub fn begin_read(&self) -> Result<ReadTransaction> {
-        let id = self.allocate_read_transaction()?;
-        #[cfg(feature = "logging")]
-        info!("Beginning read transaction id={:?}", id);
-        Ok(ReadTransaction::new(self, id))
+        let (reader, expiration) = self.allocate_reader(None, panic::Location::caller())?;
+        Ok(ReadTransaction::new(
+            self,
+            self.mem.get_data_root(),
+            reader,
+            expiration,
+        ))
+    }
+
+    /// Begins a read transaction of the database as it was when `savepoint` was created
+    ///
+    /// This allows old data to be read, or compared with the current data, without restoring the
+    /// savepoint. The returned transaction remains usable after `savepoint` is dropped.
+    ///
+    /// Returns [`Error::InvalidSavepoint`] if the savepoint has been invalidated
+    #[track_caller]
+    pub fn begin_read_at(&self, savepoint: &Savepoint) -> Result<ReadTransaction<'_>> {
+        // Ensure that user does not try to read a Savepoint that is from a different Database
+        assert_eq!(
+            self.transaction_tracker.as_ref() as *const _,
+            savepoint.db_address()
+        );
+        let (reader, expiration) =
+            self.allocate_reader(Some(savepoint), panic::Location::caller())?;
+        Ok(ReadTransaction::new(
+            self,
+            savepoint.get_root(),
+            reader,
+            expiration,
+        ))
+    }
+
+    /// Returns the read transactions which are in progress, from the oldest to the newest
+    ///
+    /// This is useful to find read transactions which are kept open for a long time, and so
+    /// prevent space from being reclaimed
+    pub fn live_read_transactions(&self) -> Vec<ReadTransactionInfo> {
+        let now = Instant::now();
+        let mut result: Vec<ReadTransactionInfo> = self
+            .transaction_tracker
+            .lock()
+            .unwrap()
+            .live_readers()
+            .map(|reader| {
+                ReadTransactionInfo::new(
+                    reader.transaction_id,
+                    now.saturating_duration_since(reader.started),
+                    reader.location,
+                )
+            })
+            .collect();
+        result.sort_by_key(|info| std::cmp::Reverse(info.age()));
+
+        result
+    }
+
+    /// Subscribes to the changes committed to the database
+    ///
+    /// Returns a receiver which is sent a [`ChangeSet`] for every [`WriteTransaction`] that begins
+    /// after this call, and commits at least one change. Dropping the receiver unsubscribes.
+    ///
+    /// Changes are only recorded while there is at least one subscriber, so transactions are not
+    /// slowed down otherwise
+    pub fn subscribe(&self) -> Receiver<ChangeSet> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Applies a [`ChangeSet`] from another database, which this database is a follower of
+    ///
+    /// The changes are applied atomically, in a single write transaction. `tables` must include
+    /// the definitions of all the tables which are changed. Indexes of the tables are maintained
+    /// by the follower, and are not replicated.
+    ///
+    /// Returns `false`, without applying the changes, if this changeset or a later one was already
+    /// applied, so a follower can resume from the transaction after
+    /// [`Database::last_applied_transaction`]. Returns [`Error::InvalidChangeSet`] if the leader
+    /// restored a savepoint, or if an earlier changeset was missed, since the changes which were
+    /// reverted or missed are unknown
+    pub fn apply_changeset(
+        &self,
+        changeset: &ChangeSet,
+        tables: &[&dyn ReplicatedTable],
+    ) -> Result<bool> {
+        replication::apply_changeset(self, changeset, tables)
+    }
+
+    /// Returns the id of the leader's transaction whose [`ChangeSet`] was last applied, or `None`
+    /// if none has been
+    pub fn last_applied_transaction(&self) -> Result<Option<u64>> {
+        replication::last_applied_transaction(self)
+    }
+
+    /// Returns statistics about the read cache and I/O of the database
+    ///
+    /// These can be used to check whether the read cache is large enough, with
+    /// [`Builder::set_read_cache_size`]
+    pub fn cache_stats(&self) -> CacheStats {
+        self.mem.cache_stats()
+    }
+
+    pub(crate) fn report_cache_stats(&self) {
+        if let Some(ref callback) = self.cache_stats_callback {
+            callback(&self.cache_stats());
+        }
+    }
+
+    // Changes are recorded if they are published to subscribers, or may conflict with an
+    // optimistic transaction
+    pub(crate) fn records_changes(&self) -> bool {
+        !self.subscribers.lock().unwrap().is_empty()
+            || self.conflict_tracker.lock().unwrap().is_tracking()
+    }
+
+    // Records that the transaction `id` committed without recording its changes, so it may have
+    // changed the database
+    pub(crate) fn record_unpublished_commit(&self, id: TransactionId) {
+        *self.last_change.lock().unwrap() = id;
+        let mut conflict_tracker = self.conflict_tracker.lock().unwrap();
+        if conflict_tracker.is_tracking() {
+            conflict_tracker.record_unknown_commit(id);
+        }
+    }
+
+    pub(crate) fn publish_changes(&self, mut changes: ChangeSet) {
+        {
+            let mut last_change = self.last_change.lock().unwrap();
+            changes.set_previous_transaction_id(*last_change);
+            *last_change = TransactionId(changes.transaction_id());
+        }
+        {
+            let mut conflict_tracker = self.conflict_tracker.lock().unwrap();
+            if conflict_tracker.is_tracking() {
+                conflict_tracker.record_commit(&changes);
+            }
+        }
+        let mut subscribers = self.subscribers.lock().unwrap();
+        // Receivers which have been dropped are unsubscribed
+        subscribers.retain(|subscriber| subscriber.send(changes.clone()).is_ok());
     }
 }
 
@@ -500,6 +953,9 @@ pub struct Builder {
     read_cache_size_bytes: usize,
     write_cache_size_bytes: usize,
     write_strategy: Option<WriteStrategy>,
+    encryption_key: Option<EncryptionKey>,
+    read_transaction_timeout: Option<Duration>,
+    cache_stats_callback: Option<CacheStatsCallback>,
 }
 
 impl Builder {
@@ -524,6 +980,9 @@ impl Builder {
             // TODO: Default should probably take into account the total system memory
             write_cache_size_bytes: 100 * 1024 * 1024,
             write_strategy: None,
+            encryption_key: None,
+            read_transaction_timeout: None,
+            cache_stats_callback: None,
         }
     }
 
@@ -549,9 +1008,53 @@ impl Builder {
         self
     }
 
+    /// Encrypt the database with `key`
+    ///
+    /// Every page, other than the header, is encrypted and authenticated with XChaCha20-Poly1305.
+    /// A check value derived from the key is stored in the header, so opening the database with
+    /// the wrong key, or without a key, returns [`Error::InvalidEncryptionKey`].
+    ///
+    /// Encryption is not supported by `create_mmapped()`/`open_mmapped()`, which will return an
+    /// error, and this setting is ignored by `create_in_memory()`
+    #[cfg(feature = "encryption")]
+    pub fn set_encryption_key(&mut self, key: [u8; 32]) -> &mut Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Limit how long a read transaction may be open
+    ///
+    /// A read transaction prevents the pages of its snapshot from being freed, so one which is
+    /// never dropped causes the file to grow without bound. Once a read transaction has been open
+    /// for longer than `timeout` its snapshot is released by the next commit, and accessing it, or
+    /// the tables opened from it, returns [`Error::ReadTransactionExpired`]. Iterators over an
+    /// expired transaction return it as an error item.
+    ///
+    /// A transaction which is in the middle of an access is not expired until the access
+    /// completes.
+    ///
+    /// [`Database::live_read_transactions`] can be used to find read transactions which are open
+    /// for a long time
+    pub fn set_read_transaction_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.read_transaction_timeout = Some(timeout);
+        self
+    }
+
+    /// Call `callback` with the [`CacheStats`] of the database after every commit
+    ///
+    /// This can be used to export the statistics to a metrics system. The callback is called on
+    /// the thread which committed, so should return quickly
+    pub fn set_cache_stats_callback(
+        &mut self,
+        callback: impl Fn(&CacheStats) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.cache_stats_callback = Some(Arc::new(callback));
+        self
+    }
+
     /// Set the amount of memory (in bytes) used for caching data that has been read
     ///
-    /// This setting is ignored when calling `create_mmapped()`/`open_mmapped()`
+    /// This setting is ignored when calling `create_mmapped()`/`open_mmapped()`/`create_in_memory()`
     pub fn set_read_cache_size(&mut self, bytes: usize) -> &mut Self {
         self.read_cache_size_bytes = bytes;
         self
@@ -559,7 +1062,7 @@ impl Builder {
 
     /// Set the amount of memory (in bytes) used for caching data that has been written
     ///
-    /// This setting is ignored when calling `create_mmapped()`/`open_mmapped()`
+    /// This setting is ignored when calling `create_mmapped()`/`open_mmapped()`/`create_in_memory()`
     pub fn set_write_cache_size(&mut self, bytes: usize) -> &mut Self {
         self.write_cache_size_bytes = bytes;
         self
@@ -594,14 +1097,16 @@ impl Builder {
             .open(path)?;
 
         Database::new(
-            file,
-            false,
+            StorageSource::File(file),
             self.page_size,
             self.region_size,
             self.initial_size,
             self.read_cache_size_bytes,
             self.write_cache_size_bytes,
             self.write_strategy,
+            self.encryption_key,
+            self.read_transaction_timeout,
+            self.cache_stats_callback.clone(),
         )
     }
 
@@ -625,14 +1130,16 @@ impl Builder {
             .open(path)?;
 
         Database::new(
-            file,
-            true,
+            StorageSource::Mmap(file),
             self.page_size,
             self.region_size,
             self.initial_size,
             self.read_cache_size_bytes,
             self.write_cache_size_bytes,
             self.write_strategy,
+            self.encryption_key,
+            self.read_transaction_timeout,
+            self.cache_stats_callback.clone(),
         )
     }
 
@@ -643,14 +1150,45 @@ impl Builder {
         } else if File::open(path.as_ref())?.metadata()?.len() > 0 {
             let file = OpenOptions::new().read(true).write(true).open(path)?;
             Database::new(
-                file,
-                false,
+                StorageSource::File(file),
                 self.page_size,
                 None,
                 self.initial_size,
                 self.read_cache_size_bytes,
                 self.write_cache_size_bytes,
                 None,
+                self.encryption_key,
+                self.read_transaction_timeout,
+                self.cache_stats_callback.clone(),
+            )
+        } else {
+            Err(Error::Io(io::Error::from(ErrorKind::InvalidData)))
+        }
+    }
+
+    /// Opens an existing redb database for reading only.
+    ///
+    /// The file is opened read-only, and only a shared lock is taken on it, so the same database
+    /// may be opened by several readers at once. [`Database::begin_write`] will return
+    /// [`Error::DatabaseReadOnly`].
+    ///
+    /// Returns [`Error::RepairRequired`] if the database was not shutdown cleanly
+    pub fn open_read_only(&self, path: impl AsRef<Path>) -> Result<Database> {
+        if !path.as_ref().exists() {
+            Err(Error::Io(ErrorKind::NotFound.into()))
+        } else if File::open(path.as_ref())?.metadata()?.len() > 0 {
+            let file = OpenOptions::new().read(true).open(path)?;
+            Database::new(
+                StorageSource::ReadOnlyFile(file),
+                self.page_size,
+                None,
+                self.initial_size,
+                self.read_cache_size_bytes,
+                self.write_cache_size_bytes,
+                None,
+                self.encryption_key,
+                self.read_transaction_timeout,
+                self.cache_stats_callback.clone(),
             )
         } else {
             Err(Error::Io(io::Error::from(ErrorKind::InvalidData)))
@@ -672,19 +1210,58 @@ impl Builder {
         } else if File::open(path.as_ref())?.metadata()?.len() > 0 {
             let file = OpenOptions::new().read(true).write(true).open(path)?;
             Database::new(
-                file,
-                true,
+                StorageSource::Mmap(file),
                 self.page_size,
                 None,
                 self.initial_size,
                 self.read_cache_size_bytes,
                 self.write_cache_size_bytes,
                 None,
+                self.encryption_key,
+                self.read_transaction_timeout,
+                self.cache_stats_callback.clone(),
             )
         } else {
             Err(Error::Io(io::Error::from(ErrorKind::InvalidData)))
         }
     }
+
+    /// Opens a redb database stored in the given [`StorageBackend`].
+    /// * if the backend is empty, a new database will be initialized in it
+    /// * if the backend contains a valid redb database, it will be opened
+    /// * otherwise this function will return an error
+    pub fn create_with_backend(&self, backend: impl StorageBackend) -> Result<Database> {
+        Database::new(
+            StorageSource::Backend(Box::new(backend)),
+            self.page_size,
+            self.region_size,
+            self.initial_size,
+            self.read_cache_size_bytes,
+            self.write_cache_size_bytes,
+            self.write_strategy,
+            self.encryption_key,
+            self.read_transaction_timeout,
+            self.cache_stats_callback.clone(),
+        )
+    }
+
+    /// Creates a new redb database, which is stored in memory rather than in a file.
+    ///
+    /// All the data is lost when the [`Database`] is dropped
+    pub fn create_in_memory(&self) -> Result<Database> {
+        Database::new(
+            StorageSource::InMemory,
+            self.page_size,
+            self.region_size,
+            self.initial_size,
+            self.read_cache_size_bytes,
+            self.write_cache_size_bytes,
+            self.write_strategy,
+            self.encryption_key,
+            self.read_transaction_timeout,
+            self.cache_stats_callback.clone(),
+        )
+    }
 }
 
 // This just makes it easier to throw `dbg` etc statements on `Result<Database>`
@@ -698,7 +1275,10 @@ impl std::fmt::Debug for Database {
 mod test {
     use tempfile::NamedTempFile;
 
-    use crate::{Database, Durability, ReadableTable, TableDefinition, WriteStrategy};
+    use crate::{
+        Database, Durability, MultimapTableDefinition, ReadableMultimapTable, ReadableTable,
+        TableDefinition, WriteStrategy,
+    };
 
     #[test]
     fn small_pages() {
@@ -928,4 +1508,263 @@ mod test {
         let final_file_size = tmpfile.as_file().metadata().unwrap().len();
         assert!(final_file_size < file_size);
     }
+
+    #[test]
+    #[cfg(unix)]
+    fn compaction() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let multimap_definition: MultimapTableDefinition<u64, u64> =
+            MultimapTableDefinition::new("y");
+        let big_value = vec![0u8; 1024];
+
+        let mut db = Database::builder()
+            .set_region_size(1024 * 1024)
+            .create(tmpfile.path())
+            .unwrap();
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..4096 {
+                table.insert(&i, big_value.as_slice()).unwrap();
+            }
+            let mut multimap = txn.open_multimap_table(multimap_definition).unwrap();
+            for i in 0..1024 {
+                multimap.insert(&0, &i).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+
+        // Delete the entries at the start of the file, so that the remaining ones need to be moved
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..4000 {
+                table.remove(&i).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        // Perform a couple more commits to be sure the database has a chance to shrink on its own
+        for _ in 0..3 {
+            let txn = db.begin_write().unwrap();
+            txn.commit().unwrap();
+        }
+
+        let file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(db.compact().unwrap());
+        let compacted_file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(compacted_file_size < file_size);
+
+        drop(db);
+        let db = Database::open(tmpfile.path()).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.len().unwrap(), 96);
+        for i in 4000..4096 {
+            assert_eq!(
+                table.get(&i).unwrap().unwrap().value(),
+                big_value.as_slice()
+            );
+        }
+        let multimap = txn.open_multimap_table(multimap_definition).unwrap();
+        let values: Vec<u64> = multimap.get(&0).unwrap().map(|x| x.value()).collect();
+        assert_eq!(values, (0..1024).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn compaction_small_values() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = vec![0u8; 100];
+
+        let mut db = Database::builder()
+            .set_region_size(1024 * 1024)
+            .create(tmpfile.path())
+            .unwrap();
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..4096 {
+                table.insert(&i, value.as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..4088 {
+                table.remove(&i).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        for _ in 0..3 {
+            let txn = db.begin_write().unwrap();
+            txn.commit().unwrap();
+        }
+
+        // Small pages are split from the free space at the start of the file, rather than reusing
+        // the order 0 pages left over near its end
+        let file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(db.compact().unwrap());
+        let compacted_file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(compacted_file_size < file_size / 4);
+    }
+
+    #[test]
+    fn overflow_index_spans_pages() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        // Each 512 byte index page holds 20 chunks, so the index of this value spans three pages
+        let value: Vec<u8> = (0..=250u8).cycle().take(3 * 1024 * 1024).collect();
+
+        let mut db = Database::builder()
+            .set_page_size(512)
+            .create(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            table.insert(&0, value.as_slice()).unwrap();
+            table.insert(&1, value.as_slice()).unwrap();
+        }
+        txn.commit().unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            table.remove(&0).unwrap();
+        }
+        txn.commit().unwrap();
+        for _ in 0..3 {
+            let txn = db.begin_write().unwrap();
+            txn.commit().unwrap();
+        }
+
+        // The chunks and index pages of the remaining value are moved into the freed space
+        let file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(db.compact().unwrap());
+        let compacted_file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(compacted_file_size < file_size);
+        drop(db);
+
+        let db = Database::builder()
+            .set_page_size(512)
+            .open(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.get(&1).unwrap().unwrap().value(), value.as_slice());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn compaction_freed_tree_at_tail() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = vec![0u8; 100];
+
+        let mut db = Database::builder()
+            .set_region_size(1024 * 1024)
+            .create(tmpfile.path())
+            .unwrap();
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..1024 {
+                table.insert(&i, value.as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+
+        // A live read transaction keeps the freed pages from being reused, so the file grows and the
+        // freed tree ends up in the last region
+        let read_txn = db.begin_read().unwrap();
+        for round in 0..10 {
+            let txn = db.begin_write().unwrap();
+            {
+                let mut table = txn.open_table(table_definition).unwrap();
+                for i in (round..1024).step_by(10) {
+                    table.insert(&i, value.as_slice()).unwrap();
+                }
+            }
+            txn.commit().unwrap();
+        }
+        drop(read_txn);
+        let (freed_root, _) = db.get_memory().get_freed_root().unwrap();
+        assert!(freed_root.region > 1);
+
+        let file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(db.compact().unwrap());
+        let compacted_file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(compacted_file_size < file_size / 4);
+        let (freed_root, _) = db.get_memory().get_freed_root().unwrap();
+        assert_eq!(freed_root.region, 0);
+
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.len().unwrap(), 1024);
+    }
+
+    #[test]
+    fn change_to_checksum_strategy() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+
+        let db = Database::builder()
+            .set_write_strategy(WriteStrategy::TwoPhase)
+            .create(tmpfile.path())
+            .unwrap();
+        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let multimap_definition: MultimapTableDefinition<u64, u64> =
+            MultimapTableDefinition::new("y");
+
+        let value = vec![0u8; 100];
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..1000 {
+                table.insert(&i, value.as_slice()).unwrap();
+            }
+            let mut multimap = txn.open_multimap_table(multimap_definition).unwrap();
+            for i in 0..1000 {
+                multimap.insert(&0, &i).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        // Leave some entries in the freed table
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..500 {
+                table.remove(&i).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+
+        db.set_write_strategy(WriteStrategy::Checksum).unwrap();
+        assert!(Database::verify_primary_checksums(&db.mem).unwrap());
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            table.insert(&0, value.as_slice()).unwrap();
+        }
+        txn.commit().unwrap();
+        assert!(Database::verify_primary_checksums(&db.mem).unwrap());
+
+        drop(db);
+        let db = Database::builder()
+            .set_write_strategy(WriteStrategy::Checksum)
+            .open(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.len().unwrap(), 501);
+        assert_eq!(table.get(&999).unwrap().unwrap().value(), value.as_slice());
+        let multimap = txn.open_multimap_table(multimap_definition).unwrap();
+        let values: Vec<u64> = multimap.get(&0).unwrap().map(|x| x.value()).collect();
+        assert_eq!(values, (0..1000).collect::<Vec<u64>>());
+    }
 }
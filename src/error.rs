@@ -8,8 +8,20 @@ use std::{io, panic};
 pub enum Error {
     /// The Database is already open. Cannot acquire lock.
     DatabaseAlreadyOpen,
-    /// This savepoint is invalid because an older savepoint was restored after it was created
+    /// The Database was opened in read-only mode, so it cannot be written to
+    DatabaseReadOnly,
+    /// The Database was not shutdown cleanly, and must be repaired, which is not possible in
+    /// read-only mode
+    RepairRequired,
+    /// The Database is encrypted and the key is missing or wrong, or a key was provided for a
+    /// Database which is not encrypted
+    InvalidEncryptionKey,
+    /// This savepoint is invalid because an older savepoint was restored after it was created, or
+    /// there is no persistent savepoint with the given id
     InvalidSavepoint,
+    /// The operation is not possible while there are persistent savepoints. They must be deleted
+    /// first
+    PersistentSavepointExists,
     /// The Database is corrupted
     Corrupted(String),
     /// The database file is in an old file format and must be manually upgraded
@@ -18,6 +30,14 @@ pub enum Error {
     TableTypeMismatch(String),
     /// Table name does not match any table in database
     TableDoesNotExist(String),
+    /// The changeset is malformed, or cannot be applied to this Database
+    InvalidChangeSet(String),
+    /// The optimistic transaction read or wrote a key which another transaction changed after it
+    /// began. It should be retried
+    Conflict,
+    /// The read transaction was open for longer than the limit set with
+    /// [`crate::Builder::set_read_transaction_timeout`]
+    ReadTransactionExpired,
     // Tables cannot be opened for writing multiple times, since they could retrieve immutable &
     // mutable references to the same dirty pages, or multiple mutable references via insert_reserve()
     TableAlreadyOpen(String, &'static panic::Location<'static>),
@@ -52,6 +72,9 @@ impl Display for Error {
             Error::TableDoesNotExist(table) => {
                 write!(f, "Table '{table}' does not exist")
             }
+            Error::InvalidChangeSet(msg) => {
+                write!(f, "Invalid changeset: {msg}")
+            }
             Error::TableAlreadyOpen(name, location) => {
                 write!(f, "Table '{name}' already opened at: {location}")
             }
@@ -64,12 +87,39 @@ impl Display for Error {
             Error::DatabaseAlreadyOpen => {
                 write!(f, "Database already open. Cannot acquire lock.")
             }
+            Error::DatabaseReadOnly => {
+                write!(f, "Database is open in read-only mode.")
+            }
+            Error::RepairRequired => {
+                write!(
+                    f,
+                    "Database requires repair, which cannot be performed in read-only mode."
+                )
+            }
+            Error::Conflict => {
+                write!(
+                    f,
+                    "Transaction conflicted with a concurrent transaction, and must be retried."
+                )
+            }
+            Error::ReadTransactionExpired => {
+                write!(f, "Read transaction expired.")
+            }
+            Error::InvalidEncryptionKey => {
+                write!(f, "Encryption key does not match the database.")
+            }
             Error::InvalidSavepoint => {
                 write!(
                     f,
                     "Savepoint is invalid because an older savepoint was already restored."
                 )
             }
+            Error::PersistentSavepointExists => {
+                write!(
+                    f,
+                    "Persistent savepoints exist, and must be deleted before this operation."
+                )
+            }
         }
     }
 }
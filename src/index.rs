@@ -0,0 +1,287 @@
+use crate::transaction_tracker::Expiration;
+use crate::tree_store::{PageHint, TableTree, TableType, TransactionalMemory};
+use crate::types::{RedbKey, RedbValue};
+use crate::{
+    AccessGuard, Error, MultimapTable, MultimapTableDefinition, ReadOnlyMultimapTable,
+    ReadableMultimapTable, Result, WriteTransaction,
+};
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+
+/// Defines a secondary index, which maps a key extracted from the values of a table back to the
+/// keys of the table
+///
+/// An index is attached to a table with [`crate::TableDefinition::with_indexes`], and is then
+/// updated automatically whenever the table is modified. It can be read with
+/// [`crate::ReadableTable::index`]
+///
+/// # Examples
+///
+/// ```rust
+/// use redb::*;
+/// # use tempfile::NamedTempFile;
+/// const BY_NAME: IndexDefinition<u64, (&str, u64), &str> =
+///     IndexDefinition::new("by_name", |person| person.value().0);
+/// const PEOPLE: TableDefinition<u64, (&str, u64)> =
+///     TableDefinition::new("people").with_indexes(&[&BY_NAME]);
+///
+/// # fn main() -> Result<(), Error> {
+/// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+/// # let filename = tmpfile.path();
+/// let db = Database::create(filename)?;
+/// let write_txn = db.begin_write()?;
+/// {
+///     let mut table = write_txn.open_table(PEOPLE)?;
+///     table.insert(&0, &("alice", 31))?;
+///     table.insert(&1, &("bob", 42))?;
+/// }
+/// write_txn.commit()?;
+///
+/// let read_txn = db.begin_read()?;
+/// let table = read_txn.open_table(PEOPLE)?;
+/// let index = table.index(BY_NAME)?;
+/// let mut ids = index.get("bob")?;
+/// assert_eq!(1, ids.next().unwrap().value());
+/// # Ok(())
+/// # }
+/// ```
+pub struct IndexDefinition<'a, K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static> {
+    name: &'a str,
+    index_key: for<'v> fn(&'v AccessGuard<'v, V>) -> I::SelfType<'v>,
+    // Definitions are shared between threads, whatever their key type
+    _key_type: PhantomData<fn() -> K>,
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static>
+    IndexDefinition<'a, K, V, I>
+{
+    /// Construct a new index with given `name`, whose keys are extracted from values by
+    /// `index_key`
+    ///
+    /// ## Invariant
+    ///
+    /// `name` must not be empty, and must not contain a nul byte.
+    pub const fn new(
+        name: &'a str,
+        index_key: for<'v> fn(&'v AccessGuard<'v, V>) -> I::SelfType<'v>,
+    ) -> Self {
+        assert!(!name.is_empty());
+        assert!(!is_reserved_table_name(name));
+        // Custom alignment is not currently supported
+        assert!(I::ALIGNMENT == 1);
+        Self {
+            name,
+            index_key,
+            _key_type: PhantomData,
+        }
+    }
+
+    /// Returns a reference of the `name` of the current `IndexDefinition`.
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    // Opens the multimap table which stores this index, from a snapshot of the database
+    pub(crate) fn open_read_only<'txn>(
+        &self,
+        table: &str,
+        tree: &TableTree,
+        hint: PageHint,
+        expiration: Expiration,
+        mem: &'txn TransactionalMemory,
+    ) -> Result<ReadOnlyMultimapTable<'txn, I, K>> {
+        // Writes which were made without the index leave it stale, until the table is next opened
+        // with it
+        if !tree
+            .get_table_indexes(table)?
+            .iter()
+            .any(|index| index == self.name)
+        {
+            return Err(index_not_found(table, self.name));
+        }
+        let name = index_table_name(table, self.name);
+        let header = tree
+            .get_table::<I, K>(&name, TableType::Multimap, None)?
+            .ok_or_else(|| index_not_found(table, self.name))?;
+
+        Ok(ReadOnlyMultimapTable::new(
+            header.get_root(),
+            header.get_length(),
+            hint,
+            expiration,
+            mem,
+        ))
+    }
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static> Clone
+    for IndexDefinition<'a, K, V, I>
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static> Copy
+    for IndexDefinition<'a, K, V, I>
+{
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static> Display
+    for IndexDefinition<'a, K, V, I>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}<{}, {}>",
+            self.name,
+            I::type_name().name(),
+            K::type_name().name()
+        )
+    }
+}
+
+/// An index which can be attached to a table, with [`crate::TableDefinition::with_indexes`]
+///
+/// This trait is implemented by [`IndexDefinition`], and cannot be implemented outside of redb
+pub trait TableIndex<K: RedbKey + 'static, V: RedbValue + 'static>: Sync {
+    /// Returns the name of the index
+    fn name(&self) -> &str;
+
+    #[doc(hidden)]
+    fn open<'db, 'txn>(
+        &self,
+        table: &str,
+        length: u64,
+        current: bool,
+        transaction: &'txn WriteTransaction<'db>,
+    ) -> Result<Box<dyn IndexWriter + 'txn>>;
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static> TableIndex<K, V>
+    for IndexDefinition<'a, K, V, I>
+{
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn open<'db, 'txn>(
+        &self,
+        table: &str,
+        length: u64,
+        current: bool,
+        transaction: &'txn WriteTransaction<'db>,
+    ) -> Result<Box<dyn IndexWriter + 'txn>> {
+        let name = index_table_name(table, self.name);
+        let definition = MultimapTableDefinition::<I, K>::internal(&name);
+        let mut index = transaction.open_multimap_table(definition)?;
+        // An index which isn't recorded as current was not updated by some writes to the table,
+        // because they were made without it. Every entry of the table has exactly one entry in the
+        // index, so an index of a different length is also stale. Either way, it is rebuilt from
+        // scratch
+        if !current || index.len()? as u64 != length {
+            drop(index);
+            transaction.delete_multimap_table(definition)?;
+            index = transaction.open_multimap_table(definition)?;
+        }
+
+        Ok(Box::new(OpenIndex {
+            name: self.name.to_string(),
+            table: index,
+            index_key: self.index_key,
+            _value_type: PhantomData::<V>,
+        }))
+    }
+}
+
+// An index which is open for writing, along with the table that it indexes.
+// Keys and values are passed as serialized, and uncompressed, bytes so that the index type
+// doesn't need to be known by the table
+pub trait IndexWriter: Send {
+    fn name(&self) -> &str;
+
+    // Number of entries in the index
+    fn len(&self) -> Result<u64>;
+
+    // Indexes the entry `key` -> `value` of the table
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Result;
+
+    // Removes the entry `key` -> `value` of the table from the index
+    fn remove(&mut self, key: &[u8], value: &[u8]) -> Result;
+
+    // Makes the current state of the index visible to reads from the transaction
+    fn stage(&self);
+}
+
+struct OpenIndex<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static> {
+    name: String,
+    table: MultimapTable<'db, 'txn, I, K>,
+    index_key: for<'v> fn(&'v AccessGuard<'v, V>) -> I::SelfType<'v>,
+    _value_type: PhantomData<V>,
+}
+
+// Safety: `K`, `V`, and `I` only determine how serialized data is interpreted, and no values of
+// these types are stored. The index is otherwise as Send as the table which it belongs to
+unsafe impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static> Send
+    for OpenIndex<'db, 'txn, K, V, I>
+{
+}
+
+impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static> IndexWriter
+    for OpenIndex<'db, 'txn, K, V, I>
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn len(&self) -> Result<u64> {
+        Ok(self.table.len()? as u64)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Result {
+        let value = AccessGuard::with_owned_value(value.to_vec());
+        let index_key = (self.index_key)(&value);
+        self.table.insert(index_key, K::from_bytes(key))?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8], value: &[u8]) -> Result {
+        let value = AccessGuard::with_owned_value(value.to_vec());
+        let index_key = (self.index_key)(&value);
+        self.table.remove(index_key, K::from_bytes(key))?;
+        Ok(())
+    }
+
+    fn stage(&self) {
+        self.table.stage();
+    }
+}
+
+// Names of the tables which redb maintains start with a nul byte. Names which contain a nul byte
+// anywhere are reserved, so that they can't collide with the user's tables, and so that the table
+// and index which an index table belongs to are unambiguous
+pub(crate) const fn is_reserved_table_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == 0 {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+// Indexes are stored in multimap tables with reserved names
+pub(crate) fn index_table_name(table: &str, index: &str) -> String {
+    format!("\0{table}\0{index}")
+}
+
+// Indexes, and other tables which are maintained by redb, are hidden from the user
+pub(crate) fn is_internal_table(name: &str) -> bool {
+    name.starts_with('\0')
+}
+
+pub(crate) fn index_not_found(table: &str, index: &str) -> Error {
+    Error::TableDoesNotExist(format!("{table}/{index}"))
+}
@@ -8,15 +8,23 @@
 
 extern crate core;
 
+pub use changes::{Change, ChangeSet};
 pub use db::{Builder, Database, MultimapTableDefinition, TableDefinition, WriteStrategy};
 pub use error::Error;
+pub use index::{IndexDefinition, TableIndex};
 pub use multimap_table::{
     MultimapRangeIter, MultimapTable, MultimapValueIter, ReadOnlyMultimapTable,
     ReadableMultimapTable,
 };
+pub use optimistic::{OptimisticRangeIter, OptimisticTable, OptimisticTransaction};
+pub use replication::ReplicatedTable;
 pub use table::{RangeIter, ReadOnlyTable, ReadableTable, Table};
-pub use transactions::{DatabaseStats, Durability, ReadTransaction, WriteTransaction};
-pub use tree_store::{AccessGuard, Savepoint};
+pub use transactions::{
+    DatabaseStats, Durability, ReadTransaction, ReadTransactionInfo, WriteTransaction,
+};
+pub use tree_store::{
+    AccessGuard, CacheStats, Compression, Savepoint, StorageBackend, ValueReader,
+};
 pub use types::{RedbKey, RedbValue, TypeName};
 
 type Result<T = (), E = Error> = std::result::Result<T, E>;
@@ -24,11 +32,15 @@ type Result<T = (), E = Error> = std::result::Result<T, E>;
 #[cfg(feature = "python")]
 pub use crate::python::redb;
 
+mod changes;
 mod db;
 mod error;
+mod index;
 mod multimap_table;
+mod optimistic;
 #[cfg(feature = "python")]
 mod python;
+mod replication;
 mod table;
 mod transaction_tracker;
 mod transactions;
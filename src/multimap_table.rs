@@ -1,10 +1,13 @@
+use crate::changes::Change;
 use crate::multimap_table::DynamicCollectionType::{Inline, Subtree};
+use crate::transaction_tracker::Expiration;
 use crate::tree_store::{
-    AllPageNumbersBtreeIter, Btree, BtreeMut, BtreeRangeIter, Checksum, LeafAccessor, Page,
-    PageHint, PageNumber, RawLeafBuilder, TransactionalMemory, BRANCH, LEAF,
+    copy_tree, copy_tree_with_values, AllPageNumbersBtreeIter, Btree, BtreeMut, BtreeRangeIter,
+    Checksum, LeafAccessor, Page, PageHint, PageNumber, RawBtree, RawLeafBuilder, RelocateHelper,
+    TransactionalMemory, BRANCH, LEAF,
 };
 use crate::types::{RedbKey, RedbValue, TypeName};
-use crate::{AccessGuard, Result, WriteTransaction};
+use crate::{AccessGuard, Error, Result, WriteTransaction};
 use std::borrow::Borrow;
 use std::convert::TryInto;
 use std::marker::PhantomData;
@@ -39,6 +42,128 @@ pub(crate) fn parse_subtree_roots<T: Page>(
     }
 }
 
+// Verifies the checksums of a multimap table, including the subtrees stored in its values
+pub(crate) fn verify_tree_and_subtree_checksums(
+    root: Option<(PageNumber, Checksum)>,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+    mem: &TransactionalMemory,
+) -> Result<bool> {
+    let tree = RawBtree::new(
+        root,
+        fixed_key_size,
+        <&DynamicCollection>::fixed_width(),
+        mem,
+    );
+    if !tree.verify_checksum()? {
+        return Ok(false);
+    }
+
+    if let Some((root, _)) = root {
+        let table_pages_iter = AllPageNumbersBtreeIter::new(
+            root,
+            fixed_key_size,
+            <&DynamicCollection>::fixed_width(),
+            mem,
+        )?;
+        for table_page in table_pages_iter {
+            let page = mem.get_page(table_page)?;
+            if page.memory()[0] != LEAF {
+                continue;
+            }
+            let accessor = LeafAccessor::new(
+                page.memory(),
+                fixed_key_size,
+                <&DynamicCollection>::fixed_width(),
+            );
+            for i in 0..accessor.num_pairs() {
+                let entry = accessor.entry(i).unwrap();
+                let collection = <&DynamicCollection>::from_bytes(entry.value());
+                if matches!(collection.collection_type(), Subtree) {
+                    let subtree = RawBtree::new(
+                        Some(collection.as_subtree()),
+                        fixed_value_size,
+                        <() as RedbValue>::fixed_width(),
+                        mem,
+                    );
+                    if !subtree.verify_checksum()? {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+// Relocates the pages of a multimap table, including the subtrees stored in its values.
+// Returns the new root, if it changed
+pub(crate) fn relocate_multimap_tree(
+    helper: &mut RelocateHelper,
+    root: PageNumber,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+) -> Result<Option<(PageNumber, Checksum)>> {
+    helper.relocate_with_values(
+        root,
+        fixed_key_size,
+        <&DynamicCollection>::fixed_width(),
+        &mut |helper, value| {
+            let collection = <&DynamicCollection>::from_bytes(value);
+            if matches!(collection.collection_type(), Subtree) {
+                let (subtree_root, _) = collection.as_subtree();
+                if let Some((new_root, checksum)) = helper.relocate(
+                    subtree_root,
+                    fixed_value_size,
+                    <() as RedbValue>::fixed_width(),
+                )? {
+                    return Ok(Some(DynamicCollection::make_subtree_data(
+                        new_root, checksum,
+                    )));
+                }
+            }
+            Ok(None)
+        },
+    )
+}
+
+// Copies a multimap table, including the subtrees stored in its values, from `source_mem` into
+// `mem`. Returns the root of the copy
+pub(crate) fn copy_multimap_tree(
+    source_root: PageNumber,
+    source_mem: &TransactionalMemory,
+    mem: &TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+) -> Result<Option<(PageNumber, Checksum)>> {
+    copy_tree_with_values(
+        source_root,
+        source_mem,
+        mem,
+        fixed_key_size,
+        <&DynamicCollection>::fixed_width(),
+        &mut |value| {
+            let collection = <&DynamicCollection>::from_bytes(value);
+            if matches!(collection.collection_type(), Subtree) {
+                let (subtree_root, _) = collection.as_subtree();
+                let (new_root, checksum) = copy_tree(
+                    subtree_root,
+                    source_mem,
+                    mem,
+                    fixed_value_size,
+                    <() as RedbValue>::fixed_width(),
+                )?
+                .unwrap();
+                return Ok(Some(DynamicCollection::make_subtree_data(
+                    new_root, checksum,
+                )));
+            }
+            Ok(None)
+        },
+    )
+}
+
 pub(crate) struct LeafKeyIter<'a> {
     inline_collection: AccessGuard<'a, &'static DynamicCollection>,
     fixed_key_size: Option<usize>,
@@ -198,6 +323,24 @@ impl DynamicCollection {
         (page_number, checksum)
     }
 
+    // Returns the number of values in the collection
+    fn len<V: RedbKey>(&self, mem: &TransactionalMemory) -> Result<usize> {
+        Ok(match self.collection_type() {
+            Inline => LeafAccessor::new(
+                self.as_inline(),
+                V::fixed_width(),
+                <() as RedbValue>::fixed_width(),
+            )
+            .num_pairs(),
+            Subtree => BtreeRangeIter::<V, ()>::new::<RangeFull, &V::SelfType<'_>>(
+                ..,
+                Some(self.as_subtree().0),
+                mem,
+            )?
+            .count(),
+        })
+    }
+
     fn iter<'a, V: RedbKey>(
         collection: AccessGuard<'a, &'static DynamicCollection>,
         mem: &'a TransactionalMemory,
@@ -271,6 +414,9 @@ pub struct MultimapValueIter<'a, V: RedbKey + 'static> {
     inner: Option<ValueIterState<'a, V>>,
     freed_pages: Option<Arc<Mutex<Vec<PageNumber>>>>,
     free_on_drop: Vec<PageNumber>,
+    expiration: Expiration,
+    // The error which ended the iteration early, if any
+    error: Option<Error>,
     mem: Option<&'a TransactionalMemory>,
     _value_type: PhantomData<V>,
 }
@@ -281,6 +427,8 @@ impl<'a, V: RedbKey + 'static> MultimapValueIter<'a, V> {
             inner: Some(ValueIterState::Subtree(inner)),
             freed_pages: None,
             free_on_drop: vec![],
+            expiration: Default::default(),
+            error: None,
             mem: None,
             _value_type: Default::default(),
         }
@@ -296,6 +444,8 @@ impl<'a, V: RedbKey + 'static> MultimapValueIter<'a, V> {
             inner: Some(ValueIterState::Subtree(inner)),
             freed_pages: Some(freed_pages),
             free_on_drop: pages,
+            expiration: Default::default(),
+            error: None,
             mem: Some(mem),
             _value_type: Default::default(),
         }
@@ -306,33 +456,60 @@ impl<'a, V: RedbKey + 'static> MultimapValueIter<'a, V> {
             inner: Some(ValueIterState::InlineLeaf(inner)),
             freed_pages: None,
             free_on_drop: vec![],
+            expiration: Default::default(),
+            error: None,
             mem: None,
             _value_type: Default::default(),
         }
     }
+
+    fn with_expiration(mut self, expiration: Expiration) -> Self {
+        self.expiration = expiration;
+        self
+    }
+
+    /// Returns the error which ended the iteration early, if any
+    ///
+    /// The iteration ends early if the read transaction has expired
+    pub fn check(mut self) -> Result {
+        self.error.take().map_or(Ok(()), Err)
+    }
+
+    fn next_value(&mut self, reverse: bool) -> Option<AccessGuard<'a, V>> {
+        if self.error.is_some() {
+            return None;
+        }
+        let _pin = match self.expiration.pin() {
+            Ok(pin) => pin,
+            Err(err) => {
+                self.error = Some(err);
+                return None;
+            }
+        };
+        // TODO: optimize out this copy
+        let bytes = match (self.inner.as_mut().unwrap(), reverse) {
+            (ValueIterState::Subtree(ref mut iter), false) => iter.next().map(|e| e.key_data())?,
+            (ValueIterState::Subtree(ref mut iter), true) => {
+                iter.next_back().map(|e| e.key_data())?
+            }
+            (ValueIterState::InlineLeaf(ref mut iter), false) => iter.next_key()?.to_vec(),
+            (ValueIterState::InlineLeaf(ref mut iter), true) => iter.next_key_back()?.to_vec(),
+        };
+        Some(AccessGuard::with_owned_value(bytes))
+    }
 }
 
 impl<'a, V: RedbKey + 'static> Iterator for MultimapValueIter<'a, V> {
     type Item = AccessGuard<'a, V>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // TODO: optimize out this copy
-        let bytes = match self.inner.as_mut().unwrap() {
-            ValueIterState::Subtree(ref mut iter) => iter.next().map(|e| e.key_data())?,
-            ValueIterState::InlineLeaf(ref mut iter) => iter.next_key()?.to_vec(),
-        };
-        Some(AccessGuard::with_owned_value(bytes))
+        self.next_value(false)
     }
 }
 
 impl<'a, V: RedbKey + 'static> DoubleEndedIterator for MultimapValueIter<'a, V> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        // TODO: optimize out this copy
-        let bytes = match self.inner.as_mut().unwrap() {
-            ValueIterState::Subtree(ref mut iter) => iter.next_back().map(|e| e.key_data())?,
-            ValueIterState::InlineLeaf(ref mut iter) => iter.next_key_back()?.to_vec(),
-        };
-        Some(AccessGuard::with_owned_value(bytes))
+        self.next_value(true)
     }
 }
 
@@ -356,6 +533,9 @@ impl<'a, V: RedbKey + 'static> Drop for MultimapValueIter<'a, V> {
 
 pub struct MultimapRangeIter<'a, K: RedbKey + 'static, V: RedbKey + 'static> {
     inner: BtreeRangeIter<'a, K, &'static DynamicCollection>,
+    expiration: Expiration,
+    // The error which ended the iteration early, if any
+    error: Option<Error>,
     mem: &'a TransactionalMemory,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
@@ -364,29 +544,64 @@ pub struct MultimapRangeIter<'a, K: RedbKey + 'static, V: RedbKey + 'static> {
 impl<'a, K: RedbKey + 'static, V: RedbKey + 'static> MultimapRangeIter<'a, K, V> {
     fn new(
         inner: BtreeRangeIter<'a, K, &'static DynamicCollection>,
+        expiration: Expiration,
         mem: &'a TransactionalMemory,
     ) -> Self {
         Self {
             inner,
+            expiration,
+            error: None,
             mem,
             _key_type: Default::default(),
             _value_type: Default::default(),
         }
     }
-}
 
-impl<'a, K: RedbKey + 'static, V: RedbKey + 'static> Iterator for MultimapRangeIter<'a, K, V> {
-    type Item = (AccessGuard<'a, K>, MultimapValueIter<'a, V>);
+    /// Returns the error which ended the iteration early, if any
+    ///
+    /// The iteration ends at the first entry which can't be read, such as when the read transaction
+    /// has expired
+    pub fn check(self) -> Result {
+        self.error.map_or(Ok(()), Err)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let entry = self.inner.next()?;
+    fn next_entry(
+        &mut self,
+        reverse: bool,
+    ) -> Option<(AccessGuard<'a, K>, MultimapValueIter<'a, V>)> {
+        if self.error.is_some() {
+            return None;
+        }
+        let _pin = match self.expiration.pin() {
+            Ok(pin) => pin,
+            Err(err) => {
+                self.error = Some(err);
+                return None;
+            }
+        };
+        let entry = if reverse {
+            self.inner.next_back()?
+        } else {
+            self.inner.next()?
+        };
         let key = AccessGuard::with_owned_value(entry.key_data());
         let (page, _, value_range) = entry.into_raw();
         let collection = AccessGuard::with_page(page, value_range);
-        // TODO: propagate error
-        let iter = DynamicCollection::iter(collection, self.mem).unwrap();
+        match DynamicCollection::iter(collection, self.mem) {
+            Ok(iter) => Some((key, iter.with_expiration(self.expiration.clone()))),
+            Err(err) => {
+                self.error = Some(err);
+                None
+            }
+        }
+    }
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbKey + 'static> Iterator for MultimapRangeIter<'a, K, V> {
+    type Item = (AccessGuard<'a, K>, MultimapValueIter<'a, V>);
 
-        Some((key, iter))
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry(false)
     }
 }
 
@@ -394,14 +609,7 @@ impl<'a, K: RedbKey + 'static, V: RedbKey + 'static> DoubleEndedIterator
     for MultimapRangeIter<'a, K, V>
 {
     fn next_back(&mut self) -> Option<Self::Item> {
-        let entry = self.inner.next_back()?;
-        let key = AccessGuard::with_owned_value(entry.key_data());
-        let (page, _, value_range) = entry.into_raw();
-        let collection = AccessGuard::with_page(page, value_range);
-        // TODO: propagate error
-        let iter = DynamicCollection::iter(collection, self.mem).unwrap();
-
-        Some((key, iter))
+        self.next_entry(true)
     }
 }
 
@@ -413,6 +621,8 @@ pub struct MultimapTable<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static>
     transaction: &'txn WriteTransaction<'db>,
     freed_pages: Arc<Mutex<Vec<PageNumber>>>,
     tree: BtreeMut<'txn, K, &'static DynamicCollection>,
+    // Number of key-value pairs in the table
+    length: u64,
     mem: &'db TransactionalMemory,
     _value_type: PhantomData<V>,
 }
@@ -421,6 +631,7 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
     pub(crate) fn new(
         name: &str,
         table_root: Option<(PageNumber, Checksum)>,
+        length: u64,
         freed_pages: Arc<Mutex<Vec<PageNumber>>>,
         mem: &'db TransactionalMemory,
         transaction: &'txn WriteTransaction<'db>,
@@ -430,6 +641,7 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
             transaction,
             freed_pages: freed_pages.clone(),
             tree: BtreeMut::new(table_root, mem, freed_pages),
+            length,
             mem,
             _value_type: Default::default(),
         }
@@ -440,6 +652,31 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
         self.tree.print_debug(include_values)
     }
 
+    // Makes the current state of the table visible to the rest of the transaction, while it is
+    // still open
+    pub(crate) fn stage(&self) {
+        self.transaction
+            .stage_table_root(&self.name, self.tree.get_root(), self.length);
+    }
+
+    // Records that a value of `key` was inserted or removed, if the transaction has subscribers
+    fn record_change(
+        &self,
+        key: &K::SelfType<'_>,
+        old_value: Option<&[u8]>,
+        new_value: Option<&[u8]>,
+    ) {
+        if self.transaction.records_changes() {
+            self.transaction.record_change(Change::new(
+                &self.name,
+                true,
+                K::as_bytes(key).as_ref(),
+                old_value,
+                new_value,
+            ));
+        }
+    }
+
     /// Add the given value to the mapping of the key
     ///
     /// Returns `true` if the key-value pair was present
@@ -596,6 +833,10 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
             }
             false
         };
+        if !existed {
+            self.length += 1;
+            self.record_change(key.borrow(), None, Some(value_bytes_ref));
+        }
 
         Ok(existed)
     }
@@ -732,6 +973,14 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
                 existed
             }
         };
+        if existed {
+            self.length -= 1;
+            self.record_change(
+                key.borrow(),
+                Some(V::as_bytes(value.borrow()).as_ref()),
+                None,
+            );
+        }
 
         Ok(existed)
     }
@@ -746,12 +995,24 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
     where
         K: 'a,
     {
+        if self.transaction.records_changes() {
+            let removed: Vec<Vec<u8>> = match self.tree.get(key.borrow())? {
+                Some(collection) => DynamicCollection::iter::<V>(collection, self.mem)?
+                    .map(|value| V::as_bytes(&value.value()).as_ref().to_vec())
+                    .collect(),
+                None => vec![],
+            };
+            for value in removed {
+                self.record_change(key.borrow(), Some(&value), None);
+            }
+        }
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
         let iter = if let Some((collection, mut pages)) =
             self.tree.remove_retain_uncommitted(key.borrow())?
         {
+            self.length -= collection.value().len::<V>(self.mem)? as u64;
             if matches!(
                 collection.value().collection_type(),
                 DynamicCollectionType::Subtree
@@ -813,18 +1074,12 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> ReadableMultimapTabl
         KR: Borrow<K::SelfType<'b>> + 'b,
     {
         let inner = self.tree.range(range)?;
-        Ok(MultimapRangeIter::new(inner, self.mem))
+        Ok(MultimapRangeIter::new(inner, Default::default(), self.mem))
     }
 
     /// Returns the number of key-value pairs in the table
     fn len(&self) -> Result<usize> {
-        let mut count = 0;
-        for (_, mut values) in self.iter()? {
-            while values.next().is_some() {
-                count += 1;
-            }
-        }
-        Ok(count)
+        Ok(self.length.try_into().unwrap())
     }
 
     /// Returns `true` if the table is empty
@@ -837,7 +1092,8 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> Drop
     for MultimapTable<'db, 'txn, K, V>
 {
     fn drop(&mut self) {
-        self.transaction.close_table(&self.name, &mut self.tree);
+        self.transaction
+            .close_table(&self.name, &mut self.tree, self.length);
     }
 }
 
@@ -869,6 +1125,8 @@ pub trait ReadableMultimapTable<K: RedbKey + 'static, V: RedbKey + 'static> {
 /// A read-only multimap table
 pub struct ReadOnlyMultimapTable<'txn, K: RedbKey + 'static, V: RedbKey + 'static> {
     tree: Btree<'txn, K, &'static DynamicCollection>,
+    length: u64,
+    expiration: Expiration,
     mem: &'txn TransactionalMemory,
     _value_type: PhantomData<V>,
 }
@@ -876,11 +1134,15 @@ pub struct ReadOnlyMultimapTable<'txn, K: RedbKey + 'static, V: RedbKey + 'stati
 impl<'txn, K: RedbKey + 'static, V: RedbKey + 'static> ReadOnlyMultimapTable<'txn, K, V> {
     pub(crate) fn new(
         root_page: Option<(PageNumber, Checksum)>,
+        length: u64,
         hint: PageHint,
+        expiration: Expiration,
         mem: &'txn TransactionalMemory,
     ) -> ReadOnlyMultimapTable<'txn, K, V> {
         ReadOnlyMultimapTable {
             tree: Btree::new(root_page, hint, mem),
+            length,
+            expiration,
             mem,
             _value_type: Default::default(),
         }
@@ -892,6 +1154,7 @@ impl<'txn, K: RedbKey + 'static, V: RedbKey + 'static> ReadableMultimapTable<K,
 {
     /// Returns an iterator over all values for the given key. Values are in ascending order.
     fn get<'a>(&'a self, key: impl Borrow<K::SelfType<'a>>) -> Result<MultimapValueIter<'a, V>> {
+        let _pin = self.expiration.pin()?;
         let iter = if let Some(collection) = self.tree.get(key.borrow())? {
             DynamicCollection::iter(collection, self.mem)?
         } else {
@@ -902,7 +1165,7 @@ impl<'txn, K: RedbKey + 'static, V: RedbKey + 'static> ReadableMultimapTable<K,
             )?)
         };
 
-        Ok(iter)
+        Ok(iter.with_expiration(self.expiration.clone()))
     }
 
     fn range<'a: 'b, 'b, KR>(
@@ -913,18 +1176,17 @@ impl<'txn, K: RedbKey + 'static, V: RedbKey + 'static> ReadableMultimapTable<K,
         K: 'a,
         KR: Borrow<K::SelfType<'b>> + 'b,
     {
+        let _pin = self.expiration.pin()?;
         let inner = self.tree.range(range)?;
-        Ok(MultimapRangeIter::new(inner, self.mem))
+        Ok(MultimapRangeIter::new(
+            inner,
+            self.expiration.clone(),
+            self.mem,
+        ))
     }
 
     fn len(&self) -> Result<usize> {
-        let mut count = 0;
-        for (_, mut values) in self.iter()? {
-            while values.next().is_some() {
-                count += 1;
-            }
-        }
-        Ok(count)
+        Ok(self.length.try_into().unwrap())
     }
 
     fn is_empty(&self) -> Result<bool> {
@@ -0,0 +1,541 @@
+use crate::changes::{Change, ChangeSet, ChangedTable};
+use crate::replication::ReplicatedTable;
+use crate::transaction_tracker::TransactionId;
+use crate::types::{RedbKey, RedbValue};
+use crate::{
+    AccessGuard, Database, Durability, Error, RangeIter, ReadOnlyTable, ReadTransaction,
+    ReadableTable, Result, TableDefinition,
+};
+#[cfg(feature = "logging")]
+use log::info;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, Bound, HashMap, HashSet, VecDeque};
+use std::ops::RangeBounds;
+use std::sync::Mutex;
+
+// A key of a table
+type TableKey = (String, Vec<u8>);
+
+// A range of keys of a table, as a function which returns true if a key is in the range
+type TableRange = (String, Box<dyn Fn(&[u8]) -> bool>);
+
+// Tracks the keys changed by each commit, while there are optimistic transactions which may
+// conflict with it
+#[derive(Default)]
+pub(crate) struct ConflictTracker {
+    // The number of live optimistic transactions, by the id of the commit that they began after
+    live_transactions: BTreeMap<TransactionId, usize>,
+    // The keys changed by each commit after the oldest live optimistic transaction began, or
+    // `None` if any key may have changed
+    commits: Vec<(TransactionId, Option<HashSet<TableKey>>)>,
+}
+
+impl ConflictTracker {
+    pub(crate) fn is_tracking(&self) -> bool {
+        !self.live_transactions.is_empty()
+    }
+
+    fn register(&mut self, start: TransactionId) {
+        *self.live_transactions.entry(start).or_default() += 1;
+    }
+
+    fn unregister(&mut self, start: TransactionId) {
+        let count = self.live_transactions.get_mut(&start).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.live_transactions.remove(&start);
+        }
+        // Commits from before the oldest live transaction began can't conflict with anything
+        match self.live_transactions.keys().next() {
+            Some(oldest) => self.commits.retain(|(id, _)| id > oldest),
+            None => self.commits.clear(),
+        }
+    }
+
+    pub(crate) fn record_commit(&mut self, changes: &ChangeSet) {
+        let keys = if changes.restored_savepoint() {
+            None
+        } else {
+            Some(
+                changes
+                    .changes()
+                    .iter()
+                    .map(|change| (change.table().to_string(), change.key().to_vec()))
+                    .collect(),
+            )
+        };
+        self.commits
+            .push((TransactionId(changes.transaction_id()), keys));
+    }
+
+    // Records a commit by a transaction which did not record its changes, because it began before
+    // any optimistic transaction
+    pub(crate) fn record_unknown_commit(&mut self, id: TransactionId) {
+        self.commits.push((id, None));
+    }
+
+    // Returns true if a commit after `start` changed any of `keys`, or any key in `ranges`
+    fn conflicts(
+        &self,
+        start: TransactionId,
+        keys: &HashSet<TableKey>,
+        ranges: &[TableRange],
+    ) -> bool {
+        self.commits
+            .iter()
+            .filter(|(id, _)| *id > start)
+            .any(|(_, changed)| match changed {
+                Some(changed) => {
+                    !changed.is_disjoint(keys)
+                        || changed.iter().any(|(table, key)| {
+                            ranges
+                                .iter()
+                                .any(|(name, contains)| name == table && contains(key))
+                        })
+                }
+                None => true,
+            })
+    }
+}
+
+// The writes made to a table by an optimistic transaction, which are applied when it commits
+struct PendingTable<'db> {
+    table: ChangedTable,
+    definition: Box<dyn ReplicatedTable + 'db>,
+    // The latest value of each key which was written, or `None` if it was removed
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+#[derive(Default)]
+struct TransactionState<'db> {
+    // Keys which were read or written, and so must not have been changed by another transaction
+    // when this one commits
+    keys: HashSet<TableKey>,
+    // Ranges which were iterated over, in which no key must have been changed either
+    ranges: Vec<TableRange>,
+    tables: HashMap<String, PendingTable<'db>>,
+}
+
+/// A write transaction which runs concurrently with other write transactions
+///
+/// Writes are buffered privately, on top of a snapshot of the database taken when the transaction
+/// began, and are only applied when the transaction commits, through a regular
+/// [`WriteTransaction`](crate::WriteTransaction). Commits therefore still happen one at a time. If
+/// another transaction committed a change to any key which this transaction read or wrote in the
+/// meantime, commit fails with [`Error::Conflict`], and the transaction should be retried.
+///
+/// Only the entries of regular tables can be accessed, with [`OptimisticTransaction::open_table`].
+/// Multimap tables, indexes, savepoints, and deleting or listing tables are not supported. Writes
+/// are buffered in memory until the transaction commits, so it should not write a large number of
+/// entries.
+///
+/// Created by [`Database::begin_optimistic_write`]
+pub struct OptimisticTransaction<'db> {
+    db: &'db Database,
+    snapshot: ReadTransaction<'db>,
+    start: TransactionId,
+    durability: Durability,
+    state: Mutex<TransactionState<'db>>,
+}
+
+impl<'db> OptimisticTransaction<'db> {
+    pub(crate) fn new(db: &'db Database) -> Result<Self> {
+        // The snapshot is taken after the last commit is read, so it contains at least that
+        // commit. Any later commit is recorded, since it can only finish after the transaction is
+        // registered, and may conflict
+        let start = {
+            let mut conflict_tracker = db.conflict_tracker().lock().unwrap();
+            let start = db.get_memory().get_last_committed_transaction_id()?;
+            conflict_tracker.register(start);
+            start
+        };
+        let snapshot = match db.begin_read() {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                db.conflict_tracker().lock().unwrap().unregister(start);
+                return Err(err);
+            }
+        };
+        #[cfg(feature = "logging")]
+        info!("Beginning optimistic write transaction at id={:?}", start);
+
+        Ok(Self {
+            db,
+            snapshot,
+            start,
+            durability: Durability::Immediate,
+            state: Mutex::new(Default::default()),
+        })
+    }
+
+    /// Set the durability level with which the transaction will be committed
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Open the given table
+    ///
+    /// The table will be created when the transaction commits, if it does not exist
+    pub fn open_table<'txn, K: RedbKey + 'static, V: RedbValue + 'static>(
+        &'txn self,
+        definition: TableDefinition<'db, K, V>,
+    ) -> Result<OptimisticTable<'db, 'txn, K, V>> {
+        let snapshot = match self.snapshot.open_table(definition) {
+            Ok(table) => Some(table),
+            Err(Error::TableDoesNotExist(_)) => None,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .tables
+            .entry(definition.name().to_string())
+            .or_insert_with(|| PendingTable {
+                table: ChangedTable::new(definition.name(), false, K::type_name(), V::type_name()),
+                definition: Box::new(definition),
+                writes: Default::default(),
+            });
+
+        Ok(OptimisticTable {
+            name: definition.name().to_string(),
+            transaction: self,
+            snapshot,
+        })
+    }
+
+    // Returns the value of `key` in `table`, as seen by this transaction, and records that it
+    // was read. The outer `Option` is `None` if the key was not written by this transaction
+    #[allow(clippy::option_option)]
+    fn read(&self, table: &str, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        state.keys.insert((table.to_string(), key.to_vec()));
+        state.tables[table].writes.get(key).cloned()
+    }
+
+    // Returns the entries in `range` of `table` which were written by this transaction, ordered
+    // by key, and records that the range was read
+    fn read_range<K: RedbKey + 'static>(
+        &self,
+        table: &str,
+        start: Bound<Vec<u8>>,
+        end: Bound<Vec<u8>>,
+    ) -> VecDeque<(Vec<u8>, Option<Vec<u8>>)> {
+        let contains = move |key: &[u8]| range_contains::<K>(&start, &end, key);
+        let mut state = self.state.lock().unwrap();
+        let mut writes: Vec<(Vec<u8>, Option<Vec<u8>>)> = state.tables[table]
+            .writes
+            .iter()
+            .filter(|(key, _)| contains(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        writes.sort_by(|(a, _), (b, _)| K::compare(a, b));
+        state.ranges.push((table.to_string(), Box::new(contains)));
+
+        writes.into()
+    }
+
+    fn write(&self, table: &str, key: &[u8], value: Option<&[u8]>) {
+        let mut state = self.state.lock().unwrap();
+        state.keys.insert((table.to_string(), key.to_vec()));
+        state
+            .tables
+            .get_mut(table)
+            .unwrap()
+            .writes
+            .insert(key.to_vec(), value.map(|x| x.to_vec()));
+    }
+
+    /// Commit the transaction
+    ///
+    /// Waits for any other write transaction to complete, like [`Database::begin_write`], and then
+    /// returns [`Error::Conflict`] if a transaction which committed after this one began changed
+    /// any of the keys which this one read or wrote. Otherwise, all writes performed in this
+    /// transaction are applied, and will be visible to future transactions
+    pub fn commit(self) -> Result {
+        let state = std::mem::take(&mut *self.state.lock().unwrap());
+        let mut txn = self.db.begin_write()?;
+        txn.set_durability(self.durability);
+        if self.db.conflict_tracker().lock().unwrap().conflicts(
+            self.start,
+            &state.keys,
+            &state.ranges,
+        ) {
+            #[cfg(feature = "logging")]
+            info!(
+                "Optimistic write transaction at id={:?} conflicted",
+                self.start
+            );
+            txn.abort()?;
+            return Err(Error::Conflict);
+        }
+
+        for table in state.tables.values() {
+            let changes: Vec<Change> = table
+                .writes
+                .iter()
+                .map(|(key, value)| {
+                    Change::new(table.table.name(), false, key, None, value.as_deref())
+                })
+                .collect();
+            let changes: Vec<&Change> = changes.iter().collect();
+            table.definition.apply(&table.table, &changes, &txn)?;
+        }
+        txn.commit()
+    }
+
+    /// Abort the transaction
+    ///
+    /// All writes performed in this transaction will be discarded
+    pub fn abort(self) -> Result {
+        Ok(())
+    }
+}
+
+impl<'db> Drop for OptimisticTransaction<'db> {
+    fn drop(&mut self) {
+        self.db
+            .conflict_tracker()
+            .lock()
+            .unwrap()
+            .unregister(self.start);
+    }
+}
+
+/// A table which is open in an [`OptimisticTransaction`]
+///
+/// Reads see the writes made earlier in the same transaction. Iterating over a range conflicts
+/// with a change to any key in the range, even if the iterator is not advanced to it
+pub struct OptimisticTable<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> {
+    name: String,
+    transaction: &'txn OptimisticTransaction<'db>,
+    snapshot: Option<ReadOnlyTable<'txn, K, V>>,
+}
+
+impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> OptimisticTable<'db, 'txn, K, V> {
+    /// Returns the value corresponding to the given key
+    pub fn get<'a>(&self, key: impl Borrow<K::SelfType<'a>>) -> Result<Option<AccessGuard<'_, V>>>
+    where
+        K: 'a,
+    {
+        let written = self
+            .transaction
+            .read(&self.name, K::as_bytes(key.borrow()).as_ref());
+        match written {
+            Some(value) => Ok(value.map(AccessGuard::with_owned_value)),
+            None => match self.snapshot {
+                Some(ref snapshot) => snapshot.get(key),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Returns a double-ended iterator over a range of elements in the table, including the writes
+    /// made earlier in the same transaction
+    pub fn range<'a: 'b, 'b, KR>(
+        &'a self,
+        range: impl RangeBounds<KR> + 'b,
+    ) -> Result<OptimisticRangeIter<'a, K, V>>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'b>> + 'b,
+    {
+        let start = owned_bound::<K, KR>(range.start_bound());
+        let end = owned_bound::<K, KR>(range.end_bound());
+        let writes = self.transaction.read_range::<K>(&self.name, start, end);
+        let snapshot = match self.snapshot {
+            Some(ref snapshot) => Some(snapshot.range(range)?),
+            None => None,
+        };
+
+        Ok(OptimisticRangeIter {
+            snapshot,
+            front: None,
+            back: None,
+            writes,
+        })
+    }
+
+    /// Returns a double-ended iterator over all elements in the table, including the writes made
+    /// earlier in the same transaction
+    pub fn iter(&self) -> Result<OptimisticRangeIter<'_, K, V>> {
+        self.range::<K::SelfType<'_>>(..)
+    }
+
+    // Returns a copy of the value corresponding to the given key
+    fn get_owned<'a>(&self, key: &K::SelfType<'a>) -> Result<Option<Vec<u8>>>
+    where
+        K: 'a,
+    {
+        Ok(self
+            .get(key)?
+            .map(|value| V::as_bytes(&value.value()).as_ref().to_vec()))
+    }
+
+    /// Insert mapping of the given key to the given value
+    ///
+    /// Returns the old value, if the key was present in the table
+    pub fn insert<'a>(
+        &mut self,
+        key: impl Borrow<K::SelfType<'a>>,
+        value: impl Borrow<V::SelfType<'a>>,
+    ) -> Result<Option<AccessGuard<'_, V>>>
+    where
+        K: 'a,
+        V: 'a,
+    {
+        let old_value = self.get_owned(key.borrow())?;
+        self.transaction.write(
+            &self.name,
+            K::as_bytes(key.borrow()).as_ref(),
+            Some(V::as_bytes(value.borrow()).as_ref()),
+        );
+        Ok(old_value.map(AccessGuard::with_owned_value))
+    }
+
+    /// Removes the given key
+    ///
+    /// Returns the old value, if the key was present in the table
+    pub fn remove<'a>(
+        &mut self,
+        key: impl Borrow<K::SelfType<'a>>,
+    ) -> Result<Option<AccessGuard<'_, V>>>
+    where
+        K: 'a,
+    {
+        let old_value = self.get_owned(key.borrow())?;
+        self.transaction
+            .write(&self.name, K::as_bytes(key.borrow()).as_ref(), None);
+        Ok(old_value.map(AccessGuard::with_owned_value))
+    }
+}
+
+fn owned_bound<'b, K: RedbKey + 'b, KR: Borrow<K::SelfType<'b>>>(
+    bound: Bound<&KR>,
+) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(K::as_bytes(key.borrow()).as_ref().to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(K::as_bytes(key.borrow()).as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn range_contains<K: RedbKey>(start: &Bound<Vec<u8>>, end: &Bound<Vec<u8>>, key: &[u8]) -> bool {
+    let after_start = match start {
+        Bound::Included(start) => K::compare(key, start).is_ge(),
+        Bound::Excluded(start) => K::compare(key, start).is_gt(),
+        Bound::Unbounded => true,
+    };
+    let before_end = match end {
+        Bound::Included(end) => K::compare(key, end).is_le(),
+        Bound::Excluded(end) => K::compare(key, end).is_lt(),
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+type Entry<'a, K, V> = (AccessGuard<'a, K>, AccessGuard<'a, V>);
+
+/// A double-ended iterator over a range of an [`OptimisticTable`], which merges the writes made
+/// by the transaction over the snapshot that it began with
+pub struct OptimisticRangeIter<'a, K: RedbKey + 'static, V: RedbValue + 'static> {
+    snapshot: Option<RangeIter<'a, K, V>>,
+    // Entries of the snapshot which were taken from either end, but not yet returned
+    front: Option<Entry<'a, K, V>>,
+    back: Option<Entry<'a, K, V>>,
+    // The entries in the range which were written, or `None` if they were removed
+    writes: VecDeque<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> OptimisticRangeIter<'a, K, V> {
+    /// Returns the error which ended the iteration early, if any. See [`RangeIter::check`]
+    pub fn check(self) -> Result {
+        self.snapshot.map_or(Ok(()), RangeIter::check)
+    }
+
+    // Returns the next entry of the snapshot from the front, or the back if `reverse` is true
+    fn next_snapshot(&mut self, reverse: bool) -> Option<Entry<'a, K, V>> {
+        let (near, far) = if reverse {
+            (&mut self.back, &mut self.front)
+        } else {
+            (&mut self.front, &mut self.back)
+        };
+        if let Some(entry) = near.take() {
+            return Some(entry);
+        }
+        let next = self.snapshot.as_mut().and_then(|snapshot| {
+            if reverse {
+                snapshot.next_back()
+            } else {
+                snapshot.next()
+            }
+        });
+        // The last remaining entry may have been taken from the other end
+        next.or_else(|| far.take())
+    }
+
+    fn next_entry(&mut self, reverse: bool) -> Option<Entry<'a, K, V>> {
+        loop {
+            let entry = self.next_snapshot(reverse);
+            if self.snapshot.as_ref().map_or(false, RangeIter::failed) {
+                return None;
+            }
+            let write = if reverse {
+                self.writes.back()
+            } else {
+                self.writes.front()
+            };
+            let write_key = match write {
+                Some((key, _)) => key,
+                None => return entry,
+            };
+            if let Some(entry) = entry {
+                let mut order = K::compare(entry.0.raw_value(), write_key);
+                if reverse {
+                    order = order.reverse();
+                }
+                match order {
+                    Ordering::Less => return Some(entry),
+                    // The written value replaces the one in the snapshot
+                    Ordering::Equal => {}
+                    Ordering::Greater => {
+                        if reverse {
+                            self.back = Some(entry);
+                        } else {
+                            self.front = Some(entry);
+                        }
+                    }
+                }
+            }
+            let (key, value) = if reverse {
+                self.writes.pop_back().unwrap()
+            } else {
+                self.writes.pop_front().unwrap()
+            };
+            if let Some(value) = value {
+                return Some((
+                    AccessGuard::with_owned_value(key),
+                    AccessGuard::with_owned_value(value),
+                ));
+            }
+        }
+    }
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Iterator for OptimisticRangeIter<'a, K, V> {
+    type Item = (AccessGuard<'a, K>, AccessGuard<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry(false)
+    }
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> DoubleEndedIterator
+    for OptimisticRangeIter<'a, K, V>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_entry(true)
+    }
+}
@@ -0,0 +1,166 @@
+use crate::changes::{Change, ChangedTable};
+use crate::types::{RedbKey, RedbValue};
+use crate::{
+    ChangeSet, Database, Error, MultimapTableDefinition, ReadableTable, Result, TableDefinition,
+    WriteTransaction,
+};
+
+// The id of the last transaction whose changes were applied is stored in an internal table
+const REPLICATION_STATE: TableDefinition<&str, u64> = TableDefinition::internal("\0replication");
+const LAST_APPLIED_TRANSACTION: &str = "last_applied_transaction";
+
+/// A table which can be replicated to a follower, with [`crate::Database::apply_changeset`]
+///
+/// This trait is implemented by [`crate::TableDefinition`] and
+/// [`crate::MultimapTableDefinition`], and cannot be implemented outside of redb
+pub trait ReplicatedTable {
+    /// Returns the name of the table
+    fn name(&self) -> &str;
+
+    #[doc(hidden)]
+    fn apply(
+        &self,
+        table: &ChangedTable,
+        changes: &[&Change],
+        transaction: &WriteTransaction,
+    ) -> Result;
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> ReplicatedTable
+    for TableDefinition<'a, K, V>
+{
+    fn name(&self) -> &str {
+        TableDefinition::name(self)
+    }
+
+    fn apply(
+        &self,
+        table: &ChangedTable,
+        changes: &[&Change],
+        transaction: &WriteTransaction,
+    ) -> Result {
+        table.check_type(false, K::type_name(), V::type_name())?;
+        let mut destination = transaction.open_table(*self)?;
+        for change in changes {
+            let key = K::from_bytes(check_width::<K>(change.key())?);
+            if let Some(value) = change.new_value() {
+                destination.insert(key, V::from_bytes(check_width::<V>(value)?))?;
+            } else {
+                destination.remove(key)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbKey + 'static> ReplicatedTable
+    for MultimapTableDefinition<'a, K, V>
+{
+    fn name(&self) -> &str {
+        MultimapTableDefinition::name(self)
+    }
+
+    fn apply(
+        &self,
+        table: &ChangedTable,
+        changes: &[&Change],
+        transaction: &WriteTransaction,
+    ) -> Result {
+        table.check_type(true, K::type_name(), V::type_name())?;
+        let mut destination = transaction.open_multimap_table(*self)?;
+        for change in changes {
+            let key = K::from_bytes(check_width::<K>(change.key())?);
+            // Each change either inserts or removes a single value
+            if let Some(value) = change.new_value() {
+                destination.insert(key, V::from_bytes(check_width::<V>(value)?))?;
+            } else if let Some(value) = change.old_value() {
+                destination.remove(key, V::from_bytes(check_width::<V>(value)?))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Keys and values of a fixed width type can only be decoded from exactly that many bytes
+fn check_width<T: RedbValue>(bytes: &[u8]) -> Result<&[u8]> {
+    match T::fixed_width() {
+        Some(width) if width != bytes.len() => Err(Error::InvalidChangeSet(format!(
+            "expected {} bytes for {}, but found {}",
+            width,
+            T::type_name().name(),
+            bytes.len()
+        ))),
+        _ => Ok(bytes),
+    }
+}
+
+pub(crate) fn apply_changeset(
+    db: &Database,
+    changeset: &ChangeSet,
+    tables: &[&dyn ReplicatedTable],
+) -> Result<bool> {
+    if changeset.restored_savepoint() {
+        return Err(Error::InvalidChangeSet(
+            "the leader restored a savepoint, so the follower must be copied from it again"
+                .to_string(),
+        ));
+    }
+
+    let txn = db.begin_write()?;
+    {
+        let mut state = txn.open_table(REPLICATION_STATE)?;
+        let last_applied = state
+            .get(LAST_APPLIED_TRANSACTION)?
+            .map(|guard| guard.value());
+        if matches!(last_applied, Some(id) if id >= changeset.transaction_id()) {
+            drop(state);
+            txn.abort()?;
+            return Ok(false);
+        }
+        if matches!(last_applied, Some(id) if id != changeset.previous_transaction_id()) {
+            return Err(Error::InvalidChangeSet(format!(
+                "the changes of transactions after {} and before {} are missing",
+                last_applied.unwrap(),
+                changeset.transaction_id()
+            )));
+        }
+        state.insert(LAST_APPLIED_TRANSACTION, changeset.transaction_id())?;
+    }
+
+    for table in changeset.tables() {
+        let changes: Vec<&Change> = changeset
+            .changes()
+            .iter()
+            .filter(|change| change.table() == table.name())
+            .collect();
+        if changes.is_empty() {
+            continue;
+        }
+        let definition = tables
+            .iter()
+            .find(|definition| definition.name() == table.name())
+            .ok_or_else(|| {
+                Error::InvalidChangeSet(format!("no definition for table '{}'", table.name()))
+            })?;
+        definition.apply(table, &changes, &txn)?;
+    }
+    txn.commit()?;
+
+    Ok(true)
+}
+
+pub(crate) fn last_applied_transaction(db: &Database) -> Result<Option<u64>> {
+    let txn = db.begin_read()?;
+    let state = match txn.open_table(REPLICATION_STATE) {
+        Ok(state) => state,
+        Err(Error::TableDoesNotExist(_)) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let last_applied = state
+        .get(LAST_APPLIED_TRANSACTION)?
+        .map(|guard| guard.value());
+
+    Ok(last_applied)
+}
@@ -1,12 +1,19 @@
+use crate::changes::Change;
+use crate::index::{index_not_found, IndexWriter};
+use crate::transaction_tracker::Expiration;
 use crate::tree_store::{
-    AccessGuardMut, Btree, BtreeDrain, BtreeDrainFilter, BtreeMut, BtreeRangeIter, Checksum,
-    PageHint, PageNumber, TransactionalMemory,
+    encode_value, overflow_value_pages, overflow_value_reader, store_overflow_stream,
+    store_overflow_value, AccessGuardMut, Btree, BtreeDrain, BtreeDrainFilter, BtreeMut,
+    BtreeRangeIter, Checksum, Compression, EntryGuard, OnRemove, PageHint, PageNumber, TableTree,
+    TransactionalMemory, ValueReader, UNCOMPRESSED_HEADER,
 };
 use crate::types::{RedbKey, RedbValue};
-use crate::Result;
-use crate::{AccessGuard, WriteTransaction};
+use crate::{AccessGuard, IndexDefinition, ReadOnlyMultimapTable, TableIndex, WriteTransaction};
+use crate::{Error, Result};
 use std::borrow::Borrow;
-use std::ops::RangeBounds;
+use std::io;
+use std::io::Read;
+use std::ops::{RangeBounds, RangeFull};
 use std::sync::{Arc, Mutex};
 
 /// A table containing key-value mappings
@@ -14,12 +21,22 @@ pub struct Table<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> {
     name: String,
     transaction: &'txn WriteTransaction<'db>,
     tree: BtreeMut<'txn, K, V>,
+    compression: Option<Compression>,
+    // Number of entries in the table
+    length: u64,
+    indexes: Vec<Box<dyn IndexWriter + 'txn>>,
+    freed_pages: Arc<Mutex<Vec<PageNumber>>>,
+    mem: &'db TransactionalMemory,
 }
 
 impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K, V> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         name: &str,
         table_root: Option<(PageNumber, Checksum)>,
+        length: u64,
+        compression: Option<Compression>,
+        indexes: Vec<Box<dyn IndexWriter + 'txn>>,
         freed_pages: Arc<Mutex<Vec<PageNumber>>>,
         mem: &'db TransactionalMemory,
         transaction: &'txn WriteTransaction<'db>,
@@ -27,10 +44,44 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
         Table {
             name: name.to_string(),
             transaction,
-            tree: BtreeMut::new(table_root, mem, freed_pages),
+            tree: BtreeMut::new(table_root, mem, freed_pages.clone()),
+            compression,
+            length,
+            indexes,
+            freed_pages,
+            mem,
         }
     }
 
+    // Populates any indexes which are empty, but whose table is not
+    pub(crate) fn build_indexes(&mut self) -> Result {
+        for index in self.indexes.iter_mut() {
+            if index.len()? == self.length {
+                continue;
+            }
+            let mut iter = RangeIter::new(
+                self.tree.range::<RangeFull, K::SelfType<'_>>(..)?,
+                self.compression,
+                Default::default(),
+                self.mem,
+            );
+            for (key, value) in iter.by_ref() {
+                index.insert(
+                    K::as_bytes(&key.value()).as_ref(),
+                    V::as_bytes(&value.value()).as_ref(),
+                )?;
+            }
+            iter.check()?;
+        }
+        Ok(())
+    }
+
+    // Whether changes to entries have to be propagated to the indexes of the table, or to the
+    // change log of the transaction
+    fn tracks_changes(&self) -> bool {
+        !self.indexes.is_empty() || self.transaction.records_changes()
+    }
+
     #[allow(dead_code)]
     pub(crate) fn print_debug(&self, include_values: bool) -> Result {
         self.tree.print_debug(include_values)
@@ -39,15 +90,18 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
     /// Removes and returns the first key-value pair in the table
     pub fn pop_first(&mut self) -> Result<Option<(AccessGuard<K>, AccessGuard<V>)>> {
         // TODO: optimize this
-        let first = self.iter()?.next();
+        let mut iter = self.iter()?;
+        let first = iter.next();
         if let Some((ref key, _)) = first {
             let owned_key = K::as_bytes(key.value().borrow()).as_ref().to_vec();
             drop(first);
+            drop(iter);
             let key = K::from_bytes(&owned_key);
             let value = self.remove(&key)?.unwrap();
             drop(key);
             Ok(Some((AccessGuard::with_owned_value(owned_key), value)))
         } else {
+            iter.check()?;
             Ok(None)
         }
     }
@@ -55,15 +109,18 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
     /// Removes and returns the last key-value pair in the table
     pub fn pop_last(&mut self) -> Result<Option<(AccessGuard<K>, AccessGuard<V>)>> {
         // TODO: optimize this
-        let first = self.iter()?.rev().next();
-        if let Some((ref key, _)) = first {
+        let mut iter = self.iter()?;
+        let last = iter.next_back();
+        if let Some((ref key, _)) = last {
             let owned_key = K::as_bytes(key.value().borrow()).as_ref().to_vec();
-            drop(first);
+            drop(last);
+            drop(iter);
             let key = K::from_bytes(&owned_key);
             let value = self.remove(&key)?.unwrap();
             drop(key);
             Ok(Some((AccessGuard::with_owned_value(owned_key), value)))
         } else {
+            iter.check()?;
             Ok(None)
         }
     }
@@ -78,10 +135,24 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
         // TODO: we should not require Clone here
         KR: Borrow<K::SelfType<'b>> + Clone + 'b,
     {
+        let tracks_changes = self.tracks_changes();
+        let (name, indexes, transaction) = (&self.name, &mut self.indexes, self.transaction);
+        let mut on_remove = |key: &[u8], value: &[u8]| {
+            propagate_change(name, indexes, transaction, key, Some(value), None)
+        };
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
-        unsafe { self.tree.drain(range).map(Drain::new) }
+        let inner = unsafe {
+            self.tree.drain(
+                range,
+                V::fixed_width().is_none(),
+                self.compression,
+                tracks_changes.then_some(&mut on_remove as OnRemove),
+            )?
+        };
+        self.length -= inner.removed() as u64;
+        Ok(Drain::new(inner, self.compression, self.mem))
     }
 
     /// Applies `predicate` to all key-value pairs in the specified range. All entries for which
@@ -96,14 +167,25 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
         // TODO: we should not require Clone here
         KR: Borrow<K::SelfType<'b>> + Clone + 'b,
     {
+        let tracks_changes = self.tracks_changes();
+        let (name, indexes, transaction) = (&self.name, &mut self.indexes, self.transaction);
+        let mut on_remove = |key: &[u8], value: &[u8]| {
+            propagate_change(name, indexes, transaction, key, Some(value), None)
+        };
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
-        unsafe {
-            self.tree
-                .drain_filter(range, predicate)
-                .map(DrainFilter::new)
-        }
+        let inner = unsafe {
+            self.tree.drain_filter(
+                range,
+                predicate,
+                V::fixed_width().is_none(),
+                self.compression,
+                tracks_changes.then_some(&mut on_remove as OnRemove),
+            )?
+        };
+        self.length -= inner.removed() as u64;
+        Ok(DrainFilter::new(inner, self.compression, self.mem))
     }
 
     /// Insert mapping of the given key to the given value
@@ -118,14 +200,112 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
         K: 'a,
         V: 'a,
     {
+        let tracks_changes = self.tracks_changes();
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let old_value = unsafe {
+            if V::fixed_width().is_none() {
+                let value_bytes = V::as_bytes(value.borrow());
+                // Values are only encoded in tables with compression
+                let encoded = self
+                    .compression
+                    .map(|compression| encode_value(compression, value_bytes.as_ref()));
+                let stored = encoded.as_deref().unwrap_or(value_bytes.as_ref());
+                if let Some(reference) = store_overflow_value(stored, self.mem)? {
+                    self.tree.insert_bytes(key.borrow(), &reference)?
+                } else {
+                    self.tree.insert_bytes(key.borrow(), stored)?
+                }
+            } else {
+                self.tree.insert(key.borrow(), value.borrow())?
+            }
+        };
+        let inserted = old_value.is_none();
+        let old_value = decode_removed(old_value, self.compression, &self.freed_pages, self.mem)?;
+        if tracks_changes {
+            let old_bytes = old_value
+                .as_ref()
+                .map(|old_value| V::as_bytes(&old_value.value()).as_ref().to_vec());
+            propagate_change(
+                &self.name,
+                &mut self.indexes,
+                self.transaction,
+                K::as_bytes(key.borrow()).as_ref(),
+                old_bytes.as_deref(),
+                Some(V::as_bytes(value.borrow()).as_ref()),
+            )?;
+        }
+        if inserted {
+            self.length += 1;
+        }
+        Ok(old_value)
+    }
+
+    /// Insert mapping of the given key to a value of `len` bytes, which are read from `value`
+    ///
+    /// Unlike [`Table::insert`], large values are copied into the database as they are read, so
+    /// they are never held in memory as a whole. Such values are stored uncompressed
+    ///
+    /// Returns an error if the value type has a fixed width, and `len` doesn't match it
+    pub fn insert_stream<'a>(
+        &mut self,
+        key: impl Borrow<K::SelfType<'a>>,
+        mut value: impl Read,
+        len: usize,
+    ) -> Result
+    where
+        K: 'a,
+    {
+        check_value_length::<V>(len)?;
+        let tracks_changes = self.tracks_changes();
+        let old_bytes = if tracks_changes {
+            self.get(key.borrow())?
+                .map(|old_value| V::as_bytes(&old_value.value()).as_ref().to_vec())
+        } else {
+            None
+        };
+        let stored = if V::fixed_width().is_none() {
+            store_overflow_stream(&mut value, len, self.compression, self.mem)?
+        } else {
+            let mut buffer = vec![0; len];
+            value.read_exact(&mut buffer)?;
+            buffer
+        };
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
-        unsafe { self.tree.insert(key.borrow(), value.borrow()) }
+        let inserted = match unsafe { self.tree.insert_bytes(key.borrow(), &stored)? } {
+            Some(old_value) => {
+                free_overflow_pages::<V>(old_value.raw_value(), &self.freed_pages, self.mem)?;
+                false
+            }
+            None => true,
+        };
+        if tracks_changes {
+            // The value has to be read back, since it was never held in memory
+            let new_value =
+                decode(self.tree.get(key.borrow())?, self.compression, self.mem)?.unwrap();
+            propagate_change(
+                &self.name,
+                &mut self.indexes,
+                self.transaction,
+                K::as_bytes(key.borrow()).as_ref(),
+                old_bytes.as_deref(),
+                Some(V::as_bytes(&new_value.value()).as_ref()),
+            )?;
+        }
+        if inserted {
+            self.length += 1;
+        }
+        Ok(())
     }
 
     /// Reserve space to insert a key-value pair
     /// The returned reference will have length equal to value_length
+    ///
+    /// Returns an error if the value type has a fixed width, and `value_length` doesn't match it,
+    /// or if the table has indexes, since the value is not known until after it is inserted
     // TODO: return type should be V, not [u8]
     pub fn insert_reserve<'a>(
         &mut self,
@@ -135,10 +315,64 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
     where
         K: 'a,
     {
+        if !self.indexes.is_empty() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "insert_reserve() is not supported on tables with indexes",
+            )));
+        }
+        check_value_length::<V>(value_length)?;
+        // The old value is overwritten in place, so it has to be looked up first
+        let old_value = self
+            .tree
+            .get(key.borrow())?
+            .map(|guard| guard.raw_value().to_vec());
+        let old_bytes = if self.transaction.records_changes() {
+            self.get(key.borrow())?
+                .map(|old_value| V::as_bytes(&old_value.value()).as_ref().to_vec())
+        } else {
+            None
+        };
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
-        unsafe { self.tree.insert_reserve(key.borrow(), value_length) }
+        let mut guard = if V::fixed_width().is_some() {
+            unsafe { self.tree.insert_reserve(key.borrow(), value_length)? }
+        } else if self.compression.is_some() {
+            // Reserved values are stored uncompressed and inline, since they're written in place
+            let header = UNCOMPRESSED_HEADER;
+            let mut guard = unsafe {
+                self.tree
+                    .insert_reserve(key.borrow(), header.len() + value_length)?
+            };
+            guard.write_prefix(&header);
+            guard
+        } else {
+            let mut guard = unsafe { self.tree.insert_reserve(key.borrow(), value_length)? };
+            guard.set_escape_reference();
+            guard
+        };
+        if let Some(old_value) = old_value {
+            free_overflow_pages::<V>(&old_value, &self.freed_pages, self.mem)?;
+        } else {
+            self.length += 1;
+        }
+        if self.transaction.records_changes() {
+            // The change is recorded once the value has been written
+            let transaction = self.transaction;
+            let name = self.name.clone();
+            let key = K::as_bytes(key.borrow()).as_ref().to_vec();
+            guard.set_on_drop(Box::new(move |value| {
+                transaction.record_change(Change::new(
+                    &name,
+                    false,
+                    &key,
+                    old_bytes.as_deref(),
+                    Some(value),
+                ));
+            }));
+        }
+        Ok(guard)
     }
 
     /// Removes the given key
@@ -151,10 +385,27 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
     where
         K: 'a,
     {
+        let tracks_changes = self.tracks_changes();
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
-        unsafe { self.tree.remove(key.borrow()) }
+        let old_value = unsafe { self.tree.remove(key.borrow())? };
+        let removed = old_value.is_some();
+        let old_value = decode_removed(old_value, self.compression, &self.freed_pages, self.mem)?;
+        if let (true, Some(old_value)) = (tracks_changes, &old_value) {
+            propagate_change(
+                &self.name,
+                &mut self.indexes,
+                self.transaction,
+                K::as_bytes(key.borrow()).as_ref(),
+                Some(V::as_bytes(&old_value.value()).as_ref()),
+                None,
+            )?;
+        }
+        if removed {
+            self.length -= 1;
+        }
+        Ok(old_value)
     }
 }
 
@@ -165,7 +416,25 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V
     where
         K: 'a,
     {
-        self.tree.get(key.borrow())
+        decode(self.tree.get(key.borrow())?, self.compression, self.mem)
+    }
+
+    fn get_reader<'a>(&self, key: impl Borrow<K::SelfType<'a>>) -> Result<Option<ValueReader<'_>>>
+    where
+        K: 'a,
+    {
+        value_reader(self.tree.get(key.borrow())?, self.compression, self.mem)
+    }
+
+    fn nth(&self, n: usize) -> Result<Option<(AccessGuard<'_, K>, AccessGuard<'_, V>)>> {
+        decode_entry(self.tree.nth(n as u64)?, self.compression, self.mem)
+    }
+
+    fn rank<'a>(&self, key: impl Borrow<K::SelfType<'a>>) -> Result<usize>
+    where
+        K: 'a,
+    {
+        Ok(self.tree.rank(key.borrow())?.try_into().unwrap())
     }
 
     fn range<'a: 'b, 'b, KR>(
@@ -176,11 +445,39 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V
         K: 'a,
         KR: Borrow<K::SelfType<'b>> + 'b,
     {
-        self.tree.range(range).map(RangeIter::new)
+        self.tree
+            .range(range)
+            .map(|inner| RangeIter::new(inner, self.compression, Default::default(), self.mem))
+    }
+
+    fn range_uncached<'a: 'b, 'b, KR>(
+        &'a self,
+        range: impl RangeBounds<KR> + 'b,
+    ) -> Result<RangeIter<'a, K, V>>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'b>> + 'b,
+    {
+        self.tree
+            .range_extended(range, PageHint::Uncached)
+            .map(|inner| RangeIter::new(inner, self.compression, Default::default(), self.mem))
+    }
+
+    fn index<I: RedbKey + 'static>(
+        &self,
+        definition: IndexDefinition<K, V, I>,
+    ) -> Result<ReadOnlyMultimapTable<'_, I, K>> {
+        let index = self
+            .indexes
+            .iter()
+            .find(|index| index.name() == TableIndex::name(&definition))
+            .ok_or_else(|| index_not_found(&self.name, TableIndex::name(&definition)))?;
+        index.stage();
+        self.transaction.open_index(&self.name, definition)
     }
 
     fn len(&self) -> Result<usize> {
-        self.tree.len()
+        Ok(self.length.try_into().unwrap())
     }
 
     fn is_empty(&self) -> Result<bool> {
@@ -190,7 +487,140 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V
 
 impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Drop for Table<'db, 'txn, K, V> {
     fn drop(&mut self) {
-        self.transaction.close_table(&self.name, &mut self.tree);
+        self.transaction
+            .close_table(&self.name, &mut self.tree, self.length);
+    }
+}
+
+// Propagates a change of the value of `key` from `old_value` to `new_value` to the indexes of
+// `table`, and to the change log of its transaction
+fn propagate_change(
+    table: &str,
+    indexes: &mut [Box<dyn IndexWriter + '_>],
+    transaction: &WriteTransaction,
+    key: &[u8],
+    old_value: Option<&[u8]>,
+    new_value: Option<&[u8]>,
+) -> Result {
+    for index in indexes.iter_mut() {
+        if let Some(old_value) = old_value {
+            index.remove(key, old_value)?;
+        }
+        if let Some(new_value) = new_value {
+            index.insert(key, new_value)?;
+        }
+    }
+    if transaction.records_changes() {
+        transaction.record_change(Change::new(table, false, key, old_value, new_value));
+    }
+    Ok(())
+}
+
+// Decodes a value read from a table, reading it from overflow pages if necessary
+fn decode<'a, V: RedbValue>(
+    guard: Option<AccessGuard<'a, V>>,
+    compression: Option<Compression>,
+    mem: &TransactionalMemory,
+) -> Result<Option<AccessGuard<'a, V>>> {
+    guard
+        .map(|guard| guard.decode(compression, mem))
+        .transpose()
+}
+
+// Like decode(), but for a key-value pair
+#[allow(clippy::type_complexity)]
+fn decode_entry<'a, K: RedbKey, V: RedbValue>(
+    entry: Option<(AccessGuard<'a, K>, AccessGuard<'a, V>)>,
+    compression: Option<Compression>,
+    mem: &TransactionalMemory,
+) -> Result<Option<(AccessGuard<'a, K>, AccessGuard<'a, V>)>> {
+    entry
+        .map(|(key, value)| Ok((key, value.decode(compression, mem)?)))
+        .transpose()
+}
+
+// Converts an entry yielded by a btree iterator into its key, and decoded value. If the value can't
+// be decoded, the error is stored in `error` and None is returned, which ends the iteration
+fn decode_iter_entry<'a, K: RedbKey, V: RedbValue>(
+    entry: EntryGuard<'a, K, V>,
+    compression: Option<Compression>,
+    mem: &TransactionalMemory,
+    error: &mut Option<Error>,
+) -> Option<(AccessGuard<'a, K>, AccessGuard<'a, V>)> {
+    let (page, key_range, value_range) = entry.into_raw();
+    let key = AccessGuard::with_page(page.clone(), key_range);
+    match AccessGuard::with_page(page, value_range).decode(compression, mem) {
+        Ok(value) => Some((key, value)),
+        Err(err) => {
+            *error = Some(err);
+            None
+        }
+    }
+}
+
+fn value_reader<'a, V: RedbValue>(
+    guard: Option<AccessGuard<V>>,
+    compression: Option<Compression>,
+    mem: &'a TransactionalMemory,
+) -> Result<Option<ValueReader<'a>>> {
+    if let Some(guard) = guard {
+        if V::fixed_width().is_none() {
+            overflow_value_reader(guard.raw_value(), compression, mem).map(Some)
+        } else {
+            Ok(Some(ValueReader::new(guard.raw_value().to_vec())))
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+// Decodes a value that was removed from a table, and frees its overflow pages
+fn decode_removed<'a, V: RedbValue>(
+    guard: Option<AccessGuard<'a, V>>,
+    compression: Option<Compression>,
+    freed_pages: &Mutex<Vec<PageNumber>>,
+    mem: &TransactionalMemory,
+) -> Result<Option<AccessGuard<'a, V>>> {
+    if let Some(guard) = guard {
+        let raw_value = guard.raw_value().to_vec();
+        // The value has to be read out of its overflow pages before they can be freed
+        let guard = guard.decode(compression, mem)?;
+        free_overflow_pages::<V>(&raw_value, freed_pages, mem)?;
+        Ok(Some(guard))
+    } else {
+        Ok(None)
+    }
+}
+
+// Frees the overflow pages of a value which was removed from a table
+fn free_overflow_pages<V: RedbValue>(
+    raw_value: &[u8],
+    freed_pages: &Mutex<Vec<PageNumber>>,
+    mem: &TransactionalMemory,
+) -> Result {
+    if V::fixed_width().is_some() {
+        return Ok(());
+    }
+    let pages = overflow_value_pages(raw_value, mem)?;
+    let mut freed_pages = freed_pages.lock().unwrap();
+    for page in pages {
+        // Safety: the entry which referenced the page has been removed, and its value is no longer
+        // read from the page
+        if unsafe { !mem.free_if_uncommitted(page) } {
+            freed_pages.push(page);
+        }
+    }
+    Ok(())
+}
+
+// Returns an error if `V` has a fixed width, and `len` doesn't match it
+fn check_value_length<V: RedbValue>(len: usize) -> Result {
+    match V::fixed_width() {
+        Some(width) if width != len => Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Expected a value of {width} bytes, but the length is {len}"),
+        ))),
+        _ => Ok(()),
     }
 }
 
@@ -200,8 +630,34 @@ pub trait ReadableTable<K: RedbKey + 'static, V: RedbValue + 'static> {
     where
         K: 'a;
 
+    /// Returns a reader over the serialized value corresponding to the given key
+    ///
+    /// Unlike [`ReadableTable::get`], large values are read incrementally, so they are never held
+    /// in memory as a whole. Compressed values are still decoded up front
+    fn get_reader<'a>(&self, key: impl Borrow<K::SelfType<'a>>) -> Result<Option<ValueReader<'_>>>
+    where
+        K: 'a;
+
+    /// Returns the entry at position `n` of the table, in key order, or `None` if the table has
+    /// `n` or fewer entries
+    ///
+    /// This runs in logarithmic time, so it can be used to jump to an arbitrary page of results
+    #[allow(clippy::type_complexity)]
+    fn nth(&self, n: usize) -> Result<Option<(AccessGuard<'_, K>, AccessGuard<'_, V>)>>;
+
+    /// Returns the number of entries whose keys are less than `key`. If `key` is in the table,
+    /// this is its position
+    ///
+    /// This runs in logarithmic time. [`RangeIter::count`] is also logarithmic, for counting the
+    /// entries in a range
+    fn rank<'a>(&self, key: impl Borrow<K::SelfType<'a>>) -> Result<usize>
+    where
+        K: 'a;
+
     /// Returns a double-ended iterator over a range of elements in the table
     ///
+    /// The iteration ends early if an entry can't be read. See [`RangeIter::check`]
+    ///
     /// # Examples
     ///
     /// Usage:
@@ -240,6 +696,30 @@ pub trait ReadableTable<K: RedbKey + 'static, V: RedbValue + 'static> {
         K: 'a,
         KR: Borrow<K::SelfType<'b>> + 'b;
 
+    /// Returns a double-ended iterator over a range of elements in the table, which doesn't fill
+    /// the read cache
+    ///
+    /// This is intended for large scans, such as exports, which would otherwise evict the pages
+    /// used by other transactions from the cache. Leaf pages are only taken from the cache if they
+    /// are already there, and the following pages of the file are prefetched by the OS, where
+    /// supported. Branch pages are still cached
+    fn range_uncached<'a: 'b, 'b, KR>(
+        &'a self,
+        range: impl RangeBounds<KR> + 'b,
+    ) -> Result<RangeIter<'a, K, V>>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'b>> + 'b;
+
+    /// Opens the given index of the table, which maps the keys extracted from its values back to
+    /// its keys
+    ///
+    /// Returns [`crate::Error::TableDoesNotExist`] if the table does not have the index
+    fn index<I: RedbKey + 'static>(
+        &self,
+        definition: IndexDefinition<K, V, I>,
+    ) -> Result<ReadOnlyMultimapTable<'_, I, K>>;
+
     /// Returns the number of entries in the table
     fn len(&self) -> Result<usize>;
 
@@ -254,17 +734,36 @@ pub trait ReadableTable<K: RedbKey + 'static, V: RedbValue + 'static> {
 
 /// A read-only table
 pub struct ReadOnlyTable<'txn, K: RedbKey + 'static, V: RedbValue + 'static> {
+    name: String,
     tree: Btree<'txn, K, V>,
+    length: u64,
+    compression: Option<Compression>,
+    // The tables of the transaction, in which the indexes of this table are found
+    table_tree: &'txn TableTree<'txn>,
+    expiration: Expiration,
+    mem: &'txn TransactionalMemory,
 }
 
 impl<'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadOnlyTable<'txn, K, V> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
+        name: &str,
         root_page: Option<(PageNumber, Checksum)>,
+        length: u64,
+        compression: Option<Compression>,
+        table_tree: &'txn TableTree<'txn>,
         hint: PageHint,
+        expiration: Expiration,
         mem: &'txn TransactionalMemory,
     ) -> ReadOnlyTable<'txn, K, V> {
         ReadOnlyTable {
+            name: name.to_string(),
             tree: Btree::new(root_page, hint, mem),
+            length,
+            compression,
+            table_tree,
+            expiration,
+            mem,
         }
     }
 }
@@ -276,7 +775,29 @@ impl<'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V>
     where
         K: 'a,
     {
-        self.tree.get(key.borrow())
+        let _pin = self.expiration.pin()?;
+        decode(self.tree.get(key.borrow())?, self.compression, self.mem)
+    }
+
+    fn get_reader<'a>(&self, key: impl Borrow<K::SelfType<'a>>) -> Result<Option<ValueReader<'_>>>
+    where
+        K: 'a,
+    {
+        let _pin = self.expiration.pin()?;
+        value_reader(self.tree.get(key.borrow())?, self.compression, self.mem)
+    }
+
+    fn nth(&self, n: usize) -> Result<Option<(AccessGuard<'_, K>, AccessGuard<'_, V>)>> {
+        let _pin = self.expiration.pin()?;
+        decode_entry(self.tree.nth(n as u64)?, self.compression, self.mem)
+    }
+
+    fn rank<'a>(&self, key: impl Borrow<K::SelfType<'a>>) -> Result<usize>
+    where
+        K: 'a,
+    {
+        let _pin = self.expiration.pin()?;
+        Ok(self.tree.rank(key.borrow())?.try_into().unwrap())
     }
 
     fn range<'a: 'b, 'b, KR>(
@@ -287,11 +808,42 @@ impl<'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V>
         K: 'a,
         KR: Borrow<K::SelfType<'b>> + 'b,
     {
-        self.tree.range(range).map(RangeIter::new)
+        let _pin = self.expiration.pin()?;
+        self.tree
+            .range(range)
+            .map(|inner| RangeIter::new(inner, self.compression, self.expiration.clone(), self.mem))
+    }
+
+    fn range_uncached<'a: 'b, 'b, KR>(
+        &'a self,
+        range: impl RangeBounds<KR> + 'b,
+    ) -> Result<RangeIter<'a, K, V>>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'b>> + 'b,
+    {
+        let _pin = self.expiration.pin()?;
+        self.tree
+            .range_extended(range, PageHint::Uncached)
+            .map(|inner| RangeIter::new(inner, self.compression, self.expiration.clone(), self.mem))
+    }
+
+    fn index<I: RedbKey + 'static>(
+        &self,
+        definition: IndexDefinition<K, V, I>,
+    ) -> Result<ReadOnlyMultimapTable<'_, I, K>> {
+        let _pin = self.expiration.pin()?;
+        definition.open_read_only(
+            &self.name,
+            self.table_tree,
+            PageHint::Clean,
+            self.expiration.clone(),
+            self.mem,
+        )
     }
 
     fn len(&self) -> Result<usize> {
-        self.tree.len()
+        Ok(self.length.try_into().unwrap())
     }
 
     fn is_empty(&self) -> Result<bool> {
@@ -301,11 +853,32 @@ impl<'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V>
 
 pub struct Drain<'a, K: RedbKey + 'static, V: RedbValue + 'static> {
     inner: BtreeDrain<'a, K, V>,
+    compression: Option<Compression>,
+    // The error which ended the iteration early, if any
+    error: Option<Error>,
+    mem: &'a TransactionalMemory,
 }
 
 impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Drain<'a, K, V> {
-    fn new(inner: BtreeDrain<'a, K, V>) -> Self {
-        Self { inner }
+    fn new(
+        inner: BtreeDrain<'a, K, V>,
+        compression: Option<Compression>,
+        mem: &'a TransactionalMemory,
+    ) -> Self {
+        Self {
+            inner,
+            compression,
+            error: None,
+            mem,
+        }
+    }
+
+    /// Returns the error which ended the iteration early, if any
+    ///
+    /// The iteration ends at the first entry whose value can't be read, such as a compressed value
+    /// which is corrupted
+    pub fn check(self) -> Result {
+        self.error.map_or(Ok(()), Err)
     }
 }
 
@@ -313,21 +886,21 @@ impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Iterator for Drain<'a, K,
     type Item = (AccessGuard<'a, K>, AccessGuard<'a, V>);
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
         let entry = self.inner.next()?;
-        let (page, key_range, value_range) = entry.into_raw();
-        let key = AccessGuard::with_page(page.clone(), key_range);
-        let value = AccessGuard::with_page(page, value_range);
-        Some((key, value))
+        decode_iter_entry(entry, self.compression, self.mem, &mut self.error)
     }
 }
 
 impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> DoubleEndedIterator for Drain<'a, K, V> {
     fn next_back(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
         let entry = self.inner.next_back()?;
-        let (page, key_range, value_range) = entry.into_raw();
-        let key = AccessGuard::with_page(page.clone(), key_range);
-        let value = AccessGuard::with_page(page, value_range);
-        Some((key, value))
+        decode_iter_entry(entry, self.compression, self.mem, &mut self.error)
     }
 }
 
@@ -338,6 +911,10 @@ pub struct DrainFilter<
     F: for<'f> FnMut(K::SelfType<'f>, V::SelfType<'f>) -> bool,
 > {
     inner: BtreeDrainFilter<'a, K, V, F>,
+    compression: Option<Compression>,
+    // The error which ended the iteration early, if any
+    error: Option<Error>,
+    mem: &'a TransactionalMemory,
 }
 
 impl<
@@ -347,8 +924,25 @@ impl<
         F: for<'f> FnMut(K::SelfType<'f>, V::SelfType<'f>) -> bool,
     > DrainFilter<'a, K, V, F>
 {
-    fn new(inner: BtreeDrainFilter<'a, K, V, F>) -> Self {
-        Self { inner }
+    fn new(
+        inner: BtreeDrainFilter<'a, K, V, F>,
+        compression: Option<Compression>,
+        mem: &'a TransactionalMemory,
+    ) -> Self {
+        Self {
+            inner,
+            compression,
+            error: None,
+            mem,
+        }
+    }
+
+    /// Returns the error which ended the iteration early, if any
+    ///
+    /// The iteration ends at the first entry whose value can't be read, such as a compressed value
+    /// which is corrupted
+    pub fn check(self) -> Result {
+        self.error.map_or(Ok(()), Err)
     }
 }
 
@@ -362,11 +956,11 @@ impl<
     type Item = (AccessGuard<'a, K>, AccessGuard<'a, V>);
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
         let entry = self.inner.next()?;
-        let (page, key_range, value_range) = entry.into_raw();
-        let key = AccessGuard::with_page(page.clone(), key_range);
-        let value = AccessGuard::with_page(page, value_range);
-        Some((key, value))
+        decode_iter_entry(entry, self.compression, self.mem, &mut self.error)
     }
 }
 
@@ -378,21 +972,68 @@ impl<
     > DoubleEndedIterator for DrainFilter<'a, K, V, F>
 {
     fn next_back(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
         let entry = self.inner.next_back()?;
-        let (page, key_range, value_range) = entry.into_raw();
-        let key = AccessGuard::with_page(page.clone(), key_range);
-        let value = AccessGuard::with_page(page, value_range);
-        Some((key, value))
+        decode_iter_entry(entry, self.compression, self.mem, &mut self.error)
     }
 }
 
 pub struct RangeIter<'a, K: RedbKey + 'static, V: RedbValue + 'static> {
     inner: BtreeRangeIter<'a, K, V>,
+    compression: Option<Compression>,
+    expiration: Expiration,
+    // The error which ended the iteration early, if any
+    error: Option<Error>,
+    mem: &'a TransactionalMemory,
 }
 
 impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> RangeIter<'a, K, V> {
-    fn new(inner: BtreeRangeIter<'a, K, V>) -> Self {
-        Self { inner }
+    fn new(
+        inner: BtreeRangeIter<'a, K, V>,
+        compression: Option<Compression>,
+        expiration: Expiration,
+        mem: &'a TransactionalMemory,
+    ) -> Self {
+        Self {
+            inner,
+            compression,
+            expiration,
+            error: None,
+            mem,
+        }
+    }
+
+    /// Returns the error which ended the iteration early, if any
+    ///
+    /// The iteration ends at the first entry which can't be read, such as when the read transaction
+    /// has expired, or a compressed value is corrupted
+    pub fn check(self) -> Result {
+        self.error.map_or(Ok(()), Err)
+    }
+
+    pub(crate) fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    fn next_entry(&mut self, reverse: bool) -> Option<(AccessGuard<'a, K>, AccessGuard<'a, V>)> {
+        if self.error.is_some() {
+            return None;
+        }
+        let _pin = match self.expiration.pin() {
+            Ok(pin) => pin,
+            Err(err) => {
+                self.error = Some(err);
+                return None;
+            }
+        };
+        let entry = if reverse {
+            self.inner.next_back()?
+        } else {
+            self.inner.next()?
+        };
+        decode_iter_entry(entry, self.compression, self.mem, &mut self.error)
     }
 }
 
@@ -400,26 +1041,23 @@ impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Iterator for RangeIter<'a
     type Item = (AccessGuard<'a, K>, AccessGuard<'a, V>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(entry) = self.inner.next() {
-            let (page, key_range, value_range) = entry.into_raw();
-            let key = AccessGuard::with_page(page.clone(), key_range);
-            let value = AccessGuard::with_page(page, value_range);
-            Some((key, value))
-        } else {
-            None
+        self.next_entry(false)
+    }
+
+    /// Returns the number of entries remaining in the range, in logarithmic time
+    fn count(self) -> usize {
+        if self.error.is_some() {
+            return 0;
+        }
+        match self.expiration.pin() {
+            Ok(_pin) => self.inner.count(),
+            Err(_) => 0,
         }
     }
 }
 
 impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> DoubleEndedIterator for RangeIter<'a, K, V> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        if let Some(entry) = self.inner.next_back() {
-            let (page, key_range, value_range) = entry.into_raw();
-            let key = AccessGuard::with_page(page.clone(), key_range);
-            let value = AccessGuard::with_page(page, value_range);
-            Some((key, value))
-        } else {
-            None
-        }
+        self.next_entry(true)
     }
 }
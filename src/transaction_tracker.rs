@@ -1,6 +1,9 @@
-use crate::Savepoint;
+use crate::{Error, Result, Savepoint};
 use std::collections::btree_map::BTreeMap;
 use std::collections::btree_set::BTreeSet;
+use std::panic;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub(crate) struct TransactionId(pub u64);
@@ -20,10 +23,93 @@ impl SavepointId {
     }
 }
 
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub(crate) struct ReaderId(u64);
+
+struct ReaderState {
+    // Number of accesses to the database which are in progress
+    pins: u64,
+    // Set once the snapshot has been released, after which the database must not be accessed
+    expired: bool,
+}
+
+struct ExpiringReader {
+    deadline: Instant,
+    state: Mutex<ReaderState>,
+}
+
+// The time after which a read transaction must no longer access the database, since the pages of
+// its snapshot may be freed. Each access pins the transaction, and only unpinned transactions are
+// expired, so that pages are never freed while they are being read
+#[derive(Clone, Default)]
+pub(crate) struct Expiration(Option<Arc<ExpiringReader>>);
+
+impl Expiration {
+    pub(crate) fn new(deadline: Option<Instant>) -> Self {
+        Self(deadline.map(|deadline| {
+            Arc::new(ExpiringReader {
+                deadline,
+                state: Mutex::new(ReaderState {
+                    pins: 0,
+                    expired: false,
+                }),
+            })
+        }))
+    }
+
+    // Pins the transaction until the returned guard is dropped, or returns an error if it has
+    // expired
+    pub(crate) fn pin(&self) -> Result<ReaderPin<'_>> {
+        if let Some(ref reader) = self.0 {
+            let mut state = reader.state.lock().unwrap();
+            if state.expired || Instant::now() >= reader.deadline {
+                return Err(Error::ReadTransactionExpired);
+            }
+            state.pins += 1;
+        }
+        Ok(ReaderPin(self.0.as_deref()))
+    }
+
+    // Marks the transaction expired if its deadline has passed, and it's not pinned. Returns true
+    // if it's expired
+    fn try_expire(&self, now: Instant) -> bool {
+        match self.0 {
+            Some(ref reader) if reader.deadline <= now => {
+                let mut state = reader.state.lock().unwrap();
+                if state.pins == 0 {
+                    state.expired = true;
+                }
+                state.expired
+            }
+            _ => false,
+        }
+    }
+}
+
+pub(crate) struct ReaderPin<'a>(Option<&'a ExpiringReader>);
+
+impl<'a> Drop for ReaderPin<'a> {
+    fn drop(&mut self) {
+        if let Some(reader) = self.0 {
+            reader.state.lock().unwrap().pins -= 1;
+        }
+    }
+}
+
+// A read transaction which is in progress
+pub(crate) struct LiveReader {
+    pub(crate) transaction_id: TransactionId,
+    pub(crate) started: Instant,
+    pub(crate) location: &'static panic::Location<'static>,
+    pub(crate) expiration: Expiration,
+}
+
 pub(crate) struct TransactionTracker {
     next_savepoint_id: SavepointId,
+    next_reader_id: ReaderId,
     // reference count of read transactions per transaction id
     live_read_transactions: BTreeMap<TransactionId, u64>,
+    live_readers: BTreeMap<ReaderId, LiveReader>,
     valid_savepoints: BTreeSet<SavepointId>,
 }
 
@@ -31,7 +117,9 @@ impl TransactionTracker {
     pub(crate) fn new() -> Self {
         Self {
             next_savepoint_id: SavepointId(0),
+            next_reader_id: ReaderId(0),
             live_read_transactions: Default::default(),
+            live_readers: Default::default(),
             valid_savepoints: Default::default(),
         }
     }
@@ -51,6 +139,39 @@ impl TransactionTracker {
         }
     }
 
+    pub(crate) fn register_reader(&mut self, reader: LiveReader) -> ReaderId {
+        let id = self.next_reader_id;
+        self.next_reader_id = ReaderId(id.0 + 1);
+        self.register_read_transaction(reader.transaction_id);
+        self.live_readers.insert(id, reader);
+        id
+    }
+
+    pub(crate) fn deallocate_reader(&mut self, id: ReaderId) {
+        // The reader may already have expired
+        if let Some(reader) = self.live_readers.remove(&id) {
+            self.deallocate_read_transaction(reader.transaction_id);
+        }
+    }
+
+    // Releases the snapshots of readers which have expired, so that their pages can be freed.
+    // Readers which are accessing the database are expired by a later call
+    pub(crate) fn expire_readers(&mut self, now: Instant) {
+        let expired: Vec<ReaderId> = self
+            .live_readers
+            .iter()
+            .filter(|(_, reader)| reader.expiration.try_expire(now))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            self.deallocate_reader(id);
+        }
+    }
+
+    pub(crate) fn live_readers(&self) -> impl Iterator<Item = &LiveReader> {
+        self.live_readers.values()
+    }
+
     pub(crate) fn allocate_savepoint(&mut self) -> SavepointId {
         let id = self.next_savepoint_id.next();
         self.next_savepoint_id = id;
@@ -58,9 +179,23 @@ impl TransactionTracker {
         id
     }
 
-    pub(crate) fn deallocate_savepoint(&mut self, savepoint: &Savepoint) {
-        self.valid_savepoints.remove(&savepoint.get_id());
-        self.deallocate_read_transaction(savepoint.get_transaction_id());
+    // Registers a persistent savepoint which was stored in the database when it was opened
+    pub(crate) fn register_persistent_savepoint(&mut self, savepoint: &Savepoint) {
+        self.register_read_transaction(savepoint.get_transaction_id());
+        self.valid_savepoints.insert(savepoint.get_id());
+        if savepoint.get_id() > self.next_savepoint_id {
+            self.next_savepoint_id = savepoint.get_id();
+        }
+    }
+
+    pub(crate) fn deallocate_savepoint(&mut self, id: SavepointId, transaction_id: TransactionId) {
+        self.valid_savepoints.remove(&id);
+        self.deallocate_read_transaction(transaction_id);
+    }
+
+    // Makes a savepoint valid again, after a transaction which invalidated it was aborted
+    pub(crate) fn revalidate_savepoint(&mut self, id: SavepointId) {
+        self.valid_savepoints.insert(id);
     }
 
     pub(crate) fn is_valid_savepoint(&self, id: SavepointId) -> bool {
@@ -79,3 +214,38 @@ impl TransactionTracker {
         self.live_read_transactions.keys().next().cloned()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::transaction_tracker::{Expiration, LiveReader, TransactionId, TransactionTracker};
+    use crate::Error;
+    use std::panic;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn pinned_readers_are_not_expired() {
+        let mut tracker = TransactionTracker::new();
+        let start = Instant::now();
+        let expiration = Expiration::new(Some(start + Duration::from_secs(1)));
+        tracker.register_reader(LiveReader {
+            transaction_id: TransactionId(1),
+            started: start,
+            location: panic::Location::caller(),
+            expiration: expiration.clone(),
+        });
+
+        let pin = expiration.pin().unwrap();
+        tracker.expire_readers(start + Duration::from_secs(2));
+        assert_eq!(
+            tracker.oldest_live_read_transaction(),
+            Some(TransactionId(1))
+        );
+        drop(pin);
+        tracker.expire_readers(start + Duration::from_secs(2));
+        assert_eq!(tracker.oldest_live_read_transaction(), None);
+        assert!(matches!(
+            expiration.pin().err().unwrap(),
+            Error::ReadTransactionExpired
+        ));
+    }
+}
@@ -1,22 +1,35 @@
-use crate::transaction_tracker::{TransactionId, TransactionTracker};
+use crate::changes::{Change, ChangeSet};
+use crate::index::{index_table_name, is_internal_table, IndexDefinition};
+use crate::transaction_tracker::{
+    Expiration, ReaderId, SavepointId, TransactionId, TransactionTracker,
+};
 use crate::tree_store::{
-    Btree, BtreeMut, FreedTableKey, InternalTableDefinition, PageHint, PageNumber, TableTree,
-    TableType, TransactionalMemory,
+    Btree, BtreeMut, Checksum, ChecksumType, FreedTableKey, InternalTableDefinition, PageHint,
+    PageNumber, TableTree, TableType, TransactionalMemory,
 };
 use crate::types::{RedbKey, RedbValue};
 use crate::{
     Database, Error, MultimapTable, MultimapTableDefinition, ReadOnlyMultimapTable, ReadOnlyTable,
-    Result, Savepoint, Table, TableDefinition,
+    ReadableMultimapTable, ReadableTable, Result, Savepoint, Table, TableDefinition, WriteStrategy,
 };
 #[cfg(feature = "logging")]
 use log::{info, warn};
 use std::cmp::min;
 use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io;
 use std::mem::size_of;
 use std::ops::RangeFull;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard, RwLock};
-use std::{panic, thread};
+use std::time::{Duration, Instant};
+use std::{panic, process, thread};
+
+// Persistent savepoints are stored in an internal table, by id
+const PERSISTENT_SAVEPOINTS: TableDefinition<u64, &[u8]> =
+    TableDefinition::internal("\0savepoints");
 
 /// Informational storage stats about the database
 #[derive(Debug)]
@@ -108,12 +121,20 @@ pub struct WriteTransaction<'db> {
     completed: bool,
     dirty: AtomicBool,
     durability: Durability,
-    live_write_transaction: MutexGuard<'db, Option<TransactionId>>,
+    // The changes made by this transaction, which are recorded only if the database has subscribers
+    changes: Option<Mutex<ChangeSet>>,
+    // Persistent savepoints which were created, or deleted, by this transaction. The pages that
+    // they reference are only released once the deletion is committed
+    created_persistent_savepoints: Mutex<Vec<(SavepointId, TransactionId)>>,
+    deleted_persistent_savepoints: Mutex<Vec<(SavepointId, TransactionId)>>,
 }
 
 impl<'db> WriteTransaction<'db> {
-    pub(crate) fn new(db: &'db Database) -> Result<Self> {
-        let mut live_write_transaction = db.live_write_transaction.lock().unwrap();
+    // The live write transaction is released when this transaction is dropped
+    pub(crate) fn new(
+        db: &'db Database,
+        mut live_write_transaction: MutexGuard<'db, Option<TransactionId>>,
+    ) -> Result<Self> {
         assert!(live_write_transaction.is_none());
         let transaction_id = db.increment_transaction_id();
         #[cfg(feature = "logging")]
@@ -148,7 +169,11 @@ impl<'db> WriteTransaction<'db> {
             completed: false,
             dirty: AtomicBool::new(false),
             durability: Durability::Immediate,
-            live_write_transaction,
+            changes: db
+                .records_changes()
+                .then(|| Mutex::new(ChangeSet::new(transaction_id))),
+            created_persistent_savepoints: Mutex::new(vec![]),
+            deleted_persistent_savepoints: Mutex::new(vec![]),
         })
     }
 
@@ -214,6 +239,8 @@ impl<'db> WriteTransaction<'db> {
             savepoint.get_checksum_type()
         );
         self.dirty.store(true, Ordering::Release);
+        let persistent_savepoints =
+            read_persistent_savepoints(&self.table_tree.read().unwrap(), self.mem)?;
 
         let allocated_since_savepoint = self
             .mem
@@ -271,6 +298,35 @@ impl<'db> WriteTransaction<'db> {
 
         *self.freed_tree.lock().unwrap() = freed_tree;
 
+        // Persistent savepoints are not reverted, except for those which were created after the
+        // savepoint, since they are now on another timeline
+        self.table_tree
+            .write()
+            .unwrap()
+            .delete_table::<u64, &[u8]>(PERSISTENT_SAVEPOINTS.name(), TableType::Normal, None)?;
+        if !persistent_savepoints.is_empty() {
+            let mut table = self.open_table(PERSISTENT_SAVEPOINTS)?;
+            for (id, data) in persistent_savepoints {
+                if id <= savepoint.get_id().0 {
+                    table.insert(id, data.as_slice())?;
+                } else {
+                    let persistent = Savepoint::from_bytes(
+                        SavepointId(id),
+                        &data,
+                        self.db.transaction_tracker(),
+                    )?;
+                    self.deleted_persistent_savepoints
+                        .lock()
+                        .unwrap()
+                        .push((persistent.get_id(), persistent.get_transaction_id()));
+                }
+            }
+        }
+
+        if let Some(ref changes) = self.changes {
+            changes.lock().unwrap().restore_savepoint();
+        }
+
         // Invalidate all savepoints that are newer than the one being applied to prevent the user
         // from later trying to restore a savepoint "on another timeline"
         self.transaction_tracker
@@ -281,6 +337,81 @@ impl<'db> WriteTransaction<'db> {
         Ok(())
     }
 
+    /// Creates a persistent snapshot of the current database state, which can be used to rollback
+    /// the database, even after it is closed and reopened
+    ///
+    /// Returns the id of the savepoint, which can be passed to
+    /// [`WriteTransaction::restore_persistent_savepoint`]. The savepoint is stored in the database
+    /// when this transaction commits, and the pages that it references are not freed until it is
+    /// deleted with [`WriteTransaction::delete_persistent_savepoint`].
+    ///
+    /// Returns `[Error::InvalidSavepoint`], if the transaction is "dirty" (any tables have been openned)
+    pub fn persistent_savepoint(&self) -> Result<u64> {
+        let mut savepoint = self.savepoint()?;
+        savepoint.make_persistent();
+        self.created_persistent_savepoints
+            .lock()
+            .unwrap()
+            .push((savepoint.get_id(), savepoint.get_transaction_id()));
+        #[cfg(feature = "logging")]
+        info!("Creating persistent savepoint id={:?}", savepoint.get_id());
+
+        let mut table = self.open_table(PERSISTENT_SAVEPOINTS)?;
+        table.insert(savepoint.get_id().0, savepoint.to_bytes().as_slice())?;
+
+        Ok(savepoint.get_id().0)
+    }
+
+    /// Restore the state of the database to the persistent savepoint with the given id
+    ///
+    /// Calling this method invalidates all savepoints created after the savepoint, and deletes
+    /// those which are persistent. Returns [`Error::InvalidSavepoint`] if there is no persistent
+    /// savepoint with the given id
+    pub fn restore_persistent_savepoint(&mut self, id: u64) -> Result {
+        let data = read_persistent_savepoints(&self.table_tree.read().unwrap(), self.mem)?
+            .into_iter()
+            .find(|(x, _)| *x == id)
+            .map(|(_, data)| data)
+            .ok_or(Error::InvalidSavepoint)?;
+        let savepoint =
+            Savepoint::from_bytes(SavepointId(id), &data, self.db.transaction_tracker())?;
+        self.restore_savepoint(&savepoint)
+    }
+
+    /// List the ids of all the persistent savepoints, in the order that they were created
+    pub fn list_persistent_savepoints(&self) -> Result<impl Iterator<Item = u64>> {
+        Ok(
+            read_persistent_savepoints(&self.table_tree.read().unwrap(), self.mem)?
+                .into_iter()
+                .map(|(id, _)| id),
+        )
+    }
+
+    /// Delete the persistent savepoint with the given id
+    ///
+    /// The pages which are only referenced by the savepoint are freed once this transaction
+    /// commits. Returns a bool indicating whether the savepoint existed
+    pub fn delete_persistent_savepoint(&self, id: u64) -> Result<bool> {
+        if read_persistent_savepoints(&self.table_tree.read().unwrap(), self.mem)?
+            .iter()
+            .all(|(x, _)| *x != id)
+        {
+            return Ok(false);
+        }
+        #[cfg(feature = "logging")]
+        info!("Deleting persistent savepoint id={:?}", id);
+        let mut table = self.open_table(PERSISTENT_SAVEPOINTS)?;
+        let data = table.remove(id)?.unwrap().value().to_vec();
+        let savepoint =
+            Savepoint::from_bytes(SavepointId(id), &data, self.db.transaction_tracker())?;
+        self.deleted_persistent_savepoints
+            .lock()
+            .unwrap()
+            .push((savepoint.get_id(), savepoint.get_transaction_id()));
+
+        Ok(true)
+    }
+
     /// Set the desired durability level for writes made in this transaction
     /// Defaults to [`Durability::Immediate`]
     pub fn set_durability(&mut self, durability: Durability) {
@@ -303,24 +434,68 @@ impl<'db> WriteTransaction<'db> {
             ));
         }
         self.dirty.store(true, Ordering::Release);
-        self.open_tables
-            .lock()
-            .unwrap()
-            .insert(definition.name().to_string(), panic::Location::caller());
 
         let internal_table = self
             .table_tree
             .write()
             .unwrap()
-            .get_or_create_table::<K, V>(definition.name(), TableType::Normal)?;
+            .get_or_create_table::<K, V>(
+                definition.name(),
+                TableType::Normal,
+                definition.compression(),
+            )?;
+        self.record_table::<K, V>(definition.name(), false);
+        // Indexes which are no longer defined would become stale, so they're deleted
+        let stored = internal_table.get_indexes();
+        let mut index_names: Vec<String> = definition
+            .indexes()
+            .iter()
+            .map(|index| index.name().to_string())
+            .collect();
+        index_names.sort();
+        for name in stored.iter().filter(|name| !index_names.contains(name)) {
+            self.table_tree
+                .write()
+                .unwrap()
+                .delete_table_untyped(&index_table_name(definition.name(), name))?;
+        }
+        let indexes = definition
+            .indexes()
+            .iter()
+            .map(|index| {
+                let current = stored.iter().any(|name| name == index.name());
+                index.open(
+                    definition.name(),
+                    internal_table.get_length(),
+                    current,
+                    self,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.open_tables
+            .lock()
+            .unwrap()
+            .insert(definition.name().to_string(), panic::Location::caller());
 
-        Ok(Table::new(
+        let mut table = Table::new(
             definition.name(),
             internal_table.get_root(),
+            internal_table.get_length(),
+            definition.compression(),
+            indexes,
             self.freed_pages.clone(),
             self.mem,
             self,
-        ))
+        );
+        table.build_indexes()?;
+        if index_names != stored {
+            self.table_tree
+                .write()
+                .unwrap()
+                .set_table_indexes(definition.name(), index_names)?;
+        }
+
+        Ok(table)
     }
 
     /// Open the given table
@@ -348,11 +523,13 @@ impl<'db> WriteTransaction<'db> {
             .table_tree
             .write()
             .unwrap()
-            .get_or_create_table::<K, V>(definition.name(), TableType::Multimap)?;
+            .get_or_create_table::<K, V>(definition.name(), TableType::Multimap, None)?;
+        self.record_table::<K, V>(definition.name(), true);
 
         Ok(MultimapTable::new(
             definition.name(),
             internal_table.get_root(),
+            internal_table.get_length(),
             self.freed_pages.clone(),
             self.mem,
             self,
@@ -363,15 +540,67 @@ impl<'db> WriteTransaction<'db> {
         &self,
         name: &str,
         table: &mut BtreeMut<K, V>,
+        length: u64,
     ) {
         self.open_tables.lock().unwrap().remove(name).unwrap();
+        self.stage_table_root(name, table.get_root(), length);
+    }
+
+    pub(crate) fn records_changes(&self) -> bool {
+        self.changes.is_some()
+    }
+
+    // Records the types of a table, so that its changes can be decoded by subscribers
+    fn record_table<K: RedbKey, V: RedbValue>(&self, name: &str, multimap: bool) {
+        if is_internal_table(name) {
+            return;
+        }
+        if let Some(ref changes) = self.changes {
+            changes
+                .lock()
+                .unwrap()
+                .record_table(name, multimap, K::type_name(), V::type_name());
+        }
+    }
+
+    pub(crate) fn record_change(&self, change: Change) {
+        // Internal tables are maintained by redb, so changes to them are not of interest
+        if is_internal_table(change.table()) {
+            return;
+        }
+        if let Some(ref changes) = self.changes {
+            changes.lock().unwrap().push(change);
+        }
+    }
+
+    pub(crate) fn stage_table_root(
+        &self,
+        name: &str,
+        table_root: Option<(PageNumber, Checksum)>,
+        length: u64,
+    ) {
         self.table_tree
             .write()
             .unwrap()
-            .stage_update_table_root(name, table.get_root());
+            .stage_update_table_root(name, table_root, length);
     }
 
-    /// Delete the given table
+    // Opens an index of the table `table`, as of the last time that it was staged
+    pub(crate) fn open_index<K: RedbKey + 'static, V: RedbValue + 'static, I: RedbKey + 'static>(
+        &self,
+        table: &str,
+        definition: IndexDefinition<K, V, I>,
+    ) -> Result<ReadOnlyMultimapTable<'db, I, K>> {
+        definition.open_read_only(
+            table,
+            &self.table_tree.read().unwrap(),
+            PageHint::None,
+            Default::default(),
+            self.mem,
+        )
+    }
+
+    /// Delete the given table, along with its indexes
     ///
     /// Returns a bool indicating whether the table existed
     pub fn delete_table<K: RedbKey + 'static, V: RedbValue + 'static>(
@@ -381,10 +610,53 @@ impl<'db> WriteTransaction<'db> {
         #[cfg(feature = "logging")]
         info!("Deleting table: {}", definition);
         self.dirty.store(true, Ordering::Release);
-        self.table_tree
-            .write()
+        let index_names = self
+            .table_tree
+            .read()
             .unwrap()
-            .delete_table::<K, V>(definition.name(), TableType::Normal)
+            .get_table_indexes(definition.name())?;
+        for name in index_names {
+            self.table_tree
+                .write()
+                .unwrap()
+                .delete_table_untyped(&index_table_name(definition.name(), &name))?;
+        }
+        if self.records_changes() {
+            let tree = self.table_tree.read().unwrap();
+            if let Some(internal_table) = tree.get_table::<K, V>(
+                definition.name(),
+                TableType::Normal,
+                definition.compression(),
+            )? {
+                self.record_table::<K, V>(definition.name(), false);
+                let table: ReadOnlyTable<K, V> = ReadOnlyTable::new(
+                    definition.name(),
+                    internal_table.get_root(),
+                    internal_table.get_length(),
+                    definition.compression(),
+                    &tree,
+                    PageHint::None,
+                    Default::default(),
+                    self.mem,
+                );
+                let mut iter = table.iter()?;
+                for (key, value) in iter.by_ref() {
+                    self.record_change(Change::new(
+                        definition.name(),
+                        false,
+                        K::as_bytes(&key.value()).as_ref(),
+                        Some(V::as_bytes(&value.value()).as_ref()),
+                        None,
+                    ));
+                }
+                iter.check()?;
+            }
+        }
+        self.table_tree.write().unwrap().delete_table::<K, V>(
+            definition.name(),
+            TableType::Normal,
+            definition.compression(),
+        )
     }
 
     /// Delete the given table
@@ -397,10 +669,41 @@ impl<'db> WriteTransaction<'db> {
         #[cfg(feature = "logging")]
         info!("Deleting multimap table: {}", definition);
         self.dirty.store(true, Ordering::Release);
-        self.table_tree
-            .write()
-            .unwrap()
-            .delete_table::<K, V>(definition.name(), TableType::Multimap)
+        if self.records_changes() {
+            let internal_table = self.table_tree.read().unwrap().get_table::<K, V>(
+                definition.name(),
+                TableType::Multimap,
+                None,
+            )?;
+            if let Some(internal_table) = internal_table {
+                self.record_table::<K, V>(definition.name(), true);
+                let table: ReadOnlyMultimapTable<K, V> = ReadOnlyMultimapTable::new(
+                    internal_table.get_root(),
+                    internal_table.get_length(),
+                    PageHint::None,
+                    Default::default(),
+                    self.mem,
+                );
+                let mut iter = table.iter()?;
+                for (key, values) in iter.by_ref() {
+                    for value in values {
+                        self.record_change(Change::new(
+                            definition.name(),
+                            true,
+                            K::as_bytes(&key.value()).as_ref(),
+                            Some(V::as_bytes(&value.value()).as_ref()),
+                            None,
+                        ));
+                    }
+                }
+                iter.check()?;
+            }
+        }
+        self.table_tree.write().unwrap().delete_table::<K, V>(
+            definition.name(),
+            TableType::Multimap,
+            None,
+        )
     }
 
     /// List all the tables
@@ -409,7 +712,7 @@ impl<'db> WriteTransaction<'db> {
             .read()
             .unwrap()
             .list_tables(TableType::Normal)
-            .map(|x| x.into_iter())
+            .map(|x| x.into_iter().filter(|name| !is_internal_table(name)))
     }
 
     /// List all the multimap tables
@@ -418,7 +721,7 @@ impl<'db> WriteTransaction<'db> {
             .read()
             .unwrap()
             .list_tables(TableType::Multimap)
-            .map(|x| x.into_iter())
+            .map(|x| x.into_iter().filter(|name| !is_internal_table(name)))
     }
 
     /// Commit the transaction
@@ -446,12 +749,27 @@ impl<'db> WriteTransaction<'db> {
         }
 
         self.completed = true;
+        let mut tracker = self.transaction_tracker.lock().unwrap();
+        for (id, transaction_id) in self.deleted_persistent_savepoints.lock().unwrap().drain(..) {
+            tracker.deallocate_savepoint(id, transaction_id);
+        }
+        drop(tracker);
         #[cfg(feature = "logging")]
         info!(
             "Finished commit of transaction id={:?}",
             self.transaction_id
         );
 
+        if let Some(changes) = self.changes.take() {
+            let changes = changes.into_inner().unwrap();
+            if !changes.is_empty() {
+                self.db.publish_changes(changes);
+            }
+        } else {
+            self.db.record_unpublished_commit(self.transaction_id);
+        }
+        self.db.report_cache_stats();
+
         Ok(())
     }
 
@@ -468,18 +786,30 @@ impl<'db> WriteTransaction<'db> {
         self.table_tree.write().unwrap().clear_table_root_updates();
         self.mem.rollback_uncommitted_writes()?;
         self.completed = true;
+        let mut tracker = self.transaction_tracker.lock().unwrap();
+        let created = std::mem::take(&mut *self.created_persistent_savepoints.lock().unwrap());
+        for (id, _) in self.deleted_persistent_savepoints.lock().unwrap().drain(..) {
+            if !created.iter().any(|(x, _)| *x == id) {
+                tracker.revalidate_savepoint(id);
+            }
+        }
+        for (id, transaction_id) in created {
+            tracker.deallocate_savepoint(id, transaction_id);
+        }
+        drop(tracker);
         #[cfg(feature = "logging")]
         info!("Finished abort of transaction id={:?}", self.transaction_id);
         Ok(())
     }
 
     pub(crate) fn durable_commit(&mut self, eventual: bool) -> Result {
-        let oldest_live_read = self
-            .transaction_tracker
-            .lock()
-            .unwrap()
-            .oldest_live_read_transaction()
-            .unwrap_or(self.transaction_id);
+        let oldest_live_read = {
+            let mut tracker = self.transaction_tracker.lock().unwrap();
+            tracker.expire_readers(Instant::now());
+            tracker
+                .oldest_live_read_transaction()
+                .unwrap_or(self.transaction_id)
+        };
 
         // SAFETY: durable_commit() is called from commit() which takes ownership of self,
         // and oldest_live_read tracks the oldest read transaction that is in progress
@@ -499,7 +829,7 @@ impl<'db> WriteTransaction<'db> {
         let freed_root = self.freed_tree.lock().unwrap().get_root();
 
         self.mem
-            .commit(root, freed_root, self.transaction_id, eventual, None)?;
+            .commit(root, freed_root, self.transaction_id, eventual)?;
         Ok(())
     }
 
@@ -593,6 +923,55 @@ impl<'db> WriteTransaction<'db> {
         Ok(())
     }
 
+    // Moves pages into free space at lower addresses, so that the end of the file can be truncated.
+    // Returns true if any page was moved
+    pub(crate) fn compact_pages(&mut self) -> Result<bool> {
+        self.dirty.store(true, Ordering::Release);
+        let relocated = self.table_tree.write().unwrap().compact_tables()?;
+        // The freed tree's pages can be at the end of the file too, and would prevent truncating it.
+        // It's rewritten by every commit though, so moving it isn't counted as progress
+        self.freed_tree.lock().unwrap().relocate()?;
+
+        Ok(relocated)
+    }
+
+    // Copies all the tables from a snapshot of another database into this transaction
+    pub(crate) fn copy_tables_from(&self, source: &TableTree) -> Result {
+        self.dirty.store(true, Ordering::Release);
+        let mut table_tree = self.table_tree.write().unwrap();
+        table_tree.copy_tables_from(source)?;
+        // Persistent savepoints refer to pages of the source database
+        table_tree.delete_table::<u64, &[u8]>(
+            PERSISTENT_SAVEPOINTS.name(),
+            TableType::Normal,
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    // Frees the given pages, once there are no read transactions or savepoints which may reference
+    // them
+    pub(crate) fn free_on_commit(&self, pages: impl IntoIterator<Item = PageNumber>) {
+        self.freed_pages.lock().unwrap().extend(pages);
+    }
+
+    // Switches the database to the given write strategy when this transaction commits. If the new
+    // strategy requires checksums, every page in the database is rewritten to compute them
+    pub(crate) fn change_write_strategy(&mut self, strategy: WriteStrategy) -> Result {
+        self.dirty.store(true, Ordering::Release);
+        let checksum_type = strategy.into();
+        let rewrite_needed =
+            checksum_type != ChecksumType::Unused && checksum_type != self.mem.checksum_type();
+        self.mem.set_in_progress_checksum_type(checksum_type);
+        if rewrite_needed {
+            self.table_tree.write().unwrap().rewrite_tables()?;
+            self.freed_tree.lock().unwrap().rewrite()?;
+        }
+
+        Ok(())
+    }
+
     /// Retrieves information about storage usage in the database
     pub fn stats(&self) -> Result<DatabaseStats> {
         let table_tree = self.table_tree.read().unwrap();
@@ -638,7 +1017,6 @@ impl<'db> WriteTransaction<'db> {
 
 impl<'a> Drop for WriteTransaction<'a> {
     fn drop(&mut self) {
-        *self.live_write_transaction = None;
         if !self.completed && !thread::panicking() {
             #[allow(unused_variables)]
             if let Err(error) = self.abort_inner() {
@@ -646,6 +1024,80 @@ impl<'a> Drop for WriteTransaction<'a> {
                 warn!("Failure automatically aborting transaction: {}", error);
             }
         }
+        self.db.end_write_transaction();
+    }
+}
+
+// Returns the serialized persistent savepoints stored in `tree`, by id
+pub(crate) fn read_persistent_savepoints(
+    tree: &TableTree,
+    mem: &TransactionalMemory,
+) -> Result<Vec<(u64, Vec<u8>)>> {
+    let definition = match tree.get_table::<u64, &[u8]>(
+        PERSISTENT_SAVEPOINTS.name(),
+        TableType::Normal,
+        None,
+    )? {
+        Some(definition) => definition,
+        None => return Ok(vec![]),
+    };
+    let table: ReadOnlyTable<u64, &[u8]> = ReadOnlyTable::new(
+        PERSISTENT_SAVEPOINTS.name(),
+        definition.get_root(),
+        definition.get_length(),
+        None,
+        tree,
+        PageHint::None,
+        Default::default(),
+        mem,
+    );
+
+    let mut iter = table.iter()?;
+    let result = iter
+        .by_ref()
+        .map(|(id, data)| (id.value(), data.value().to_vec()))
+        .collect();
+    iter.check()?;
+
+    Ok(result)
+}
+
+/// Information about a read transaction which is in progress
+///
+/// Returned by [`Database::live_read_transactions`]
+#[derive(Debug)]
+pub struct ReadTransactionInfo {
+    transaction_id: TransactionId,
+    age: Duration,
+    location: &'static panic::Location<'static>,
+}
+
+impl ReadTransactionInfo {
+    pub(crate) fn new(
+        transaction_id: TransactionId,
+        age: Duration,
+        location: &'static panic::Location<'static>,
+    ) -> Self {
+        Self {
+            transaction_id,
+            age,
+            location,
+        }
+    }
+
+    /// Returns the id of the last transaction which was committed when the read transaction began
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id.0
+    }
+
+    /// Returns how long the read transaction has been open
+    pub fn age(&self) -> Duration {
+        self.age
+    }
+
+    /// Returns where [`Database::begin_read`] was called to begin the read transaction
+    pub fn location(&self) -> &'static panic::Location<'static> {
+        self.location
     }
 }
 
@@ -655,16 +1107,22 @@ impl<'a> Drop for WriteTransaction<'a> {
 pub struct ReadTransaction<'a> {
     db: &'a Database,
     tree: TableTree<'a>,
-    transaction_id: TransactionId,
+    reader_id: ReaderId,
+    expiration: Expiration,
 }
 
 impl<'db> ReadTransaction<'db> {
-    pub(crate) fn new(db: &'db Database, transaction_id: TransactionId) -> Self {
-        let root_page = db.get_memory().get_data_root();
+    pub(crate) fn new(
+        db: &'db Database,
+        root_page: Option<(PageNumber, Checksum)>,
+        reader_id: ReaderId,
+        expiration: Expiration,
+    ) -> Self {
         Self {
             db,
             tree: TableTree::new(root_page, db.get_memory(), Default::default()),
-            transaction_id,
+            reader_id,
+            expiration,
         }
     }
 
@@ -673,14 +1131,24 @@ impl<'db> ReadTransaction<'db> {
         &self,
         definition: TableDefinition<K, V>,
     ) -> Result<ReadOnlyTable<K, V>> {
+        let _pin = self.expiration.pin()?;
         let header = self
             .tree
-            .get_table::<K, V>(definition.name(), TableType::Normal)?
+            .get_table::<K, V>(
+                definition.name(),
+                TableType::Normal,
+                definition.compression(),
+            )?
             .ok_or_else(|| Error::TableDoesNotExist(definition.name().to_string()))?;
 
         Ok(ReadOnlyTable::new(
+            definition.name(),
             header.get_root(),
+            header.get_length(),
+            definition.compression(),
+            &self.tree,
             PageHint::Clean,
+            self.expiration.clone(),
             self.db.get_memory(),
         ))
     }
@@ -690,14 +1158,17 @@ impl<'db> ReadTransaction<'db> {
         &self,
         definition: MultimapTableDefinition<K, V>,
     ) -> Result<ReadOnlyMultimapTable<K, V>> {
+        let _pin = self.expiration.pin()?;
         let header = self
             .tree
-            .get_table::<K, V>(definition.name(), TableType::Multimap)?
+            .get_table::<K, V>(definition.name(), TableType::Multimap, None)?
             .ok_or_else(|| Error::TableDoesNotExist(definition.name().to_string()))?;
 
         Ok(ReadOnlyMultimapTable::new(
             header.get_root(),
+            header.get_length(),
             PageHint::Clean,
+            self.expiration.clone(),
             self.db.get_memory(),
         ))
     }
@@ -705,17 +1176,77 @@ impl<'db> ReadTransaction<'db> {
     /// List all the tables
     // TODO: should return an iterator of &str, once GATs are available
     pub fn list_tables(&self) -> Result<impl Iterator<Item = String>> {
+        let _pin = self.expiration.pin()?;
         self.tree
             .list_tables(TableType::Normal)
-            .map(|x| x.into_iter())
+            .map(|x| x.into_iter().filter(|name| !is_internal_table(name)))
     }
 
     /// List all the multimap tables
     // TODO: should return an iterator of &str, once GATs are available
     pub fn list_multimap_tables(&self) -> Result<impl Iterator<Item = String>> {
+        let _pin = self.expiration.pin()?;
         self.tree
             .list_tables(TableType::Multimap)
-            .map(|x| x.into_iter())
+            .map(|x| x.into_iter().filter(|name| !is_internal_table(name)))
+    }
+
+    /// Writes a copy of this snapshot of the database to a new file at `path`
+    ///
+    /// The tables are rebuilt in the new file, rather than copied page by page, so it contains no
+    /// free space. The copy uses the same [`WriteStrategy`], and encryption key if any, as this
+    /// database. Write transactions may continue while the backup is in progress.
+    ///
+    /// The copy is written to a temporary file in the same directory, and only linked to `path` once
+    /// it's complete, so no partial backup is left behind if an error occurs. The filesystem must
+    /// support hard links.
+    ///
+    /// Returns an error if `path` already exists
+    pub fn backup_to(&self, path: impl AsRef<Path>) -> Result {
+        static BACKUP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let _pin = self.expiration.pin()?;
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists).into());
+        }
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(
+            ".{}-{}.tmp",
+            process::id(),
+            BACKUP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let temp_path = path.with_file_name(temp_name);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+
+        let result = self.write_backup(&temp_path).and_then(|_| {
+            // The file is only published once the backup database has been closed. Linking, rather
+            // than renaming, fails with AlreadyExists instead of replacing a file created at `path`
+            // in the meantime
+            fs::hard_link(&temp_path, path).map_err(Error::from)
+        });
+        let _ = fs::remove_file(&temp_path);
+        result
+    }
+
+    fn write_backup(&self, path: &Path) -> Result {
+        let mut builder = Database::builder();
+        builder.set_write_strategy(self.db.get_memory().checksum_type().into());
+        #[cfg(feature = "encryption")]
+        if let Some(key) = self.db.get_memory().encryption_key() {
+            builder.set_encryption_key(key);
+        }
+        let backup = builder.create(path)?;
+        let txn = backup.begin_write()?;
+        txn.copy_tables_from(&self.tree)?;
+        txn.commit()
     }
 }
 
@@ -725,7 +1256,7 @@ impl<'a> Drop for ReadTransaction<'a> {
             .transaction_tracker()
             .lock()
             .unwrap()
-            .deallocate_read_transaction(self.transaction_id);
+            .deallocate_reader(self.reader_id);
     }
 }
 
@@ -3,19 +3,26 @@ use crate::tree_store::btree_base::{
     LEAF,
 };
 use crate::tree_store::btree_iters::BtreeDrain;
-use crate::tree_store::btree_mutator::MutateHelper;
+use crate::tree_store::btree_mutator::{MutateHelper, RelocateHelper};
 use crate::tree_store::page_store::{Page, PageImpl, TransactionalMemory};
-use crate::tree_store::{AccessGuardMut, BtreeDrainFilter, BtreeRangeIter, PageHint, PageNumber};
+use crate::tree_store::{
+    overflow, AccessGuardMut, BtreeDrainFilter, BtreeRangeIter, Compression, PageHint, PageNumber,
+};
 use crate::types::{RedbKey, RedbValue};
-use crate::{AccessGuard, Result};
+use crate::{AccessGuard, Error, Result};
 #[cfg(feature = "logging")]
 use log::trace;
 use std::borrow::Borrow;
 use std::cmp::max;
+use std::collections::VecDeque;
+use std::io;
 use std::marker::PhantomData;
-use std::ops::{RangeBounds, RangeFull};
+use std::ops::RangeBounds;
 use std::sync::{Arc, Mutex};
 
+// Called with the key and value of each entry which is drained from a tree
+pub(crate) type OnRemove<'c> = &'c mut dyn FnMut(&[u8], &[u8]) -> Result;
+
 pub(crate) struct BtreeStats {
     pub(crate) tree_height: usize,
     pub(crate) leaf_pages: usize,
@@ -29,6 +36,7 @@ pub(crate) struct BtreeMut<'a, K: RedbKey, V: RedbValue> {
     mem: &'a TransactionalMemory,
     root: Arc<Mutex<Option<(PageNumber, Checksum)>>>,
     freed_pages: Arc<Mutex<Vec<PageNumber>>>,
+    hint: PageHint,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -43,11 +51,18 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
             mem,
             root: Arc::new(Mutex::new(root)),
             freed_pages,
+            hint: PageHint::None,
             _key_type: Default::default(),
             _value_type: Default::default(),
         }
     }
 
+    // Sets the hint with which the pages of the tree are read
+    pub(crate) fn with_hint(mut self, hint: PageHint) -> Self {
+        self.hint = hint;
+        self
+    }
+
     pub(crate) fn get_root(&self) -> Option<(PageNumber, Checksum)> {
         *(*self.root).lock().unwrap()
     }
@@ -58,12 +73,22 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         key: &K::SelfType<'_>,
         value: &V::SelfType<'_>,
     ) -> Result<Option<AccessGuard<V>>> {
+        self.insert_bytes(key, V::as_bytes(value).as_ref())
+    }
+
+    // Like insert(), but takes the value in its serialized form
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn insert_bytes(
+        &mut self,
+        key: &K::SelfType<'_>,
+        value: &[u8],
+    ) -> Result<Option<AccessGuard<'_, V>>> {
         #[cfg(feature = "logging")]
         trace!(
             "Btree(root={:?}): Inserting {:?} with value of length {}",
             &self.root,
             key,
-            V::as_bytes(value).as_ref().len()
+            value.len()
         );
         let mut freed_pages = self.freed_pages.lock().unwrap();
         let mut root = self.root.lock().unwrap();
@@ -73,7 +98,7 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
             self.mem,
             freed_pages.as_mut(),
         );
-        let (old_value, _) = operation.insert(key, value)?;
+        let (old_value, _) = operation.insert_bytes(key, value)?;
         Ok(old_value)
     }
 
@@ -139,6 +164,36 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         Ok(result.map(|x| (x, freed_pages)))
     }
 
+    // Moves the pages of this tree into lower free space, where possible.
+    // Returns true if any page was moved
+    pub(crate) fn relocate(&mut self) -> Result<bool> {
+        self.relocate_inner(false)
+    }
+
+    // Rewrites every page of this tree, recomputing their checksums with the current checksum type
+    pub(crate) fn rewrite(&mut self) -> Result {
+        self.relocate_inner(true)?;
+        Ok(())
+    }
+
+    fn relocate_inner(&mut self, rewrite_all: bool) -> Result<bool> {
+        let mut root = self.root.lock().unwrap();
+        let mut freed_pages = self.freed_pages.lock().unwrap();
+        if let Some((page, _)) = *root {
+            let mut helper = if rewrite_all {
+                RelocateHelper::rewrite_all(self.mem, freed_pages.as_mut())
+            } else {
+                RelocateHelper::new(self.mem, freed_pages.as_mut())
+            };
+            if let Some(new_root) = helper.relocate(page, K::fixed_width(), V::fixed_width())? {
+                *root = Some(new_root);
+            }
+            Ok(helper.relocated())
+        } else {
+            Ok(false)
+        }
+    }
+
     #[allow(dead_code)]
     pub(crate) fn print_debug(&self, include_values: bool) -> Result {
         self.read_tree().print_debug(include_values)
@@ -154,13 +209,22 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
     }
 
     fn read_tree(&self) -> Btree<'a, K, V> {
-        Btree::new(self.get_root(), PageHint::None, self.mem)
+        Btree::new(self.get_root(), self.hint, self.mem)
     }
 
     pub(crate) fn get(&self, key: &K::SelfType<'_>) -> Result<Option<AccessGuard<'_, V>>> {
         self.read_tree().get(key)
     }
 
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn nth(&self, n: u64) -> Result<Option<(AccessGuard<'_, K>, AccessGuard<'_, V>)>> {
+        self.read_tree().nth(n)
+    }
+
+    pub(crate) fn rank(&self, key: &K::SelfType<'_>) -> Result<u64> {
+        self.read_tree().rank(key)
+    }
+
     pub(crate) fn range<'a0, T: RangeBounds<KR> + 'a0, KR: Borrow<K::SelfType<'a0>> + 'a0>(
         &'a0 self,
         range: T,
@@ -171,7 +235,25 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         self.read_tree().range(range)
     }
 
+    pub(crate) fn range_extended<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::SelfType<'a0>> + 'a0,
+    >(
+        &'a0 self,
+        range: T,
+        hint: PageHint,
+    ) -> Result<BtreeRangeIter<'a, K, V>>
+    where
+        'a: 'a0,
+    {
+        self.read_tree().range_extended(range, hint)
+    }
+
     // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    // If `encoded_values` is set, the overflow pages of the removed values are also freed, and values
+    // are decoded according to `compression`.
+    // `on_remove` is called with the key and decoded value of each entry, as it's removed
     pub(crate) unsafe fn drain<
         'a0,
         T: RangeBounds<KR> + Clone + 'a0,
@@ -180,6 +262,9 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
     >(
         &'a0 mut self,
         range: T,
+        encoded_values: bool,
+        compression: Option<Compression>,
+        mut on_remove: Option<OnRemove<'_>>,
     ) -> Result<BtreeDrain<'a, K, V>>
     where
         'a: 'a0,
@@ -187,16 +272,28 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         let iter = self.range(range.clone())?;
         let return_iter = self.range(range)?;
         let mut free_on_drop = vec![];
+        let mut overflow_pages = vec![];
         let mut root = self.root.lock().unwrap();
         let mut operation: MutateHelper<'_, '_, K, V> =
             MutateHelper::new(&mut root, FreePolicy::Never, self.mem, &mut free_on_drop);
+        let mut removed = 0;
         for entry in iter {
+            if let Some(on_remove) = on_remove.as_mut() {
+                let value = entry.decoded_value(encoded_values, compression, self.mem)?;
+                on_remove(K::as_bytes(&entry.key()).as_ref(), &value)?;
+            }
+            if encoded_values {
+                overflow_pages.extend(overflow::value_pages(entry.raw_value(), self.mem)?);
+            }
             // TODO: optimize so that we don't have to call safe_delete in a loop
             assert!(operation.safe_delete(entry.key().borrow())?.is_some());
+            removed += 1;
         }
+        free_on_drop.extend(overflow_pages);
 
         let result = BtreeDrain::new(
             return_iter,
+            removed,
             free_on_drop,
             self.freed_pages.clone(),
             self.mem,
@@ -206,6 +303,9 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
     }
 
     // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    // If `encoded_values` is set, values are decoded according to `compression` before being passed
+    // to `predicate`, and the overflow pages of the removed values are also freed.
+    // `on_remove` is called with the key and decoded value of each entry, as it's removed
     pub(crate) unsafe fn drain_filter<
         'a0,
         T: RangeBounds<KR> + Clone + 'a0,
@@ -216,6 +316,9 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         &'a0 mut self,
         range: T,
         predicate: F,
+        encoded_values: bool,
+        compression: Option<Compression>,
+        mut on_remove: Option<OnRemove<'_>>,
     ) -> Result<BtreeDrainFilter<'a, K, V, F>>
     where
         'a: 'a0,
@@ -223,19 +326,34 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         let iter = self.range(range.clone())?;
         let return_iter = self.range(range)?;
         let mut free_on_drop = vec![];
+        let mut overflow_pages = vec![];
         let mut root = self.root.lock().unwrap();
         let mut operation: MutateHelper<'_, '_, K, V> =
             MutateHelper::new(&mut root, FreePolicy::Never, self.mem, &mut free_on_drop);
+        let mut removed = 0;
+        let mut matches = VecDeque::new();
         for entry in iter {
+            let value = entry.decoded_value(encoded_values, compression, self.mem)?;
+            let matched = predicate(entry.key(), V::from_bytes(&value));
+            matches.push_back(matched);
             // TODO: optimize so that we don't have to call safe_delete in a loop
-            if predicate(entry.key(), entry.value()) {
+            if matched {
+                if let Some(on_remove) = on_remove.as_mut() {
+                    on_remove(K::as_bytes(&entry.key()).as_ref(), &value)?;
+                }
+                if encoded_values {
+                    overflow_pages.extend(overflow::value_pages(entry.raw_value(), self.mem)?);
+                }
                 assert!(operation.safe_delete(entry.key().borrow())?.is_some());
+                removed += 1;
             }
         }
+        free_on_drop.extend(overflow_pages);
 
         let result = BtreeDrainFilter::new(
             return_iter,
-            predicate,
+            matches,
+            removed,
             free_on_drop,
             self.freed_pages.clone(),
             self.mem,
@@ -243,10 +361,6 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
 
         Ok(result)
     }
-
-    pub(crate) fn len(&self) -> Result<usize> {
-        self.read_tree().len()
-    }
 }
 
 pub(crate) struct RawBtree<'a> {
@@ -284,7 +398,12 @@ impl<'a> RawBtree<'a> {
         page_number: PageNumber,
         expected_checksum: Checksum,
     ) -> Result<bool> {
-        let page = self.mem.get_page(page_number)?;
+        let page = match self.mem.get_page(page_number) {
+            Ok(page) => page,
+            // Pages of an encrypted database which were torn by a crash fail authentication
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::InvalidData => return Ok(false),
+            Err(err) => return Err(err),
+        };
         let node_mem = page.memory();
         Ok(match node_mem[0] {
             LEAF => {
@@ -313,7 +432,8 @@ impl<'a> RawBtree<'a> {
                 }
                 true
             }
-            _ => unreachable!(),
+            // A page which was never written, or was torn by a crash
+            _ => false,
         })
     }
 }
@@ -367,6 +487,7 @@ impl<'a, K: RedbKey, V: RedbValue> Btree<'a, K, V> {
                 }
             }
             BRANCH => {
+                self.mem.hint_page(&page, PageHint::Branch);
                 let accessor = BranchAccessor::new(&page, K::fixed_width());
                 let (_, child_page) = accessor.child_for_key::<K>(query);
                 self.get_helper(self.mem.get_page_extended(child_page, self.hint)?, query)
@@ -375,6 +496,86 @@ impl<'a, K: RedbKey, V: RedbValue> Btree<'a, K, V> {
         }
     }
 
+    // Returns the nth entry of the tree, in key order
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn nth(&self, n: u64) -> Result<Option<(AccessGuard<'a, K>, AccessGuard<'a, V>)>> {
+        if let Some((p, _)) = self.root {
+            let root_page = self.mem.get_page_extended(p, self.hint)?;
+            self.nth_helper(root_page, n)
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn nth_helper(
+        &self,
+        page: PageImpl<'a>,
+        mut n: u64,
+    ) -> Result<Option<(AccessGuard<'a, K>, AccessGuard<'a, V>)>> {
+        let node_mem = page.memory();
+        match node_mem[0] {
+            LEAF => {
+                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                if n >= accessor.num_pairs() as u64 {
+                    return Ok(None);
+                }
+                let (key_range, value_range) =
+                    accessor.entry_ranges(n.try_into().unwrap()).unwrap();
+                let key = AccessGuard::with_page(page.clone(), key_range);
+                let value = AccessGuard::with_page(page, value_range);
+                Ok(Some((key, value)))
+            }
+            BRANCH => {
+                self.mem.hint_page(&page, PageHint::Branch);
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                for i in 0..accessor.count_children() {
+                    let entries = accessor.child_entries(i).unwrap();
+                    if n < entries {
+                        let child_page = accessor.child_page(i).unwrap();
+                        return self
+                            .nth_helper(self.mem.get_page_extended(child_page, self.hint)?, n);
+                    }
+                    n -= entries;
+                }
+                Ok(None)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Returns the number of entries with keys less than `key`
+    pub(crate) fn rank(&self, key: &K::SelfType<'_>) -> Result<u64> {
+        if let Some((p, _)) = self.root {
+            let root_page = self.mem.get_page_extended(p, self.hint)?;
+            self.rank_helper(root_page, K::as_bytes(key).as_ref())
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn rank_helper(&self, page: PageImpl<'a>, query: &[u8]) -> Result<u64> {
+        let node_mem = page.memory();
+        match node_mem[0] {
+            LEAF => {
+                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                let (position, _) = accessor.position::<K>(query);
+                Ok(position as u64)
+            }
+            BRANCH => {
+                self.mem.hint_page(&page, PageHint::Branch);
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                let (child_index, child_page) = accessor.child_for_key::<K>(query);
+                let preceding: u64 = (0..child_index)
+                    .map(|i| accessor.child_entries(i).unwrap())
+                    .sum();
+                let child_page = self.mem.get_page_extended(child_page, self.hint)?;
+                Ok(preceding + self.rank_helper(child_page, query)?)
+            }
+            _ => unreachable!(),
+        }
+    }
+
     pub(crate) fn range<'a0, T: RangeBounds<KR> + 'a0, KR: Borrow<K::SelfType<'a0>> + 'a0>(
         &self,
         range: T,
@@ -385,17 +586,20 @@ impl<'a, K: RedbKey, V: RedbValue> Btree<'a, K, V> {
         BtreeRangeIter::new(range, self.root.map(|(p, _)| p), self.mem)
     }
 
-    pub(crate) fn len(&self) -> Result<usize> {
-        let mut iter: BtreeRangeIter<K, V> = BtreeRangeIter::new::<RangeFull, K::SelfType<'_>>(
-            ..,
-            self.root.map(|(p, _)| p),
-            self.mem,
-        )?;
-        let mut count = 0;
-        while iter.next().is_some() {
-            count += 1;
-        }
-        Ok(count)
+    // Pages of the range are read with `hint`
+    pub(crate) fn range_extended<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::SelfType<'a0>> + 'a0,
+    >(
+        &self,
+        range: T,
+        hint: PageHint,
+    ) -> Result<BtreeRangeIter<'a, K, V>>
+    where
+        'a: 'a0,
+    {
+        BtreeRangeIter::new_extended(range, self.root.map(|(p, _)| p), hint, self.mem)
     }
 
     #[allow(dead_code)]
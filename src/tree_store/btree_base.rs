@@ -1,7 +1,8 @@
 use crate::tree_store::page_store::{ChecksumType, Page, PageImpl, PageMut, TransactionalMemory};
-use crate::tree_store::{page_store, PageNumber};
+use crate::tree_store::{compression, overflow, page_store, Compression, PageNumber};
 use crate::types::{RedbKey, RedbValue};
 use crate::Result;
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::marker::PhantomData;
 use std::mem::size_of;
@@ -14,6 +15,11 @@ pub(crate) const BRANCH: u8 = 2;
 
 pub(crate) type Checksum = u128;
 
+// Size of the fields stored for each child of a branch page: checksum, page number, and number of
+// entries
+const BRANCH_CHILD_SIZE: usize =
+    size_of::<Checksum>() + PageNumber::serialized_size() + size_of::<u64>();
+
 pub(super) fn leaf_checksum<T: Page>(
     page: &T,
     fixed_key_size: Option<usize>,
@@ -45,6 +51,21 @@ pub(super) fn branch_checksum<T: Page>(
     }
 }
 
+// Returns the number of entries in the subtree rooted at `page`
+pub(super) fn subtree_entries<T: Page>(
+    page: &T,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+) -> u64 {
+    match page.memory()[0] {
+        LEAF => {
+            LeafAccessor::new(page.memory(), fixed_key_size, fixed_value_size).num_pairs() as u64
+        }
+        BRANCH => BranchAccessor::new(page, fixed_key_size).total_entries(),
+        _ => unreachable!(),
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub(crate) enum FreePolicy {
     // Never free pages during the operation. Defer until commit
@@ -112,6 +133,8 @@ pub struct AccessGuard<'a, V: RedbValue> {
     len: usize,
     on_drop: OnDrop,
     mem: Option<&'a TransactionalMemory>,
+    // The value, and its offset, if it was stored compressed or in overflow pages
+    decoded: Option<(Vec<u8>, usize)>,
     _value_type: PhantomData<V>,
 }
 
@@ -136,6 +159,7 @@ impl<'a, V: RedbValue> AccessGuard<'a, V> {
                 OnDrop::None
             },
             mem: Some(mem),
+            decoded: None,
             _value_type: Default::default(),
         }
     }
@@ -147,6 +171,7 @@ impl<'a, V: RedbValue> AccessGuard<'a, V> {
             len: range.len(),
             on_drop: OnDrop::None,
             mem: None,
+            decoded: None,
             _value_type: Default::default(),
         }
     }
@@ -159,6 +184,7 @@ impl<'a, V: RedbValue> AccessGuard<'a, V> {
             len,
             on_drop: OnDrop::None,
             mem: None,
+            decoded: None,
             _value_type: Default::default(),
         }
     }
@@ -182,12 +208,59 @@ impl<'a, V: RedbValue> AccessGuard<'a, V> {
                 fixed_key_size,
             },
             mem: Some(mem),
+            decoded: None,
             _value_type: Default::default(),
         }
     }
 
+    // Decodes a value read from a table, which stores variable width values in overflow pages if
+    // they're large. If the table has compression, the values also have a header that records
+    // whether they are compressed
+    pub(crate) fn decode(
+        mut self,
+        compression: Option<Compression>,
+        mem: &TransactionalMemory,
+    ) -> Result<Self> {
+        if V::fixed_width().is_some() {
+            return Ok(self);
+        }
+        let data = &self.page.memory()[self.offset..(self.offset + self.len)];
+        if let Some(encoded) = overflow::read_value(data, mem)? {
+            let (decompressed, offset) = if compression.is_some() {
+                match compression::decode_value(&encoded)? {
+                    Cow::Borrowed(value) => (None, encoded.len() - value.len()),
+                    Cow::Owned(value) => (Some(value), 0),
+                }
+            } else {
+                (None, 0)
+            };
+            self.decoded = Some((decompressed.unwrap_or(encoded), offset));
+        } else if compression.is_some() {
+            match compression::decode_value(data)? {
+                Cow::Borrowed(value) => {
+                    self.offset += self.len - value.len();
+                    self.len = value.len();
+                }
+                Cow::Owned(value) => {
+                    self.decoded = Some((value, 0));
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
+    // Returns the value as it is stored in the table, before decoding
+    pub(crate) fn raw_value(&self) -> &[u8] {
+        &self.page.memory()[self.offset..(self.offset + self.len)]
+    }
+
     pub fn value(&self) -> V::SelfType<'_> {
-        V::from_bytes(&self.page.memory()[self.offset..(self.offset + self.len)])
+        if let Some((ref decoded, offset)) = self.decoded {
+            V::from_bytes(&decoded[offset..])
+        } else {
+            V::from_bytes(&self.page.memory()[self.offset..(self.offset + self.len)])
+        }
     }
 }
 
@@ -220,6 +293,9 @@ impl<'a, V: RedbValue> Drop for AccessGuard<'a, V> {
     }
 }
 
+// Called with the value written through an AccessGuardMut
+type DropCallback<'a> = Box<dyn FnOnce(&[u8]) + Send + 'a>;
+
 pub struct AccessGuardMut<'a, K: RedbKey, V: RedbValue> {
     root: Arc<Mutex<Option<(PageNumber, Checksum)>>>,
     key: Vec<u8>,
@@ -227,6 +303,10 @@ pub struct AccessGuardMut<'a, K: RedbKey, V: RedbValue> {
     page: PageMut<'a>,
     offset: usize,
     len: usize,
+    on_drop: Option<DropCallback<'a>>,
+    // Whether the value has to be moved to overflow pages once it's written, if it could be
+    // mistaken for a reference to them
+    escape_reference: bool,
     // TODO: kind of a hack that we have to have the key type to find the leaf page again. We
     // could instead save the path from the root to the leaf during .insert_reserve()
     _key_type: PhantomData<K>,
@@ -248,15 +328,34 @@ impl<'a, K: RedbKey, V: RedbValue> AccessGuardMut<'a, K, V> {
             page,
             offset,
             len,
+            on_drop: None,
+            escape_reference: false,
             _key_type: Default::default(),
             _value_type: Default::default(),
         }
     }
 
+    // Values in tables without compression are stored as they are, so a value which looks like a
+    // reference to overflow pages is moved into them, and replaced by such a reference
+    pub(crate) fn set_escape_reference(&mut self) {
+        self.escape_reference = true;
+    }
+
+    pub(crate) fn set_on_drop(&mut self, on_drop: DropCallback<'a>) {
+        self.on_drop = Some(on_drop);
+    }
+
     pub(crate) fn set_root_for_drop(&mut self, root: Arc<Mutex<Option<(PageNumber, Checksum)>>>) {
         self.root = root;
     }
 
+    // Writes `prefix` to the start of the value, and excludes it from the mutable buffer
+    pub(crate) fn write_prefix(&mut self, prefix: &[u8]) {
+        self.as_mut()[..prefix.len()].copy_from_slice(prefix);
+        self.offset += prefix.len();
+        self.len -= prefix.len();
+    }
+
     // Repairs the checksums after the user has filled the mutable buffer. This is necessary
     // because the checksums will have been calculated with the values during .insert_reserve(),
     // but the user is given a mutable reference and will have modified the value, which invalidates
@@ -273,10 +372,11 @@ impl<'a, K: RedbKey, V: RedbValue> AccessGuardMut<'a, K, V> {
             assert_eq!(BRANCH, page.memory()[0]);
             let accessor = BranchAccessor::new(&page, K::fixed_width());
             let (child_index, child_page) = accessor.child_for_key::<K>(&self.key);
+            let child_entries = accessor.child_entries(child_index).unwrap();
             let child_checksum = self.finalize_checksum(child_page)?;
             drop(accessor);
             let mut mutator = BranchMutator::new(&mut page);
-            mutator.write_child_page(child_index, child_page, child_checksum);
+            mutator.write_child_page(child_index, child_page, child_checksum, child_entries);
             Ok(self.checksum_helper(&page))
         }
     }
@@ -311,12 +411,23 @@ impl<'a, K: RedbKey, V: RedbValue> Drop for AccessGuardMut<'a, K, V> {
         if self.root.lock().unwrap().is_none() {
             return;
         }
+        let range = self.offset..(self.offset + self.len);
+        let escaped = if self.escape_reference {
+            overflow::escape_reference(&mut self.page.memory_mut()[range.clone()], self.mem)
+                .unwrap()
+        } else {
+            None
+        };
         let new_checksum = self
             .finalize_checksum((self.root.clone()).lock().unwrap().unwrap().0)
             .unwrap();
         let mut borrow = self.root.lock().unwrap();
         let (_, root_checksum_ref) = borrow.as_mut().unwrap();
         *root_checksum_ref = new_checksum;
+        drop(borrow);
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop(escaped.as_deref().unwrap_or(&self.page.memory()[range]));
+        }
     }
 }
 
@@ -1209,10 +1320,9 @@ impl<'a: 'b, 'b, T: Page + 'a> BranchAccessor<'a, 'b, T> {
 
     fn key_section_start(&self) -> usize {
         if self.fixed_key_size.is_none() {
-            8 + (PageNumber::serialized_size() + size_of::<Checksum>()) * self.count_children()
-                + size_of::<u32>() * self.num_keys()
+            8 + BRANCH_CHILD_SIZE * self.count_children() + size_of::<u32>() * self.num_keys()
         } else {
-            8 + (PageNumber::serialized_size() + size_of::<Checksum>()) * self.count_children()
+            8 + BRANCH_CHILD_SIZE * self.count_children()
         }
     }
 
@@ -1228,9 +1338,7 @@ impl<'a: 'b, 'b, T: Page + 'a> BranchAccessor<'a, 'b, T> {
         if let Some(fixed) = self.fixed_key_size {
             return self.key_section_start() + fixed * (n + 1);
         }
-        let offset = 8
-            + (PageNumber::serialized_size() + size_of::<Checksum>()) * self.count_children()
-            + size_of::<u32>() * n;
+        let offset = 8 + BRANCH_CHILD_SIZE * self.count_children() + size_of::<u32>() * n;
         u32::from_le_bytes(
             self.page.memory()[offset..(offset + size_of::<u32>())]
                 .try_into()
@@ -1278,13 +1386,37 @@ impl<'a: 'b, 'b, T: Page + 'a> BranchAccessor<'a, 'b, T> {
         ))
     }
 
+    // Returns the number of entries in the subtree rooted at the nth child
+    pub(super) fn child_entries(&self, n: usize) -> Option<u64> {
+        if n >= self.count_children() {
+            return None;
+        }
+
+        let offset = 8
+            + (size_of::<Checksum>() + PageNumber::serialized_size()) * self.count_children()
+            + size_of::<u64>() * n;
+        Some(u64::from_le_bytes(
+            self.page.memory()[offset..(offset + size_of::<u64>())]
+                .try_into()
+                .unwrap(),
+        ))
+    }
+
+    // Returns the number of entries in the subtree rooted at this page
+    pub(super) fn total_entries(&self) -> u64 {
+        (0..self.count_children())
+            .map(|i| self.child_entries(i).unwrap())
+            .sum()
+    }
+
     fn num_keys(&self) -> usize {
         self.num_keys
     }
 }
 
 pub(super) struct BranchBuilder<'a, 'b> {
-    children: Vec<(PageNumber, Checksum)>,
+    // Page, checksum, and number of entries of each child
+    children: Vec<(PageNumber, Checksum, u64)>,
     keys: Vec<&'a [u8]>,
     total_key_bytes: usize,
     fixed_key_size: Option<usize>,
@@ -1306,12 +1438,18 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
         }
     }
 
-    pub(super) fn replace_child(&mut self, index: usize, child: PageNumber, checksum: Checksum) {
-        self.children[index] = (child, checksum);
+    pub(super) fn replace_child(
+        &mut self,
+        index: usize,
+        child: PageNumber,
+        checksum: Checksum,
+        entries: u64,
+    ) {
+        self.children[index] = (child, checksum, entries);
     }
 
-    pub(super) fn push_child(&mut self, child: PageNumber, checksum: Checksum) {
-        self.children.push((child, checksum));
+    pub(super) fn push_child(&mut self, child: PageNumber, checksum: Checksum, entries: u64) {
+        self.children.push((child, checksum, entries));
     }
 
     pub(super) fn push_key(&mut self, key: &'a [u8]) {
@@ -1323,14 +1461,15 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
         for i in 0..accessor.count_children() {
             let child = accessor.child_page(i).unwrap();
             let checksum = accessor.child_checksum(i).unwrap();
-            self.push_child(child, checksum);
+            let entries = accessor.child_entries(i).unwrap();
+            self.push_child(child, checksum, entries);
         }
         for i in 0..(accessor.count_children() - 1) {
             self.push_key(accessor.key(i).unwrap());
         }
     }
 
-    pub(super) fn to_single_child(&self) -> Option<(PageNumber, Checksum)> {
+    pub(super) fn to_single_child(&self) -> Option<(PageNumber, Checksum, u64)> {
         if self.children.len() > 1 {
             None
         } else {
@@ -1347,10 +1486,12 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
         );
         let mut page = self.mem.allocate(size)?;
         let mut builder = RawBranchBuilder::new(&mut page, self.keys.len(), self.fixed_key_size);
-        builder.write_first_page(self.children[0].0, self.children[0].1);
+        let (first_page, first_checksum, first_entries) = self.children[0];
+        builder.write_first_page(first_page, first_checksum, first_entries);
         for i in 1..self.children.len() {
             let key = &self.keys[i - 1];
-            builder.write_nth_key(key.as_ref(), self.children[i].0, self.children[i].1, i - 1);
+            let (page_number, checksum, entries) = self.children[i];
+            builder.write_nth_key(key.as_ref(), page_number, checksum, entries, i - 1);
         }
         drop(builder);
 
@@ -1378,15 +1519,12 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
             RawBranchBuilder::required_bytes(division, first_split_key_len, self.fixed_key_size);
         let mut page1 = self.mem.allocate(size)?;
         let mut builder = RawBranchBuilder::new(&mut page1, division, self.fixed_key_size);
-        builder.write_first_page(self.children[0].0, self.children[0].1);
+        let (first_page, first_checksum, first_entries) = self.children[0];
+        builder.write_first_page(first_page, first_checksum, first_entries);
         for i in 0..division {
             let key = &self.keys[i];
-            builder.write_nth_key(
-                key.as_ref(),
-                self.children[i + 1].0,
-                self.children[i + 1].1,
-                i,
-            );
+            let (page_number, checksum, entries) = self.children[i + 1];
+            builder.write_nth_key(key.as_ref(), page_number, checksum, entries, i);
         }
         drop(builder);
 
@@ -1401,13 +1539,16 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
             self.keys.len() - division - 1,
             self.fixed_key_size,
         );
-        builder.write_first_page(self.children[division + 1].0, self.children[division + 1].1);
+        let (first_page, first_checksum, first_entries) = self.children[division + 1];
+        builder.write_first_page(first_page, first_checksum, first_entries);
         for i in (division + 1)..self.keys.len() {
             let key = &self.keys[i];
+            let (page_number, checksum, entries) = self.children[i + 1];
             builder.write_nth_key(
                 key.as_ref(),
-                self.children[i + 1].0,
-                self.children[i + 1].1,
+                page_number,
+                checksum,
+                entries,
                 i - division - 1,
             );
         }
@@ -1428,6 +1569,8 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
 // 16 bytes: child page checksum
 // repeating (num_keys + 1 times):
 // 8 bytes: page number
+// repeating (num_keys + 1 times):
+// 8 bytes: number of entries in the child subtree
 // (optional) repeating (num_keys times):
 // * 4 bytes: key end. Ending offset of the key, exclusive
 // repeating (num_keys times):
@@ -1446,13 +1589,10 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
         fixed_key_size: Option<usize>,
     ) -> usize {
         if fixed_key_size.is_none() {
-            let fixed_size = 8
-                + (PageNumber::serialized_size() + size_of::<Checksum>()) * (num_keys + 1)
-                + size_of::<u32>() * num_keys;
+            let fixed_size = 8 + BRANCH_CHILD_SIZE * (num_keys + 1) + size_of::<u32>() * num_keys;
             size_of_keys + fixed_size
         } else {
-            let fixed_size =
-                8 + (PageNumber::serialized_size() + size_of::<Checksum>()) * (num_keys + 1);
+            let fixed_size = 8 + BRANCH_CHILD_SIZE * (num_keys + 1);
             size_of_keys + fixed_size
         }
     }
@@ -1470,9 +1610,7 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
         {
             // Poison all the child pointers & key offsets, in case the caller forgets to write them
             let start = 8 + size_of::<Checksum>() * (num_keys + 1);
-            let last = 8
-                + (PageNumber::serialized_size() + size_of::<Checksum>()) * (num_keys + 1)
-                + size_of::<u32>() * num_keys;
+            let last = 8 + BRANCH_CHILD_SIZE * (num_keys + 1) + size_of::<u32>() * num_keys;
             for x in &mut page.memory_mut()[start..last] {
                 *x = 0xFF;
             }
@@ -1485,18 +1623,26 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
         }
     }
 
-    pub(super) fn write_first_page(&mut self, page_number: PageNumber, checksum: Checksum) {
+    pub(super) fn write_first_page(
+        &mut self,
+        page_number: PageNumber,
+        checksum: Checksum,
+        entries: u64,
+    ) {
         let offset = 8;
         self.page.memory_mut()[offset..(offset + size_of::<Checksum>())]
             .copy_from_slice(&checksum.to_le_bytes());
         let offset = 8 + size_of::<Checksum>() * (self.num_keys + 1);
         self.page.memory_mut()[offset..(offset + PageNumber::serialized_size())]
             .copy_from_slice(&page_number.to_le_bytes());
+        let offset =
+            8 + (size_of::<Checksum>() + PageNumber::serialized_size()) * (self.num_keys + 1);
+        self.page.memory_mut()[offset..(offset + size_of::<u64>())]
+            .copy_from_slice(&entries.to_le_bytes());
     }
 
     fn key_section_start(&self) -> usize {
-        let mut offset =
-            8 + (PageNumber::serialized_size() + size_of::<Checksum>()) * (self.num_keys + 1);
+        let mut offset = 8 + BRANCH_CHILD_SIZE * (self.num_keys + 1);
         if self.fixed_key_size.is_none() {
             offset += size_of::<u32>() * self.num_keys;
         }
@@ -1508,9 +1654,7 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
         if let Some(fixed) = self.fixed_key_size {
             return self.key_section_start() + fixed * (n + 1);
         }
-        let offset = 8
-            + (PageNumber::serialized_size() + size_of::<Checksum>()) * (self.num_keys + 1)
-            + size_of::<u32>() * n;
+        let offset = 8 + BRANCH_CHILD_SIZE * (self.num_keys + 1) + size_of::<u32>() * n;
         u32::from_le_bytes(
             self.page.memory()[offset..(offset + size_of::<u32>())]
                 .try_into()
@@ -1525,6 +1669,7 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
         key: &[u8],
         page_number: PageNumber,
         checksum: Checksum,
+        entries: u64,
         n: usize,
     ) {
         assert!(n < self.num_keys);
@@ -1538,6 +1683,11 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
             + PageNumber::serialized_size() * (n + 1);
         self.page.memory_mut()[offset..(offset + PageNumber::serialized_size())]
             .copy_from_slice(&page_number.to_le_bytes());
+        let offset = 8
+            + (size_of::<Checksum>() + PageNumber::serialized_size()) * (self.num_keys + 1)
+            + size_of::<u64>() * (n + 1);
+        self.page.memory_mut()[offset..(offset + size_of::<u64>())]
+            .copy_from_slice(&entries.to_le_bytes());
 
         let data_offset = if n > 0 {
             self.key_end(n - 1)
@@ -1545,9 +1695,7 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
             self.key_section_start()
         };
         if self.fixed_key_size.is_none() {
-            let offset = 8
-                + (PageNumber::serialized_size() + size_of::<Checksum>()) * (self.num_keys + 1)
-                + size_of::<u32>() * n;
+            let offset = 8 + BRANCH_CHILD_SIZE * (self.num_keys + 1) + size_of::<u32>() * n;
             self.page.memory_mut()[offset..(offset + size_of::<u32>())].copy_from_slice(
                 &u32::try_from(data_offset + key.len())
                     .unwrap()
@@ -1587,6 +1735,7 @@ impl<'a: 'b, 'b> BranchMutator<'a, 'b> {
         i: usize,
         page_number: PageNumber,
         checksum: Checksum,
+        entries: u64,
     ) {
         debug_assert!(i <= self.num_keys());
         let offset = 8 + size_of::<Checksum>() * i;
@@ -1596,5 +1745,10 @@ impl<'a: 'b, 'b> BranchMutator<'a, 'b> {
             8 + size_of::<Checksum>() * (self.num_keys() + 1) + PageNumber::serialized_size() * i;
         self.page.memory_mut()[offset..(offset + PageNumber::serialized_size())]
             .copy_from_slice(&page_number.to_le_bytes());
+        let offset = 8
+            + (size_of::<Checksum>() + PageNumber::serialized_size()) * (self.num_keys() + 1)
+            + size_of::<u64>() * i;
+        self.page.memory_mut()[offset..(offset + size_of::<u64>())]
+            .copy_from_slice(&entries.to_le_bytes());
     }
 }
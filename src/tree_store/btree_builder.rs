@@ -0,0 +1,203 @@
+use crate::tree_store::btree_base::{
+    branch_checksum, leaf_checksum, BranchBuilder, Checksum, RawBranchBuilder, RawLeafBuilder,
+};
+use crate::tree_store::{
+    AllPageNumbersBtreeIter, LeafAccessor, Page, PageNumber, TransactionalMemory, BRANCH, LEAF,
+};
+use crate::Result;
+use std::mem;
+
+// Builds a new btree bottom-up, from entries supplied in ascending key order. Every page is filled
+// as much as possible, so the resulting tree has no fragmentation
+pub(crate) struct BtreeBuilder<'a> {
+    mem: &'a TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    pairs_bytes: usize,
+    // Page, checksum, number of entries, and last key of each leaf that has been built
+    leaves: Vec<(PageNumber, Checksum, u64, Vec<u8>)>,
+}
+
+impl<'a> BtreeBuilder<'a> {
+    pub(crate) fn new(
+        mem: &'a TransactionalMemory,
+        fixed_key_size: Option<usize>,
+        fixed_value_size: Option<usize>,
+    ) -> Self {
+        Self {
+            mem,
+            fixed_key_size,
+            fixed_value_size,
+            pairs: vec![],
+            pairs_bytes: 0,
+            leaves: vec![],
+        }
+    }
+
+    // Caller must push keys in strictly increasing order
+    pub(crate) fn push(&mut self, key: &[u8], value: &[u8]) -> Result {
+        let required_size = RawLeafBuilder::required_bytes(
+            self.pairs.len() + 1,
+            self.pairs_bytes + key.len() + value.len(),
+        );
+        if !self.pairs.is_empty() && required_size > self.mem.get_page_size() {
+            self.build_leaf()?;
+        }
+        self.pairs_bytes += key.len() + value.len();
+        self.pairs.push((key.to_vec(), value.to_vec()));
+
+        Ok(())
+    }
+
+    fn build_leaf(&mut self) -> Result {
+        let key_bytes = self.pairs.iter().map(|(key, _)| key.len()).sum();
+        let required_size = RawLeafBuilder::required_bytes(self.pairs.len(), self.pairs_bytes);
+        let mut page = self.mem.allocate(required_size)?;
+        let mut builder = RawLeafBuilder::new(
+            page.memory_mut(),
+            self.pairs.len(),
+            self.fixed_key_size,
+            self.fixed_value_size,
+            key_bytes,
+        );
+        for (key, value) in self.pairs.iter() {
+            builder.append(key, value);
+        }
+        drop(builder);
+
+        let checksum = leaf_checksum(
+            &page,
+            self.fixed_key_size,
+            self.fixed_value_size,
+            self.mem.checksum_type(),
+        );
+        let entries = self.pairs.len() as u64;
+        let (last_key, _) = self.pairs.pop().unwrap();
+        self.pairs.clear();
+        self.pairs_bytes = 0;
+        self.leaves
+            .push((page.get_page_number(), checksum, entries, last_key));
+
+        Ok(())
+    }
+
+    // Returns the root of the new tree, or None if no entries were pushed
+    pub(crate) fn finish(mut self) -> Result<Option<(PageNumber, Checksum)>> {
+        if !self.pairs.is_empty() {
+            self.build_leaf()?;
+        }
+
+        let mut level = mem::take(&mut self.leaves);
+        while level.len() > 1 {
+            let mut next_level = vec![];
+            let mut start = 0;
+            while start < level.len() {
+                // Fill the branch up to the page size, but with at least two children
+                let mut end = start + 1;
+                let mut key_bytes = 0;
+                while end < level.len() {
+                    let required_size = RawBranchBuilder::required_bytes(
+                        end - start,
+                        key_bytes + level[end - 1].3.len(),
+                        self.fixed_key_size,
+                    );
+                    if end - start >= 2 && required_size > self.mem.get_page_size() {
+                        break;
+                    }
+                    key_bytes += level[end - 1].3.len();
+                    end += 1;
+                }
+                // Never leave a single child for the next branch. This branch is full, so move its
+                // last child to the next one instead, unless that would leave it with only one
+                if level.len() - end == 1 {
+                    if end - start > 2 {
+                        end -= 1;
+                    } else {
+                        end = level.len();
+                    }
+                }
+
+                let mut builder = BranchBuilder::new(self.mem, end - start, self.fixed_key_size);
+                let mut total_entries = 0;
+                for (i, (child, checksum, entries, last_key)) in
+                    level[start..end].iter().enumerate()
+                {
+                    builder.push_child(*child, *checksum, *entries);
+                    total_entries += entries;
+                    if i < end - start - 1 {
+                        builder.push_key(last_key);
+                    }
+                }
+                let page = builder.build()?;
+                let checksum =
+                    branch_checksum(&page, self.fixed_key_size, self.mem.checksum_type());
+                next_level.push((
+                    page.get_page_number(),
+                    checksum,
+                    total_entries,
+                    level[end - 1].3.clone(),
+                ));
+                start = end;
+            }
+            level = next_level;
+        }
+
+        Ok(level.pop().map(|(page, checksum, _, _)| (page, checksum)))
+    }
+}
+
+// Copies the tree rooted at `source_root` in `source_mem` into `mem`, by rebuilding it bottom-up.
+// value_fixup is called on each value, and may return a replacement for it. This is used to copy
+// trees that are nested inside values
+pub(crate) fn copy_tree_with_values<F: FnMut(&[u8]) -> Result<Option<Vec<u8>>>>(
+    source_root: PageNumber,
+    source_mem: &TransactionalMemory,
+    mem: &TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+    value_fixup: &mut F,
+) -> Result<Option<(PageNumber, Checksum)>> {
+    let mut builder = BtreeBuilder::new(mem, fixed_key_size, fixed_value_size);
+    // Leaves are visited in key order
+    for page_number in
+        AllPageNumbersBtreeIter::new(source_root, fixed_key_size, fixed_value_size, source_mem)?
+    {
+        let page = source_mem.get_page(page_number)?;
+        match page.memory()[0] {
+            LEAF => {
+                let accessor = LeafAccessor::new(page.memory(), fixed_key_size, fixed_value_size);
+                for i in 0..accessor.num_pairs() {
+                    let entry = accessor.entry(i).unwrap();
+                    if let Some(value) = value_fixup(entry.value())? {
+                        builder.push(entry.key(), &value)?;
+                    } else {
+                        builder.push(entry.key(), entry.value())?;
+                    }
+                }
+            }
+            BRANCH => {}
+            _ => unreachable!(),
+        }
+    }
+
+    builder.finish()
+}
+
+// Copies the tree rooted at `source_root` in `source_mem` into `mem`, by rebuilding it bottom-up
+pub(crate) fn copy_tree(
+    source_root: PageNumber,
+    source_mem: &TransactionalMemory,
+    mem: &TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+) -> Result<Option<(PageNumber, Checksum)>> {
+    copy_tree_with_values(
+        source_root,
+        source_mem,
+        mem,
+        fixed_key_size,
+        fixed_value_size,
+        &mut |_| Ok(None),
+    )
+}
@@ -1,16 +1,20 @@
-use crate::tree_store::btree_base::{BranchAccessor, LeafAccessor};
+use crate::tree_store::btree_base::{subtree_entries, BranchAccessor, LeafAccessor};
 use crate::tree_store::btree_base::{BRANCH, LEAF};
 use crate::tree_store::btree_iters::RangeIterState::{Internal, Leaf};
-use crate::tree_store::page_store::{Page, PageImpl, TransactionalMemory};
-use crate::tree_store::PageNumber;
+use crate::tree_store::page_store::{Page, PageHint, PageImpl, TransactionalMemory};
+use crate::tree_store::{overflow, Compression, PageNumber};
 use crate::types::{RedbKey, RedbValue};
 use crate::Result;
-use std::borrow::Borrow;
-use std::collections::Bound;
+use std::borrow::{Borrow, Cow};
+use std::collections::{Bound, VecDeque};
 use std::marker::PhantomData;
 use std::ops::{Range, RangeBounds};
 use std::sync::{Arc, Mutex};
 
+// Number of leaves, ahead of the one being iterated, which are prefetched at once. They're found
+// from the branch pages on the iterator's stack
+const PREFETCH_LEAVES: usize = 8;
+
 #[derive(Debug)]
 pub enum RangeIterState<'a> {
     Leaf {
@@ -25,6 +29,8 @@ pub enum RangeIterState<'a> {
         fixed_key_size: Option<usize>,
         fixed_value_size: Option<usize>,
         child: usize,
+        // Number of branch pages above this one. All the leaves of a tree are at the same depth
+        depth: usize,
         parent: Option<Box<RangeIterState<'a>>>,
     },
 }
@@ -40,6 +46,7 @@ impl<'a> RangeIterState<'a> {
     fn next(
         self,
         reverse: bool,
+        hint: PageHint,
         manager: &'a TransactionalMemory,
     ) -> Result<Option<RangeIterState>> {
         match self {
@@ -70,11 +77,12 @@ impl<'a> RangeIterState<'a> {
                 fixed_key_size,
                 fixed_value_size,
                 child,
+                depth,
                 mut parent,
             } => {
                 let accessor = BranchAccessor::new(&page, fixed_key_size);
                 let child_page = accessor.child_page(child).unwrap();
-                let child_page = manager.get_page(child_page)?;
+                let child_page = manager.get_page_extended(child_page, hint)?;
                 let direction = if reverse { -1 } else { 1 };
                 let next_child = isize::try_from(child).unwrap() + direction;
                 if 0 <= next_child && next_child < accessor.count_children().try_into().unwrap() {
@@ -83,6 +91,7 @@ impl<'a> RangeIterState<'a> {
                         fixed_key_size,
                         fixed_value_size,
                         child: next_child.try_into().unwrap(),
+                        depth,
                         parent,
                     }));
                 }
@@ -107,6 +116,7 @@ impl<'a> RangeIterState<'a> {
                         }))
                     }
                     BRANCH => {
+                        manager.hint_page(&child_page, PageHint::Branch);
                         let child_accessor = BranchAccessor::new(&child_page, fixed_key_size);
                         let child = if reverse {
                             child_accessor.count_children() - 1
@@ -118,6 +128,7 @@ impl<'a> RangeIterState<'a> {
                             fixed_key_size,
                             fixed_value_size,
                             child,
+                            depth: depth + 1,
                             parent,
                         }))
                     }
@@ -177,6 +188,24 @@ impl<'a, K: RedbKey, V: RedbValue> EntryGuard<'a, K, V> {
         V::from_bytes(&self.page.memory()[self.value_range.clone()])
     }
 
+    pub(crate) fn raw_value(&self) -> &[u8] {
+        &self.page.memory()[self.value_range.clone()]
+    }
+
+    // Returns the value, decoding it first if `encoded_values` is set
+    pub(crate) fn decoded_value(
+        &self,
+        encoded_values: bool,
+        compression: Option<Compression>,
+        mem: &TransactionalMemory,
+    ) -> Result<Cow<'_, [u8]>> {
+        if encoded_values {
+            overflow::decode_value(self.raw_value(), compression, mem)
+        } else {
+            Ok(Cow::Borrowed(self.raw_value()))
+        }
+    }
+
     pub(crate) fn into_raw(self) -> (PageImpl<'a>, Range<usize>, Range<usize>) {
         (self.page, self.key_range, self.value_range)
     }
@@ -209,6 +238,7 @@ impl<'a> AllPageNumbersBtreeIter<'a> {
                 fixed_key_size,
                 fixed_value_size,
                 child: 0,
+                depth: 0,
                 parent: None,
             },
             _ => unreachable!(),
@@ -233,7 +263,7 @@ impl<'a> Iterator for AllPageNumbersBtreeIter<'a> {
                 Internal { child, .. } => child == 0,
             };
             // TODO: propagate this error
-            self.next = state.next(false, self.manager).unwrap();
+            self.next = state.next(false, PageHint::None, self.manager).unwrap();
             if once {
                 return Some(value);
             }
@@ -243,6 +273,7 @@ impl<'a> Iterator for AllPageNumbersBtreeIter<'a> {
 
 pub(crate) struct BtreeDrain<'a, K: RedbKey + 'a, V: RedbValue + 'a> {
     inner: BtreeRangeIter<'a, K, V>,
+    removed: usize,
     free_on_drop: Vec<PageNumber>,
     master_free_list: Arc<Mutex<Vec<PageNumber>>>,
     mem: &'a TransactionalMemory,
@@ -253,17 +284,24 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeDrain<'a, K, V> {
     // within `inner`
     pub(crate) unsafe fn new(
         inner: BtreeRangeIter<'a, K, V>,
+        removed: usize,
         free_on_drop: Vec<PageNumber>,
         master_free_list: Arc<Mutex<Vec<PageNumber>>>,
         mem: &'a TransactionalMemory,
     ) -> Self {
         Self {
             inner,
+            removed,
             free_on_drop,
             master_free_list,
             mem,
         }
     }
+
+    // Number of entries that were removed from the tree
+    pub(crate) fn removed(&self) -> usize {
+        self.removed
+    }
 }
 
 impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> Iterator for BtreeDrain<'a, K, V> {
@@ -306,7 +344,10 @@ pub(crate) struct BtreeDrainFilter<
     F: for<'f> FnMut(K::SelfType<'f>, V::SelfType<'f>) -> bool,
 > {
     inner: BtreeRangeIter<'a, K, V>,
-    predicate: F,
+    // Whether each entry of `inner` matched the predicate, so that it's only called once per entry
+    matches: VecDeque<bool>,
+    _predicate: PhantomData<F>,
+    removed: usize,
     free_on_drop: Vec<PageNumber>,
     master_free_list: Arc<Mutex<Vec<PageNumber>>>,
     mem: &'a TransactionalMemory,
@@ -323,19 +364,27 @@ impl<
     // within `inner`
     pub(crate) unsafe fn new(
         inner: BtreeRangeIter<'a, K, V>,
-        predicate: F,
+        matches: VecDeque<bool>,
+        removed: usize,
         free_on_drop: Vec<PageNumber>,
         master_free_list: Arc<Mutex<Vec<PageNumber>>>,
         mem: &'a TransactionalMemory,
     ) -> Self {
         Self {
             inner,
-            predicate,
+            matches,
+            _predicate: Default::default(),
+            removed,
             free_on_drop,
             master_free_list,
             mem,
         }
     }
+
+    // Number of entries that were removed from the tree
+    pub(crate) fn removed(&self) -> usize {
+        self.removed
+    }
 }
 
 impl<
@@ -349,8 +398,8 @@ impl<
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut item = self.inner.next();
-        while let Some(ref entry) = item {
-            if (self.predicate)(entry.key(), entry.value()) {
+        while item.is_some() {
+            if self.matches.pop_front().unwrap() {
                 break;
             }
             item = self.inner.next();
@@ -368,8 +417,8 @@ impl<
 {
     fn next_back(&mut self) -> Option<Self::Item> {
         let mut item = self.inner.next_back();
-        while let Some(ref entry) = item {
-            if (self.predicate)(entry.key(), entry.value()) {
+        while item.is_some() {
+            if self.matches.pop_back().unwrap() {
                 break;
             }
             item = self.inner.next_back();
@@ -408,6 +457,11 @@ pub(crate) struct BtreeRangeIter<'a, K: RedbKey + 'a, V: RedbValue + 'a> {
     right: Option<RangeIterState<'a>>, // Exclusive. The previous element returned
     include_left: bool,               // left is inclusive, instead of exclusive
     include_right: bool,              // right is inclusive, instead of exclusive
+    remaining: u64,                   // Number of entries in the range which have not been returned
+    hint: PageHint,
+    prefetch: bool,          // Whether the storage benefits from prefetching leaves
+    left_prefetched: usize,  // Number of leaves ahead of left which have been prefetched
+    right_prefetched: usize, // Number of leaves ahead of right which have been prefetched
     manager: &'a TransactionalMemory,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
@@ -419,49 +473,85 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeRangeIter<'a, K, V> {
         table_root: Option<PageNumber>,
         manager: &'a TransactionalMemory,
     ) -> Result<Self>
+    where
+        'a: 'a0,
+    {
+        Self::new_extended(query_range, table_root, PageHint::None, manager)
+    }
+
+    // Pages are read with `hint`
+    pub(crate) fn new_extended<'a0, T: RangeBounds<KR> + 'a0, KR: Borrow<K::SelfType<'a0>> + 'a0>(
+        query_range: T,
+        table_root: Option<PageNumber>,
+        hint: PageHint,
+        manager: &'a TransactionalMemory,
+    ) -> Result<Self>
     where
         'a: 'a0,
     {
         if let Some(root) = table_root {
+            // Number of entries which precede the range, and number of entries up to its end
+            let mut left_rank = 0;
+            let mut right_rank = 0;
             let (include_left, left) = match query_range.start_bound() {
                 Bound::Included(k) => find_iter_left::<K, V>(
-                    manager.get_page(root)?,
+                    manager.get_page_extended(root, hint)?,
                     None,
+                    0,
                     K::as_bytes(k.borrow()).as_ref(),
                     true,
+                    &mut left_rank,
+                    hint,
                     manager,
                 )?,
                 Bound::Excluded(k) => find_iter_left::<K, V>(
-                    manager.get_page(root)?,
+                    manager.get_page_extended(root, hint)?,
                     None,
+                    0,
                     K::as_bytes(k.borrow()).as_ref(),
                     false,
+                    &mut left_rank,
+                    hint,
                     manager,
                 )?,
                 Bound::Unbounded => {
-                    let state =
-                        find_iter_unbounded::<K, V>(manager.get_page(root)?, None, false, manager)?;
+                    let state = find_iter_unbounded::<K, V>(
+                        manager.get_page_extended(root, hint)?,
+                        None,
+                        0,
+                        false,
+                        hint,
+                        manager,
+                    )?;
                     (true, state)
                 }
             };
             let (include_right, right) = match query_range.end_bound() {
                 Bound::Included(k) => find_iter_right::<K, V>(
-                    manager.get_page(root)?,
+                    manager.get_page_extended(root, hint)?,
                     None,
+                    0,
                     K::as_bytes(k.borrow()).as_ref(),
                     true,
+                    &mut right_rank,
+                    hint,
                     manager,
                 )?,
                 Bound::Excluded(k) => find_iter_right::<K, V>(
-                    manager.get_page(root)?,
+                    manager.get_page_extended(root, hint)?,
                     None,
+                    0,
                     K::as_bytes(k.borrow()).as_ref(),
                     false,
+                    &mut right_rank,
+                    hint,
                     manager,
                 )?,
                 Bound::Unbounded => {
+                    let root_page = manager.get_page_extended(root, hint)?;
+                    right_rank = subtree_entries(&root_page, K::fixed_width(), V::fixed_width());
                     let state =
-                        find_iter_unbounded::<K, V>(manager.get_page(root)?, None, true, manager)?;
+                        find_iter_unbounded::<K, V>(root_page, None, 0, true, hint, manager)?;
                     (true, state)
                 }
             };
@@ -470,6 +560,11 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeRangeIter<'a, K, V> {
                 right,
                 include_left,
                 include_right,
+                remaining: right_rank.saturating_sub(left_rank),
+                hint,
+                prefetch: manager.can_prefetch(),
+                left_prefetched: 0,
+                right_prefetched: 0,
                 manager,
                 _key_type: Default::default(),
                 _value_type: Default::default(),
@@ -480,12 +575,117 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeRangeIter<'a, K, V> {
                 right: None,
                 include_left: false,
                 include_right: false,
+                remaining: 0,
+                hint,
+                prefetch: false,
+                left_prefetched: 0,
+                right_prefetched: 0,
                 manager,
                 _key_type: Default::default(),
                 _value_type: Default::default(),
             })
         }
     }
+
+    // Called each time a cursor enters a new leaf. Once it reaches the last of the leaves that
+    // were prefetched ahead of it, the following ones are prefetched
+    fn entered_leaf(&mut self, reverse: bool, leaf_depth: usize) {
+        let prefetched = if reverse {
+            &mut self.right_prefetched
+        } else {
+            &mut self.left_prefetched
+        };
+        *prefetched = prefetched.saturating_sub(1);
+        if *prefetched > 0 || !self.prefetch {
+            return;
+        }
+
+        let (cursor, end) = if reverse {
+            (&self.right, &self.left)
+        } else {
+            (&self.left, &self.right)
+        };
+        // Leaves beyond the other end of the range won't be read
+        let end = match end {
+            Some(Leaf { page, .. }) => Some(page.get_page_number()),
+            _ => None,
+        };
+        let mut leaves = Vec::with_capacity(PREFETCH_LEAVES);
+        let mut next = match cursor {
+            Some(Leaf { parent, .. }) => parent.as_deref(),
+            _ => None,
+        };
+        // Each branch on the stack holds the next child to visit, so its remaining children follow
+        // those of the branches below it
+        while let Some(Internal {
+            page,
+            child,
+            depth,
+            parent,
+            ..
+        }) = next
+        {
+            // Errors are returned once the cursor reaches the page, since this is only a hint
+            match self.collect_leaves(page, *child, leaf_depth - depth, reverse, end, &mut leaves) {
+                Ok(false) => {}
+                Ok(true) | Err(_) => break,
+            }
+            next = parent.as_deref();
+        }
+
+        let count = leaves.len();
+        self.manager.prefetch(leaves.into_iter(), self.hint);
+        if reverse {
+            self.right_prefetched = count;
+        } else {
+            self.left_prefetched = count;
+        }
+    }
+
+    // Appends the leaves below `page`, starting at its `child`th child, to `leaves`. `height` is
+    // the number of levels between `page` and the leaves. Returns true once there are
+    // PREFETCH_LEAVES of them, or `end` has been reached
+    fn collect_leaves(
+        &self,
+        page: &PageImpl<'a>,
+        mut child: usize,
+        height: usize,
+        reverse: bool,
+        end: Option<PageNumber>,
+        leaves: &mut Vec<PageNumber>,
+    ) -> Result<bool> {
+        let accessor = BranchAccessor::new(page, K::fixed_width());
+        while let Some(child_page) = accessor.child_page(child) {
+            if height == 1 {
+                leaves.push(child_page);
+                if leaves.len() == PREFETCH_LEAVES || end == Some(child_page) {
+                    return Ok(true);
+                }
+            } else {
+                // The cursor will need the branch soon, so reading it now is no extra work
+                let branch = self.manager.get_page_extended(child_page, self.hint)?;
+                self.manager.hint_page(&branch, PageHint::Branch);
+                let first = if reverse {
+                    BranchAccessor::new(&branch, K::fixed_width()).count_children() - 1
+                } else {
+                    0
+                };
+                if self.collect_leaves(&branch, first, height - 1, reverse, end, leaves)? {
+                    return Ok(true);
+                }
+            }
+            if reverse {
+                if child == 0 {
+                    break;
+                }
+                child -= 1;
+            } else {
+                child += 1;
+            }
+        }
+
+        Ok(false)
+    }
 }
 
 impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> Iterator for BtreeRangeIter<'a, K, V> {
@@ -515,8 +715,19 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> Iterator for BtreeRangeIter<'a, K,
 
         loop {
             if !self.include_left {
+                let entering = match self.left {
+                    Some(Internal { depth, .. }) => Some(depth + 1),
+                    _ => None,
+                };
                 // TODO: propagate this error
-                self.left = self.left.take()?.next(false, self.manager).unwrap();
+                self.left = self
+                    .left
+                    .take()?
+                    .next(false, self.hint, self.manager)
+                    .unwrap();
+                if let (Some(leaf_depth), Some(Leaf { .. })) = (entering, &self.left) {
+                    self.entered_leaf(false, leaf_depth);
+                }
             }
             // Return None if the next state is None
             self.left.as_ref()?;
@@ -544,10 +755,15 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> Iterator for BtreeRangeIter<'a, K,
 
             self.include_left = false;
             if self.left.as_ref().unwrap().get_entry::<K, V>().is_some() {
+                self.remaining -= 1;
                 return self.left.as_ref().map(|s| s.get_entry().unwrap());
             }
         }
     }
+
+    fn count(self) -> usize {
+        self.remaining.try_into().unwrap()
+    }
 }
 
 impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> DoubleEndedIterator for BtreeRangeIter<'a, K, V> {
@@ -575,8 +791,19 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> DoubleEndedIterator for BtreeRangeI
 
         loop {
             if !self.include_right {
+                let entering = match self.right {
+                    Some(Internal { depth, .. }) => Some(depth + 1),
+                    _ => None,
+                };
                 // TODO: propagate this error
-                self.right = self.right.take()?.next(true, self.manager).unwrap();
+                self.right = self
+                    .right
+                    .take()?
+                    .next(true, self.hint, self.manager)
+                    .unwrap();
+                if let (Some(leaf_depth), Some(Leaf { .. })) = (entering, &self.right) {
+                    self.entered_leaf(true, leaf_depth);
+                }
             }
             // Return None if the next state is None
             self.right.as_ref()?;
@@ -604,6 +831,7 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> DoubleEndedIterator for BtreeRangeI
 
             self.include_right = false;
             if self.right.as_ref().unwrap().get_entry::<K, V>().is_some() {
+                self.remaining -= 1;
                 return self.right.as_ref().map(|s| s.get_entry().unwrap());
             }
         }
@@ -613,7 +841,9 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> DoubleEndedIterator for BtreeRangeI
 fn find_iter_unbounded<'a, K: RedbKey, V: RedbValue>(
     page: PageImpl<'a>,
     mut parent: Option<Box<RangeIterState<'a>>>,
+    depth: usize,
     reverse: bool,
+    hint: PageHint,
     manager: &'a TransactionalMemory,
 ) -> Result<Option<RangeIterState<'a>>> {
     let node_mem = page.memory();
@@ -630,6 +860,7 @@ fn find_iter_unbounded<'a, K: RedbKey, V: RedbValue>(
             }))
         }
         BRANCH => {
+            manager.hint_page(&page, PageHint::Branch);
             let accessor = BranchAccessor::new(&page, K::fixed_width());
             let child_index = if reverse {
                 accessor.count_children() - 1
@@ -637,7 +868,7 @@ fn find_iter_unbounded<'a, K: RedbKey, V: RedbValue>(
                 0
             };
             let child_page_number = accessor.child_page(child_index).unwrap();
-            let child_page = manager.get_page(child_page_number)?;
+            let child_page = manager.get_page_extended(child_page_number, hint)?;
             let direction = if reverse { -1isize } else { 1 };
             parent = Some(Box::new(Internal {
                 page,
@@ -646,21 +877,26 @@ fn find_iter_unbounded<'a, K: RedbKey, V: RedbValue>(
                 child: (isize::try_from(child_index).unwrap() + direction)
                     .try_into()
                     .unwrap(),
+                depth,
                 parent,
             }));
-            find_iter_unbounded::<K, V>(child_page, parent, reverse, manager)
+            find_iter_unbounded::<K, V>(child_page, parent, depth + 1, reverse, hint, manager)
         }
         _ => unreachable!(),
     }
 }
 
 // Returns a bool indicating whether the first entry pointed to by the state is included in the
-// queried range
+// queried range. The number of entries which precede the range is added to `rank`
+#[allow(clippy::too_many_arguments)]
 fn find_iter_left<'a, K: RedbKey, V: RedbValue>(
     page: PageImpl<'a>,
     mut parent: Option<Box<RangeIterState<'a>>>,
+    depth: usize,
     query: &[u8],
     include_query: bool,
+    rank: &mut u64,
+    hint: PageHint,
     manager: &'a TransactionalMemory,
 ) -> Result<(bool, Option<RangeIterState<'a>>)> {
     let node_mem = page.memory();
@@ -668,6 +904,10 @@ fn find_iter_left<'a, K: RedbKey, V: RedbValue>(
         LEAF => {
             let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
             let (mut position, found) = accessor.position::<K>(query);
+            *rank += position as u64;
+            if found && !include_query {
+                *rank += 1;
+            }
             let include = if position < accessor.num_pairs() {
                 include_query || !found
             } else {
@@ -686,29 +926,48 @@ fn find_iter_left<'a, K: RedbKey, V: RedbValue>(
             Ok((include, Some(result)))
         }
         BRANCH => {
+            manager.hint_page(&page, PageHint::Branch);
             let accessor = BranchAccessor::new(&page, K::fixed_width());
             let (child_index, child_page_number) = accessor.child_for_key::<K>(query);
-            let child_page = manager.get_page(child_page_number)?;
+            *rank += (0..child_index)
+                .map(|i| accessor.child_entries(i).unwrap())
+                .sum::<u64>();
+            let child_page = manager.get_page_extended(child_page_number, hint)?;
             if child_index < accessor.count_children() - 1 {
                 parent = Some(Box::new(Internal {
                     page,
                     fixed_key_size: K::fixed_width(),
                     fixed_value_size: V::fixed_width(),
                     child: child_index + 1,
+                    depth,
                     parent,
                 }));
             }
-            find_iter_left::<K, V>(child_page, parent, query, include_query, manager)
+            find_iter_left::<K, V>(
+                child_page,
+                parent,
+                depth + 1,
+                query,
+                include_query,
+                rank,
+                hint,
+                manager,
+            )
         }
         _ => unreachable!(),
     }
 }
 
+// The number of entries up to the end of the range is added to `rank`
+#[allow(clippy::too_many_arguments)]
 fn find_iter_right<'a, K: RedbKey, V: RedbValue>(
     page: PageImpl<'a>,
     mut parent: Option<Box<RangeIterState<'a>>>,
+    depth: usize,
     query: &[u8],
     include_query: bool,
+    rank: &mut u64,
+    hint: PageHint,
     manager: &'a TransactionalMemory,
 ) -> Result<(bool, Option<RangeIterState<'a>>)> {
     let node_mem = page.memory();
@@ -716,6 +975,10 @@ fn find_iter_right<'a, K: RedbKey, V: RedbValue>(
         LEAF => {
             let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
             let (mut position, found) = accessor.position::<K>(query);
+            *rank += position as u64;
+            if found && include_query {
+                *rank += 1;
+            }
             let include = if position < accessor.num_pairs() {
                 include_query && found
             } else {
@@ -734,19 +997,33 @@ fn find_iter_right<'a, K: RedbKey, V: RedbValue>(
             Ok((include, Some(result)))
         }
         BRANCH => {
+            manager.hint_page(&page, PageHint::Branch);
             let accessor = BranchAccessor::new(&page, K::fixed_width());
             let (child_index, child_page_number) = accessor.child_for_key::<K>(query);
-            let child_page = manager.get_page(child_page_number)?;
+            *rank += (0..child_index)
+                .map(|i| accessor.child_entries(i).unwrap())
+                .sum::<u64>();
+            let child_page = manager.get_page_extended(child_page_number, hint)?;
             if child_index > 0 && accessor.child_page(child_index - 1).is_some() {
                 parent = Some(Box::new(Internal {
                     page,
                     fixed_key_size: K::fixed_width(),
                     fixed_value_size: V::fixed_width(),
                     child: child_index - 1,
+                    depth,
                     parent,
                 }));
             }
-            find_iter_right::<K, V>(child_page, parent, query, include_query, manager)
+            find_iter_right::<K, V>(
+                child_page,
+                parent,
+                depth + 1,
+                query,
+                include_query,
+                rank,
+                hint,
+                manager,
+            )
         }
         _ => unreachable!(),
     }
@@ -1,11 +1,11 @@
 use crate::tree_store::btree_base::{
-    branch_checksum, leaf_checksum, BranchAccessor, BranchBuilder, BranchMutator, Checksum,
-    FreePolicy, LeafAccessor, LeafBuilder, LeafMutator, BRANCH, LEAF,
+    branch_checksum, leaf_checksum, subtree_entries, BranchAccessor, BranchBuilder, BranchMutator,
+    Checksum, FreePolicy, LeafAccessor, LeafBuilder, LeafMutator, BRANCH, LEAF,
 };
 use crate::tree_store::btree_mutator::DeletionResult::{
     DeletedBranch, DeletedLeaf, PartialBranch, PartialLeaf, Subtree,
 };
-use crate::tree_store::page_store::{ChecksumType, Page, PageImpl};
+use crate::tree_store::page_store::{ChecksumType, Page, PageImpl, PageMut};
 use crate::tree_store::{AccessGuardMut, PageNumber, TransactionalMemory};
 use crate::types::{RedbKey, RedbValue};
 use crate::{AccessGuard, Result};
@@ -14,8 +14,8 @@ use std::marker::PhantomData;
 
 #[derive(Debug)]
 enum DeletionResult {
-    // A proper subtree
-    Subtree(PageNumber, Checksum),
+    // A proper subtree, and the number of entries in it
+    Subtree(PageNumber, Checksum, u64),
     // A leaf with zero children
     DeletedLeaf,
     // A leaf with fewer entries than desired
@@ -23,7 +23,7 @@ enum DeletionResult {
     // A branch page subtree with fewer children than desired
     PartialBranch(PageNumber, Checksum),
     // Indicates that the branch node was deleted, and includes the only remaining child
-    DeletedBranch(PageNumber, Checksum),
+    DeletedBranch(PageNumber, Checksum, u64),
 }
 
 struct InsertionResult<'a, K: RedbKey, V: RedbValue> {
@@ -31,8 +31,10 @@ struct InsertionResult<'a, K: RedbKey, V: RedbValue> {
     new_root: PageNumber,
     // checksum of the root page
     root_checksum: Checksum,
+    // number of entries in the subtree rooted at the root page
+    root_entries: u64,
     // Following sibling, if the root had to be split
-    additional_sibling: Option<(Vec<u8>, PageNumber, Checksum)>,
+    additional_sibling: Option<(Vec<u8>, PageNumber, Checksum, u64)>,
     // The inserted value for .insert_reserve() to use
     inserted_value: AccessGuardMut<'a, K, V>,
     // The previous value, if any
@@ -83,7 +85,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
             let (deletion_result, found) =
                 self.delete_helper(self.mem.get_page(p)?, checksum, K::as_bytes(key).as_ref())?;
             let new_root = match deletion_result {
-                Subtree(page, checksum) => Some((page, checksum)),
+                Subtree(page, checksum, _) => Some((page, checksum)),
                 DeletedLeaf => None,
                 PartialLeaf { deleted_pair } => {
                     let page = self.mem.get_page(p)?;
@@ -100,7 +102,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     Some((page.get_page_number(), self.checksum_helper(&page)))
                 }
                 PartialBranch(page_number, checksum) => Some((page_number, checksum)),
-                DeletedBranch(remaining_child, checksum) => Some((remaining_child, checksum)),
+                DeletedBranch(remaining_child, checksum, _) => Some((remaining_child, checksum)),
             };
             *self.root = new_root;
             Ok(found)
@@ -115,20 +117,33 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         &mut self,
         key: &K::SelfType<'_>,
         value: &V::SelfType<'_>,
+    ) -> Result<(Option<AccessGuard<'a, V>>, AccessGuardMut<'a, K, V>)> {
+        self.insert_bytes(key, V::as_bytes(value).as_ref())
+    }
+
+    // Like insert(), but takes the value in its serialized form
+    // Safety: caller must ensure that no references to uncommitted pages in this tree exist
+    #[allow(clippy::type_complexity)]
+    pub(crate) unsafe fn insert_bytes(
+        &mut self,
+        key: &K::SelfType<'_>,
+        value_bytes: &[u8],
     ) -> Result<(Option<AccessGuard<'a, V>>, AccessGuardMut<'a, K, V>)> {
         let (new_root, old_value, guard) = if let Some((p, checksum)) = *self.root {
             let result = self.insert_helper(
                 self.mem.get_page(p)?,
                 checksum,
                 K::as_bytes(key).as_ref(),
-                V::as_bytes(value).as_ref(),
+                value_bytes,
             )?;
 
-            let new_root = if let Some((key, page2, page2_checksum)) = result.additional_sibling {
+            let new_root = if let Some((key, page2, page2_checksum, page2_entries)) =
+                result.additional_sibling
+            {
                 let mut builder = BranchBuilder::new(self.mem, 2, K::fixed_width());
-                builder.push_child(result.new_root, result.root_checksum);
+                builder.push_child(result.new_root, result.root_checksum, result.root_entries);
                 builder.push_key(&key);
-                builder.push_child(page2, page2_checksum);
+                builder.push_child(page2, page2_checksum, page2_entries);
                 let new_page = builder.build()?;
                 (new_page.get_page_number(), self.checksum_helper(&new_page))
             } else {
@@ -137,9 +152,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
             (new_root, result.old_value, result.inserted_value)
         } else {
             let key_bytes = K::as_bytes(key);
-            let value_bytes = V::as_bytes(value);
             let key_bytes = key_bytes.as_ref();
-            let value_bytes = value_bytes.as_ref();
             let mut builder = LeafBuilder::new(self.mem, 1, K::fixed_width(), V::fixed_width());
             builder.push(key_bytes, value_bytes);
             let page = builder.build()?;
@@ -189,10 +202,12 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                         Ok(InsertionResult {
                             new_root: new_page_number,
                             root_checksum: new_page_checksum,
+                            root_entries: 1,
                             additional_sibling: Some((
                                 key.to_vec(),
                                 page.get_page_number(),
                                 page_checksum,
+                                1,
                             )),
                             inserted_value: guard,
                             old_value: None,
@@ -202,10 +217,12 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                         Ok(InsertionResult {
                             new_root: page.get_page_number(),
                             root_checksum: page_checksum,
+                            root_entries: 1,
                             additional_sibling: Some((
                                 split_key,
                                 new_page_number,
                                 new_page_checksum,
+                                1,
                             )),
                             inserted_value: guard,
                             old_value: None,
@@ -242,10 +259,12 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     let offset = new_page_accessor.offset_of_value(position).unwrap();
                     drop(new_page_accessor);
                     let new_checksum = self.checksum_helper(&page_mut);
+                    let new_entries = self.entries_helper(&page_mut);
                     let guard = AccessGuardMut::new(key, page_mut, offset, value.len(), self.mem);
                     return Ok(InsertionResult {
                         new_root: page_number,
                         root_checksum: new_checksum,
+                        root_entries: new_entries,
                         additional_sibling: None,
                         inserted_value: guard,
                         old_value: existing_value,
@@ -296,6 +315,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
 
                     let new_page_number = new_page.get_page_number();
                     let new_page_checksum = self.checksum_helper(&new_page);
+                    let new_page_entries = self.entries_helper(&new_page);
                     let accessor =
                         LeafAccessor::new(new_page.memory(), K::fixed_width(), V::fixed_width());
                     let offset = accessor.offset_of_value(position).unwrap();
@@ -304,6 +324,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     InsertionResult {
                         new_root: new_page_number,
                         root_checksum: new_page_checksum,
+                        root_entries: new_page_entries,
                         additional_sibling: None,
                         inserted_value: guard,
                         old_value: existing_value,
@@ -334,8 +355,10 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
 
                     let new_page_number = new_page1.get_page_number();
                     let new_page_checksum = self.checksum_helper(&new_page1);
+                    let new_page_entries = self.entries_helper(&new_page1);
                     let new_page_number2 = new_page2.get_page_number();
                     let new_page2_checksum = self.checksum_helper(&new_page2);
+                    let new_page2_entries = self.entries_helper(&new_page2);
                     let accessor =
                         LeafAccessor::new(new_page1.memory(), K::fixed_width(), V::fixed_width());
                     let division = accessor.num_pairs();
@@ -360,7 +383,13 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     InsertionResult {
                         new_root: new_page_number,
                         root_checksum: new_page_checksum,
-                        additional_sibling: Some((split_key, new_page_number2, new_page2_checksum)),
+                        root_entries: new_page_entries,
+                        additional_sibling: Some((
+                            split_key,
+                            new_page_number2,
+                            new_page2_checksum,
+                            new_page2_entries,
+                        )),
                         inserted_value: guard,
                         old_value: existing_value,
                     }
@@ -370,6 +399,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 let accessor = BranchAccessor::new(&page, K::fixed_width());
                 let (child_index, child_page) = accessor.child_for_key::<K>(key);
                 let child_checksum = accessor.child_checksum(child_index).unwrap();
+                let child_entries = accessor.child_entries(child_index).unwrap();
                 let sub_result =
                     self.insert_helper(self.mem.get_page(child_page)?, child_checksum, key, value)?;
 
@@ -378,11 +408,13 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     // when checksums are disabled
                     if sub_result.new_root == child_page
                         && sub_result.root_checksum == child_checksum
+                        && sub_result.root_entries == child_entries
                     {
                         // NO-OP. One of our descendants is uncommitted, so there was no change
                         return Ok(InsertionResult {
                             new_root: page.get_page_number(),
                             root_checksum: self.checksum_helper(&page),
+                            root_entries: self.entries_helper(&page),
                             additional_sibling: None,
                             inserted_value: sub_result.inserted_value,
                             old_value: sub_result.old_value,
@@ -398,10 +430,12 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                             child_index,
                             sub_result.new_root,
                             sub_result.root_checksum,
+                            sub_result.root_entries,
                         );
                         return Ok(InsertionResult {
                             new_root: mutpage.get_page_number(),
                             root_checksum: self.checksum_helper(&mutpage),
+                            root_entries: self.entries_helper(&mutpage),
                             additional_sibling: None,
                             inserted_value: sub_result.inserted_value,
                             old_value: sub_result.old_value,
@@ -413,34 +447,44 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 let mut builder =
                     BranchBuilder::new(self.mem, accessor.count_children() + 1, K::fixed_width());
                 if child_index == 0 {
-                    builder.push_child(sub_result.new_root, sub_result.root_checksum);
-                    if let Some((ref index_key2, page2, page2_checksum)) =
+                    builder.push_child(
+                        sub_result.new_root,
+                        sub_result.root_checksum,
+                        sub_result.root_entries,
+                    );
+                    if let Some((ref index_key2, page2, page2_checksum, page2_entries)) =
                         sub_result.additional_sibling
                     {
                         builder.push_key(index_key2);
-                        builder.push_child(page2, page2_checksum);
+                        builder.push_child(page2, page2_checksum, page2_entries);
                     }
                 } else {
                     builder.push_child(
                         accessor.child_page(0).unwrap(),
                         accessor.child_checksum(0).unwrap(),
+                        accessor.child_entries(0).unwrap(),
                     );
                 }
                 for i in 1..accessor.count_children() {
                     if let Some(key) = accessor.key(i - 1) {
                         builder.push_key(key);
                         if i == child_index {
-                            builder.push_child(sub_result.new_root, sub_result.root_checksum);
-                            if let Some((ref index_key2, page2, page2_checksum)) =
+                            builder.push_child(
+                                sub_result.new_root,
+                                sub_result.root_checksum,
+                                sub_result.root_entries,
+                            );
+                            if let Some((ref index_key2, page2, page2_checksum, page2_entries)) =
                                 sub_result.additional_sibling
                             {
                                 builder.push_key(index_key2);
-                                builder.push_child(page2, page2_checksum);
+                                builder.push_child(page2, page2_checksum, page2_entries);
                             }
                         } else {
                             builder.push_child(
                                 accessor.child_page(i).unwrap(),
                                 accessor.child_checksum(i).unwrap(),
+                                accessor.child_entries(i).unwrap(),
                             );
                         }
                     } else {
@@ -453,10 +497,12 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     InsertionResult {
                         new_root: new_page1.get_page_number(),
                         root_checksum: self.checksum_helper(&new_page1),
+                        root_entries: self.entries_helper(&new_page1),
                         additional_sibling: Some((
                             split_key.to_vec(),
                             new_page2.get_page_number(),
                             self.checksum_helper(&new_page2),
+                            self.entries_helper(&new_page2),
                         )),
                         inserted_value: sub_result.inserted_value,
                         old_value: sub_result.old_value,
@@ -466,6 +512,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     InsertionResult {
                         new_root: new_page.get_page_number(),
                         root_checksum: self.checksum_helper(&new_page),
+                        root_entries: self.entries_helper(&new_page),
                         additional_sibling: None,
                         inserted_value: sub_result.inserted_value,
                         old_value: sub_result.old_value,
@@ -495,7 +542,8 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
         let (position, found) = accessor.position::<K>(key);
         if !found {
-            return Ok((Subtree(page.get_page_number(), checksum), None));
+            let entries = accessor.num_pairs() as u64;
+            return Ok((Subtree(page.get_page_number(), checksum, entries), None));
         }
         let new_kv_bytes = accessor.length_of_pairs(0, accessor.num_pairs())
             - accessor.length_of_pairs(position, position + 1);
@@ -522,6 +570,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
             let mut mutator = LeafMutator::new(&mut temp, K::fixed_width(), V::fixed_width());
             mutator.remove(position);
             let checksum = self.checksum_helper(&temp);
+            let entries = self.entries_helper(&temp);
             let temp_page_number = temp.get_page_number();
             drop(temp);
             self.mem.free(temp_page_number);
@@ -534,7 +583,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 K::fixed_width(),
                 self.mem,
             );
-            return Ok((Subtree(page_number, checksum), Some(guard)));
+            return Ok((Subtree(page_number, checksum, entries), Some(guard)));
         }
 
         let result = if accessor.num_pairs() == 1 {
@@ -560,7 +609,11 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 builder.push(entry.key(), entry.value());
             }
             let new_page = builder.build()?;
-            Subtree(new_page.get_page_number(), self.checksum_helper(&new_page))
+            Subtree(
+                new_page.get_page_number(),
+                self.checksum_helper(&new_page),
+                self.entries_helper(&new_page),
+            )
         };
         let free_on_drop = if !uncommitted || matches!(self.free_policy, FreePolicy::Never) {
             // Won't be freed until the end of the transaction, so returning the page
@@ -582,8 +635,8 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
     }
 
     fn finalize_branch_builder(&self, builder: BranchBuilder<'_, '_>) -> Result<DeletionResult> {
-        let result = if let Some((only_child, checksum)) = builder.to_single_child() {
-            DeletedBranch(only_child, checksum)
+        let result = if let Some((only_child, checksum, entries)) = builder.to_single_child() {
+            DeletedBranch(only_child, checksum, entries)
         } else {
             // TODO: can we optimize away this page allocation?
             // The PartialInternal gets returned, and then the caller has to merge it immediately
@@ -594,7 +647,11 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
             if accessor.total_length() < self.mem.get_page_size() / 3 {
                 PartialBranch(new_page.get_page_number(), self.checksum_helper(&new_page))
             } else {
-                Subtree(new_page.get_page_number(), self.checksum_helper(&new_page))
+                Subtree(
+                    new_page.get_page_number(),
+                    self.checksum_helper(&new_page),
+                    self.entries_helper(&new_page),
+                )
             }
         };
         Ok(result)
@@ -616,6 +673,10 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         }
     }
 
+    fn entries_helper<T: Page>(&self, page: &T) -> u64 {
+        subtree_entries(page, K::fixed_width(), V::fixed_width())
+    }
+
     // Safety: caller must ensure that no references to uncommitted pages in this table exist
     unsafe fn delete_branch_helper(
         &mut self,
@@ -630,35 +691,55 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         let (result, found) =
             self.delete_helper(self.mem.get_page(child_page_number)?, child_checksum, key)?;
         if found.is_none() {
-            return Ok((Subtree(original_page_number, checksum), None));
+            let entries = accessor.total_entries();
+            return Ok((Subtree(original_page_number, checksum, entries), None));
         }
-        if let Subtree(new_child, new_child_checksum) = result {
-            let (result_page, result_checksum) = if self.mem.uncommitted(original_page_number) {
-                drop(page);
-                // Safety: Caller guarantees there are no references to uncommitted pages,
-                // and we just dropped our reference to it on the line above
-                let mut mutpage = self.mem.get_page_mut(original_page_number)?;
-                let mut mutator = BranchMutator::new(&mut mutpage);
-                mutator.write_child_page(child_index, new_child, new_child_checksum);
-                (original_page_number, self.checksum_helper(&mutpage))
-            } else {
-                let mut builder =
-                    BranchBuilder::new(self.mem, accessor.count_children(), K::fixed_width());
-                builder.push_all(&accessor);
-                builder.replace_child(child_index, new_child, new_child_checksum);
-                let new_page = builder.build()?;
-                self.free_policy
-                    .conditional_free(original_page_number, self.freed, self.mem);
-                (new_page.get_page_number(), self.checksum_helper(&new_page))
-            };
-            return Ok((Subtree(result_page, result_checksum), found));
+        if let Subtree(new_child, new_child_checksum, new_child_entries) = result {
+            let (result_page, result_checksum, result_entries) =
+                if self.mem.uncommitted(original_page_number) {
+                    drop(page);
+                    // Safety: Caller guarantees there are no references to uncommitted pages,
+                    // and we just dropped our reference to it on the line above
+                    let mut mutpage = self.mem.get_page_mut(original_page_number)?;
+                    let mut mutator = BranchMutator::new(&mut mutpage);
+                    mutator.write_child_page(
+                        child_index,
+                        new_child,
+                        new_child_checksum,
+                        new_child_entries,
+                    );
+                    (
+                        original_page_number,
+                        self.checksum_helper(&mutpage),
+                        self.entries_helper(&mutpage),
+                    )
+                } else {
+                    let mut builder =
+                        BranchBuilder::new(self.mem, accessor.count_children(), K::fixed_width());
+                    builder.push_all(&accessor);
+                    builder.replace_child(
+                        child_index,
+                        new_child,
+                        new_child_checksum,
+                        new_child_entries,
+                    );
+                    let new_page = builder.build()?;
+                    self.free_policy
+                        .conditional_free(original_page_number, self.freed, self.mem);
+                    (
+                        new_page.get_page_number(),
+                        self.checksum_helper(&new_page),
+                        self.entries_helper(&new_page),
+                    )
+                };
+            return Ok((Subtree(result_page, result_checksum, result_entries), found));
         }
 
         // Child is requesting to be merged with a sibling
         let mut builder = BranchBuilder::new(self.mem, accessor.count_children(), K::fixed_width());
 
         let final_result = match result {
-            Subtree(_, _) => {
+            Subtree(..) => {
                 // Handled in the if above
                 unreachable!();
             }
@@ -670,6 +751,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     builder.push_child(
                         accessor.child_page(i).unwrap(),
                         accessor.child_checksum(i).unwrap(),
+                        accessor.child_entries(i).unwrap(),
                     );
                 }
                 let end = if child_index == accessor.count_children() - 1 {
@@ -720,6 +802,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                         child_index,
                         new_page.get_page_number(),
                         self.checksum_helper(&new_page),
+                        self.entries_helper(&new_page),
                     );
 
                     let result = self.finalize_branch_builder(builder)?;
@@ -739,6 +822,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     }
                     let page_number = accessor.child_page(i).unwrap();
                     let page_checksum = accessor.child_checksum(i).unwrap();
+                    let page_entries = accessor.child_entries(i).unwrap();
                     if i == merge_with {
                         let mut child_builder = LeafBuilder::new(
                             self.mem,
@@ -762,16 +846,19 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                             builder.push_child(
                                 new_page1.get_page_number(),
                                 self.checksum_helper(&new_page1),
+                                self.entries_helper(&new_page1),
                             );
                             builder.push_child(
                                 new_page2.get_page_number(),
                                 self.checksum_helper(&new_page2),
+                                self.entries_helper(&new_page2),
                             );
                         } else {
                             let new_page = child_builder.build()?;
                             builder.push_child(
                                 new_page.get_page_number(),
                                 self.checksum_helper(&new_page),
+                                self.entries_helper(&new_page),
                             );
                         }
 
@@ -780,7 +867,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                             builder.push_key(accessor.key(merged_key_index).unwrap());
                         }
                     } else {
-                        builder.push_child(page_number, page_checksum);
+                        builder.push_child(page_number, page_checksum, page_entries);
                         if i < accessor.count_children() - 1 {
                             builder.push_key(accessor.key(i).unwrap());
                         }
@@ -798,7 +885,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
 
                 result
             }
-            DeletedBranch(only_grandchild, grandchild_checksum) => {
+            DeletedBranch(only_grandchild, grandchild_checksum, grandchild_entries) => {
                 let merge_with = if child_index == 0 { 1 } else { child_index - 1 };
                 let merge_with_page = self
                     .mem
@@ -811,6 +898,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     }
                     let page_number = accessor.child_page(i).unwrap();
                     let page_checksum = accessor.child_checksum(i).unwrap();
+                    let page_entries = accessor.child_entries(i).unwrap();
                     if i == merge_with {
                         let mut child_builder = BranchBuilder::new(
                             self.mem,
@@ -819,30 +907,41 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                         );
                         let separator_key = accessor.key(min(child_index, merge_with)).unwrap();
                         if child_index < merge_with {
-                            child_builder.push_child(only_grandchild, grandchild_checksum);
+                            child_builder.push_child(
+                                only_grandchild,
+                                grandchild_checksum,
+                                grandchild_entries,
+                            );
                             child_builder.push_key(separator_key);
                         }
                         child_builder.push_all(&merge_with_accessor);
                         if child_index > merge_with {
                             child_builder.push_key(separator_key);
-                            child_builder.push_child(only_grandchild, grandchild_checksum);
+                            child_builder.push_child(
+                                only_grandchild,
+                                grandchild_checksum,
+                                grandchild_entries,
+                            );
                         }
                         if child_builder.should_split() {
                             let (new_page1, separator, new_page2) = child_builder.build_split()?;
                             builder.push_child(
                                 new_page1.get_page_number(),
                                 self.checksum_helper(&new_page1),
+                                self.entries_helper(&new_page1),
                             );
                             builder.push_key(separator);
                             builder.push_child(
                                 new_page2.get_page_number(),
                                 self.checksum_helper(&new_page2),
+                                self.entries_helper(&new_page2),
                             );
                         } else {
                             let new_page = child_builder.build()?;
                             builder.push_child(
                                 new_page.get_page_number(),
                                 self.checksum_helper(&new_page),
+                                self.entries_helper(&new_page),
                             );
                         }
 
@@ -851,7 +950,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                             builder.push_key(accessor.key(merged_key_index).unwrap());
                         }
                     } else {
-                        builder.push_child(page_number, page_checksum);
+                        builder.push_child(page_number, page_checksum, page_entries);
                         if i < accessor.count_children() - 1 {
                             builder.push_key(accessor.key(i).unwrap());
                         }
@@ -882,6 +981,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     }
                     let page_number = accessor.child_page(i).unwrap();
                     let page_checksum = accessor.child_checksum(i).unwrap();
+                    let page_entries = accessor.child_entries(i).unwrap();
                     if i == merge_with {
                         let mut child_builder = BranchBuilder::new(
                             self.mem,
@@ -904,17 +1004,20 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                             builder.push_child(
                                 new_page1.get_page_number(),
                                 self.checksum_helper(&new_page1),
+                                self.entries_helper(&new_page1),
                             );
                             builder.push_key(separator);
                             builder.push_child(
                                 new_page2.get_page_number(),
                                 self.checksum_helper(&new_page2),
+                                self.entries_helper(&new_page2),
                             );
                         } else {
                             let new_page = child_builder.build()?;
                             builder.push_child(
                                 new_page.get_page_number(),
                                 self.checksum_helper(&new_page),
+                                self.entries_helper(&new_page),
                             );
                         }
 
@@ -923,7 +1026,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                             builder.push_key(accessor.key(merged_key_index).unwrap());
                         }
                     } else {
-                        builder.push_child(page_number, page_checksum);
+                        builder.push_child(page_number, page_checksum, page_entries);
                         if i < accessor.count_children() - 1 {
                             builder.push_key(accessor.key(i).unwrap());
                         }
@@ -968,3 +1071,190 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         }
     }
 }
+
+// Moves the pages of a btree into free space closer to the start of the file. Pages are copied on
+// write, so that committed pages are never modified
+pub(crate) struct RelocateHelper<'a, 'b> {
+    mem: &'a TransactionalMemory,
+    freed: &'b mut Vec<PageNumber>,
+    relocated: bool,
+    rewrite_all: bool,
+}
+
+impl<'a, 'b> RelocateHelper<'a, 'b> {
+    pub(crate) fn new(mem: &'a TransactionalMemory, freed: &'b mut Vec<PageNumber>) -> Self {
+        Self {
+            mem,
+            freed,
+            relocated: false,
+            rewrite_all: false,
+        }
+    }
+
+    // Creates a helper which does not move pages to lower addresses, but instead rewrites every
+    // page, so that all checksums are recomputed with the current checksum type
+    pub(crate) fn rewrite_all(
+        mem: &'a TransactionalMemory,
+        freed: &'b mut Vec<PageNumber>,
+    ) -> Self {
+        Self {
+            mem,
+            freed,
+            relocated: false,
+            rewrite_all: true,
+        }
+    }
+
+    // Returns true if any page was moved to a lower address
+    pub(crate) fn relocated(&self) -> bool {
+        self.relocated
+    }
+
+    // Returns the new root of the tree, if it changed
+    pub(crate) fn relocate(
+        &mut self,
+        root: PageNumber,
+        fixed_key_size: Option<usize>,
+        fixed_value_size: Option<usize>,
+    ) -> Result<Option<(PageNumber, Checksum)>> {
+        self.relocate_helper::<fn(&mut Self, &[u8]) -> Result<Option<Vec<u8>>>>(
+            root,
+            fixed_key_size,
+            fixed_value_size,
+            None,
+        )
+    }
+
+    // Like relocate(), but also calls value_fixup on each value stored in the tree. If value_fixup
+    // returns a new value, which must be the same length as the original, it replaces the original.
+    // This is used to relocate trees that are nested inside values
+    pub(crate) fn relocate_with_values<F: FnMut(&mut Self, &[u8]) -> Result<Option<Vec<u8>>>>(
+        &mut self,
+        root: PageNumber,
+        fixed_key_size: Option<usize>,
+        fixed_value_size: Option<usize>,
+        value_fixup: &mut F,
+    ) -> Result<Option<(PageNumber, Checksum)>> {
+        self.relocate_helper(root, fixed_key_size, fixed_value_size, Some(value_fixup))
+    }
+
+    fn relocate_helper<F: FnMut(&mut Self, &[u8]) -> Result<Option<Vec<u8>>>>(
+        &mut self,
+        page_number: PageNumber,
+        fixed_key_size: Option<usize>,
+        fixed_value_size: Option<usize>,
+        mut value_fixup: Option<&mut F>,
+    ) -> Result<Option<(PageNumber, Checksum)>> {
+        let old_page = self.mem.get_page(page_number)?;
+        let node_type = old_page.memory()[0];
+        let mut child_updates = vec![];
+        let mut value_updates = vec![];
+        match node_type {
+            LEAF => {
+                if let Some(fixup) = value_fixup.as_deref_mut() {
+                    let accessor =
+                        LeafAccessor::new(old_page.memory(), fixed_key_size, fixed_value_size);
+                    for i in 0..accessor.num_pairs() {
+                        let (_, value_range) = accessor.entry_ranges(i).unwrap();
+                        if let Some(new_value) =
+                            fixup(self, &old_page.memory()[value_range.clone()])?
+                        {
+                            assert_eq!(new_value.len(), value_range.len());
+                            value_updates.push((value_range, new_value));
+                        }
+                    }
+                }
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&old_page, fixed_key_size);
+                for i in 0..accessor.count_children() {
+                    let child = accessor.child_page(i).unwrap();
+                    if let Some((new_child, checksum)) = self.relocate_helper(
+                        child,
+                        fixed_key_size,
+                        fixed_value_size,
+                        value_fixup.as_deref_mut(),
+                    )? {
+                        let entries = accessor.child_entries(i).unwrap();
+                        child_updates.push((i, new_child, checksum, entries));
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        drop(old_page);
+        let modified = self.rewrite_all || !child_updates.is_empty() || !value_updates.is_empty();
+        let mut new_page = if let Some(page) = self.move_page(page_number, modified)? {
+            page
+        } else {
+            return Ok(None);
+        };
+
+        for (range, value) in value_updates {
+            new_page.memory_mut()[range].copy_from_slice(&value);
+        }
+        if !child_updates.is_empty() {
+            let mut mutator = BranchMutator::new(&mut new_page);
+            for (i, child, checksum, entries) in child_updates {
+                mutator.write_child_page(i, child, checksum, entries);
+            }
+        }
+
+        let checksum = match node_type {
+            LEAF => leaf_checksum(
+                &new_page,
+                fixed_key_size,
+                fixed_value_size,
+                self.mem.checksum_type(),
+            ),
+            BRANCH => branch_checksum(&new_page, fixed_key_size, self.mem.checksum_type()),
+            _ => unreachable!(),
+        };
+
+        Ok(Some((new_page.get_page_number(), checksum)))
+    }
+
+    // Moves the page to lower free space, if possible, and returns the new page. If `modified` is
+    // true, a writable copy of the page is returned even if it can't be moved
+    pub(crate) fn move_page(
+        &mut self,
+        page_number: PageNumber,
+        modified: bool,
+    ) -> Result<Option<PageMut<'a>>> {
+        let old_page = self.mem.get_page(page_number)?;
+        let page_len = old_page.memory().len();
+        let lower_page = if self.rewrite_all {
+            None
+        } else {
+            self.mem.allocate_lower(page_len, page_number)?
+        };
+        if let Some(mut page) = lower_page {
+            page.memory_mut().copy_from_slice(old_page.memory());
+            drop(old_page);
+            // Safety: the reference to the old page was just dropped
+            unsafe {
+                FreePolicy::Uncommitted.conditional_free(page_number, self.freed, self.mem);
+            }
+            self.relocated = true;
+            Ok(Some(page))
+        } else if !modified {
+            Ok(None)
+        } else if self.mem.uncommitted(page_number) {
+            drop(old_page);
+            // Safety: the page is uncommitted, and the only reference to it was just dropped
+            Ok(Some(unsafe { self.mem.get_page_mut(page_number)? }))
+        } else {
+            // The page is being modified, but there's no lower space for it. Copy it, since it's
+            // committed
+            let mut page = self.mem.allocate(page_len)?;
+            page.memory_mut().copy_from_slice(old_page.memory());
+            drop(old_page);
+            // Safety: the reference to the old page was just dropped
+            unsafe {
+                FreePolicy::Uncommitted.conditional_free(page_number, self.freed, self.mem);
+            }
+            Ok(Some(page))
+        }
+    }
+}
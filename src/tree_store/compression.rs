@@ -0,0 +1,262 @@
+use crate::{Error, Result};
+use std::borrow::Cow;
+use std::cmp::min;
+use std::mem::size_of;
+
+// Values shorter than this are never compressed
+const COMPRESSION_THRESHOLD: usize = 64;
+
+// Every value in a table with compression is prefixed with one of these tags, or with the tag of
+// a value stored in overflow pages (see overflow.rs). Values in other tables are stored as they are
+const UNCOMPRESSED: u8 = 0;
+const LZ: u8 = 1;
+pub(crate) const VALUE_HEADER_SIZE: usize = 1;
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+/// Codec used to compress the values stored in a table
+///
+/// Values shorter than 64 bytes, and values which the codec does not make smaller, are stored
+/// uncompressed. Compression is only supported for variable width value types
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Compression {
+    /// A simple LZ77-style codec, which favors speed over compression ratio
+    Lz,
+}
+
+impl Compression {
+    pub(crate) fn to_byte(compression: Option<Compression>) -> u8 {
+        match compression {
+            None => 0,
+            Some(Compression::Lz) => 1,
+        }
+    }
+
+    pub(crate) fn from_byte(value: u8) -> Result<Option<Compression>> {
+        match value {
+            0 => Ok(None),
+            1 => Ok(Some(Compression::Lz)),
+            _ => Err(Error::Corrupted(format!(
+                "Invalid compression type {value}"
+            ))),
+        }
+    }
+}
+
+// Encodes a value for storage in a table with compression, compressing it with `compression` if
+// that makes it smaller
+pub(crate) fn encode_value(compression: Compression, value: &[u8]) -> Vec<u8> {
+    if value.len() >= COMPRESSION_THRESHOLD {
+        let compressed = match compression {
+            Compression::Lz => lz_compress(value),
+        };
+        let compressed_len = VALUE_HEADER_SIZE + size_of::<u32>() + compressed.len();
+        if compressed_len < VALUE_HEADER_SIZE + value.len() {
+            let mut result = Vec::with_capacity(compressed_len);
+            result.push(LZ);
+            result.extend_from_slice(&u32::try_from(value.len()).unwrap().to_le_bytes());
+            result.extend_from_slice(&compressed);
+            return result;
+        }
+    }
+
+    let mut result = Vec::with_capacity(VALUE_HEADER_SIZE + value.len());
+    result.push(UNCOMPRESSED);
+    result.extend_from_slice(value);
+    result
+}
+
+// Header of a value which is stored uncompressed, and immediately follows it
+pub(crate) const UNCOMPRESSED_HEADER: [u8; VALUE_HEADER_SIZE] = [UNCOMPRESSED];
+
+// Decodes a value, which was encoded with encode_value()
+pub(crate) fn decode_value(data: &[u8]) -> Result<Cow<'_, [u8]>> {
+    match data.first() {
+        Some(&UNCOMPRESSED) => Ok(Cow::Borrowed(&data[VALUE_HEADER_SIZE..])),
+        Some(&LZ) if data.len() >= VALUE_HEADER_SIZE + size_of::<u32>() => {
+            let len_bytes = &data[VALUE_HEADER_SIZE..(VALUE_HEADER_SIZE + size_of::<u32>())];
+            let len = u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+            let compressed = &data[(VALUE_HEADER_SIZE + size_of::<u32>())..];
+            Ok(Cow::Owned(lz_decompress(compressed, len)?))
+        }
+        _ => Err(corrupted()),
+    }
+}
+
+fn corrupted() -> Error {
+    Error::Corrupted("Invalid compressed value".to_string())
+}
+
+fn hash(sequence: &[u8]) -> usize {
+    let value = u32::from_le_bytes(sequence[..MIN_MATCH].try_into().unwrap());
+    (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_length(output: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        output.push(255);
+        len -= 255;
+    }
+    output.push(len.try_into().unwrap());
+}
+
+fn read_length(input: &[u8], offset: &mut usize, mut len: usize) -> Result<usize> {
+    if len == 15 {
+        loop {
+            let byte = *input.get(*offset).ok_or_else(corrupted)?;
+            *offset += 1;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+// Writes a sequence of literals, optionally followed by a match of the given (offset, length)
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], back_reference: Option<(usize, usize)>) {
+    let match_len = back_reference.map(|(_, len)| len - MIN_MATCH).unwrap_or(0);
+    let token = (u8::try_from(literals.len().min(15)).unwrap() << 4)
+        | u8::try_from(match_len.min(15)).unwrap();
+    output.push(token);
+    if literals.len() >= 15 {
+        write_length(output, literals.len() - 15);
+    }
+    output.extend_from_slice(literals);
+    if let Some((offset, _)) = back_reference {
+        output.extend_from_slice(&u16::try_from(offset).unwrap().to_le_bytes());
+        if match_len >= 15 {
+            write_length(output, match_len - 15);
+        }
+    }
+}
+
+// Compresses `input` into a sequence of literal runs and back references, using the same block
+// layout as LZ4
+fn lz_compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2);
+    // Position + 1 of the last occurrence of each hashed 4-byte sequence, or 0 if none
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let mut literal_start = 0;
+    let mut position = 0;
+    while position + MIN_MATCH <= input.len() {
+        let slot = hash(&input[position..]);
+        let candidate = table[slot];
+        table[slot] = position + 1;
+        if candidate > 0 {
+            let candidate = candidate - 1;
+            if position - candidate <= MAX_OFFSET
+                && input[candidate..(candidate + MIN_MATCH)]
+                    == input[position..(position + MIN_MATCH)]
+            {
+                let mut match_len = MIN_MATCH;
+                while position + match_len < input.len()
+                    && input[candidate + match_len] == input[position + match_len]
+                {
+                    match_len += 1;
+                }
+                write_sequence(
+                    &mut output,
+                    &input[literal_start..position],
+                    Some((position - candidate, match_len)),
+                );
+                position += match_len;
+                literal_start = position;
+                continue;
+            }
+        }
+        position += 1;
+    }
+    write_sequence(&mut output, &input[literal_start..], None);
+
+    output
+}
+
+fn lz_decompress(input: &[u8], len: usize) -> Result<Vec<u8>> {
+    // Don't trust the length until the value has been decoded
+    let mut output = Vec::with_capacity(min(len, input.len().saturating_mul(255)));
+    let mut offset = 0;
+    while offset < input.len() {
+        let token = input[offset];
+        offset += 1;
+
+        let literals = read_length(input, &mut offset, (token >> 4) as usize)?;
+        if offset + literals > input.len() || output.len() + literals > len {
+            return Err(corrupted());
+        }
+        output.extend_from_slice(&input[offset..(offset + literals)]);
+        offset += literals;
+
+        // The final sequence contains only literals
+        if offset == input.len() {
+            break;
+        }
+
+        if offset + size_of::<u16>() > input.len() {
+            return Err(corrupted());
+        }
+        let distance = u16::from_le_bytes([input[offset], input[offset + 1]]) as usize;
+        offset += size_of::<u16>();
+        let match_len = read_length(input, &mut offset, (token & 0xF) as usize)? + MIN_MATCH;
+        if distance == 0 || distance > output.len() || output.len() + match_len > len {
+            return Err(corrupted());
+        }
+        // The match may overlap the bytes it produces, so copy one byte at a time
+        let start = output.len() - distance;
+        for i in 0..match_len {
+            output.push(output[start + i]);
+        }
+    }
+
+    if output.len() != len {
+        return Err(corrupted());
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_store::compression::{decode_value, encode_value, VALUE_HEADER_SIZE};
+    use crate::Compression;
+
+    fn round_trip(value: &[u8]) -> usize {
+        let encoded = encode_value(Compression::Lz, value);
+        let decoded = decode_value(&encoded).unwrap();
+        assert_eq!(value, decoded.as_ref());
+        encoded.len()
+    }
+
+    #[test]
+    fn lz_round_trip() {
+        assert_eq!(round_trip(&[]), VALUE_HEADER_SIZE);
+        assert_eq!(round_trip(b"short"), VALUE_HEADER_SIZE + 5);
+
+        let json = br#"{"name": "redb", "tags": ["embedded", "database"], "value": 1}"#.repeat(100);
+        assert!(round_trip(&json) < json.len() / 10);
+
+        // Long runs exercise overlapping back references and extended lengths
+        assert!(round_trip(&[7u8; 100_000]) < 1000);
+
+        // Incompressible data is stored as-is
+        let random: Vec<u8> = (0..10_000).map(|_| fastrand::u8(..)).collect();
+        assert_eq!(round_trip(&random), VALUE_HEADER_SIZE + random.len());
+    }
+
+    #[test]
+    fn lz_corrupted() {
+        let json = br#"{"name": "redb", "value": 1}"#.repeat(100);
+        let encoded = encode_value(Compression::Lz, &json);
+        assert!(decode_value(&encoded[..(encoded.len() / 2)]).is_err());
+        // Claims to be longer than it is
+        let mut wrong_length = encoded.clone();
+        wrong_length[VALUE_HEADER_SIZE] += 1;
+        assert!(decode_value(&wrong_length).is_err());
+        assert!(decode_value(&[]).is_err());
+        assert!(decode_value(&[2, 0, 0]).is_err());
+        assert!(Compression::from_byte(2).is_err());
+    }
+}
@@ -1,20 +1,34 @@
 mod btree;
 mod btree_base;
+mod btree_builder;
 mod btree_iters;
 mod btree_mutator;
+mod compression;
+mod overflow;
 mod page_store;
 mod table_tree;
 
-pub(crate) use btree::{Btree, BtreeMut, RawBtree};
+pub(crate) use btree::{Btree, BtreeMut, OnRemove, RawBtree};
 pub use btree_base::AccessGuard;
 pub(crate) use btree_base::AccessGuardMut;
 pub(crate) use btree_base::Checksum;
 pub(crate) use btree_base::{LeafAccessor, RawLeafBuilder, BRANCH, LEAF};
+pub(crate) use btree_builder::{copy_tree, copy_tree_with_values};
 pub(crate) use btree_iters::{
-    AllPageNumbersBtreeIter, BtreeDrain, BtreeDrainFilter, BtreeRangeIter,
+    AllPageNumbersBtreeIter, BtreeDrain, BtreeDrainFilter, BtreeRangeIter, EntryGuard,
 };
-pub use page_store::Savepoint;
+pub(crate) use btree_mutator::RelocateHelper;
+pub use compression::Compression;
+pub(crate) use compression::{encode_value, UNCOMPRESSED_HEADER};
+pub use overflow::ValueReader;
+pub(crate) use overflow::{
+    store_stream as store_overflow_stream, store_value as store_overflow_value,
+    tree_overflow_pages, value_pages as overflow_value_pages,
+    value_reader as overflow_value_reader,
+};
+pub use page_store::{CacheStats, Savepoint, StorageBackend};
 pub(crate) use page_store::{
-    Page, PageHint, PageNumber, TransactionalMemory, FILE_FORMAT_VERSION, PAGE_SIZE,
+    ChecksumType, EncryptionKey, Page, PageHint, PageNumber, StorageSource, TransactionalMemory,
+    FILE_FORMAT_VERSION, PAGE_SIZE,
 };
 pub(crate) use table_tree::{FreedTableKey, InternalTableDefinition, TableTree, TableType};
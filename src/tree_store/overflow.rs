@@ -0,0 +1,660 @@
+use crate::tree_store::btree_base::{Checksum, LeafAccessor, LEAF};
+use crate::tree_store::btree_iters::AllPageNumbersBtreeIter;
+use crate::tree_store::btree_mutator::RelocateHelper;
+use crate::tree_store::compression::{self, Compression, UNCOMPRESSED_HEADER};
+use crate::tree_store::page_store::{hash128_with_seed, Page, PageImpl};
+use crate::tree_store::{PageNumber, TransactionalMemory};
+use crate::{Error, Result};
+use std::borrow::Cow;
+use std::cmp::min;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::mem::size_of;
+
+// Tag of a value which is stored in overflow pages. The other tags are defined in compression.rs.
+// Values in tables without compression have no tag, so any such value which looks like a reference
+// is moved into overflow pages, and never mistaken for one
+const OVERFLOW: u8 = 2;
+
+// Encoded values larger than this are moved out of the leaf, into overflow pages
+const OVERFLOW_THRESHOLD: usize = 16 * 1024;
+// Overflow values are split into chunks of at most this size, so that they never require a large
+// contiguous allocation
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Reference to an overflow value, which is stored in the leaf in place of the value:
+// 1 byte: tag
+// 8 bytes: length of the encoded value
+// 16 bytes: checksum of the first index page
+// 8 bytes: first index page
+const LENGTH_OFFSET: usize = size_of::<u8>();
+const CHECKSUM_OFFSET: usize = LENGTH_OFFSET + size_of::<u64>();
+const INDEX_OFFSET: usize = CHECKSUM_OFFSET + size_of::<Checksum>();
+const REFERENCE_SIZE: usize = INDEX_OFFSET + PageNumber::serialized_size();
+
+// The index is a chain of single pages, so that it never requires a large contiguous allocation.
+// Each index page contains:
+// 8 bytes: next index page, or zero if this is the last one
+// 16 bytes: checksum of the next index page, or zero if this is the last one
+// followed by as many entries as fit in the page, one for each chunk, so that chunks can be read
+// and verified independently:
+// 8 bytes: chunk page
+// 16 bytes: checksum of the chunk
+//
+// The checksum of an index page covers its header, and its entries
+const INDEX_HEADER_SIZE: usize = PageNumber::serialized_size() + size_of::<Checksum>();
+const INDEX_ENTRY_SIZE: usize = PageNumber::serialized_size() + size_of::<Checksum>();
+
+// Page and checksum of a chunk
+type ChunkEntry = (PageNumber, Checksum);
+
+// Number of entries in each index page
+fn index_capacity(mem: &TransactionalMemory) -> usize {
+    (mem.get_page_size() - INDEX_HEADER_SIZE) / INDEX_ENTRY_SIZE
+}
+
+struct OverflowReference {
+    len: usize,
+    checksum: Checksum,
+    index: PageNumber,
+}
+
+impl OverflowReference {
+    // Returns None if `data` is a value stored inline
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        if !is_reference(data) {
+            return None;
+        }
+        let len = u64::from_le_bytes(data[LENGTH_OFFSET..CHECKSUM_OFFSET].try_into().unwrap());
+        let checksum =
+            Checksum::from_le_bytes(data[CHECKSUM_OFFSET..INDEX_OFFSET].try_into().unwrap());
+        let index = PageNumber::from_le_bytes(data[INDEX_OFFSET..].try_into().unwrap());
+        Some(Self {
+            len: len.try_into().unwrap(),
+            checksum,
+            index,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(REFERENCE_SIZE);
+        result.push(OVERFLOW);
+        result.extend_from_slice(&u64::try_from(self.len).unwrap().to_le_bytes());
+        result.extend_from_slice(&self.checksum.to_le_bytes());
+        result.extend_from_slice(&self.index.to_le_bytes());
+        result
+    }
+
+    fn num_chunks(&self) -> usize {
+        (self.len + CHUNK_SIZE - 1) / CHUNK_SIZE
+    }
+
+    fn chunk_len(&self, chunk: usize) -> usize {
+        min(CHUNK_SIZE, self.len - chunk * CHUNK_SIZE)
+    }
+
+    fn chunks(&self, mem: &TransactionalMemory) -> Result<Vec<ChunkEntry>> {
+        Ok(self.read_index(mem)?.1)
+    }
+
+    // Returns the index pages, in order, and the entries of all the chunks
+    fn read_index(&self, mem: &TransactionalMemory) -> Result<(Vec<PageNumber>, Vec<ChunkEntry>)> {
+        let capacity = index_capacity(mem);
+        let num_chunks = self.num_chunks();
+        let mut pages = vec![];
+        let mut chunks = Vec::with_capacity(num_chunks);
+        let mut next = Some((self.index, self.checksum));
+        while let Some((page_number, checksum)) = next {
+            let count = min(capacity, num_chunks - chunks.len());
+            let page = mem.get_page(page_number)?;
+            let data = &page.memory()[..(INDEX_HEADER_SIZE + count * INDEX_ENTRY_SIZE)];
+            if hash128_with_seed(data, 0) != checksum {
+                return Err(Error::Corrupted(format!(
+                    "Overflow index at {page_number:?} has an invalid checksum"
+                )));
+            }
+            chunks.extend(
+                data[INDEX_HEADER_SIZE..]
+                    .chunks(INDEX_ENTRY_SIZE)
+                    .map(|entry| {
+                        let (page_number, checksum) = entry.split_at(PageNumber::serialized_size());
+                        (
+                            PageNumber::from_le_bytes(page_number.try_into().unwrap()),
+                            Checksum::from_le_bytes(checksum.try_into().unwrap()),
+                        )
+                    }),
+            );
+            pages.push(page_number);
+            next = if chunks.len() < num_chunks {
+                let (page_number, checksum) =
+                    data[..INDEX_HEADER_SIZE].split_at(PageNumber::serialized_size());
+                Some((
+                    PageNumber::from_le_bytes(page_number.try_into().unwrap()),
+                    Checksum::from_le_bytes(checksum.try_into().unwrap()),
+                ))
+            } else {
+                None
+            };
+        }
+
+        Ok((pages, chunks))
+    }
+}
+
+fn is_reference(data: &[u8]) -> bool {
+    data.len() == REFERENCE_SIZE && data[0] == OVERFLOW
+}
+
+// Returns the page of a chunk, after checking that its first `len` bytes match `checksum`
+fn read_chunk(
+    page_number: PageNumber,
+    checksum: Checksum,
+    len: usize,
+    mem: &TransactionalMemory,
+) -> Result<PageImpl<'_>> {
+    let page = mem.get_page(page_number)?;
+    if hash128_with_seed(&page.memory()[..len], 0) != checksum {
+        return Err(Error::Corrupted(format!(
+            "Overflow chunk at {page_number:?} has an invalid checksum"
+        )));
+    }
+
+    Ok(page)
+}
+
+// Writes `len` bytes read from `value` to chunks, and returns a reference to them.
+// If reading from `value` fails, the chunks which were already written are freed
+fn write_chunks(
+    value: &mut dyn Read,
+    len: usize,
+    mem: &TransactionalMemory,
+) -> Result<OverflowReference> {
+    let mut chunks = vec![];
+    if let Err(err) = write_chunks_helper(value, len, &mut chunks, mem) {
+        for (page_number, _) in chunks {
+            // Safety: the chunks were allocated by this transaction, and nothing references them
+            let freed = unsafe { mem.free_if_uncommitted(page_number) };
+            debug_assert!(freed);
+        }
+        return Err(err);
+    }
+
+    let (index, checksum) = write_index(&chunks, mem)?;
+    Ok(OverflowReference {
+        len,
+        checksum,
+        index,
+    })
+}
+
+fn write_chunks_helper(
+    value: &mut dyn Read,
+    len: usize,
+    chunks: &mut Vec<ChunkEntry>,
+    mem: &TransactionalMemory,
+) -> Result {
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk_len = min(CHUNK_SIZE, remaining);
+        let mut page = mem.allocate(chunk_len)?;
+        let page_number = page.get_page_number();
+        let data = &mut page.memory_mut()[..chunk_len];
+        let result = value.read_exact(data);
+        chunks.push((page_number, hash128_with_seed(data, 0)));
+        result?;
+        remaining -= chunk_len;
+    }
+
+    Ok(())
+}
+
+// Returns the contents of an index page, which links to `next` and contains `chunks`
+fn index_page(next: Option<(PageNumber, Checksum)>, chunks: &[ChunkEntry]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(INDEX_HEADER_SIZE + chunks.len() * INDEX_ENTRY_SIZE);
+    if let Some((page_number, checksum)) = next {
+        result.extend_from_slice(&page_number.to_le_bytes());
+        result.extend_from_slice(&checksum.to_le_bytes());
+    } else {
+        result.resize(INDEX_HEADER_SIZE, 0);
+    }
+    for (page_number, checksum) in chunks {
+        result.extend_from_slice(&page_number.to_le_bytes());
+        result.extend_from_slice(&checksum.to_le_bytes());
+    }
+    result
+}
+
+// Writes the index of `chunks`, and returns its first page and the checksum of that page.
+// The pages are written from last to first, since each one contains the checksum of the next
+fn write_index(chunks: &[ChunkEntry], mem: &TransactionalMemory) -> Result<(PageNumber, Checksum)> {
+    let mut next = None;
+    for entries in chunks.chunks(index_capacity(mem)).rev() {
+        let data = index_page(next, entries);
+        let mut page = mem.allocate(mem.get_page_size())?;
+        page.memory_mut()[..data.len()].copy_from_slice(&data);
+        next = Some((page.get_page_number(), hash128_with_seed(&data, 0)));
+    }
+    Ok(next.unwrap())
+}
+
+// Writes `value` to overflow pages, and returns a reference to it, if it's too large to store in a
+// leaf or could be mistaken for a reference. Otherwise, returns None and `value` is stored as it is.
+// In tables with compression, `value` was produced by compression::encode_value()
+pub(crate) fn store_value(mut value: &[u8], mem: &TransactionalMemory) -> Result<Option<Vec<u8>>> {
+    if value.len() <= OVERFLOW_THRESHOLD && !is_reference(value) {
+        return Ok(None);
+    }
+    let len = value.len();
+    let reference = write_chunks(&mut value, len, mem)?;
+
+    Ok(Some(reference.to_bytes()))
+}
+
+// Moves `value`, which was written in place in a leaf, into overflow pages if it could be mistaken
+// for a reference, and overwrites it with a reference to them. The reference has the same length,
+// so the leaf doesn't change shape. Returns the original value, if it was moved
+pub(crate) fn escape_reference(
+    value: &mut [u8],
+    mem: &TransactionalMemory,
+) -> Result<Option<Vec<u8>>> {
+    if !is_reference(value) {
+        return Ok(None);
+    }
+    let original = value.to_vec();
+    let reference = write_chunks(&mut original.as_slice(), original.len(), mem)?;
+    value.copy_from_slice(&reference.to_bytes());
+
+    Ok(Some(original))
+}
+
+// Like store_value(), but reads the `len` bytes of the value from `value`, and returns the bytes to
+// store in the leaf. Values which are large enough to be stored in overflow pages are copied into
+// them as they're read, without compression
+pub(crate) fn store_stream(
+    value: &mut dyn Read,
+    len: usize,
+    compression: Option<Compression>,
+    mem: &TransactionalMemory,
+) -> Result<Vec<u8>> {
+    let header = header(compression);
+    if header.len() + len <= OVERFLOW_THRESHOLD {
+        let mut buffer = vec![0; len];
+        value.read_exact(&mut buffer)?;
+        let encoded = match compression {
+            Some(compression) => compression::encode_value(compression, &buffer),
+            None => buffer,
+        };
+        return Ok(store_value(&encoded, mem)?.unwrap_or(encoded));
+    }
+    let mut encoded = header.chain(value);
+    let reference = write_chunks(&mut encoded, header.len() + len, mem)?;
+
+    Ok(reference.to_bytes())
+}
+
+// Header of an uncompressed value, which is empty in tables without compression
+fn header(compression: Option<Compression>) -> &'static [u8] {
+    if compression.is_some() {
+        &UNCOMPRESSED_HEADER
+    } else {
+        &[]
+    }
+}
+
+// Returns the encoded value stored in overflow pages, or None if `data` is a value stored inline
+pub(crate) fn read_value(data: &[u8], mem: &TransactionalMemory) -> Result<Option<Vec<u8>>> {
+    let reference = if let Some(reference) = OverflowReference::from_bytes(data) {
+        reference
+    } else {
+        return Ok(None);
+    };
+    let mut result = Vec::with_capacity(reference.len);
+    for (i, (page_number, checksum)) in reference.chunks(mem)?.into_iter().enumerate() {
+        let len = reference.chunk_len(i);
+        let page = read_chunk(page_number, checksum, len, mem)?;
+        result.extend_from_slice(&page.memory()[..len]);
+    }
+
+    Ok(Some(result))
+}
+
+// Decodes a value stored in a table with variable width values, reading it from overflow pages
+// if necessary. Only values in tables with compression are encoded
+pub(crate) fn decode_value<'a>(
+    data: &'a [u8],
+    compression: Option<Compression>,
+    mem: &TransactionalMemory,
+) -> Result<Cow<'a, [u8]>> {
+    match (read_value(data, mem)?, compression) {
+        (Some(encoded), Some(_)) => Ok(Cow::Owned(
+            compression::decode_value(&encoded)?.into_owned(),
+        )),
+        (Some(value), None) => Ok(Cow::Owned(value)),
+        (None, Some(_)) => compression::decode_value(data),
+        (None, None) => Ok(Cow::Borrowed(data)),
+    }
+}
+
+// Returns the overflow pages used by `data`, including the index pages
+pub(crate) fn value_pages(data: &[u8], mem: &TransactionalMemory) -> Result<Vec<PageNumber>> {
+    if let Some(reference) = OverflowReference::from_bytes(data) {
+        let (mut pages, chunks) = reference.read_index(mem)?;
+        pages.extend(chunks.into_iter().map(|(page_number, _)| page_number));
+        Ok(pages)
+    } else {
+        Ok(vec![])
+    }
+}
+
+// Calls `f` with every value in the tree rooted at `root`, which must have variable width values
+fn for_each_value<F: FnMut(&[u8]) -> Result>(
+    root: PageNumber,
+    fixed_key_size: Option<usize>,
+    mem: &TransactionalMemory,
+    mut f: F,
+) -> Result {
+    for page_number in AllPageNumbersBtreeIter::new(root, fixed_key_size, None, mem)? {
+        let page = mem.get_page(page_number)?;
+        if page.memory()[0] == LEAF {
+            let accessor = LeafAccessor::new(page.memory(), fixed_key_size, None);
+            for i in 0..accessor.num_pairs() {
+                f(accessor.entry(i).unwrap().value())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Returns the overflow pages used by all values in the tree rooted at `root`
+pub(crate) fn tree_overflow_pages(
+    root: PageNumber,
+    fixed_key_size: Option<usize>,
+    mem: &TransactionalMemory,
+) -> Result<Vec<PageNumber>> {
+    let mut pages = vec![];
+    for_each_value(root, fixed_key_size, mem, |value| {
+        pages.extend(value_pages(value, mem)?);
+        Ok(())
+    })?;
+
+    Ok(pages)
+}
+
+// Space used by the encoding of the values in a tree, which btree_stats() counts as stored bytes
+// because it only looks at the leaves
+pub(crate) struct ValueStats {
+    // Tags and overflow references, stored in the leaves
+    pub(crate) leaf_header_bytes: usize,
+    pub(crate) overflow_stored_bytes: usize,
+    pub(crate) overflow_metadata_bytes: usize,
+    pub(crate) overflow_fragmented_bytes: usize,
+}
+
+pub(crate) fn tree_value_stats(
+    root: PageNumber,
+    fixed_key_size: Option<usize>,
+    compression: Option<Compression>,
+    mem: &TransactionalMemory,
+) -> Result<ValueStats> {
+    let header_len = header(compression).len();
+    let mut stats = ValueStats {
+        leaf_header_bytes: 0,
+        overflow_stored_bytes: 0,
+        overflow_metadata_bytes: 0,
+        overflow_fragmented_bytes: 0,
+    };
+    for_each_value(root, fixed_key_size, mem, |value| {
+        if let Some(reference) = OverflowReference::from_bytes(value) {
+            stats.leaf_header_bytes += REFERENCE_SIZE;
+            stats.overflow_stored_bytes += reference.len - header_len;
+            stats.overflow_metadata_bytes += header_len;
+            let (index_pages, chunks) = reference.read_index(mem)?;
+            let capacity = index_capacity(mem);
+            for (i, page_number) in index_pages.into_iter().enumerate() {
+                let entries = min(capacity, reference.num_chunks() - i * capacity);
+                let index_bytes = INDEX_HEADER_SIZE + entries * INDEX_ENTRY_SIZE;
+                stats.overflow_metadata_bytes += index_bytes;
+                stats.overflow_fragmented_bytes +=
+                    mem.get_page(page_number)?.memory().len() - index_bytes;
+            }
+            for (i, (page_number, _)) in chunks.into_iter().enumerate() {
+                stats.overflow_fragmented_bytes +=
+                    mem.get_page(page_number)?.memory().len() - reference.chunk_len(i);
+            }
+        } else {
+            stats.leaf_header_bytes += header_len;
+        }
+        Ok(())
+    })?;
+
+    Ok(stats)
+}
+
+// Copies an overflow value from `source_mem` into `mem`, and returns the new reference to it.
+// Returns None if `data` is a value stored inline
+pub(crate) fn copy_value(
+    data: &[u8],
+    source_mem: &TransactionalMemory,
+    mem: &TransactionalMemory,
+) -> Result<Option<Vec<u8>>> {
+    if let Some(reference) = OverflowReference::from_bytes(data) {
+        let mut chunks = vec![];
+        for (page_number, checksum) in reference.chunks(source_mem)? {
+            let source = source_mem.get_page(page_number)?;
+            let mut page = mem.allocate(source.memory().len())?;
+            page.memory_mut().copy_from_slice(source.memory());
+            chunks.push((page.get_page_number(), checksum));
+        }
+        let (index, checksum) = write_index(&chunks, mem)?;
+        let copy = OverflowReference {
+            checksum,
+            index,
+            ..reference
+        };
+        Ok(Some(copy.to_bytes()))
+    } else {
+        Ok(None)
+    }
+}
+
+// Moves the overflow pages of a value into lower free space, where possible, and returns the new
+// reference to it if any page moved
+pub(crate) fn relocate_value(
+    helper: &mut RelocateHelper,
+    data: &[u8],
+    mem: &TransactionalMemory,
+) -> Result<Option<Vec<u8>>> {
+    let reference = if let Some(reference) = OverflowReference::from_bytes(data) {
+        reference
+    } else {
+        return Ok(None);
+    };
+    let (index_pages, mut chunks) = reference.read_index(mem)?;
+    let capacity = index_capacity(mem);
+    let mut moved_chunks = vec![false; index_pages.len()];
+    for (i, (page_number, _)) in chunks.iter_mut().enumerate() {
+        if let Some(page) = helper.move_page(*page_number, false)? {
+            *page_number = page.get_page_number();
+            moved_chunks[i / capacity] = true;
+        }
+    }
+    // An index page must be rewritten if any of its chunks moved, or the next index page moved,
+    // so they're processed from last to first
+    let mut next: Option<(PageNumber, Checksum)> = None;
+    let mut moved_next = false;
+    for (i, page_number) in index_pages.iter().enumerate().rev() {
+        let entries = &chunks[(i * capacity)..min(chunks.len(), (i + 1) * capacity)];
+        let data = index_page(next, entries);
+        let checksum = hash128_with_seed(&data, 0);
+        if let Some(mut page) = helper.move_page(*page_number, moved_chunks[i] || moved_next)? {
+            page.memory_mut()[..data.len()].copy_from_slice(&data);
+            next = Some((page.get_page_number(), checksum));
+            moved_next = true;
+        } else {
+            next = Some((*page_number, checksum));
+            moved_next = false;
+        }
+    }
+    if moved_next {
+        let (index, checksum) = next.unwrap();
+        let moved = OverflowReference {
+            checksum,
+            index,
+            ..reference
+        };
+        Ok(Some(moved.to_bytes()))
+    } else {
+        Ok(None)
+    }
+}
+
+// Returns a reader over a value stored in a table with variable width values. Uncompressed values
+// in overflow pages are read one chunk at a time, and all others are decoded up front
+pub(crate) fn value_reader<'a>(
+    data: &[u8],
+    compression: Option<Compression>,
+    mem: &'a TransactionalMemory,
+) -> Result<ValueReader<'a>> {
+    if let Some(reference) = OverflowReference::from_bytes(data) {
+        let chunks = reference.chunks(mem)?;
+        let (page_number, checksum) = chunks[0];
+        let first = read_chunk(page_number, checksum, reference.chunk_len(0), mem)?;
+        let header = header(compression);
+        if first.memory()[..header.len()] == *header {
+            return Ok(ValueReader {
+                source: ValueSource::Chunked {
+                    mem,
+                    reference,
+                    header_len: header.len(),
+                    chunks,
+                    current: Some((0, first)),
+                },
+                position: 0,
+            });
+        }
+    }
+
+    Ok(ValueReader::new(
+        decode_value(data, compression, mem)?.into_owned(),
+    ))
+}
+
+enum ValueSource<'a> {
+    Buffered(Vec<u8>),
+    Chunked {
+        mem: &'a TransactionalMemory,
+        reference: OverflowReference,
+        // Length of the tag which precedes the value in the chunks
+        header_len: usize,
+        chunks: Vec<ChunkEntry>,
+        // The most recently read chunk
+        current: Option<(usize, PageImpl<'a>)>,
+    },
+}
+
+/// Reader over a value stored in a table, returned by [`crate::ReadableTable::get_reader`]
+///
+/// Large values are read one chunk at a time, so they are never held in memory as a whole
+pub struct ValueReader<'a> {
+    source: ValueSource<'a>,
+    position: u64,
+}
+
+impl<'a> ValueReader<'a> {
+    pub(crate) fn new(value: Vec<u8>) -> Self {
+        Self {
+            source: ValueSource::Buffered(value),
+            position: 0,
+        }
+    }
+
+    /// Returns the length of the value in bytes
+    pub fn len(&self) -> u64 {
+        match &self.source {
+            ValueSource::Buffered(value) => value.len() as u64,
+            ValueSource::Chunked {
+                reference,
+                header_len,
+                ..
+            } => (reference.len - header_len) as u64,
+        }
+    }
+
+    /// Returns `true` if the value is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> Read for ValueReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len() {
+            return Ok(0);
+        }
+        let position: usize = self.position.try_into().unwrap();
+        let read = match &mut self.source {
+            ValueSource::Buffered(value) => {
+                let read = min(buf.len(), value.len() - position);
+                buf[..read].copy_from_slice(&value[position..(position + read)]);
+                read
+            }
+            ValueSource::Chunked {
+                mem,
+                reference,
+                header_len,
+                chunks,
+                current,
+            } => {
+                let offset = *header_len + position;
+                let chunk = offset / CHUNK_SIZE;
+                let chunk_len = reference.chunk_len(chunk);
+                if !matches!(current, Some((i, _)) if *i == chunk) {
+                    let (page_number, checksum) = chunks[chunk];
+                    let page = read_chunk(page_number, checksum, chunk_len, mem).map_err(
+                        |err| match err {
+                            Error::Io(err) => err,
+                            err => io::Error::new(io::ErrorKind::InvalidData, err),
+                        },
+                    )?;
+                    *current = Some((chunk, page));
+                }
+                let (_, page) = current.as_ref().unwrap();
+                let start = offset % CHUNK_SIZE;
+                let read = min(buf.len(), chunk_len - start);
+                buf[..read].copy_from_slice(&page.memory()[start..(start + read)]);
+                read
+            }
+        };
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl<'a> Seek for ValueReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(position) => {
+                self.position = position;
+                return Ok(position);
+            }
+            SeekFrom::End(offset) => (self.len(), offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+        let position = if offset >= 0 {
+            base.checked_add(offset.unsigned_abs())
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        if let Some(position) = position {
+            self.position = position;
+            Ok(position)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ))
+        }
+    }
+}
@@ -7,12 +7,14 @@ use std::collections::HashMap;
 #[cfg(debug_assertions)]
 use std::collections::HashSet;
 use std::fmt::{Debug, Formatter};
+use std::io;
 #[cfg(not(debug_assertions))]
 use std::marker::PhantomData;
 use std::ops::Range;
 use std::sync::Arc;
 #[cfg(debug_assertions)]
 use std::sync::Mutex;
+use std::time::Duration;
 
 // On-disk format is:
 // lowest 20bits: page index within the region
@@ -85,6 +87,16 @@ impl PageNumber {
         let pages = 1u64 << self.page_order;
         pages * (page_size as u64)
     }
+
+    // Returns true if this page starts at a lower address than `other`
+    pub(crate) fn is_before(&self, other: PageNumber) -> bool {
+        if self.region != other.region {
+            return self.region < other.region;
+        }
+        let self_order0 = (self.page_index as u64) << self.page_order;
+        let other_order0 = (other.page_index as u64) << other.page_order;
+        self_order0 < other_order0
+    }
 }
 
 impl Debug for PageNumber {
@@ -239,6 +251,121 @@ impl<'a> Drop for PageMut<'a> {
 pub(crate) enum PageHint {
     None,
     Clean,
+    // The page belongs to a tree which is read by every transaction, such as the table tree, so
+    // should be kept in the cache in preference to other pages
+    Hot,
+    // The page is read by a scan, so is unlikely to be read again soon. It's only cached if it
+    // already was, or if it's later hinted to be a branch page
+    Uncached,
+    // The page is a branch page, needed to reach many other pages, so should be kept in the cache
+    // in preference to leaves, even when it was read by a scan
+    Branch,
+}
+
+/// Statistics about the read cache and I/O of a [`crate::Database`]
+///
+/// Counts are cumulative since the database was opened. They're only collected when the database
+/// is stored in a file or a [`StorageBackend`], and are all zero for mmapped and in-memory
+/// databases
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CacheStats {
+    pub(crate) read_hits: u64,
+    pub(crate) read_misses: u64,
+    pub(crate) read_cache_bytes: usize,
+    pub(crate) write_buffer_bytes: usize,
+    pub(crate) evictions: u64,
+    pub(crate) bytes_read: u64,
+    pub(crate) bytes_written: u64,
+    pub(crate) fsyncs: u64,
+    pub(crate) fsync_time: Duration,
+    pub(crate) max_fsync_time: Duration,
+}
+
+impl CacheStats {
+    /// Number of page reads which were served from the read cache or the write buffer
+    pub fn read_hits(&self) -> u64 {
+        self.read_hits
+    }
+
+    /// Number of page reads which had to read from storage
+    pub fn read_misses(&self) -> u64 {
+        self.read_misses
+    }
+
+    /// Number of bytes currently held in the read cache
+    pub fn read_cache_bytes(&self) -> usize {
+        self.read_cache_bytes
+    }
+
+    /// Number of bytes of written pages which are currently buffered, and not yet written to
+    /// storage
+    pub fn write_buffer_bytes(&self) -> usize {
+        self.write_buffer_bytes
+    }
+
+    /// Number of pages evicted from the read cache to make room for others
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Number of bytes read from storage
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Number of bytes written to storage
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Number of times that storage was synced, with `fsync` or similar
+    pub fn fsyncs(&self) -> u64 {
+        self.fsyncs
+    }
+
+    /// Total time spent syncing storage
+    pub fn fsync_time(&self) -> Duration {
+        self.fsync_time
+    }
+
+    /// Longest time that a single sync of storage took
+    pub fn max_fsync_time(&self) -> Duration {
+        self.max_fsync_time
+    }
+}
+
+/// Storage that a [`crate::Database`] can be stored in, such as a file
+///
+/// See [`crate::Builder::create_with_backend`]
+pub trait StorageBackend: Debug + Send + Sync + 'static {
+    /// Returns the current length of the storage, in bytes
+    fn len(&self) -> Result<u64, io::Error>;
+
+    /// Returns true if the storage is empty
+    fn is_empty(&self) -> Result<bool, io::Error> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Reads `len` bytes, starting at `offset`
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, io::Error>;
+
+    /// Writes `data`, starting at `offset`
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), io::Error>;
+
+    /// Grows or truncates the storage to `len` bytes
+    fn set_len(&self, len: u64) -> Result<(), io::Error>;
+
+    /// Makes all previous writes durable
+    ///
+    /// If `eventual` is true, the writes need only be durable before any later writes are,
+    /// which allows a cheaper write barrier to be used instead of a full sync
+    fn sync_data(&self, eventual: bool) -> Result<(), io::Error>;
+
+    /// Hints that `len` bytes, starting at `offset`, are likely to be read soon, so that they can
+    /// be prefetched
+    ///
+    /// The default implementation does nothing
+    fn readahead(&self, _offset: u64, _len: usize) {}
 }
 
 // TODO simplify this trait. It leaks a lot of details of the two implementations
@@ -281,6 +408,20 @@ pub(super) trait PhysicalStorage: Send + Sync {
 
     // Invalidate any caching of the given range. After this call overlapping reads of the range are allowed
     fn invalidate_cache(&self, offset: u64, len: usize);
+
+    // Hint that the given ranges will be read soon, in the order given. Implementations may start
+    // reading them in the background, and must respect the hint when caching them, but must not
+    // block on the I/O
+    fn prefetch(&self, ranges: &[Range<u64>], hint: PageHint);
+
+    // Returns false if prefetch() does nothing, such as when all pages are already in memory
+    fn can_prefetch(&self) -> bool;
+
+    // Hint how the page at `offset`, which was returned by a previous call to read(), should be
+    // cached. The hint is only known once the page has been read, such as for branch pages
+    fn hint(&self, offset: u64, page: &PageHack, hint: PageHint);
+
+    fn cache_stats(&self) -> CacheStats;
 }
 
 #[cfg(test)]
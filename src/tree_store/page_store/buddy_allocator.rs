@@ -340,6 +340,29 @@ impl<'a> BuddyAllocatorMut<'a> {
         BuddyAllocator::new(self.data).highest_free_order()
     }
 
+    /// data must have been initialized by Self::init_new()
+    ///
+    /// Like alloc(), but returns the free page with the lowest address, splitting a higher order
+    /// page if necessary. alloc() only prefers low addresses among pages of the same order
+    pub(crate) fn alloc_lowest(&mut self, order: usize) -> Option<u64> {
+        if order > self.get_max_order() {
+            return None;
+        }
+        let mut lowest: Option<u64> = None;
+        for free_order in order..=self.get_max_order() {
+            let order_allocator = self.get_order_mut(free_order.try_into().unwrap());
+            if let Some(page) = order_allocator.find_first_unset() {
+                // The first page of the requested order within the free page
+                let page = page << (free_order - order);
+                lowest = Some(lowest.map_or(page, |x| min(x, page)));
+            }
+        }
+        let page = lowest?;
+        self.record_alloc(page, order);
+
+        Some(page)
+    }
+
     /// data must have been initialized by Self::init_new()
     pub(crate) fn alloc(&mut self, order: usize) -> Option<u64> {
         if order > self.get_max_order() {
@@ -1,17 +1,16 @@
 use crate::transaction_tracker::TransactionId;
-use crate::tree_store::page_store::base::{PageHack, PageHackMut, PageHint, PhysicalStorage};
-use crate::tree_store::page_store::file_lock::LockedFile;
+use crate::tree_store::page_store::base::{
+    CacheStats, PageHack, PageHackMut, PageHint, PhysicalStorage, StorageBackend,
+};
 use crate::{Error, Result};
 use std::collections::BTreeMap;
-use std::fs::File;
 use std::io;
 use std::mem;
-use std::ops::{DerefMut, Index, IndexMut};
-#[cfg(any(target_os = "linux", all(unix, not(fuzzing))))]
-use std::os::unix::io::AsRawFd;
+use std::ops::{DerefMut, Index, IndexMut, Range};
 use std::slice::SliceIndex;
-use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 pub(super) struct WritablePage<'a> {
     buffer: &'a Mutex<BTreeMap<u64, Arc<Vec<u8>>>>,
@@ -20,6 +19,19 @@ pub(super) struct WritablePage<'a> {
 }
 
 impl<'a> WritablePage<'a> {
+    // `data` is inserted into `buffer`, at `offset`, when the page is dropped
+    pub(super) fn new(
+        buffer: &'a Mutex<BTreeMap<u64, Arc<Vec<u8>>>>,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Self {
+        Self {
+            buffer,
+            offset,
+            data,
+        }
+    }
+
     pub(super) fn mem(&self) -> &[u8] {
         &self.data
     }
@@ -55,8 +67,103 @@ impl<'a, I: SliceIndex<[u8]>> IndexMut<I> for WritablePage<'a> {
     }
 }
 
+// The maximum usage count of a page in the read cache
+const MAX_USAGE: u8 = 3;
+// Number of bytes following a page read by a scan which are prefetched, since the pages of a tree
+// are often laid out sequentially
+const SCAN_READAHEAD_BYTES: usize = 256 * 1024;
+
+struct CachedPage {
+    data: Arc<Vec<u8>>,
+    // Incremented each time the page is read, and decremented each time the clock hand passes
+    // over it. The page is evicted once the hand finds it at zero
+    usage: AtomicU8,
+}
+
+// A stripe of the read cache. Pages are evicted with the CLOCK algorithm, so that pages which are
+// read repeatedly, such as the roots and branches of trees, are kept in preference to pages which
+// are only read once, such as the leaves visited by a scan
+#[derive(Default)]
+struct ReadCacheStripe {
+    pages: BTreeMap<u64, CachedPage>,
+    // The offset at which the clock hand resumes
+    hand: u64,
+    // Incremented each time a page of the stripe is written or invalidated, so that pages which
+    // were read before then, and may be stale, aren't cached
+    writes: u64,
+}
+
+impl ReadCacheStripe {
+    fn get(&self, offset: u64) -> Option<Arc<Vec<u8>>> {
+        let page = self.pages.get(&offset)?;
+        let _ = page
+            .usage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+                (usage < MAX_USAGE).then_some(usage + 1)
+            });
+        Some(page.data.clone())
+    }
+
+    fn insert(&mut self, offset: u64, data: Arc<Vec<u8>>, hint: PageHint) {
+        // Pages of the trees that every transaction reads start out with the maximum usage, and
+        // branch pages are favored over leaves, since they're needed to reach many other pages
+        let usage = match hint {
+            PageHint::Hot => MAX_USAGE,
+            PageHint::Branch => 1,
+            PageHint::None | PageHint::Clean | PageHint::Uncached => 0,
+        };
+        self.pages.insert(
+            offset,
+            CachedPage {
+                data,
+                usage: AtomicU8::new(usage),
+            },
+        );
+    }
+
+    fn contains(&self, offset: u64) -> bool {
+        self.pages.contains_key(&offset)
+    }
+
+    // Raises the usage of a cached branch page to that of a newly inserted one. Returns false if
+    // the page isn't cached
+    fn promote_branch(&self, offset: u64) -> bool {
+        if let Some(page) = self.pages.get(&offset) {
+            page.usage.fetch_max(1, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn remove(&mut self, offset: u64) -> Option<Arc<Vec<u8>>> {
+        self.pages.remove(&offset).map(|page| page.data)
+    }
+
+    // Evicts the next page that the clock hand finds with a usage of zero
+    fn evict(&mut self) -> Option<Arc<Vec<u8>>> {
+        loop {
+            let (&offset, page) = self
+                .pages
+                .range(self.hand..)
+                .next()
+                .or_else(|| self.pages.iter().next())?;
+            self.hand = offset + 1;
+            let usage = page.usage.load(Ordering::Relaxed);
+            if usage == 0 {
+                return self.remove(offset);
+            }
+            page.usage.store(usage - 1, Ordering::Relaxed);
+        }
+    }
+
+    fn clear(&mut self) -> BTreeMap<u64, CachedPage> {
+        mem::take(&mut self.pages)
+    }
+}
+
 pub(super) struct PagedCachedFile {
-    file: LockedFile,
+    file: Box<dyn StorageBackend>,
     page_size: u64,
     max_read_cache_bytes: usize,
     read_cache_bytes: AtomicUsize,
@@ -64,35 +171,32 @@ pub(super) struct PagedCachedFile {
     write_buffer_bytes: AtomicUsize,
     reads_total: AtomicU64,
     reads_hits: AtomicU64,
+    evictions: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    fsyncs: AtomicU64,
+    fsync_nanos: AtomicU64,
+    max_fsync_nanos: AtomicU64,
     fsync_failed: AtomicBool,
-    read_cache: Vec<RwLock<BTreeMap<u64, Arc<Vec<u8>>>>>,
+    read_cache: Vec<RwLock<ReadCacheStripe>>,
     // TODO: maybe move this cache to WriteTransaction?
     write_buffer: Mutex<BTreeMap<u64, Arc<Vec<u8>>>>,
 }
 
 impl PagedCachedFile {
     pub(super) fn new(
-        file: File,
+        file: Box<dyn StorageBackend>,
         page_size: u64,
         max_read_cache_bytes: usize,
         max_write_buffer_bytes: usize,
     ) -> Result<Self> {
         let mut read_cache = Vec::with_capacity(Self::lock_stripes());
         for _ in 0..Self::lock_stripes() {
-            read_cache.push(RwLock::new(BTreeMap::new()));
-        }
-
-        let lock = LockedFile::new(file)?;
-
-        // Try to flush any pages in the page cache that are out of sync with disk.
-        // See here for why: <https://github.com/cberner/redb/issues/450>
-        #[cfg(target_os = "linux")]
-        unsafe {
-            libc::posix_fadvise64(lock.file().as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+            read_cache.push(RwLock::new(ReadCacheStripe::default()));
         }
 
         Ok(Self {
-            file: lock,
+            file,
             page_size,
             max_read_cache_bytes,
             read_cache_bytes: AtomicUsize::new(0),
@@ -100,6 +204,12 @@ impl PagedCachedFile {
             write_buffer_bytes: AtomicUsize::new(0),
             reads_total: Default::default(),
             reads_hits: Default::default(),
+            evictions: Default::default(),
+            bytes_read: Default::default(),
+            bytes_written: Default::default(),
+            fsyncs: Default::default(),
+            fsync_nanos: Default::default(),
+            max_fsync_nanos: Default::default(),
             fsync_failed: Default::default(),
             read_cache,
             write_buffer: Mutex::new(BTreeMap::new()),
@@ -125,20 +235,173 @@ impl PagedCachedFile {
         self.fsync_failed.store(failed, Ordering::Release);
     }
 
+    fn sync_data(&self, eventual: bool) -> Result {
+        // Disable fsync when fuzzing, since it doesn't test crash consistency
+        #[cfg(not(fuzzing))]
+        {
+            let start = Instant::now();
+            let res = self.file.sync_data(eventual);
+            let nanos: u64 = start.elapsed().as_nanos().try_into().unwrap_or(u64::MAX);
+            self.fsyncs.fetch_add(1, Ordering::Relaxed);
+            self.fsync_nanos.fetch_add(nanos, Ordering::Relaxed);
+            self.max_fsync_nanos.fetch_max(nanos, Ordering::Relaxed);
+            if res.is_err() {
+                self.set_fsync_failed(true);
+            }
+            res?;
+        }
+        #[cfg(fuzzing)]
+        let _ = eventual;
+
+        Ok(())
+    }
+
     fn flush_write_buffer(&self) -> Result {
+        self.flush_write_buffer_with(|pages| {
+            for (offset, buffer) in pages {
+                self.file.write(*offset, buffer.as_slice())?;
+            }
+            Ok(())
+        })
+    }
+
+    // Writes out all the buffered pages with a single call to `write`, so that storage which supports
+    // batching can submit them together
+    pub(super) fn flush_write_buffer_with(
+        &self,
+        write: impl FnOnce(&[(u64, Arc<Vec<u8>>)]) -> io::Result<()>,
+    ) -> Result {
         self.check_fsync_failure()?;
-        let mut write_buffer = std::mem::take(self.write_buffer.lock().unwrap().deref_mut());
+        let write_buffer = std::mem::take(self.write_buffer.lock().unwrap().deref_mut());
         let total_bytes: usize = write_buffer.values().map(|buffer| buffer.len()).sum();
         self.write_buffer_bytes
             .fetch_sub(total_bytes, Ordering::Release);
 
-        for (offset, buffer) in write_buffer.iter() {
-            self.file.write(*offset, buffer)?;
+        let pages: Vec<(u64, Arc<Vec<u8>>)> = write_buffer.into_iter().collect();
+        let result = write(&pages);
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        for (offset, _) in pages.iter() {
+            self.invalidate_written(*offset);
+        }
+        result?;
+        self.bytes_written
+            .fetch_add(total_bytes as u64, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    // Reads the given ranges into the read cache with a single call to `read`, skipping any which
+    // are already cached
+    //
+    // The pages needn't be referenced by a live transaction, so they may be written, or freed and
+    // reallocated, while they're being read, in which case they aren't cached
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    pub(super) fn fill_read_cache(
+        &self,
+        ranges: &[Range<u64>],
+        hint: PageHint,
+        read: impl FnOnce(&[Range<u64>]) -> io::Result<Vec<Vec<u8>>>,
+    ) -> Result {
+        self.check_fsync_failure()?;
+        let mut writes = vec![];
+        let ranges: Vec<Range<u64>> = ranges
+            .iter()
+            .filter(|range| {
+                let cache_slot: usize = (range.start % Self::lock_stripes() as u64)
+                    .try_into()
+                    .unwrap();
+                let (cached, stripe_writes) = {
+                    let stripe = self.read_cache[cache_slot].read().unwrap();
+                    (stripe.contains(range.start), stripe.writes)
+                };
+                if cached || self.write_buffer.lock().unwrap().contains_key(&range.start) {
+                    return false;
+                }
+                writes.push(stripe_writes);
+                true
+            })
+            .cloned()
+            .collect();
+        if ranges.is_empty() {
+            return Ok(());
+        }
+        let buffers = read(&ranges)?;
+        for ((range, buffer), writes) in ranges.iter().zip(buffers).zip(writes) {
+            self.bytes_read
+                .fetch_add(buffer.len() as u64, Ordering::Relaxed);
+            self.insert_read_cache(range.start, Arc::new(buffer), hint, Some(writes));
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn is_cached(&self, offset: u64) -> bool {
+        if self.write_buffer.lock().unwrap().contains_key(&offset) {
+            return true;
+        }
+        let cache_slot: usize = (offset % Self::lock_stripes() as u64).try_into().unwrap();
+        self.read_cache[cache_slot].read().unwrap().contains(offset)
+    }
+
+    // If `writes` is given, the page is only inserted if no page of its stripe has been written or
+    // invalidated since the stripe's count of writes was `writes`
+    fn insert_read_cache(
+        &self,
+        offset: u64,
+        buffer: Arc<Vec<u8>>,
+        hint: PageHint,
+        writes: Option<u64>,
+    ) {
+        let len = buffer.len();
+        let cache_slot: usize = (offset % Self::lock_stripes() as u64).try_into().unwrap();
+        let mut write_lock = self.read_cache[cache_slot].write().unwrap();
+        // Another reader may have cached the page since it was read
+        if write_lock.contains(offset) {
+            return;
+        }
+        if writes.map_or(false, |writes| writes != write_lock.writes) {
+            return;
+        }
+        let cache_size = self.read_cache_bytes.fetch_add(len, Ordering::AcqRel);
+        // Evict before inserting, so that the new page isn't evicted immediately
+        let mut removed = 0;
+        if cache_size + len > self.max_read_cache_bytes {
+            while removed < len {
+                if let Some(v) = write_lock.evict() {
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                    removed += v.len();
+                } else {
+                    break;
+                }
+            }
+        }
+        write_lock.insert(offset, buffer, hint);
+        if removed > 0 {
+            self.read_cache_bytes.fetch_sub(removed, Ordering::AcqRel);
         }
-        write_buffer.clear();
+    }
 
+    // Drops any copy of a page which was written to storage from the read cache. Pages which are
+    // prefetched in the background may have been read before the write, and cached after it
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    fn invalidate_written(&self, offset: u64) {
+        self.invalidate_cache(offset, 0);
+    }
+
+    fn write_to_file(&self, offset: u64, data: &[u8]) -> Result {
+        let result = self.file.write(offset, data);
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        self.invalidate_written(offset);
+        result?;
+        self.bytes_written
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
         Ok(())
     }
+
+    fn readahead(&self, range: Range<u64>) {
+        self.file
+            .readahead(range.start, (range.end - range.start).try_into().unwrap());
+    }
 }
 
 impl PhysicalStorage for PagedCachedFile {
@@ -155,61 +418,26 @@ impl PhysicalStorage for PagedCachedFile {
     unsafe fn resize(&self, len: u64) -> Result {
         // TODO: be more fine-grained about this invalidation
         for slot in 0..self.read_cache.len() {
-            let cache = mem::take(&mut *self.read_cache[slot].write().unwrap());
-            for (_, buffer) in cache {
+            let cache = self.read_cache[slot].write().unwrap().clear();
+            for (_, page) in cache {
                 self.read_cache_bytes
-                    .fetch_sub(buffer.len(), Ordering::Release);
+                    .fetch_sub(page.data.len(), Ordering::Release);
             }
         }
 
-        self.file.file().set_len(len).map_err(Error::from)
+        self.file.set_len(len).map_err(Error::from)
     }
 
     fn flush(&self) -> Result {
         self.check_fsync_failure()?;
         self.flush_write_buffer()?;
-        // Disable fsync when fuzzing, since it doesn't test crash consistency
-        #[cfg(not(fuzzing))]
-        {
-            let res = self.file.file().sync_data().map_err(Error::from);
-            if res.is_err() {
-                self.set_fsync_failed(true);
-                // Try to flush any pages in the page cache that are out of sync with disk.
-                // See here for why: <https://github.com/cberner/redb/issues/450>
-                #[cfg(target_os = "linux")]
-                unsafe {
-                    libc::posix_fadvise64(
-                        self.file.file().as_raw_fd(),
-                        0,
-                        0,
-                        libc::POSIX_FADV_DONTNEED,
-                    );
-                }
-                return res;
-            }
-        }
-
-        Ok(())
+        self.sync_data(false)
     }
 
     fn eventual_flush(&self) -> Result {
         self.check_fsync_failure()?;
-
-        #[cfg(not(target_os = "macos"))]
-        {
-            self.flush()?;
-        }
-        #[cfg(all(target_os = "macos", not(fuzzing)))]
-        {
-            self.flush_write_buffer()?;
-            let code = unsafe { libc::fcntl(self.file.file().as_raw_fd(), libc::F_BARRIERFSYNC) };
-            if code == -1 {
-                self.set_fsync_failed(true);
-                return Err(io::Error::last_os_error().into());
-            }
-        }
-
-        Ok(())
+        self.flush_write_buffer()?;
+        self.sync_data(true)
     }
 
     // Make writes visible to readers, but does not guarantee any durability
@@ -219,7 +447,9 @@ impl PhysicalStorage for PagedCachedFile {
 
     fn read_direct(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
         self.check_fsync_failure()?;
-        self.file.read(offset, len)
+        let data = self.file.read(offset, len)?;
+        self.bytes_read.fetch_add(len as u64, Ordering::Relaxed);
+        Ok(data)
     }
 
     // Caller must explicitly invalidate overlapping regions that are read
@@ -240,30 +470,20 @@ impl PhysicalStorage for PagedCachedFile {
         let cache_slot: usize = (offset % Self::lock_stripes() as u64).try_into().unwrap();
         {
             let read_lock = self.read_cache[cache_slot].read().unwrap();
-            if let Some(cached) = read_lock.get(&offset) {
+            if let Some(cached) = read_lock.get(offset) {
                 self.reads_hits.fetch_add(1, Ordering::Release);
                 debug_assert_eq!(cached.len(), len);
-                return Ok(PageHack::ArcMem(cached.clone()));
+                return Ok(PageHack::ArcMem(cached));
             }
         }
 
         let buffer = Arc::new(self.read_direct(offset, len)?);
-        let cache_size = self.read_cache_bytes.fetch_add(len, Ordering::AcqRel);
-        let mut write_lock = self.read_cache[cache_slot].write().unwrap();
-        write_lock.insert(offset, buffer.clone());
-        let mut removed = 0;
-        if cache_size + len > self.max_read_cache_bytes {
-            while removed < len {
-                if let Some((_, v)) = write_lock.pop_first() {
-                    removed += v.len();
-                } else {
-                    break;
-                }
-            }
-        }
-        if removed > 0 {
-            self.read_cache_bytes.fetch_sub(removed, Ordering::AcqRel);
+        if matches!(hint, PageHint::Uncached) {
+            self.file
+                .readahead(offset + len as u64, SCAN_READAHEAD_BYTES);
+            return Ok(PageHack::ArcMem(buffer));
         }
+        self.insert_read_cache(offset, buffer.clone(), hint, None);
 
         Ok(PageHack::ArcMem(buffer))
     }
@@ -277,7 +497,8 @@ impl PhysicalStorage for PagedCachedFile {
     fn invalidate_cache(&self, offset: u64, _len: usize) {
         let cache_slot: usize = (offset % self.read_cache.len() as u64).try_into().unwrap();
         let mut lock = self.read_cache[cache_slot].write().unwrap();
-        if let Some(removed) = lock.remove(&offset) {
+        lock.writes += 1;
+        if let Some(removed) = lock.remove(offset) {
             // TODO: it would be nice to re-enable this assertion. However, when restoring a Savepoint
             // the information about page order is lost ;( and those order-0 pages are then stored
             // the freed tree
@@ -296,14 +517,20 @@ impl PhysicalStorage for PagedCachedFile {
         let cache_slot: usize = (offset % self.read_cache.len() as u64).try_into().unwrap();
         let existing = {
             let mut lock = self.read_cache[cache_slot].write().unwrap();
-            if let Some(removed) = lock.remove(&offset) {
+            lock.writes += 1;
+            if let Some(removed) = lock.remove(offset) {
                 // TODO: it would be nice to re-enable this assertion. However, when restoring a Savepoint
                 // the information about page order is lost ;( and those order-0 pages are then stored
                 // the freed tree
                 // assert_eq!(len, removed.len());
                 self.read_cache_bytes
                     .fetch_sub(removed.len(), Ordering::AcqRel);
-                Some(Arc::try_unwrap(removed).unwrap())
+                // A page of a different order may have been cached at the same offset
+                if removed.len() == len {
+                    Some(Arc::try_unwrap(removed).unwrap())
+                } else {
+                    None
+                }
             } else {
                 None
             }
@@ -320,7 +547,7 @@ impl PhysicalStorage for PagedCachedFile {
                         self.write_buffer_bytes
                             .fetch_sub(buffer.len(), Ordering::Release);
                         removed_bytes += buffer.len();
-                        self.file.write(offset, &buffer)?;
+                        self.write_to_file(offset, &buffer)?;
                     } else {
                         break;
                     }
@@ -338,4 +565,134 @@ impl PhysicalStorage for PagedCachedFile {
             data,
         }))
     }
+
+    fn prefetch(&self, ranges: &[Range<u64>], _hint: PageHint) {
+        // Skip pages which are already cached, and merge adjacent ranges to reduce the number of
+        // requests made to the backend
+        let mut pending: Option<Range<u64>> = None;
+        for range in ranges {
+            if self.is_cached(range.start) {
+                continue;
+            }
+            match pending {
+                Some(ref mut current) if current.end == range.start => {
+                    current.end = range.end;
+                }
+                Some(ref mut current) if current.start == range.end => {
+                    current.start = range.start;
+                }
+                _ => {
+                    if let Some(current) = pending.replace(range.clone()) {
+                        self.readahead(current);
+                    }
+                }
+            }
+        }
+        if let Some(current) = pending {
+            self.readahead(current);
+        }
+    }
+
+    fn can_prefetch(&self) -> bool {
+        true
+    }
+
+    fn hint(&self, offset: u64, page: &PageHack, hint: PageHint) {
+        // Only branch pages are hinted after being read
+        if !matches!(hint, PageHint::Branch) {
+            return;
+        }
+        let cache_slot: usize = (offset % Self::lock_stripes() as u64).try_into().unwrap();
+        if self.read_cache[cache_slot]
+            .read()
+            .unwrap()
+            .promote_branch(offset)
+        {
+            return;
+        }
+        // The page wasn't cached, because it was read by a scan or is still in the write buffer
+        if self.write_buffer.lock().unwrap().contains_key(&offset) {
+            return;
+        }
+        if let PageHack::ArcMem(data) = page {
+            self.insert_read_cache(offset, data.clone(), hint, None);
+        }
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        let reads_total = self.reads_total.load(Ordering::Acquire);
+        let read_hits = self.reads_hits.load(Ordering::Acquire);
+        CacheStats {
+            read_hits,
+            read_misses: reads_total.saturating_sub(read_hits),
+            read_cache_bytes: self.read_cache_bytes.load(Ordering::Acquire),
+            write_buffer_bytes: self.write_buffer_bytes.load(Ordering::Acquire),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            fsyncs: self.fsyncs.load(Ordering::Relaxed),
+            fsync_time: Duration::from_nanos(self.fsync_nanos.load(Ordering::Relaxed)),
+            max_fsync_time: Duration::from_nanos(self.max_fsync_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_store::page_store::base::{PageHint, PhysicalStorage};
+    use crate::tree_store::page_store::cached_file::PagedCachedFile;
+    use crate::tree_store::page_store::file_backend::FileBackend;
+    use std::sync::atomic::Ordering;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn scan_resistance() {
+        let page_size = 4096;
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let backend = FileBackend::new(tmpfile.reopen().unwrap()).unwrap();
+        let cached = PagedCachedFile::new(Box::new(backend), page_size, 4 * 4096, 4096).unwrap();
+        // Offsets in the same stripe of the cache
+        let stride = PagedCachedFile::lock_stripes() as u64 * page_size;
+        unsafe {
+            cached.resize(100 * stride).unwrap();
+        }
+
+        let read = |offset: u64| unsafe {
+            cached.read(offset, 4096, PageHint::None).unwrap();
+        };
+        // A page which is read repeatedly is not evicted by a scan over many other pages
+        read(0);
+        read(0);
+        for i in 1..100 {
+            read(i * stride);
+        }
+        let hits = cached.reads_hits.load(Ordering::Acquire);
+        read(0);
+        assert_eq!(cached.reads_hits.load(Ordering::Acquire), hits + 1);
+    }
+
+    #[test]
+    fn branch_hint() {
+        let page_size = 4096;
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let backend = FileBackend::new(tmpfile.reopen().unwrap()).unwrap();
+        let cached = PagedCachedFile::new(Box::new(backend), page_size, 4 * 4096, 4096).unwrap();
+        let stride = PagedCachedFile::lock_stripes() as u64 * page_size;
+        unsafe {
+            cached.resize(100 * stride).unwrap();
+        }
+
+        // A page read by a scan is only cached once it's hinted to be a branch page
+        let page = unsafe { cached.read(0, 4096, PageHint::Uncached).unwrap() };
+        assert!(!cached.is_cached(0));
+        cached.hint(0, &page, PageHint::Branch);
+        assert!(cached.is_cached(0));
+
+        // and then isn't evicted by the scan, which only reads each other page once
+        for i in 1..100 {
+            let page = unsafe { cached.read(i * stride, 4096, PageHint::None).unwrap() };
+            cached.hint(i * stride, &page, PageHint::None);
+        }
+        assert!(cached.is_cached(0));
+    }
 }
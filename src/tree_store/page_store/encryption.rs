@@ -0,0 +1,560 @@
+use crate::tree_store::page_store::base::StorageBackend;
+use crate::tree_store::page_store::header::{DatabaseHeader, DB_HEADER_SIZE, MAGICNUMBER};
+use crate::{Error, Result};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::rand_core::RngCore;
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{AeadCore, AeadInPlace, KeyInit, OsRng};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{Key, Tag, XChaCha20Poly1305, XNonce};
+#[cfg(feature = "encryption")]
+use std::collections::BTreeSet;
+#[cfg(feature = "encryption")]
+use std::fmt::{Debug, Formatter};
+#[cfg(feature = "encryption")]
+use std::io;
+#[cfg(feature = "encryption")]
+use std::mem::size_of;
+#[cfg(feature = "encryption")]
+use std::sync::RwLock;
+
+pub(crate) type EncryptionKey = [u8; 32];
+pub(super) const KEY_CHECK_SIZE: usize = 16;
+pub(super) type KeyCheck = [u8; KEY_CHECK_SIZE];
+
+#[cfg(feature = "encryption")]
+const TAG_SIZE: usize = 16;
+#[cfg(feature = "encryption")]
+const NONCE_SIZE: usize = 24;
+// Every encrypted block is followed by its authentication tag and nonce
+#[cfg(feature = "encryption")]
+const BLOCK_OVERHEAD: u64 = (TAG_SIZE + NONCE_SIZE) as u64;
+
+// The key check is the tag of an empty message, encrypted with a nonce that is never used for data
+#[cfg(feature = "encryption")]
+const KEY_CHECK_NONCE: [u8; NONCE_SIZE] = [0xFF; NONCE_SIZE];
+#[cfg(feature = "encryption")]
+const KEY_CHECK_DATA: &[u8] = b"redb key check";
+
+// The root of the block table is stored in plaintext, after the super-header, as:
+// [magic][version][length][offset]
+#[cfg(feature = "encryption")]
+const ROOT_OFFSET: u64 = DB_HEADER_SIZE as u64;
+#[cfg(feature = "encryption")]
+const ROOT_MAGIC: [u8; 8] = *b"redbblks";
+#[cfg(feature = "encryption")]
+const ROOT_SIZE: usize = ROOT_MAGIC.len() + 3 * size_of::<u64>();
+// Prefixes of the associated data of the block table, to distinguish it from the data blocks
+#[cfg(feature = "encryption")]
+const CHUNK_DOMAIN: &[u8] = b"redb block table chunk";
+#[cfg(feature = "encryption")]
+const DIRECTORY_DOMAIN: &[u8] = b"redb block table directory";
+
+// Checks `encryption_key` against the database stored in `backend`, if there is one, and wraps
+// `backend` so that it is encrypted with the key.
+// Returns the wrapped backend, and the key check value to store in the header of a new database
+pub(super) fn open_backend(
+    backend: Box<dyn StorageBackend>,
+    encryption_key: Option<EncryptionKey>,
+    #[allow(unused_variables)] page_size: u64,
+) -> Result<(Box<dyn StorageBackend>, Option<KeyCheck>)> {
+    // The super-header is always stored in plaintext, so that the key can be checked before use
+    let existing_key_check = if backend.len()? >= DB_HEADER_SIZE as u64 {
+        let header = backend.read(0, DB_HEADER_SIZE)?;
+        if header[..MAGICNUMBER.len()] == MAGICNUMBER {
+            Some(DatabaseHeader::key_check_from_bytes(&header)?)
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    match encryption_key {
+        None => {
+            if let Some(Some(_)) = existing_key_check {
+                return Err(Error::InvalidEncryptionKey);
+            }
+            Ok((backend, None))
+        }
+        #[cfg(feature = "encryption")]
+        Some(key) => {
+            let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+            let key_check = compute_key_check(&cipher);
+            match existing_key_check {
+                // A new database
+                None => {}
+                Some(Some(existing)) if existing == key_check => {}
+                Some(_) => {
+                    return Err(Error::InvalidEncryptionKey);
+                }
+            }
+            let mut backend = EncryptedBackend::new(backend, cipher, page_size);
+            if existing_key_check.is_some() {
+                let table = backend.load_table()?;
+                *backend.table.get_mut().unwrap() = table;
+            }
+            Ok((Box::new(backend), Some(key_check)))
+        }
+        #[cfg(not(feature = "encryption"))]
+        Some(_) => unreachable!(),
+    }
+}
+
+#[cfg(feature = "encryption")]
+fn compute_key_check(cipher: &XChaCha20Poly1305) -> KeyCheck {
+    let tag = cipher
+        .encrypt_in_place_detached(
+            XNonce::from_slice(&KEY_CHECK_NONCE),
+            KEY_CHECK_DATA,
+            &mut [],
+        )
+        .unwrap();
+    tag.into()
+}
+
+// Encrypts every page of the database with XChaCha20-Poly1305, except for the first one, which
+// contains the super-header.
+//
+// Physical layout:
+// * block 0 is stored in plaintext, at [0, block_size). The root of the block table follows the
+//   super-header, at ROOT_OFFSET
+// * block i > 0 is stored at block_size + (i - 1) * (block_size + BLOCK_OVERHEAD) as
+//   [ciphertext][tag][nonce]. Its index and generation are used as the associated data, so that
+//   blocks can't be swapped around, or replaced with an older version of themselves
+// * the block table follows the last block
+//
+// The block table stores the generation of every block, which is a random value chosen each time
+// the block is written, or zero if it has never been written. Blocks which have never been written
+// are read back as zeros, without reading the file.
+// The table is split into chunks of block_size / 8 generations, and each chunk is encrypted with
+// its own generation. The directory lists the generations of all the chunks, and is encrypted with
+// the version, length and location of the table, which are stored in the root.
+// Every chunk, and the directory, has two slots, which are written alternately so that the
+// previous version of the table remains intact until the root is updated.
+//
+// The table is only persisted by sync_data() and set_len(), so blocks which were written after
+// the last sync fail authentication after a crash. That's indistinguishable from a torn write.
+//
+// Rolling back the whole file, including the block table, to an older version can't be detected.
+#[cfg(feature = "encryption")]
+struct EncryptedBackend {
+    inner: Box<dyn StorageBackend>,
+    cipher: XChaCha20Poly1305,
+    block_size: u64,
+    table: RwLock<BlockTable>,
+}
+
+#[cfg(feature = "encryption")]
+#[derive(Default)]
+struct BlockTable {
+    // Logical length of the storage
+    len: u64,
+    // Generation of each block. Block 0 is stored in plaintext, so its generation is unused
+    generations: Vec<u64>,
+    // Generation of each chunk of the table. The low bit is the slot which the chunk is stored in
+    chunks: Vec<u64>,
+    // Chunks which have changed since the table was last persisted
+    dirty: BTreeSet<usize>,
+    // Incremented each time the table is persisted. Zero if it never has been
+    version: u64,
+    // Physical location of the persisted table
+    offset: u64,
+}
+
+#[cfg(feature = "encryption")]
+impl EncryptedBackend {
+    fn new(inner: Box<dyn StorageBackend>, cipher: XChaCha20Poly1305, block_size: u64) -> Self {
+        Self {
+            inner,
+            cipher,
+            block_size,
+            table: RwLock::new(BlockTable::default()),
+        }
+    }
+
+    fn physical_offset(&self, block: u64) -> u64 {
+        if block == 0 {
+            0
+        } else {
+            self.block_size + (block - 1) * (self.block_size + BLOCK_OVERHEAD)
+        }
+    }
+
+    // Number of blocks, including block 0, which are needed to store `len` bytes
+    fn num_blocks(&self, len: u64) -> usize {
+        ((len + self.block_size - 1) / self.block_size)
+            .max(1)
+            .try_into()
+            .unwrap()
+    }
+
+    fn entries_per_chunk(&self) -> usize {
+        let block_size: usize = self.block_size.try_into().unwrap();
+        block_size / size_of::<u64>()
+    }
+
+    fn num_chunks(&self, num_blocks: usize) -> usize {
+        let entries = self.entries_per_chunk();
+        (num_blocks + entries - 1) / entries
+    }
+
+    fn directory_size(chunks: usize) -> u64 {
+        (chunks * size_of::<u64>()) as u64 + BLOCK_OVERHEAD
+    }
+
+    // Physical length of a table with `chunks` chunks
+    fn table_len(&self, chunks: usize) -> u64 {
+        2 * (chunks as u64) * (self.block_size + BLOCK_OVERHEAD) + 2 * Self::directory_size(chunks)
+    }
+
+    fn chunk_offset(&self, table_offset: u64, chunk: usize, slot: u64) -> u64 {
+        table_offset + (2 * chunk as u64 + slot) * (self.block_size + BLOCK_OVERHEAD)
+    }
+
+    fn directory_offset(&self, table_offset: u64, chunks: usize, slot: u64) -> u64 {
+        self.chunk_offset(table_offset, chunks, 0) + slot * Self::directory_size(chunks)
+    }
+
+    // Encrypts `data` in place, and appends its tag and nonce
+    fn seal(&self, data: &mut Vec<u8>, associated_data: &[u8]) {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce, associated_data, data)
+            .unwrap();
+        data.extend_from_slice(&tag);
+        data.extend_from_slice(&nonce);
+    }
+
+    // Decrypts data which was encrypted by seal(). Returns None if it fails authentication
+    fn open(&self, mut data: Vec<u8>, associated_data: &[u8]) -> Option<Vec<u8>> {
+        let len = data.len() - TAG_SIZE - NONCE_SIZE;
+        let nonce = XNonce::clone_from_slice(&data[(len + TAG_SIZE)..]);
+        let tag = Tag::clone_from_slice(&data[len..(len + TAG_SIZE)]);
+        data.truncate(len);
+        self.cipher
+            .decrypt_in_place_detached(&nonce, associated_data, &mut data, &tag)
+            .ok()?;
+        Some(data)
+    }
+
+    fn block_associated_data(block: u64, generation: u64) -> [u8; 16] {
+        let mut result = [0; 16];
+        result[..8].copy_from_slice(&block.to_le_bytes());
+        result[8..].copy_from_slice(&generation.to_le_bytes());
+        result
+    }
+
+    fn chunk_associated_data(chunk: usize, generation: u64) -> Vec<u8> {
+        let mut result = CHUNK_DOMAIN.to_vec();
+        result.extend_from_slice(&(chunk as u64).to_le_bytes());
+        result.extend_from_slice(&generation.to_le_bytes());
+        result
+    }
+
+    fn directory_associated_data(version: u64, len: u64, offset: u64) -> Vec<u8> {
+        let mut result = DIRECTORY_DOMAIN.to_vec();
+        result.extend_from_slice(&version.to_le_bytes());
+        result.extend_from_slice(&len.to_le_bytes());
+        result.extend_from_slice(&offset.to_le_bytes());
+        result
+    }
+
+    // Reads the block table of an existing database
+    fn load_table(&self) -> Result<BlockTable> {
+        let root = self.inner.read(ROOT_OFFSET, ROOT_SIZE)?;
+        if root[..ROOT_MAGIC.len()] != ROOT_MAGIC {
+            return Err(Error::Corrupted(
+                "Block table of encrypted database is missing".to_string(),
+            ));
+        }
+        let field = |i: usize| {
+            let start = ROOT_MAGIC.len() + i * size_of::<u64>();
+            u64::from_le_bytes(root[start..(start + size_of::<u64>())].try_into().unwrap())
+        };
+        let (version, len, offset) = (field(0), field(1), field(2));
+        let corrupted = || Error::Corrupted("Block table failed authentication".to_string());
+
+        let num_blocks = self.num_blocks(len);
+        let num_chunks = self.num_chunks(num_blocks);
+        let directory = self.inner.read(
+            self.directory_offset(offset, num_chunks, version % 2),
+            Self::directory_size(num_chunks).try_into().unwrap(),
+        )?;
+        let directory = self
+            .open(
+                directory,
+                &Self::directory_associated_data(version, len, offset),
+            )
+            .ok_or_else(corrupted)?;
+        let chunks: Vec<u64> = directory
+            .chunks_exact(size_of::<u64>())
+            .map(|x| u64::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+
+        let mut generations = Vec::with_capacity(num_chunks * self.entries_per_chunk());
+        for (chunk, generation) in chunks.iter().enumerate() {
+            let data = self.inner.read(
+                self.chunk_offset(offset, chunk, generation & 1),
+                (self.block_size + BLOCK_OVERHEAD).try_into().unwrap(),
+            )?;
+            let data = self
+                .open(data, &Self::chunk_associated_data(chunk, *generation))
+                .ok_or_else(corrupted)?;
+            generations.extend(
+                data.chunks_exact(size_of::<u64>())
+                    .map(|x| u64::from_le_bytes(x.try_into().unwrap())),
+            );
+        }
+        generations.truncate(num_blocks);
+
+        Ok(BlockTable {
+            len,
+            generations,
+            chunks,
+            dirty: BTreeSet::new(),
+            version,
+            offset,
+        })
+    }
+
+    // Writes `chunks` of the table, and a new directory, at `offset`, and then updates the root to
+    // point to them once they, and all the blocks which they reference, are durable
+    fn write_table(
+        &self,
+        table: &mut BlockTable,
+        offset: u64,
+        chunks: impl IntoIterator<Item = usize>,
+        eventual: bool,
+    ) -> Result<(), io::Error> {
+        let entries = self.entries_per_chunk();
+        let mut chunk_generations = table.chunks.clone();
+        for chunk in chunks {
+            let slot = 1 - (chunk_generations[chunk] & 1);
+            let generation = (OsRng.next_u64() & !1) | slot;
+            let mut data =
+                Vec::with_capacity((self.block_size + BLOCK_OVERHEAD).try_into().unwrap());
+            for block in (chunk * entries)..((chunk + 1) * entries) {
+                let block_generation = table.generations.get(block).copied().unwrap_or(0);
+                data.extend_from_slice(&block_generation.to_le_bytes());
+            }
+            self.seal(&mut data, &Self::chunk_associated_data(chunk, generation));
+            self.inner
+                .write(self.chunk_offset(offset, chunk, slot), &data)?;
+            chunk_generations[chunk] = generation;
+        }
+
+        let version = table.version + 1;
+        let mut directory = Vec::with_capacity(
+            Self::directory_size(chunk_generations.len())
+                .try_into()
+                .unwrap(),
+        );
+        for generation in chunk_generations.iter() {
+            directory.extend_from_slice(&generation.to_le_bytes());
+        }
+        self.seal(
+            &mut directory,
+            &Self::directory_associated_data(version, table.len, offset),
+        );
+        self.inner.write(
+            self.directory_offset(offset, chunk_generations.len(), version % 2),
+            &directory,
+        )?;
+        self.inner.sync_data(eventual)?;
+
+        let mut root = ROOT_MAGIC.to_vec();
+        root.extend_from_slice(&version.to_le_bytes());
+        root.extend_from_slice(&table.len.to_le_bytes());
+        root.extend_from_slice(&offset.to_le_bytes());
+        self.inner.write(ROOT_OFFSET, &root)?;
+        self.inner.sync_data(eventual)?;
+
+        table.chunks = chunk_generations;
+        table.dirty.clear();
+        table.version = version;
+        table.offset = offset;
+
+        Ok(())
+    }
+
+    fn read_block(&self, table: &BlockTable, block: u64) -> Result<Vec<u8>, io::Error> {
+        let block_size: usize = self.block_size.try_into().unwrap();
+        if block == 0 {
+            return self.inner.read(0, block_size);
+        }
+        let generation = *usize::try_from(block)
+            .ok()
+            .and_then(|i| table.generations.get(i))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Block {block} is past the end of the storage"),
+                )
+            })?;
+        if generation == 0 {
+            return Ok(vec![0; block_size]);
+        }
+        let data = self.inner.read(
+            self.physical_offset(block),
+            block_size + TAG_SIZE + NONCE_SIZE,
+        )?;
+        self.open(data, &Self::block_associated_data(block, generation))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Page at block {block} failed authentication"),
+                )
+            })
+    }
+
+    fn write_block(
+        &self,
+        table: &mut BlockTable,
+        block: u64,
+        mut data: Vec<u8>,
+    ) -> Result<(), io::Error> {
+        if block == 0 {
+            return self.inner.write(0, &data);
+        }
+        let index: usize = block.try_into().unwrap();
+        if index >= table.generations.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Block {block} is past the end of the storage"),
+            ));
+        }
+        // Zero is reserved for blocks which have never been written
+        let generation = OsRng.next_u64().max(1);
+        self.seal(&mut data, &Self::block_associated_data(block, generation));
+        self.inner.write(self.physical_offset(block), &data)?;
+        table.generations[index] = generation;
+        table.dirty.insert(index / self.entries_per_chunk());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Debug for EncryptedBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedBackend")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl StorageBackend for EncryptedBackend {
+    fn len(&self) -> Result<u64, io::Error> {
+        Ok(self.table.read().unwrap().len)
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, io::Error> {
+        // Fast path for the super-header, which is read before the rest of the database exists
+        if offset + len as u64 <= self.block_size {
+            return self.inner.read(offset, len);
+        }
+        let table = self.table.read().unwrap();
+        let mut result = Vec::with_capacity(len);
+        let end = offset + len as u64;
+        let mut position = offset;
+        while position < end {
+            let block = position / self.block_size;
+            let block_start = block * self.block_size;
+            let data = self.read_block(&table, block)?;
+            let start: usize = (position - block_start).try_into().unwrap();
+            let stop: usize = (end.min(block_start + self.block_size) - block_start)
+                .try_into()
+                .unwrap();
+            result.extend_from_slice(&data[start..stop]);
+            position = block_start + stop as u64;
+        }
+
+        Ok(result)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), io::Error> {
+        if offset + data.len() as u64 <= self.block_size {
+            return self.inner.write(offset, data);
+        }
+        let mut table = self.table.write().unwrap();
+        let end = offset + data.len() as u64;
+        let mut position = offset;
+        while position < end {
+            let block = position / self.block_size;
+            let block_start = block * self.block_size;
+            let start: usize = (position - block_start).try_into().unwrap();
+            let stop: usize = (end.min(block_start + self.block_size) - block_start)
+                .try_into()
+                .unwrap();
+            let source_start: usize = (position - offset).try_into().unwrap();
+            let source = &data[source_start..(source_start + stop - start)];
+            let buffer = if start == 0 && stop as u64 == self.block_size {
+                source.to_vec()
+            } else {
+                let mut buffer = self.read_block(&table, block)?;
+                buffer[start..stop].copy_from_slice(source);
+                buffer
+            };
+            self.write_block(&mut table, block, buffer)?;
+            position = block_start + stop as u64;
+        }
+
+        Ok(())
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), io::Error> {
+        let mut table = self.table.write().unwrap();
+        if len == table.len && table.version > 0 {
+            return Ok(());
+        }
+        let active = if table.version > 0 {
+            Some(table.offset..(table.offset + self.table_len(table.chunks.len())))
+        } else {
+            None
+        };
+        let num_blocks = self.num_blocks(len);
+        let num_chunks = self.num_chunks(num_blocks);
+        table.len = len;
+        table.generations.resize(num_blocks, 0);
+        table.chunks.resize(num_chunks, 0);
+
+        // The table always follows the last block, so it's moved whenever the number of blocks
+        // changes. Its old location must remain intact until the root points to the new one
+        let offset = self.physical_offset(num_blocks.try_into().unwrap());
+        let table_len = self.table_len(num_chunks);
+        let target = offset..(offset + table_len);
+        if let Some(active) = active {
+            if active != target && active.start < target.end && target.start < active.end {
+                let temporary = active.end.max(target.end);
+                self.inner.set_len(temporary + table_len)?;
+                self.write_table(&mut table, temporary, 0..num_chunks, false)?;
+            }
+        }
+        if self.inner.len()? < target.end {
+            self.inner.set_len(target.end)?;
+        }
+        self.write_table(&mut table, offset, 0..num_chunks, false)?;
+        self.inner.set_len(target.end)
+    }
+
+    fn sync_data(&self, eventual: bool) -> Result<(), io::Error> {
+        let mut table = self.table.write().unwrap();
+        if table.dirty.is_empty() {
+            return self.inner.sync_data(eventual);
+        }
+        let dirty: Vec<usize> = table.dirty.iter().copied().collect();
+        let offset = table.offset;
+        self.write_table(&mut table, offset, dirty, eventual)
+    }
+
+    fn readahead(&self, offset: u64, len: usize) {
+        let first_block = offset / self.block_size;
+        let last_block = (offset + len as u64).saturating_sub(1) / self.block_size;
+        let start = self.physical_offset(first_block);
+        let end = self.physical_offset(last_block + 1);
+        self.inner
+            .readahead(start, (end - start).try_into().unwrap_or(usize::MAX));
+    }
+}
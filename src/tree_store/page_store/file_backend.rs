@@ -0,0 +1,108 @@
+use crate::tree_store::page_store::base::StorageBackend;
+use crate::tree_store::page_store::file_lock::LockedFile;
+use crate::Result;
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::os::unix::io::AsRawFd;
+
+// Stores a database in a file, which is locked for exclusive access, or with a shared lock if it is
+// only opened for reading
+pub(crate) struct FileBackend {
+    file: LockedFile,
+}
+
+impl FileBackend {
+    pub(crate) fn new(file: File) -> Result<Self> {
+        Ok(Self::from_locked(LockedFile::new(file)?))
+    }
+
+    pub(crate) fn new_read_only(file: File) -> Result<Self> {
+        Ok(Self::from_locked(LockedFile::new_shared(file)?))
+    }
+
+    fn from_locked(file: LockedFile) -> Self {
+        // Try to flush any pages in the page cache that are out of sync with disk.
+        // See here for why: <https://github.com/cberner/redb/issues/450>
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::posix_fadvise64(file.file().as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+
+        Self { file }
+    }
+}
+
+impl Debug for FileBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileBackend")
+            .field("file", self.file.file())
+            .finish()
+    }
+}
+
+impl StorageBackend for FileBackend {
+    fn len(&self) -> Result<u64, io::Error> {
+        Ok(self.file.file().metadata()?.len())
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, io::Error> {
+        self.file.read(offset, len)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), io::Error> {
+        self.file.write(offset, data)
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), io::Error> {
+        self.file.file().set_len(len)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn readahead(&self, offset: u64, len: usize) {
+        // This is only a hint, so errors are ignored
+        unsafe {
+            libc::posix_fadvise64(
+                self.file.file().as_raw_fd(),
+                offset.try_into().unwrap_or(i64::MAX),
+                len.try_into().unwrap_or(i64::MAX),
+                libc::POSIX_FADV_WILLNEED,
+            );
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    fn sync_data(&self, _eventual: bool) -> Result<(), io::Error> {
+        let result = self.file.file().sync_data();
+        if result.is_err() {
+            // Try to flush any pages in the page cache that are out of sync with disk.
+            // See here for why: <https://github.com/cberner/redb/issues/450>
+            #[cfg(target_os = "linux")]
+            unsafe {
+                libc::posix_fadvise64(
+                    self.file.file().as_raw_fd(),
+                    0,
+                    0,
+                    libc::POSIX_FADV_DONTNEED,
+                );
+            }
+        }
+
+        result
+    }
+
+    #[cfg(target_os = "macos")]
+    fn sync_data(&self, eventual: bool) -> Result<(), io::Error> {
+        if eventual {
+            let code = unsafe { libc::fcntl(self.file.file().as_raw_fd(), libc::F_BARRIERFSYNC) };
+            if code == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        } else {
+            self.file.file().sync_data()
+        }
+    }
+}
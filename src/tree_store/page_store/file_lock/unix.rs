@@ -9,9 +9,19 @@ pub(crate) struct LockedFile {
 }
 
 impl LockedFile {
+    // Takes an exclusive lock on the file
     pub(crate) fn new(file: File) -> Result<Self> {
+        Self::lock(file, libc::LOCK_EX)
+    }
+
+    // Takes a shared lock on the file, which may be held by several readers at once
+    pub(crate) fn new_shared(file: File) -> Result<Self> {
+        Self::lock(file, libc::LOCK_SH)
+    }
+
+    fn lock(file: File, operation: libc::c_int) -> Result<Self> {
         let fd = file.as_raw_fd();
-        let result = unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) };
+        let result = unsafe { libc::flock(fd, operation | libc::LOCK_NB) };
         if result != 0 {
             let err = io::Error::last_os_error();
             if err.kind() == io::ErrorKind::WouldBlock {
@@ -28,16 +38,14 @@ impl LockedFile {
         &self.file
     }
 
-    pub(crate) fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+    pub(crate) fn read(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
         let mut buffer = vec![0; len];
-        self.file
-            .read_exact_at(&mut buffer, offset)
-            .map_err(Error::from)?;
+        self.file.read_exact_at(&mut buffer, offset)?;
         Ok(buffer)
     }
 
-    pub(crate) fn write(&self, offset: u64, data: &[u8]) -> Result {
-        self.file.write_all_at(data, offset).map_err(Error::from)
+    pub(crate) fn write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.file.write_all_at(data, offset)
     }
 }
 
@@ -9,6 +9,17 @@ use std::os::windows::io::RawHandle;
 
 const ERROR_LOCK_VIOLATION: i32 = 0x21;
 const ERROR_IO_PENDING: i32 = 997;
+const LOCKFILE_FAIL_IMMEDIATELY: u32 = 0x1;
+
+/// <https://learn.microsoft.com/en-us/windows/win32/api/minwinbase/ns-minwinbase-overlapped>
+#[repr(C)]
+struct OVERLAPPED {
+    internal: usize,
+    internal_high: usize,
+    offset: u32,
+    offset_high: u32,
+    event: RawHandle,
+}
 
 extern "system" {
     /// <https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-lockfile>
@@ -20,6 +31,16 @@ extern "system" {
         length_high: u32,
     ) -> i32;
 
+    /// <https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-lockfileex>
+    fn LockFileEx(
+        file: RawHandle,
+        flags: u32,
+        reserved: u32,
+        length_low: u32,
+        length_high: u32,
+        overlapped: *mut OVERLAPPED,
+    ) -> i32;
+
     /// <https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-unlockfile>
     fn UnlockFile(
         file: RawHandle,
@@ -35,47 +56,66 @@ pub(crate) struct LockedFile {
 }
 
 impl LockedFile {
+    // Takes an exclusive lock on the file
     pub(crate) fn new(file: File) -> Result<Self> {
         let handle = file.as_raw_handle();
-        unsafe {
-            let result = LockFile(handle, 0, 0, u32::MAX, u32::MAX);
+        let result = unsafe { LockFile(handle, 0, 0, u32::MAX, u32::MAX) };
+        Self::check_lock_result(file, result)
+    }
 
-            if result == 0 {
-                let err = io::Error::last_os_error();
-                return if err.raw_os_error() == Some(ERROR_IO_PENDING)
-                    || err.raw_os_error() == Some(ERROR_LOCK_VIOLATION)
-                {
-                    Err(Error::DatabaseAlreadyOpen)
-                } else {
-                    Err(Error::Io(err))
-                };
-            }
+    // Takes a shared lock on the file, which may be held by several readers at once
+    pub(crate) fn new_shared(file: File) -> Result<Self> {
+        let handle = file.as_raw_handle();
+        let mut overlapped = OVERLAPPED {
+            internal: 0,
+            internal_high: 0,
+            offset: 0,
+            offset_high: 0,
+            event: std::ptr::null_mut(),
+        };
+        let result = unsafe {
+            LockFileEx(
+                handle,
+                LOCKFILE_FAIL_IMMEDIATELY,
+                0,
+                u32::MAX,
+                u32::MAX,
+                &mut overlapped,
+            )
         };
+        Self::check_lock_result(file, result)
+    }
+
+    fn check_lock_result(file: File, result: i32) -> Result<Self> {
+        if result == 0 {
+            let err = io::Error::last_os_error();
+            return if err.raw_os_error() == Some(ERROR_IO_PENDING)
+                || err.raw_os_error() == Some(ERROR_LOCK_VIOLATION)
+            {
+                Err(Error::DatabaseAlreadyOpen)
+            } else {
+                Err(Error::Io(err))
+            };
+        }
 
         Ok(Self { file })
     }
 
-    pub(crate) fn read(&self, mut offset: u64, len: usize) -> Result<Vec<u8>> {
+    pub(crate) fn read(&self, mut offset: u64, len: usize) -> io::Result<Vec<u8>> {
         let mut buffer = vec![0; len];
         let mut data_offset = 0;
         while data_offset < buffer.len() {
-            let read = self
-                .file
-                .seek_read(&mut buffer[data_offset..], offset)
-                .map_err(Error::from)?;
+            let read = self.file.seek_read(&mut buffer[data_offset..], offset)?;
             offset += read as u64;
             data_offset += read;
         }
         Ok(buffer)
     }
 
-    pub(crate) fn write(&self, mut offset: u64, data: &[u8]) -> Result {
+    pub(crate) fn write(&self, mut offset: u64, data: &[u8]) -> io::Result<()> {
         let mut data_offset = 0;
         while data_offset < data.len() {
-            let written = self
-                .file
-                .seek_write(&data[data_offset..], offset)
-                .map_err(Error::from)?;
+            let written = self.file.seek_write(&data[data_offset..], offset)?;
             offset += written as u64;
             data_offset += written;
         }
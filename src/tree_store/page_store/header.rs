@@ -1,8 +1,10 @@
 use crate::transaction_tracker::TransactionId;
+use crate::tree_store::page_store::encryption::{KeyCheck, KEY_CHECK_SIZE};
 use crate::tree_store::page_store::layout::{DatabaseLayout, RegionLayout};
 use crate::tree_store::page_store::page_manager::FILE_FORMAT_VERSION;
 use crate::tree_store::page_store::ChecksumType;
 use crate::tree_store::{Checksum, PageNumber};
+use crate::{Error, Result};
 use std::mem::size_of;
 
 // Database layout:
@@ -18,6 +20,9 @@ use std::mem::size_of;
 // Definition of region
 // 4 bytes: region header pages
 // 4 bytes: region max data pages
+// 1 byte: cipher (0 = unencrypted, 1 = XChaCha20-Poly1305)
+// 7 bytes: padding
+// 16 bytes: encryption key check value
 //
 // Commit slot 0 (next 128 bytes):
 // 1 byte: version
@@ -44,11 +49,17 @@ const GOD_BYTE_OFFSET: usize = MAGICNUMBER.len();
 const PAGE_SIZE_OFFSET: usize = GOD_BYTE_OFFSET + size_of::<u8>() + 2; // +2 for padding
 const REGION_HEADER_PAGES_OFFSET: usize = PAGE_SIZE_OFFSET + size_of::<u32>();
 const REGION_MAX_DATA_PAGES_OFFSET: usize = REGION_HEADER_PAGES_OFFSET + size_of::<u32>();
+const CIPHER_OFFSET: usize = REGION_MAX_DATA_PAGES_OFFSET + size_of::<u32>();
+const KEY_CHECK_OFFSET: usize = CIPHER_OFFSET + size_of::<u8>() + 7; // +7 for padding
 const TRANSACTION_SIZE: usize = 128;
 const TRANSACTION_0_OFFSET: usize = 64;
 const TRANSACTION_1_OFFSET: usize = TRANSACTION_0_OFFSET + TRANSACTION_SIZE;
 pub(super) const DB_HEADER_SIZE: usize = TRANSACTION_1_OFFSET + TRANSACTION_SIZE;
 
+// Ciphers
+const CIPHER_NONE: u8 = 0;
+const CIPHER_XCHACHA20_POLY1305: u8 = 1;
+
 // God byte flags
 const PRIMARY_BIT: u8 = 1;
 const RECOVERY_REQUIRED: u8 = 2;
@@ -95,6 +106,8 @@ pub(super) struct DatabaseHeader {
     page_size: u32,
     region_header_pages: u32,
     region_max_data_pages: u32,
+    // Present if the database is encrypted
+    pub(super) key_check: Option<KeyCheck>,
     transaction_slots: [TransactionHeader; 2],
 }
 
@@ -117,6 +130,7 @@ impl DatabaseHeader {
             page_size: layout.full_region_layout().page_size(),
             region_header_pages: layout.full_region_layout().get_header_pages(),
             region_max_data_pages: layout.full_region_layout().num_pages(),
+            key_check: None,
             transaction_slots: [slot.clone(), slot],
         }
     }
@@ -149,8 +163,21 @@ impl DatabaseHeader {
         self.primary_slot ^= 1;
     }
 
+    // Returns the encryption key check value, or None if the database is not encrypted
+    pub(super) fn key_check_from_bytes(data: &[u8]) -> Result<Option<KeyCheck>> {
+        match data[CIPHER_OFFSET] {
+            CIPHER_NONE => Ok(None),
+            CIPHER_XCHACHA20_POLY1305 => Ok(Some(
+                data[KEY_CHECK_OFFSET..(KEY_CHECK_OFFSET + KEY_CHECK_SIZE)]
+                    .try_into()
+                    .unwrap(),
+            )),
+            cipher => Err(Error::Corrupted(format!("Unknown cipher {cipher}"))),
+        }
+    }
+
     // TODO: consider returning an Err with the repair info
-    pub(super) fn from_bytes(data: &[u8]) -> (Self, HeaderRepairInfo) {
+    pub(super) fn from_bytes(data: &[u8]) -> Result<(Self, HeaderRepairInfo)> {
         let invalid_magic_number = data[..MAGICNUMBER.len()] != MAGICNUMBER;
 
         let primary_slot = usize::from(data[GOD_BYTE_OFFSET] & PRIMARY_BIT != 0);
@@ -158,6 +185,7 @@ impl DatabaseHeader {
         let page_size = get_u32(&data[PAGE_SIZE_OFFSET..]);
         let region_header_pages = get_u32(&data[REGION_HEADER_PAGES_OFFSET..]);
         let region_max_data_pages = get_u32(&data[REGION_MAX_DATA_PAGES_OFFSET..]);
+        let key_check = Self::key_check_from_bytes(data)?;
         let full_region_layout =
             RegionLayout::new(region_max_data_pages, region_header_pages, page_size);
         let (slot0, slot0_corrupted) =
@@ -176,6 +204,7 @@ impl DatabaseHeader {
             page_size,
             region_header_pages,
             region_max_data_pages,
+            key_check,
             transaction_slots: [slot0, slot1],
         };
         let repair = HeaderRepairInfo {
@@ -183,7 +212,7 @@ impl DatabaseHeader {
             primary_corrupted,
             secondary_corrupted,
         };
-        (result, repair)
+        Ok((result, repair))
     }
 
     pub(super) fn to_bytes(
@@ -208,6 +237,11 @@ impl DatabaseHeader {
             .copy_from_slice(&self.region_header_pages.to_le_bytes());
         result[REGION_MAX_DATA_PAGES_OFFSET..(REGION_MAX_DATA_PAGES_OFFSET + size_of::<u32>())]
             .copy_from_slice(&self.region_max_data_pages.to_le_bytes());
+        if let Some(key_check) = self.key_check {
+            result[CIPHER_OFFSET] = CIPHER_XCHACHA20_POLY1305;
+            result[KEY_CHECK_OFFSET..(KEY_CHECK_OFFSET + KEY_CHECK_SIZE)]
+                .copy_from_slice(&key_check);
+        }
         let slot0 = self.transaction_slots[0].to_bytes();
         result[TRANSACTION_0_OFFSET..(TRANSACTION_0_OFFSET + slot0.len())].copy_from_slice(&slot0);
         let slot1 = self.transaction_slots[1].to_bytes();
@@ -367,7 +401,7 @@ mod test {
         GOD_BYTE_OFFSET, MAGICNUMBER, PAGE_SIZE, PRIMARY_BIT, RECOVERY_REQUIRED,
         ROOT_CHECKSUM_OFFSET, TRANSACTION_0_OFFSET, TRANSACTION_1_OFFSET,
     };
-    use crate::tree_store::page_store::TransactionalMemory;
+    use crate::tree_store::page_store::{StorageSource, TransactionalMemory};
     use crate::{Database, ReadableTable, WriteStrategy};
     use std::fs::OpenOptions;
     use std::io::{Read, Seek, SeekFrom, Write};
@@ -408,14 +442,14 @@ mod test {
         file.write_all(&buffer).unwrap();
 
         assert!(TransactionalMemory::new(
-            file,
-            false,
+            StorageSource::File(file),
             PAGE_SIZE,
             None,
             None,
             0,
             0,
-            Some(WriteStrategy::TwoPhase)
+            Some(WriteStrategy::TwoPhase),
+            None
         )
         .unwrap()
         .needs_repair()
@@ -489,14 +523,14 @@ mod test {
         file.write_all(&[0; size_of::<u128>()]).unwrap();
 
         assert!(TransactionalMemory::new(
-            file,
-            false,
+            StorageSource::File(file),
             PAGE_SIZE,
             None,
             None,
             0,
             0,
-            Some(WriteStrategy::Checksum)
+            Some(WriteStrategy::Checksum),
+            None
         )
         .unwrap()
         .needs_repair()
@@ -545,14 +579,14 @@ mod test {
         file.write_all(&buffer).unwrap();
 
         assert!(TransactionalMemory::new(
-            file,
-            false,
+            StorageSource::File(file),
             PAGE_SIZE,
             None,
             None,
             0,
             0,
-            Some(WriteStrategy::TwoPhase)
+            Some(WriteStrategy::TwoPhase),
+            None
         )
         .unwrap()
         .needs_repair()
@@ -614,14 +648,14 @@ mod test {
         file.write_all(&buffer).unwrap();
 
         assert!(TransactionalMemory::new(
-            file,
-            false,
+            StorageSource::File(file),
             PAGE_SIZE,
             None,
             None,
             0,
             0,
             Some(WriteStrategy::Checksum),
+            None
         )
         .unwrap()
         .needs_repair()
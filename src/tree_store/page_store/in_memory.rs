@@ -0,0 +1,152 @@
+use crate::transaction_tracker::TransactionId;
+use crate::tree_store::page_store::base::{
+    CacheStats, PageHack, PageHackMut, PageHint, PhysicalStorage,
+};
+use crate::tree_store::page_store::cached_file::WritablePage;
+use crate::Result;
+use std::collections::BTreeMap;
+use std::mem;
+use std::ops::{DerefMut, Range};
+use std::sync::{Arc, Mutex, RwLock};
+
+// Storage backed by a growable heap buffer. Nothing is ever persisted, so flushing only needs to
+// make pending writes visible to readers
+pub(super) struct InMemoryStorage {
+    data: RwLock<Vec<u8>>,
+    write_buffer: Mutex<BTreeMap<u64, Arc<Vec<u8>>>>,
+}
+
+impl InMemoryStorage {
+    pub(super) fn new(len: u64) -> Self {
+        Self {
+            data: RwLock::new(vec![0; len.try_into().unwrap()]),
+            write_buffer: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn flush_write_buffer(&self) {
+        let write_buffer = mem::take(self.write_buffer.lock().unwrap().deref_mut());
+        let mut data = self.data.write().unwrap();
+        for (offset, buffer) in write_buffer {
+            let offset: usize = offset.try_into().unwrap();
+            data[offset..(offset + buffer.len())].copy_from_slice(&buffer);
+        }
+    }
+}
+
+impl PhysicalStorage for InMemoryStorage {
+    unsafe fn mark_transaction(&self, _id: TransactionId) {
+        // no-op
+    }
+
+    unsafe fn gc(&self, _oldest_live_id: TransactionId) -> Result {
+        // no-op
+        Ok(())
+    }
+
+    unsafe fn resize(&self, new_len: u64) -> Result {
+        // Discard pending writes that are beyond the end of the truncated buffer, and truncate any
+        // which extend past it, so that they can't be flushed out of bounds
+        let mut write_buffer = self.write_buffer.lock().unwrap();
+        write_buffer.retain(|offset, _| *offset < new_len);
+        if let Some((offset, buffer)) = write_buffer.iter_mut().next_back() {
+            if offset + buffer.len() as u64 > new_len {
+                Arc::make_mut(buffer).truncate((new_len - offset).try_into().unwrap());
+            }
+        }
+        drop(write_buffer);
+        self.data
+            .write()
+            .unwrap()
+            .resize(new_len.try_into().unwrap(), 0);
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result {
+        self.flush_write_buffer();
+        Ok(())
+    }
+
+    fn eventual_flush(&self) -> Result {
+        self.flush()
+    }
+
+    fn write_barrier(&self) -> Result {
+        self.flush()
+    }
+
+    unsafe fn read(&self, offset: u64, len: usize, hint: PageHint) -> Result<PageHack<'_>> {
+        if !matches!(hint, PageHint::Clean) {
+            if let Some(cached) = self.write_buffer.lock().unwrap().get(&offset) {
+                debug_assert_eq!(cached.len(), len);
+                return Ok(PageHack::ArcMem(cached.clone()));
+            }
+        }
+
+        Ok(PageHack::ArcMem(Arc::new(self.read_direct(offset, len)?)))
+    }
+
+    unsafe fn write(&self, offset: u64, len: usize) -> Result<PageHackMut<'_>> {
+        let existing = self.write_buffer.lock().unwrap().remove(&offset);
+        let data = if let Some(removed) = existing {
+            Arc::try_unwrap(removed).unwrap()
+        } else {
+            self.read_direct(offset, len)?
+        };
+        Ok(PageHackMut::Writable(WritablePage::new(
+            &self.write_buffer,
+            offset,
+            data,
+        )))
+    }
+
+    fn read_direct(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let offset: usize = offset.try_into().unwrap();
+        Ok(self.data.read().unwrap()[offset..(offset + len)].to_vec())
+    }
+
+    fn cancel_pending_write(&self, offset: u64, _len: usize) {
+        self.write_buffer.lock().unwrap().remove(&offset);
+    }
+
+    fn invalidate_cache(&self, _offset: u64, _len: usize) {
+        // no-op
+    }
+
+    fn prefetch(&self, _ranges: &[Range<u64>], _hint: PageHint) {
+        // no-op
+    }
+
+    fn can_prefetch(&self) -> bool {
+        false
+    }
+
+    fn hint(&self, _offset: u64, _page: &PageHack, _hint: PageHint) {
+        // no-op
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        // Not tracked
+        CacheStats::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_store::page_store::base::PhysicalStorage;
+    use crate::tree_store::page_store::in_memory::InMemoryStorage;
+
+    #[test]
+    fn resize_truncates_pending_write() {
+        let storage = InMemoryStorage::new(8192);
+        unsafe {
+            storage.write(2048, 4096).unwrap().as_mut().fill(1);
+            storage.write(6144, 2048).unwrap().as_mut().fill(2);
+            storage.resize(4096).unwrap();
+        }
+        storage.flush().unwrap();
+        assert_eq!(storage.read_direct(0, 2048).unwrap(), vec![0; 2048]);
+        assert_eq!(storage.read_direct(2048, 2048).unwrap(), vec![1; 2048]);
+    }
+}
@@ -3,6 +3,7 @@ use crate::{Error, Result};
 use std::fs::File;
 use std::io;
 use std::io::ErrorKind;
+use std::ops::Range;
 use std::slice;
 use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Mutex;
@@ -15,7 +16,9 @@ use unix::*;
 #[cfg(windows)]
 mod windows;
 use crate::transaction_tracker::TransactionId;
-use crate::tree_store::page_store::base::{PageHack, PageHackMut, PageHint, PhysicalStorage};
+use crate::tree_store::page_store::base::{
+    CacheStats, PageHack, PageHackMut, PageHint, PhysicalStorage,
+};
 #[cfg(windows)]
 use windows::*;
 
@@ -217,7 +220,7 @@ impl PhysicalStorage for Mmap {
 
     fn read_direct(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
         self.check_fsync_failure()?;
-        self.file.read(offset, len)
+        Ok(self.file.read(offset, len)?)
     }
 
     fn cancel_pending_write(&self, _offset: u64, _len: usize) {
@@ -227,4 +230,21 @@ impl PhysicalStorage for Mmap {
     fn invalidate_cache(&self, _offset: u64, _len: usize) {
         // no-op
     }
+
+    fn prefetch(&self, _ranges: &[Range<u64>], _hint: PageHint) {
+        // no-op
+    }
+
+    fn can_prefetch(&self) -> bool {
+        false
+    }
+
+    fn hint(&self, _offset: u64, _page: &PageHack, _hint: PageHint) {
+        // no-op
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        // Not tracked
+        CacheStats::default()
+    }
 }
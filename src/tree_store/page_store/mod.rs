@@ -2,20 +2,29 @@ mod base;
 mod bitmap;
 mod buddy_allocator;
 mod cached_file;
+mod encryption;
+mod file_backend;
 mod file_lock;
 mod header;
+mod in_memory;
 mod layout;
 mod mmap;
 mod page_manager;
 mod region;
 mod savepoint;
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+mod uring;
 mod utils;
 #[allow(dead_code)]
 mod xxh3;
 
+pub use base::{CacheStats, StorageBackend};
 pub(crate) use base::{Page, PageHint, PageNumber};
+pub(crate) use encryption::EncryptionKey;
 pub(crate) use header::PAGE_SIZE;
-pub(crate) use page_manager::{ChecksumType, TransactionalMemory, FILE_FORMAT_VERSION};
+pub(crate) use page_manager::{
+    ChecksumType, StorageSource, TransactionalMemory, FILE_FORMAT_VERSION,
+};
 pub use savepoint::Savepoint;
 
 pub(super) use base::{PageImpl, PageMut};
@@ -1,14 +1,20 @@
 use crate::db::WriteStrategy;
 use crate::transaction_tracker::TransactionId;
 use crate::tree_store::btree_base::Checksum;
-use crate::tree_store::page_store::base::{PageHint, PhysicalStorage};
+use crate::tree_store::page_store::base::{CacheStats, PageHint, PhysicalStorage, StorageBackend};
 use crate::tree_store::page_store::bitmap::{BtreeBitmap, BtreeBitmapMut};
 use crate::tree_store::page_store::buddy_allocator::BuddyAllocator;
 use crate::tree_store::page_store::cached_file::PagedCachedFile;
+use crate::tree_store::page_store::encryption;
+use crate::tree_store::page_store::encryption::{EncryptionKey, KeyCheck};
+use crate::tree_store::page_store::file_backend::FileBackend;
 use crate::tree_store::page_store::header::{DatabaseHeader, DB_HEADER_SIZE, MAGICNUMBER};
+use crate::tree_store::page_store::in_memory::InMemoryStorage;
 use crate::tree_store::page_store::layout::DatabaseLayout;
 use crate::tree_store::page_store::mmap::Mmap;
 use crate::tree_store::page_store::region::{RegionHeaderAccessor, RegionHeaderMutator};
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+use crate::tree_store::page_store::uring::{Uring, UringFile};
 use crate::tree_store::page_store::utils::is_page_aligned;
 use crate::tree_store::page_store::{hash128_with_seed, PageImpl, PageMut};
 use crate::tree_store::PageNumber;
@@ -24,7 +30,8 @@ use std::fs::File;
 #[cfg(unix)]
 use std::io;
 use std::mem::size_of;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::Mutex;
 
 // Regions have a maximum size of 4GiB. A `4GiB - overhead` value is the largest that can be represented,
@@ -39,7 +46,7 @@ const MIN_DESIRED_USABLE_BYTES: usize = 1024 * 1024;
 const NUM_REGIONS: u32 = 1000;
 
 // TODO: set to 1, when version 1.0 is released
-pub(crate) const FILE_FORMAT_VERSION: u8 = 109;
+pub(crate) const FILE_FORMAT_VERSION: u8 = 114;
 
 fn ceil_log2(x: usize) -> usize {
     if x.is_power_of_two() {
@@ -73,6 +80,26 @@ impl From<WriteStrategy> for ChecksumType {
     }
 }
 
+impl From<ChecksumType> for WriteStrategy {
+    fn from(checksum_type: ChecksumType) -> Self {
+        match checksum_type {
+            ChecksumType::XXH3_128 => WriteStrategy::Checksum,
+            ChecksumType::Unused => WriteStrategy::TwoPhase,
+        }
+    }
+}
+
+// The storage that a database is stored in
+#[derive(Debug)]
+pub(crate) enum StorageSource {
+    File(File),
+    // A file which is opened with a shared lock, and is never written to
+    ReadOnlyFile(File),
+    Mmap(File),
+    InMemory,
+    Backend(Box<dyn StorageBackend>),
+}
+
 impl From<u8> for ChecksumType {
     fn from(x: u8) -> Self {
         match x {
@@ -424,6 +451,10 @@ pub(crate) struct TransactionalMemory {
     log_since_commit: Mutex<Vec<AllocationOp>>,
     // True if the allocator state was corrupted when the file was opened
     needs_recovery: bool,
+    // True if the storage must never be written to
+    read_only: bool,
+    #[cfg(feature = "encryption")]
+    encryption_key: Option<EncryptionKey>,
     // TODO: should be a compile-time type parameter
     storage: Box<dyn PhysicalStorage>,
     state: Mutex<InMemoryState>,
@@ -438,6 +469,10 @@ pub(crate) struct TransactionalMemory {
     read_page_ref_counts: Mutex<HashMap<PageNumber, u64>>,
     // Indicates that a non-durable commit has been made, so reads should be served from the secondary meta page
     read_from_secondary: AtomicBool,
+    // Checksum type of the pages written by the active transaction. This is the checksum type of the
+    // database, unless the transaction is changing it. It's stored separately from the header, since
+    // it's needed every time a page checksum is computed
+    checksum_type: AtomicU8,
     page_size: u32,
     // We store these separately from the layout because they're static, and accessed on the get_page()
     // code path where there is no locking
@@ -452,14 +487,14 @@ pub(crate) struct TransactionalMemory {
 impl TransactionalMemory {
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
-        file: File,
-        use_mmap: bool,
+        source: StorageSource,
         page_size: usize,
         requested_region_size: Option<usize>,
         initial_size: Option<u64>,
         read_cache_size_bytes: usize,
         write_cache_size_bytes: usize,
         write_strategy: Option<WriteStrategy>,
+        encryption_key: Option<EncryptionKey>,
     ) -> Result<Self> {
         assert!(page_size.is_power_of_two() && page_size >= DB_HEADER_SIZE);
 
@@ -490,23 +525,63 @@ impl TransactionalMemory {
             page_size.try_into().unwrap(),
         )?;
 
-        {
-            let file_len = file.metadata()?.len();
-
-            if file_len < layout.len() {
-                file.set_len(layout.len())?;
+        let read_only = matches!(source, StorageSource::ReadOnlyFile(_));
+        let use_mmap = matches!(source, StorageSource::Mmap(_));
+        // Encrypts the backend, if a key was provided, and then caches it
+        let open_backend =
+            |backend: Box<dyn StorageBackend>| -> Result<(PagedCachedFile, Option<KeyCheck>)> {
+                let (backend, key_check) =
+                    encryption::open_backend(backend, encryption_key, page_size as u64)?;
+                if !read_only && backend.len()? < layout.len() {
+                    backend.set_len(layout.len())?;
+                }
+                let storage = PagedCachedFile::new(
+                    backend,
+                    page_size as u64,
+                    read_cache_size_bytes,
+                    write_cache_size_bytes,
+                )?;
+                Ok((storage, key_check))
+            };
+        // Submits the file's I/O through io_uring, when the kernel supports it. Encrypted files always
+        // use the regular backend, since io_uring would bypass the encryption
+        let open_file = |file: File| -> Result<(Box<dyn PhysicalStorage>, Option<KeyCheck>)> {
+            #[cfg(all(target_os = "linux", feature = "io_uring"))]
+            let duplicate = file.try_clone()?;
+            let backend = if read_only {
+                FileBackend::new_read_only(file)?
+            } else {
+                FileBackend::new(file)?
+            };
+            let (storage, key_check) = open_backend(Box::new(backend))?;
+            #[cfg(all(target_os = "linux", feature = "io_uring"))]
+            if encryption_key.is_none() {
+                if let Some(uring) = Uring::new(duplicate) {
+                    return Ok((Box::new(UringFile::new(storage, uring)), key_check));
+                }
             }
-        }
-
-        let mut storage: Box<dyn PhysicalStorage> = if use_mmap {
-            Box::new(Mmap::new(file)?)
-        } else {
-            Box::new(PagedCachedFile::new(
-                file.try_clone().unwrap(),
-                page_size as u64,
-                read_cache_size_bytes,
-                write_cache_size_bytes,
-            )?)
+            Ok((Box::new(storage), key_check))
+        };
+        let (mut storage, key_check): (Box<dyn PhysicalStorage>, _) = match source {
+            StorageSource::File(file) | StorageSource::ReadOnlyFile(file) => open_file(file)?,
+            StorageSource::Backend(backend) => {
+                let (storage, key_check) = open_backend(backend)?;
+                (Box::new(storage), key_check)
+            }
+            StorageSource::Mmap(file) => {
+                if encryption_key.is_some() {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "Encryption is not supported by the mmap backend",
+                    )));
+                }
+                if file.metadata()?.len() < layout.len() {
+                    file.set_len(layout.len())?;
+                }
+                (Box::new(Mmap::new(file)?), None)
+            }
+            // In-memory databases are never persisted, so are never encrypted
+            StorageSource::InMemory => (Box::new(InMemoryStorage::new(layout.len())), None),
         };
 
         let magic_number: [u8; MAGICNUMBER.len()] = storage
@@ -515,6 +590,10 @@ impl TransactionalMemory {
             .unwrap();
 
         if magic_number != MAGICNUMBER {
+            // The file was never fully initialized
+            if read_only {
+                return Err(Error::RepairRequired);
+            }
             let mut allocators = Allocators::new(layout);
 
             // Allocate the region tracker in the zeroth region
@@ -539,6 +618,7 @@ impl TransactionalMemory {
                 DatabaseHeader::new(layout, checksum_type, TransactionId(0), tracker_page);
 
             header.recovery_required = false;
+            header.key_check = key_check;
             // Safety: we own the storage object and have no other references to this memory
             unsafe {
                 storage
@@ -561,7 +641,7 @@ impl TransactionalMemory {
             storage.flush()?;
         }
         let header_bytes = storage.read_direct(0, DB_HEADER_SIZE)?;
-        let (mut header, repair_info) = DatabaseHeader::from_bytes(&header_bytes);
+        let (mut header, repair_info) = DatabaseHeader::from_bytes(&header_bytes)?;
 
         if let Some(requested_strategy) = write_strategy {
             let checksum_type: ChecksumType = requested_strategy.into();
@@ -590,6 +670,9 @@ impl TransactionalMemory {
 
         let needs_recovery = header.recovery_required;
         if needs_recovery {
+            if read_only {
+                return Err(Error::RepairRequired);
+            }
             if repair_info.primary_corrupted {
                 header.swap_primary_slot();
             } else {
@@ -618,7 +701,17 @@ impl TransactionalMemory {
         let region_size = layout.full_region_layout().len();
         let region_header_size = layout.full_region_layout().data_section().start;
 
-        let state = InMemoryState::from_bytes(header, storage.as_ref())?;
+        let checksum_type: u8 = header.primary_slot().checksum_type.into();
+        // The allocator state is only flushed on a clean shutdown, so it may be stale or torn if
+        // recovery is required. It's rebuilt by the repair in that case, so isn't read
+        let state = if needs_recovery {
+            InMemoryState {
+                header,
+                allocators: Allocators::new(layout),
+            }
+        } else {
+            InMemoryState::from_bytes(header, storage.as_ref())?
+        };
 
         assert!(page_size >= DB_HEADER_SIZE);
 
@@ -626,6 +719,9 @@ impl TransactionalMemory {
             allocated_since_commit: Mutex::new(HashSet::new()),
             log_since_commit: Mutex::new(vec![]),
             needs_recovery,
+            read_only,
+            #[cfg(feature = "encryption")]
+            encryption_key,
             storage,
             layout: Mutex::new(InProgressLayout {
                 layout,
@@ -637,6 +733,7 @@ impl TransactionalMemory {
             #[cfg(debug_assertions)]
             read_page_ref_counts: Mutex::new(HashMap::new()),
             read_from_secondary: AtomicBool::new(false),
+            checksum_type: AtomicU8::new(checksum_type),
             page_size: page_size.try_into().unwrap(),
             region_size,
             region_header_with_padding_size: region_header_size,
@@ -645,6 +742,15 @@ impl TransactionalMemory {
         })
     }
 
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    #[cfg(feature = "encryption")]
+    pub(crate) fn encryption_key(&self) -> Option<EncryptionKey> {
+        self.encryption_key
+    }
+
     pub(crate) fn begin_writable(&self) -> Result {
         let mut state = self.state.lock().unwrap();
         assert!(!state.header.recovery_required);
@@ -664,17 +770,25 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn checksum_type(&self) -> ChecksumType {
-        self.state
-            .lock()
-            .unwrap()
-            .header
-            .primary_slot()
-            .checksum_type
+        self.checksum_type.load(Ordering::Acquire).into()
+    }
+
+    // Changes the checksum type used for pages written by the active transaction. It becomes the
+    // checksum type of the database when the transaction commits.
+    //
+    // Caller must ensure that all pages conform to the new checksum type before committing
+    pub(crate) fn set_in_progress_checksum_type(&self, checksum_type: ChecksumType) {
+        self.checksum_type
+            .store(checksum_type.into(), Ordering::Release);
     }
 
     pub(crate) fn repair_primary_corrupted(&self) {
         let mut state = self.state.lock().unwrap();
         state.header.swap_primary_slot();
+        self.checksum_type.store(
+            state.header.primary_slot().checksum_type.into(),
+            Ordering::Release,
+        );
         let mut layout = self.layout.lock().unwrap();
         layout.layout = state.header.primary_slot().layout;
         layout.tracker_page = state.header.primary_slot().region_tracker;
@@ -782,15 +896,12 @@ impl TransactionalMemory {
     }
 
     // Commit all outstanding changes and make them visible as the primary
-    //
-    // If new_checksum_type is provided, caller must ensure that all pages conform to the new checksum
     pub(crate) fn commit(
         &self,
         data_root: Option<(PageNumber, Checksum)>,
         freed_root: Option<(PageNumber, Checksum)>,
         transaction_id: TransactionId,
         eventual: bool,
-        new_checksum_type: Option<ChecksumType>,
     ) -> Result {
         // All mutable pages must be dropped, this ensures that when a transaction completes
         // no more writes can happen to the pages it allocated. Thus it is safe to make them visible
@@ -800,8 +911,7 @@ impl TransactionalMemory {
         assert!(!self.needs_recovery);
 
         let mut state = self.state.lock().unwrap();
-        let original_checksum_type = state.header.primary_slot().checksum_type;
-        let checksum_type = new_checksum_type.unwrap_or(original_checksum_type);
+        let checksum_type = self.checksum_type();
         let mut layout = self.layout.lock().unwrap();
 
         // Trim surplus file space, before finalizing the commit
@@ -889,6 +999,10 @@ impl TransactionalMemory {
         #[cfg(debug_assertions)]
         debug_assert!(self.open_dirty_pages.lock().unwrap().is_empty());
         let mut state = self.state.lock().unwrap();
+        self.checksum_type.store(
+            state.header.primary_slot().checksum_type.into(),
+            Ordering::Release,
+        );
         // The layout to restore
         let (restore, restore_tracker_page) = if self.read_from_secondary.load(Ordering::Acquire) {
             (
@@ -1012,14 +1126,11 @@ impl TransactionalMemory {
     // Safety: the caller must ensure that no references to the memory in `page` exist
     pub(crate) unsafe fn get_page_mut(&self, page_number: PageNumber) -> Result<PageMut> {
         #[cfg(debug_assertions)]
-        {
-            assert!(!self
-                .read_page_ref_counts
-                .lock()
-                .unwrap()
-                .contains_key(&page_number));
-            assert!(self.open_dirty_pages.lock().unwrap().insert(page_number));
-        }
+        assert!(!self
+            .read_page_ref_counts
+            .lock()
+            .unwrap()
+            .contains_key(&page_number));
 
         let address_range = page_number.address_range(
             self.page_size as u64,
@@ -1031,6 +1142,9 @@ impl TransactionalMemory {
             .try_into()
             .unwrap();
         let mem = self.storage.write(address_range.start, len)?;
+        // Only track the page once it's been successfully opened, so that it's untracked when dropped
+        #[cfg(debug_assertions)]
+        assert!(self.open_dirty_pages.lock().unwrap().insert(page_number));
 
         Ok(PageMut {
             mem,
@@ -1084,13 +1198,16 @@ impl TransactionalMemory {
         let region_index = page.region;
         // Free in the regional allocator
         let mut region = state.get_region_mut(region_index);
-        region
-            .allocator_mut()
-            .free(page.page_index as u64, page.page_order as usize);
+        let mut allocator = region.allocator_mut();
+        allocator.free(page.page_index as u64, page.page_order as usize);
+        // The freed page may have merged with its buddies, so use the highest free order
+        let highest_free = allocator.highest_free_order().unwrap();
+        drop(allocator);
+        drop(region);
         // Ensure that the region is marked as having free space
         state
             .get_region_tracker_mut()
-            .mark_free(page.page_order as usize, region_index as u64);
+            .mark_free(highest_free, region_index as u64);
         self.log_since_commit
             .lock()
             .unwrap()
@@ -1116,13 +1233,16 @@ impl TransactionalMemory {
             let mut state = self.state.lock().unwrap();
             // Free in the regional allocator
             let mut region = state.get_region_mut(page.region);
-            region
-                .allocator_mut()
-                .free(page.page_index as u64, page.page_order as usize);
+            let mut allocator = region.allocator_mut();
+            allocator.free(page.page_index as u64, page.page_order as usize);
+            // The freed page may have merged with its buddies, so use the highest free order
+            let highest_free = allocator.highest_free_order().unwrap();
+            drop(allocator);
+            drop(region);
             // Ensure that the region is marked as having free space
             state
                 .get_region_tracker_mut()
-                .mark_free(page.page_order as usize, page.region as u64);
+                .mark_free(highest_free, page.region as u64);
 
             self.log_since_commit
                 .lock()
@@ -1160,10 +1280,12 @@ impl TransactionalMemory {
         self.storage.gc(oldest_live_id)
     }
 
+    // If `lowest` is true, the free page with the lowest address is allocated
     fn allocate_helper(
         &self,
         state: &mut InMemoryState,
         required_order: usize,
+        lowest: bool,
     ) -> Result<Option<PageNumber>> {
         loop {
             let candidate_region =
@@ -1173,7 +1295,12 @@ impl TransactionalMemory {
                     return Ok(None);
                 };
             let mut region = state.get_region_mut(candidate_region);
-            if let Some(page) = region.allocator_mut().alloc(required_order) {
+            let page = if lowest {
+                region.allocator_mut().alloc_lowest(required_order)
+            } else {
+                region.allocator_mut().alloc(required_order)
+            };
+            if let Some(page) = page {
                 return Ok(Some(PageNumber::new(
                     candidate_region,
                     page.try_into().unwrap(),
@@ -1303,13 +1430,60 @@ impl TransactionalMemory {
         let mut layout = self.layout.lock().unwrap();
 
         let page_number =
-            if let Some(page_number) = self.allocate_helper(&mut state, required_order)? {
+            if let Some(page_number) = self.allocate_helper(&mut state, required_order, false)? {
                 page_number
             } else {
                 self.grow(&mut state, &mut layout, required_order)?;
-                self.allocate_helper(&mut state, required_order)?.unwrap()
+                self.allocate_helper(&mut state, required_order, false)?
+                    .unwrap()
+            };
+        drop(layout);
+        drop(state);
+
+        self.finish_allocation(page_number, allocation_size)
+    }
+
+    // Allocates a page in free space that lies before `page`. Returns None, if no such space exists.
+    // The database is never grown by this method
+    pub(crate) fn allocate_lower(
+        &self,
+        allocation_size: usize,
+        page: PageNumber,
+    ) -> Result<Option<PageMut<'_>>> {
+        let required_pages = (allocation_size + self.get_page_size() - 1) / self.get_page_size();
+        let required_order = ceil_log2(required_pages);
+
+        let mut state = self.state.lock().unwrap();
+        let page_number =
+            if let Some(page_number) = self.allocate_helper(&mut state, required_order, true)? {
+                page_number
+            } else {
+                return Ok(None);
             };
+        if !page_number.is_before(page) {
+            // Return the page to the allocator. It was never handed out, so it doesn't need to be logged
+            let mut region = state.get_region_mut(page_number.region);
+            let mut allocator = region.allocator_mut();
+            allocator.free(page_number.page_index as u64, required_order);
+            let highest_free = allocator.highest_free_order().unwrap();
+            drop(allocator);
+            drop(region);
+            state
+                .get_region_tracker_mut()
+                .mark_free(highest_free, page_number.region as u64);
+            return Ok(None);
+        }
+        drop(state);
 
+        self.finish_allocation(page_number, allocation_size)
+            .map(Some)
+    }
+
+    fn finish_allocation(
+        &self,
+        page_number: PageNumber,
+        allocation_size: usize,
+    ) -> Result<PageMut<'_>> {
         self.allocated_since_commit
             .lock()
             .unwrap()
@@ -1319,14 +1493,11 @@ impl TransactionalMemory {
             .unwrap()
             .push(AllocationOp::Allocate(page_number));
         #[cfg(debug_assertions)]
-        {
-            assert!(!self
-                .read_page_ref_counts
-                .lock()
-                .unwrap()
-                .contains_key(&page_number));
-            assert!(self.open_dirty_pages.lock().unwrap().insert(page_number));
-        }
+        assert!(!self
+            .read_page_ref_counts
+            .lock()
+            .unwrap()
+            .contains_key(&page_number));
 
         let address_range = page_number.address_range(
             self.page_size as u64,
@@ -1340,19 +1511,31 @@ impl TransactionalMemory {
 
         // Safety:
         // The address range we're returning was just allocated, so no other references exist
-        #[allow(unused_mut)]
-        let mut mem = unsafe { self.storage.write(address_range.start, len)? };
+        let mem = unsafe { self.storage.write(address_range.start, len)? };
         debug_assert!(mem.as_ref().len() >= allocation_size);
+        // Only track the page once it's been successfully opened, so that it's untracked when dropped
+        #[cfg(debug_assertions)]
+        assert!(self.open_dirty_pages.lock().unwrap().insert(page_number));
+        #[allow(unused_mut)]
+        let mut page = PageMut {
+            mem,
+            page_number,
+            #[cfg(debug_assertions)]
+            open_pages: &self.open_dirty_pages,
+            #[cfg(not(debug_assertions))]
+            _debug_lifetime: Default::default(),
+        };
 
         // TODO: move this into the mmap implementation
         #[cfg(unix)]
         if self.use_mmap {
-            let len = mem.as_ref().len();
+            let mem = page.memory_mut();
+            let len = mem.len();
             // If this is a large page, hint that it should be paged in
             if self.pages_are_os_page_aligned && len > self.get_page_size() {
                 let result = unsafe {
                     libc::madvise(
-                        mem.as_mut().as_mut_ptr() as *mut libc::c_void,
+                        mem.as_mut_ptr() as *mut libc::c_void,
                         len as libc::size_t,
                         libc::MADV_WILLNEED,
                     )
@@ -1366,17 +1549,10 @@ impl TransactionalMemory {
         #[cfg(debug_assertions)]
         {
             // Poison the memory in debug mode to help detect uninitialized reads
-            mem.as_mut().fill(0xFF);
+            page.memory_mut().fill(0xFF);
         }
 
-        Ok(PageMut {
-            mem,
-            page_number,
-            #[cfg(debug_assertions)]
-            open_pages: &self.open_dirty_pages,
-            #[cfg(not(debug_assertions))]
-            _debug_lifetime: Default::default(),
-        })
+        Ok(page)
     }
 
     pub(crate) fn count_allocated_pages(&self) -> Result<usize> {
@@ -1394,17 +1570,61 @@ impl TransactionalMemory {
     pub(crate) fn get_page_size(&self) -> usize {
         self.page_size.try_into().unwrap()
     }
+
+    // Length of the database file, including any uncommitted growth or shrinkage
+    pub(crate) fn get_file_len(&self) -> u64 {
+        self.layout.lock().unwrap().layout.len()
+    }
+
+    pub(crate) fn cache_stats(&self) -> CacheStats {
+        self.storage.cache_stats()
+    }
+
+    // Hint how `page`, which has already been read, should be cached
+    pub(crate) fn hint_page(&self, page: &PageImpl, hint: PageHint) {
+        let range = page.page_number.address_range(
+            self.page_size as u64,
+            self.region_size,
+            self.region_header_with_padding_size,
+            self.page_size,
+        );
+        self.storage.hint(range.start, &page.mem, hint);
+    }
+
+    // Returns false if prefetch() does nothing, so that callers needn't find pages to prefetch
+    pub(crate) fn can_prefetch(&self) -> bool {
+        self.storage.can_prefetch()
+    }
+
+    // Hint that the given pages will be read soon
+    pub(crate) fn prefetch(&self, pages: impl Iterator<Item = PageNumber>, hint: PageHint) {
+        let ranges: Vec<Range<u64>> = pages
+            .map(|page_number| {
+                page_number.address_range(
+                    self.page_size as u64,
+                    self.region_size,
+                    self.region_header_with_padding_size,
+                    self.page_size,
+                )
+            })
+            .collect();
+        self.storage.prefetch(&ranges, hint);
+    }
 }
 
 impl Drop for TransactionalMemory {
     fn drop(&mut self) {
+        if self.read_only {
+            return;
+        }
+
         // Commit any non-durable transactions that are outstanding
         if self.read_from_secondary.load(Ordering::Acquire) {
             if let Ok(non_durable_transaction_id) = self.get_last_committed_transaction_id() {
                 let root = self.get_data_root();
                 let freed_root = self.get_freed_root();
                 if self
-                    .commit(root, freed_root, non_durable_transaction_id, false, None)
+                    .commit(root, freed_root, non_durable_transaction_id, false)
                     .is_err()
                 {
                     eprintln!(
@@ -1,9 +1,20 @@
 use crate::transaction_tracker::{SavepointId, TransactionId, TransactionTracker};
-use crate::tree_store::page_store::ChecksumType;
+use crate::tree_store::page_store::{ChecksumType, FILE_FORMAT_VERSION};
 use crate::tree_store::{Checksum, PageNumber};
-use crate::Database;
+use crate::{Database, Error, Result};
+use std::mem::size_of;
 use std::sync::{Arc, Mutex};
 
+// Persistent savepoints are serialized as:
+// * version: 1 byte
+// * checksum type: 1 byte
+// * transaction id: 8 bytes
+// * data root, and freed root: each as 1 byte, which is 1 if the root is present, followed by the
+//   page number (8 bytes) and checksum (16 bytes)
+// * number of regions: 4 bytes
+// * for each region: the length of the allocator state (4 bytes), followed by the state
+//
+// All integers are little endian
 pub struct Savepoint {
     id: SavepointId,
     // Each savepoint has an associated read transaction id to ensure that any pages it references
@@ -15,6 +26,8 @@ pub struct Savepoint {
     freed_root: Option<(PageNumber, Checksum)>,
     regional_allocators: Vec<Vec<u8>>,
     transaction_tracker: Arc<Mutex<TransactionTracker>>,
+    // Persistent savepoints remain valid when dropped, until they are deleted from the database
+    ephemeral: bool,
 }
 
 impl Savepoint {
@@ -35,9 +48,86 @@ impl Savepoint {
             freed_root,
             regional_allocators,
             transaction_tracker: db.transaction_tracker(),
+            ephemeral: true,
         }
     }
 
+    pub(crate) fn from_bytes(
+        id: SavepointId,
+        data: &[u8],
+        transaction_tracker: Arc<Mutex<TransactionTracker>>,
+    ) -> Result<Self> {
+        let mut reader = SavepointReader { data };
+        let version = reader.read::<1>()?[0];
+        if version != FILE_FORMAT_VERSION {
+            return Err(Error::Corrupted(format!(
+                "Savepoint {} has unsupported version {version}",
+                id.0
+            )));
+        }
+        let checksum_type = match reader.read::<1>()?[0] {
+            x @ (1 | 2) => ChecksumType::from(x),
+            x => {
+                return Err(Error::Corrupted(format!(
+                    "Savepoint {} has invalid checksum type {x}",
+                    id.0
+                )))
+            }
+        };
+        let transaction_id = TransactionId(u64::from_le_bytes(reader.read()?));
+        let root = reader.read_root()?;
+        let freed_root = reader.read_root()?;
+        let num_regions = u32::from_le_bytes(reader.read()?);
+        let mut regional_allocators = vec![];
+        for _ in 0..num_regions {
+            let len = u32::from_le_bytes(reader.read()?);
+            regional_allocators.push(reader.read_slice(len.try_into().unwrap())?.to_vec());
+        }
+
+        Ok(Self {
+            id,
+            transaction_id,
+            version,
+            checksum_type,
+            root,
+            freed_root,
+            regional_allocators,
+            transaction_tracker,
+            ephemeral: false,
+        })
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut result = vec![self.version, self.checksum_type.into()];
+        result.extend_from_slice(&self.transaction_id.0.to_le_bytes());
+        for root in [self.root, self.freed_root] {
+            if let Some((page, checksum)) = root {
+                result.push(1);
+                result.extend_from_slice(&page.to_le_bytes());
+                result.extend_from_slice(&checksum.to_le_bytes());
+            } else {
+                result.push(0);
+            }
+        }
+        result.extend_from_slice(
+            &u32::try_from(self.regional_allocators.len())
+                .unwrap()
+                .to_le_bytes(),
+        );
+        for state in self.regional_allocators.iter() {
+            result.extend_from_slice(&u32::try_from(state.len()).unwrap().to_le_bytes());
+            result.extend_from_slice(state);
+        }
+
+        result
+    }
+
+    // Makes the savepoint persistent. It is then deallocated when it's deleted from the database,
+    // rather than when it's dropped
+    pub(crate) fn make_persistent(&mut self) {
+        self.ephemeral = false;
+    }
+
     pub(crate) fn get_version(&self) -> u8 {
         self.version
     }
@@ -73,9 +163,64 @@ impl Savepoint {
 
 impl Drop for Savepoint {
     fn drop(&mut self) {
-        self.transaction_tracker
-            .lock()
-            .unwrap()
-            .deallocate_savepoint(self);
+        if self.ephemeral {
+            self.transaction_tracker
+                .lock()
+                .unwrap()
+                .deallocate_savepoint(self.id, self.transaction_id);
+        }
+    }
+}
+
+struct SavepointReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SavepointReader<'a> {
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(Error::Corrupted("Savepoint is truncated".to_string()));
+        }
+        let (result, remaining) = self.data.split_at(len);
+        self.data = remaining;
+        Ok(result)
+    }
+
+    fn read<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.read_slice(N)?.try_into().unwrap())
+    }
+
+    fn read_root(&mut self) -> Result<Option<(PageNumber, Checksum)>> {
+        if self.read::<1>()?[0] == 0 {
+            return Ok(None);
+        }
+        let page = PageNumber::from_le_bytes(self.read()?);
+        let checksum = Checksum::from_le_bytes(self.read::<{ size_of::<Checksum>() }>()?);
+        Ok(Some((page, checksum)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transaction_tracker::{SavepointId, TransactionTracker};
+    use crate::tree_store::page_store::savepoint::Savepoint;
+    use crate::tree_store::page_store::FILE_FORMAT_VERSION;
+    use crate::Error;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn unknown_version() {
+        let tracker = Arc::new(Mutex::new(TransactionTracker::new()));
+        let mut data = vec![FILE_FORMAT_VERSION, 1];
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&0u32.to_le_bytes());
+        assert!(Savepoint::from_bytes(SavepointId(1), &data, tracker.clone()).is_ok());
+
+        data[0] = FILE_FORMAT_VERSION + 1;
+        assert!(matches!(
+            Savepoint::from_bytes(SavepointId(1), &data, tracker),
+            Err(Error::Corrupted(_))
+        ));
     }
 }
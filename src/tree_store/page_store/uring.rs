@@ -0,0 +1,382 @@
+use crate::transaction_tracker::TransactionId;
+use crate::tree_store::page_store::base::{
+    CacheStats, PageHack, PageHackMut, PageHint, PhysicalStorage,
+};
+use crate::tree_store::page_store::cached_file::PagedCachedFile;
+use crate::Result;
+use io_uring::{opcode, squeue, types, IoUring, Probe};
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+// Maximum number of operations which are submitted to the ring at once
+const QUEUE_DEPTH: usize = 64;
+// Maximum number of prefetch hints which may be waiting for the background thread. Further hints
+// are dropped, rather than blocking the reader
+const PREFETCH_QUEUE_DEPTH: usize = 16;
+
+// The pages to prefetch, and the hint with which to cache them
+type PrefetchRequest = (Vec<Range<u64>>, PageHint);
+
+// Submits batches of reads and writes of a file through io_uring
+pub(super) struct Uring {
+    // A duplicate of the database file's descriptor
+    file: File,
+    // None if the ring failed, in which case I/O falls back to pread()/pwrite()
+    ring: Mutex<Option<IoUring>>,
+}
+
+impl Uring {
+    // Returns None if the kernel doesn't support io_uring, or the operations that are needed
+    pub(super) fn new(file: File) -> Option<Self> {
+        let ring = IoUring::new(QUEUE_DEPTH.try_into().unwrap()).ok()?;
+        let mut probe = Probe::new();
+        ring.submitter().register_probe(&mut probe).ok()?;
+        if !probe.is_supported(opcode::Read::CODE) || !probe.is_supported(opcode::Write::CODE) {
+            return None;
+        }
+
+        Some(Self {
+            file,
+            ring: Mutex::new(Some(ring)),
+        })
+    }
+
+    // Returns a separate ring for the same file
+    fn try_clone(&self) -> Option<Self> {
+        Self::new(self.file.try_clone().ok()?)
+    }
+
+    pub(super) fn write(&self, pages: &[(u64, Arc<Vec<u8>>)]) -> io::Result<()> {
+        let fd = types::Fd(self.file.as_raw_fd());
+        let mut ring = self.ring.lock().unwrap();
+        for chunk in pages.chunks(QUEUE_DEPTH) {
+            let entries = chunk.iter().map(|(offset, data)| {
+                opcode::Write::new(fd, data.as_ptr(), data.len().try_into().unwrap())
+                    .offset64((*offset).try_into().unwrap())
+                    .build()
+            });
+            let written = Self::submit(&mut ring, entries).unwrap_or_else(|_| {
+                // The kernel may still read the buffers of operations which were in flight, so
+                // they're leaked, and the writes redone synchronously
+                mem::forget(chunk.to_vec());
+                vec![0; chunk.len()]
+            });
+            for ((offset, data), written) in chunk.iter().zip(written) {
+                // Short and failed writes are completed synchronously. This also handles a failed
+                // ring
+                if written < data.len() {
+                    self.file
+                        .write_all_at(&data[written..], offset + written as u64)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn read(&self, ranges: &[Range<u64>]) -> io::Result<Vec<Vec<u8>>> {
+        let fd = types::Fd(self.file.as_raw_fd());
+        let mut buffers: Vec<Vec<u8>> = ranges
+            .iter()
+            .map(|range| vec![0; (range.end - range.start).try_into().unwrap()])
+            .collect();
+        let mut ring = self.ring.lock().unwrap();
+        for (chunk, chunk_buffers) in ranges
+            .chunks(QUEUE_DEPTH)
+            .zip(buffers.chunks_mut(QUEUE_DEPTH))
+        {
+            let entries = chunk
+                .iter()
+                .zip(chunk_buffers.iter_mut())
+                .map(|(range, buffer)| {
+                    opcode::Read::new(fd, buffer.as_mut_ptr(), buffer.len().try_into().unwrap())
+                        .offset64(range.start.try_into().unwrap())
+                        .build()
+                });
+            let read = Self::submit(&mut ring, entries).unwrap_or_else(|_| {
+                // The kernel may still write to the buffers of operations which were in flight, so
+                // they're leaked, and the reads redone synchronously into new ones
+                for buffer in chunk_buffers.iter_mut() {
+                    let len = buffer.len();
+                    mem::forget(mem::replace(buffer, vec![0; len]));
+                }
+                vec![0; chunk.len()]
+            });
+            for ((range, buffer), read) in chunk.iter().zip(chunk_buffers.iter_mut()).zip(read) {
+                // Short and failed reads are completed synchronously. This also handles a failed
+                // ring
+                if read < buffer.len() {
+                    self.file
+                        .read_exact_at(&mut buffer[read..], range.start + read as u64)?;
+                }
+            }
+        }
+
+        Ok(buffers)
+    }
+
+    // Submits the entries and waits for all of them to complete. Returns the number of bytes
+    // transferred by each one. Failed operations are reported as having transferred nothing, as are
+    // all of them if the ring has failed, so that they're redone with pwrite()/pread(), which
+    // return the error if it wasn't a transient one
+    //
+    // If the ring fails, it's abandoned and an error is returned. Operations may still be in flight
+    // in that case, so their buffers must not be freed or reused
+    fn submit(
+        ring: &mut Option<IoUring>,
+        entries: impl ExactSizeIterator<Item = squeue::Entry>,
+    ) -> io::Result<Vec<usize>> {
+        let count = entries.len();
+        let mut results = vec![0; count];
+        let uring = if let Some(uring) = ring.as_mut() {
+            uring
+        } else {
+            return Ok(vec![0; count]);
+        };
+        debug_assert!(count <= QUEUE_DEPTH);
+        for (i, entry) in entries.enumerate() {
+            // Safety: the buffers referenced by the entries are kept alive until the operations
+            // complete, or leaked if that can't be determined
+            unsafe {
+                uring
+                    .submission()
+                    .push(&entry.user_data(i.try_into().unwrap()))
+                    .unwrap();
+            }
+        }
+
+        let mut completed = 0;
+        while completed < count {
+            match uring.submit_and_wait(count - completed) {
+                Ok(_) => {}
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                    ) || err.raw_os_error() == Some(libc::EBUSY) => {}
+                Err(err) => {
+                    // Leak the ring, rather than risk the kernel accessing freed memory
+                    mem::forget(ring.take());
+                    return Err(err);
+                }
+            }
+            for cqe in uring.completion() {
+                let i: usize = cqe.user_data().try_into().unwrap();
+                // A negative result is an error, which leaves the operation to be redone
+                if let Ok(transferred) = usize::try_from(cqe.result()) {
+                    results[i] = transferred;
+                }
+                completed += 1;
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+// Caches pages in the same way as PagedCachedFile, but submits the writes of each flush as a
+// single batch through io_uring. Prefetched pages are read in batches by a background thread,
+// through a separate ring, so that neither readers nor commits wait on the other's I/O
+pub(super) struct UringFile {
+    cached: Arc<PagedCachedFile>,
+    uring: Uring,
+    // Sends the pages to prefetch to the background thread. None if it couldn't be started
+    prefetch_sender: Mutex<Option<SyncSender<PrefetchRequest>>>,
+    prefetch_thread: Option<JoinHandle<()>>,
+}
+
+impl UringFile {
+    pub(super) fn new(cached: PagedCachedFile, uring: Uring) -> Self {
+        let cached = Arc::new(cached);
+        let mut prefetch_sender = None;
+        let mut prefetch_thread = None;
+        if let Some(prefetch_ring) = uring.try_clone() {
+            let (sender, receiver) = sync_channel(PREFETCH_QUEUE_DEPTH);
+            let thread_cached = cached.clone();
+            if let Ok(thread) = thread::Builder::new()
+                .name("redb-prefetch".to_string())
+                .spawn(move || Self::prefetch_thread(&thread_cached, &prefetch_ring, receiver))
+            {
+                prefetch_sender = Some(sender);
+                prefetch_thread = Some(thread);
+            }
+        }
+
+        Self {
+            cached,
+            uring,
+            prefetch_sender: Mutex::new(prefetch_sender),
+            prefetch_thread,
+        }
+    }
+
+    // Reads each batch of pages which is received into the cache, until the sender is dropped
+    fn prefetch_thread(
+        cached: &PagedCachedFile,
+        ring: &Uring,
+        receiver: Receiver<PrefetchRequest>,
+    ) {
+        for (ranges, hint) in receiver {
+            // This is only a hint, so errors are ignored. The pages will be read again when needed
+            let _ = cached.fill_read_cache(&ranges, hint, |ranges| ring.read(ranges));
+        }
+    }
+}
+
+impl Drop for UringFile {
+    fn drop(&mut self) {
+        // The background thread exits once it's prefetched any pages still queued
+        self.prefetch_sender.get_mut().unwrap().take();
+        if let Some(thread) = self.prefetch_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl PhysicalStorage for UringFile {
+    unsafe fn mark_transaction(&self, id: TransactionId) {
+        self.cached.mark_transaction(id);
+    }
+
+    unsafe fn gc(&self, oldest_live_id: TransactionId) -> Result {
+        self.cached.gc(oldest_live_id)
+    }
+
+    unsafe fn resize(&self, new_len: u64) -> Result {
+        self.cached.resize(new_len)
+    }
+
+    fn flush(&self) -> Result {
+        self.write_barrier()?;
+        self.cached.flush()
+    }
+
+    fn eventual_flush(&self) -> Result {
+        self.write_barrier()?;
+        self.cached.eventual_flush()
+    }
+
+    fn write_barrier(&self) -> Result {
+        self.cached
+            .flush_write_buffer_with(|pages| self.uring.write(pages))
+    }
+
+    fn read_direct(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.cached.read_direct(offset, len)
+    }
+
+    unsafe fn read(&self, offset: u64, len: usize, hint: PageHint) -> Result<PageHack<'_>> {
+        self.cached.read(offset, len, hint)
+    }
+
+    unsafe fn write(&self, offset: u64, len: usize) -> Result<PageHackMut<'_>> {
+        self.cached.write(offset, len)
+    }
+
+    fn cancel_pending_write(&self, offset: u64, len: usize) {
+        self.cached.cancel_pending_write(offset, len);
+    }
+
+    fn invalidate_cache(&self, offset: u64, len: usize) {
+        self.cached.invalidate_cache(offset, len);
+    }
+
+    fn prefetch(&self, ranges: &[Range<u64>], hint: PageHint) {
+        // Pages which bypass the cache can only be hinted to the OS
+        if !matches!(hint, PageHint::Uncached) {
+            if let Some(sender) = self.prefetch_sender.lock().unwrap().as_ref() {
+                match sender.try_send((ranges.to_vec(), hint)) {
+                    // The hint is dropped if the background thread has fallen behind
+                    Ok(()) | Err(TrySendError::Full(_)) => return,
+                    Err(TrySendError::Disconnected(_)) => {}
+                }
+            }
+        }
+        self.cached.prefetch(ranges, hint);
+    }
+
+    fn can_prefetch(&self) -> bool {
+        true
+    }
+
+    fn hint(&self, offset: u64, page: &PageHack, hint: PageHint) {
+        self.cached.hint(offset, page, hint);
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        self.cached.cache_stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_store::page_store::base::{PageHint, PhysicalStorage};
+    use crate::tree_store::page_store::cached_file::PagedCachedFile;
+    use crate::tree_store::page_store::file_backend::FileBackend;
+    use crate::tree_store::page_store::uring::{Uring, UringFile};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn batched_io() {
+        let page_size = 4096;
+        let pages = 200;
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let file = tmpfile.reopen().unwrap();
+        file.set_len(page_size * pages).unwrap();
+        let uring = if let Some(uring) = Uring::new(file.try_clone().unwrap()) {
+            uring
+        } else {
+            // io_uring is not supported by this kernel
+            return;
+        };
+        let backend = FileBackend::new(file).unwrap();
+        let cached = PagedCachedFile::new(
+            Box::new(backend),
+            page_size,
+            1024 * 1024,
+            1024 * 1024 * 1024,
+        )
+        .unwrap();
+        let storage = UringFile::new(cached, uring);
+
+        let len = usize::try_from(page_size).unwrap();
+        for i in 0..pages {
+            let mut page = unsafe { storage.write(i * page_size, len).unwrap() };
+            page.as_mut().fill(u8::try_from(i).unwrap());
+        }
+        storage.flush().unwrap();
+        for i in 0..pages {
+            let data = storage.read_direct(i * page_size, len).unwrap();
+            assert!(data.iter().all(|x| *x == u8::try_from(i).unwrap()));
+        }
+
+        let ranges: Vec<_> = (0..pages)
+            .map(|i| (i * page_size)..((i + 1) * page_size))
+            .collect();
+        let before = storage.cache_stats();
+        storage.prefetch(&ranges, PageHint::None);
+        // The pages are read by the background thread
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while storage.cache_stats().bytes_read() - before.bytes_read() < page_size * pages {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(1));
+        }
+        let after = storage.cache_stats();
+        assert_eq!(after.bytes_read() - before.bytes_read(), page_size * pages);
+        for i in 0..pages {
+            let page = unsafe { storage.read(i * page_size, len, PageHint::None).unwrap() };
+            assert!(page.as_ref().iter().all(|x| *x == u8::try_from(i).unwrap()));
+        }
+        assert_eq!(storage.cache_stats().bytes_read(), after.bytes_read());
+    }
+}
@@ -1,7 +1,12 @@
+use crate::multimap_table::{copy_multimap_tree, relocate_multimap_tree};
 use crate::tree_store::btree::btree_stats;
 use crate::tree_store::btree_base::Checksum;
 use crate::tree_store::btree_iters::AllPageNumbersBtreeIter;
-use crate::tree_store::{BtreeMut, BtreeRangeIter, PageNumber, TransactionalMemory};
+use crate::tree_store::btree_mutator::RelocateHelper;
+use crate::tree_store::{
+    copy_tree, copy_tree_with_values, overflow, BtreeMut, BtreeRangeIter, Compression, PageHint,
+    PageNumber, TransactionalMemory,
+};
 use crate::types::{RedbKey, RedbValue, TypeName};
 use crate::{DatabaseStats, Error, Result};
 use std::cmp::max;
@@ -103,8 +108,13 @@ pub(crate) struct InternalTableDefinition {
     fixed_value_size: Option<usize>,
     key_alignment: usize,
     value_alignment: usize,
+    // Stored as its serialized byte, so that an invalid value is reported when the table is opened
+    compression: u8,
+    length: u64,
     key_type: TypeName,
     value_type: TypeName,
+    // Names of the indexes of the table which are up to date
+    indexes: Vec<String>,
 }
 
 impl InternalTableDefinition {
@@ -131,6 +141,18 @@ impl InternalTableDefinition {
     pub(crate) fn get_type(&self) -> TableType {
         self.table_type
     }
+
+    pub(crate) fn get_compression(&self) -> Result<Option<Compression>> {
+        Compression::from_byte(self.compression)
+    }
+
+    pub(crate) fn get_length(&self) -> u64 {
+        self.length
+    }
+
+    pub(crate) fn get_indexes(&self) -> &[String] {
+        &self.indexes
+    }
 }
 
 impl RedbValue for InternalTableDefinition {
@@ -145,7 +167,7 @@ impl RedbValue for InternalTableDefinition {
     where
         Self: 'a,
     {
-        debug_assert!(data.len() > 22);
+        debug_assert!(data.len() > 23);
         let mut offset = 0;
         let table_type = TableType::from(data[offset]);
         offset += 1;
@@ -211,6 +233,14 @@ impl RedbValue for InternalTableDefinition {
                 .unwrap(),
         ) as usize;
         offset += size_of::<u32>();
+        let compression = data[offset];
+        offset += 1;
+        let length = u64::from_le_bytes(
+            data[offset..(offset + size_of::<u64>())]
+                .try_into()
+                .unwrap(),
+        );
+        offset += size_of::<u64>();
 
         let key_type_len = u32::from_le_bytes(
             data[offset..(offset + size_of::<u32>())]
@@ -220,7 +250,26 @@ impl RedbValue for InternalTableDefinition {
         offset += size_of::<u32>();
         let key_type = TypeName::from_bytes(&data[offset..(offset + key_type_len)]);
         offset += key_type_len;
-        let value_type = TypeName::from_bytes(&data[offset..]);
+        let value_type_len = u32::from_le_bytes(
+            data[offset..(offset + size_of::<u32>())]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        offset += size_of::<u32>();
+        let value_type = TypeName::from_bytes(&data[offset..(offset + value_type_len)]);
+        offset += value_type_len;
+
+        let mut indexes = vec![];
+        while offset < data.len() {
+            let index_len = u32::from_le_bytes(
+                data[offset..(offset + size_of::<u32>())]
+                    .try_into()
+                    .unwrap(),
+            ) as usize;
+            offset += size_of::<u32>();
+            indexes.push(String::from_utf8(data[offset..(offset + index_len)].to_vec()).unwrap());
+            offset += index_len;
+        }
 
         InternalTableDefinition {
             table_root,
@@ -229,8 +278,11 @@ impl RedbValue for InternalTableDefinition {
             fixed_value_size,
             key_alignment,
             value_alignment,
+            compression,
+            length,
             key_type,
             value_type,
+            indexes,
         }
     }
 
@@ -265,10 +317,18 @@ impl RedbValue for InternalTableDefinition {
         }
         result.extend_from_slice(&u32::try_from(value.key_alignment).unwrap().to_le_bytes());
         result.extend_from_slice(&u32::try_from(value.value_alignment).unwrap().to_le_bytes());
+        result.push(value.compression);
+        result.extend_from_slice(&value.length.to_le_bytes());
         let key_type_bytes = value.key_type.to_bytes();
         result.extend_from_slice(&u32::try_from(key_type_bytes.len()).unwrap().to_le_bytes());
         result.extend_from_slice(&key_type_bytes);
-        result.extend_from_slice(&value.value_type.to_bytes());
+        let value_type_bytes = value.value_type.to_bytes();
+        result.extend_from_slice(&u32::try_from(value_type_bytes.len()).unwrap().to_le_bytes());
+        result.extend_from_slice(&value_type_bytes);
+        for index in value.indexes.iter() {
+            result.extend_from_slice(&u32::try_from(index.len()).unwrap().to_le_bytes());
+            result.extend_from_slice(index.as_bytes());
+        }
 
         result
     }
@@ -299,8 +359,9 @@ impl<'a> Iterator for TableNameIter<'a> {
 pub(crate) struct TableTree<'txn> {
     tree: BtreeMut<'txn, &'static str, InternalTableDefinition>,
     mem: &'txn TransactionalMemory,
-    // Cached updates from tables that have been closed. These must be flushed to the btree
-    pending_table_updates: HashMap<String, Option<(PageNumber, Checksum)>>,
+    // Cached updates to the root and length of tables that have been closed. These must be flushed
+    // to the btree
+    pending_table_updates: HashMap<String, (Option<(PageNumber, Checksum)>, u64)>,
     freed_pages: Arc<Mutex<Vec<PageNumber>>>,
 }
 
@@ -311,21 +372,22 @@ impl<'txn> TableTree<'txn> {
         freed_pages: Arc<Mutex<Vec<PageNumber>>>,
     ) -> Self {
         Self {
-            tree: BtreeMut::new(master_root, mem, freed_pages.clone()),
+            tree: BtreeMut::new(master_root, mem, freed_pages.clone()).with_hint(PageHint::Hot),
             mem,
             pending_table_updates: Default::default(),
             freed_pages,
         }
     }
 
-    // Queues an update to the table root
+    // Queues an update to the table root and length
     pub(crate) fn stage_update_table_root(
         &mut self,
         name: &str,
         table_root: Option<(PageNumber, Checksum)>,
+        length: u64,
     ) {
         self.pending_table_updates
-            .insert(name.to_string(), table_root);
+            .insert(name.to_string(), (table_root, length));
     }
 
     pub(crate) fn clear_table_root_updates(&mut self) {
@@ -333,15 +395,16 @@ impl<'txn> TableTree<'txn> {
     }
 
     pub(crate) fn flush_table_root_updates(&mut self) -> Result<Option<(PageNumber, Checksum)>> {
-        for (name, table_root) in self.pending_table_updates.drain() {
+        for (name, (table_root, length)) in self.pending_table_updates.drain() {
             // Bypass .get_table() since the table types are dynamic
             // TODO: optimize away this get()
             let mut definition = self.tree.get(&name.as_str()).unwrap().unwrap().value();
-            // No-op if the root has not changed
-            if definition.table_root == table_root {
+            // No-op if the table has not changed
+            if definition.table_root == table_root && definition.length == length {
                 continue;
             }
             definition.table_root = table_root;
+            definition.length = length;
             // Safety: References into the master table are never returned to the user
             unsafe {
                 self.tree.insert(&name.as_str(), &definition)?;
@@ -365,7 +428,13 @@ impl<'txn> TableTree<'txn> {
         &self,
         name: &str,
         table_type: TableType,
+        compression: Option<Compression>,
     ) -> Result<Option<InternalTableDefinition>> {
+        if compression.is_some() && V::fixed_width().is_some() {
+            return Err(Error::TableTypeMismatch(format!(
+                "{name:?} has fixed width values, which cannot be compressed",
+            )));
+        }
         if let Some(guard) = self.tree.get(&name)? {
             let mut definition = guard.value();
             if definition.get_type() != table_type {
@@ -383,6 +452,12 @@ impl<'txn> TableTree<'txn> {
                     V::type_name().name()
                 )));
             }
+            let stored_compression = definition.get_compression()?;
+            if stored_compression != compression {
+                return Err(Error::TableTypeMismatch(format!(
+                    "{name:?} is compressed with {stored_compression:?} not {compression:?}",
+                )));
+            }
             if definition.get_key_alignment() != K::ALIGNMENT {
                 return Err(Error::Corrupted(format!(
                     "{:?} key alignment {} does not match {}",
@@ -416,8 +491,9 @@ impl<'txn> TableTree<'txn> {
                 )));
             }
 
-            if let Some(updated_root) = self.pending_table_updates.get(name) {
+            if let Some((updated_root, updated_length)) = self.pending_table_updates.get(name) {
                 definition.table_root = *updated_root;
+                definition.length = *updated_length;
             }
 
             Ok(Some(definition))
@@ -426,44 +502,94 @@ impl<'txn> TableTree<'txn> {
         }
     }
 
+    // Returns the names of the indexes of the table `name` which are up to date
+    pub(crate) fn get_table_indexes(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self
+            .tree
+            .get(&name)?
+            .map(|guard| guard.value().indexes)
+            .unwrap_or_default())
+    }
+
+    // Records that `indexes` are the indexes of the table `name` which are up to date
+    pub(crate) fn set_table_indexes(&mut self, name: &str, indexes: Vec<String>) -> Result {
+        let mut definition = self.tree.get(&name)?.unwrap().value();
+        definition.indexes = indexes;
+        // Safety: References into the master table are never returned to the user
+        unsafe { self.tree.insert(&name, &definition)? };
+        Ok(())
+    }
+
     // root_page: the root of the master table
     pub(crate) fn delete_table<K: RedbKey, V: RedbValue>(
         &mut self,
         name: &str,
         table_type: TableType,
+        compression: Option<Compression>,
     ) -> Result<bool> {
-        if let Some(definition) = self.get_table::<K, V>(name, table_type)? {
-            if let Some((table_root, _)) = definition.get_root() {
-                let iter = AllPageNumbersBtreeIter::new(
-                    table_root,
-                    K::fixed_width(),
-                    V::fixed_width(),
-                    self.mem,
-                )?;
-                let mut freed_pages = self.freed_pages.lock().unwrap();
-                for page_number in iter {
-                    freed_pages.push(page_number);
-                }
-            }
+        if let Some(definition) = self.get_table::<K, V>(name, table_type, compression)? {
+            return self.delete_table_helper(name, definition);
+        }
 
-            self.pending_table_updates.remove(name);
+        Ok(false)
+    }
 
-            // Safety: References into the master table are never returned to the user
-            let found = unsafe { self.tree.remove(&name)?.is_some() };
-            return Ok(found);
+    // Like delete_table(), but for a table whose types are not known, such as an index which is
+    // no longer defined
+    pub(crate) fn delete_table_untyped(&mut self, name: &str) -> Result<bool> {
+        let definition = self.tree.get(&name)?.map(|guard| guard.value());
+        if let Some(mut definition) = definition {
+            if let Some((updated_root, updated_length)) = self.pending_table_updates.get(name) {
+                definition.table_root = *updated_root;
+                definition.length = *updated_length;
+            }
+            return self.delete_table_helper(name, definition);
         }
 
         Ok(false)
     }
 
+    fn delete_table_helper(
+        &mut self,
+        name: &str,
+        definition: InternalTableDefinition,
+    ) -> Result<bool> {
+        if let Some((table_root, _)) = definition.get_root() {
+            let iter = AllPageNumbersBtreeIter::new(
+                table_root,
+                definition.fixed_key_size,
+                definition.fixed_value_size,
+                self.mem,
+            )?;
+            let mut freed_pages = self.freed_pages.lock().unwrap();
+            for page_number in iter {
+                freed_pages.push(page_number);
+            }
+            if definition.table_type == TableType::Normal && definition.fixed_value_size.is_none() {
+                freed_pages.extend(overflow::tree_overflow_pages(
+                    table_root,
+                    definition.fixed_key_size,
+                    self.mem,
+                )?);
+            }
+        }
+
+        self.pending_table_updates.remove(name);
+
+        // Safety: References into the master table are never returned to the user
+        let found = unsafe { self.tree.remove(&name)?.is_some() };
+        Ok(found)
+    }
+
     // Returns a tuple of the table id and the new root page
     // root_page: the root of the master table
     pub(crate) fn get_or_create_table<K: RedbKey, V: RedbValue>(
         &mut self,
         name: &str,
         table_type: TableType,
+        compression: Option<Compression>,
     ) -> Result<InternalTableDefinition> {
-        if let Some(found) = self.get_table::<K, V>(name, table_type)? {
+        if let Some(found) = self.get_table::<K, V>(name, table_type, compression)? {
             return Ok(found);
         }
 
@@ -474,14 +600,130 @@ impl<'txn> TableTree<'txn> {
             fixed_value_size: V::fixed_width(),
             key_alignment: K::ALIGNMENT,
             value_alignment: V::ALIGNMENT,
+            compression: Compression::to_byte(compression),
+            length: 0,
             key_type: K::type_name(),
             value_type: V::type_name(),
+            indexes: vec![],
         };
         // Safety: References into the master table are never returned to the user
         unsafe { self.tree.insert(&name, &table)? };
         Ok(table)
     }
 
+    // Copies every table in `source`, which may belong to a different database, into this one.
+    // The copied trees are rebuilt bottom-up, so they contain no free space
+    pub(crate) fn copy_tables_from(&mut self, source: &TableTree) -> Result {
+        for entry in source.tree.range::<RangeFull, &str>(..)? {
+            let mut definition = entry.value();
+            if let Some((source_root, _)) = definition.table_root {
+                definition.table_root = match definition.table_type {
+                    TableType::Normal if definition.fixed_value_size.is_none() => {
+                        copy_tree_with_values(
+                            source_root,
+                            source.mem,
+                            self.mem,
+                            definition.fixed_key_size,
+                            None,
+                            &mut |value| overflow::copy_value(value, source.mem, self.mem),
+                        )?
+                    }
+                    TableType::Normal => copy_tree(
+                        source_root,
+                        source.mem,
+                        self.mem,
+                        definition.fixed_key_size,
+                        definition.fixed_value_size,
+                    )?,
+                    TableType::Multimap => copy_multimap_tree(
+                        source_root,
+                        source.mem,
+                        self.mem,
+                        definition.fixed_key_size,
+                        definition.fixed_value_size,
+                    )?,
+                };
+            }
+            // Safety: References into the master table are never returned to the user
+            unsafe { self.tree.insert(&entry.key(), &definition)? };
+        }
+
+        Ok(())
+    }
+
+    // Moves the pages of every table, and of the master table, into lower free space where possible.
+    // Returns true if any page was moved
+    pub(crate) fn compact_tables(&mut self) -> Result<bool> {
+        self.relocate_tables(false)
+    }
+
+    // Rewrites every page of every table, and of the master table, so that all checksums are
+    // recomputed with the current checksum type
+    pub(crate) fn rewrite_tables(&mut self) -> Result {
+        self.relocate_tables(true)?;
+        Ok(())
+    }
+
+    fn relocate_tables(&mut self, rewrite_all: bool) -> Result<bool> {
+        let mut relocated = false;
+        let mut updates = vec![];
+        let mem = self.mem;
+        {
+            let mut freed_pages = self.freed_pages.lock().unwrap();
+            let mut helper = if rewrite_all {
+                RelocateHelper::rewrite_all(self.mem, freed_pages.as_mut())
+            } else {
+                RelocateHelper::new(self.mem, freed_pages.as_mut())
+            };
+            for entry in self.tree.range::<RangeFull, &str>(..)? {
+                let mut definition = entry.value();
+                if let Some((updated_root, updated_length)) =
+                    self.pending_table_updates.get(entry.key())
+                {
+                    definition.table_root = *updated_root;
+                    definition.length = *updated_length;
+                }
+                if let Some((table_root, _)) = definition.table_root {
+                    let new_root = match definition.table_type {
+                        TableType::Normal if definition.fixed_value_size.is_none() => helper
+                            .relocate_with_values(
+                                table_root,
+                                definition.fixed_key_size,
+                                None,
+                                &mut |helper, value| overflow::relocate_value(helper, value, mem),
+                            )?,
+                        TableType::Normal => helper.relocate(
+                            table_root,
+                            definition.fixed_key_size,
+                            definition.fixed_value_size,
+                        )?,
+                        TableType::Multimap => relocate_multimap_tree(
+                            &mut helper,
+                            table_root,
+                            definition.fixed_key_size,
+                            definition.fixed_value_size,
+                        )?,
+                    };
+                    if new_root.is_some() {
+                        updates.push((entry.key().to_string(), new_root, definition.length));
+                    }
+                }
+            }
+            relocated |= helper.relocated();
+        }
+        for (name, table_root, length) in updates {
+            self.stage_update_table_root(&name, table_root, length);
+        }
+        self.flush_table_root_updates()?;
+        if rewrite_all {
+            self.tree.rewrite()?;
+        } else {
+            relocated |= self.tree.relocate()?;
+        }
+
+        Ok(relocated)
+    }
+
     pub fn stats(&self) -> Result<DatabaseStats> {
         let master_tree_stats = self.tree.stats()?;
         let mut max_subtree_height = 0;
@@ -496,7 +738,7 @@ impl<'txn> TableTree<'txn> {
 
         for entry in self.tree.range::<RangeFull, &str>(..)? {
             let mut definition = entry.value();
-            if let Some(updated_root) = self.pending_table_updates.get(entry.key()) {
+            if let Some((updated_root, _)) = self.pending_table_updates.get(entry.key()) {
                 definition.table_root = *updated_root;
             }
             let subtree_stats = btree_stats(
@@ -509,6 +751,23 @@ impl<'txn> TableTree<'txn> {
             total_stored_bytes += subtree_stats.stored_leaf_bytes;
             total_metadata_bytes += subtree_stats.metadata_bytes;
             total_fragmented += subtree_stats.fragmented_bytes;
+            if let (TableType::Normal, None, Some((root, _))) = (
+                definition.table_type,
+                definition.fixed_value_size,
+                definition.table_root,
+            ) {
+                let value_stats = overflow::tree_value_stats(
+                    root,
+                    definition.fixed_key_size,
+                    definition.get_compression()?,
+                    self.mem,
+                )?;
+                total_stored_bytes -= value_stats.leaf_header_bytes;
+                total_stored_bytes += value_stats.overflow_stored_bytes;
+                total_metadata_bytes +=
+                    value_stats.leaf_header_bytes + value_stats.overflow_metadata_bytes;
+                total_fragmented += value_stats.overflow_fragmented_bytes;
+            }
             branch_pages += subtree_stats.branch_pages;
             leaf_pages += subtree_stats.leaf_pages;
         }
@@ -527,7 +786,7 @@ impl<'txn> TableTree<'txn> {
 
 #[cfg(test)]
 mod test {
-    use crate::tree_store::{InternalTableDefinition, TableType};
+    use crate::tree_store::{Compression, InternalTableDefinition, TableType};
     use crate::types::TypeName;
     use crate::RedbValue;
 
@@ -540,8 +799,11 @@ mod test {
             fixed_value_size: Some(5),
             key_alignment: 6,
             value_alignment: 7,
+            compression: Compression::to_byte(Some(Compression::Lz)),
+            length: 8,
             key_type: TypeName::new("test::Key"),
             value_type: TypeName::new("test::Value"),
+            indexes: vec!["a".to_string(), "bc".to_string()],
         };
         let y = InternalTableDefinition::from_bytes(InternalTableDefinition::as_bytes(&x).as_ref());
         assert_eq!(x, y);
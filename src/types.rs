@@ -65,6 +65,21 @@ impl TypeName {
         }
     }
 
+    // Like from_bytes(), but for bytes from an untrusted source
+    pub(crate) fn try_from_bytes(bytes: &[u8]) -> Option<Self> {
+        let classification = match bytes.first()? {
+            1 => TypeClassification::Internal,
+            2 => TypeClassification::UserDefined,
+            _ => return None,
+        };
+        let name = std::str::from_utf8(&bytes[1..]).ok()?.to_string();
+
+        Some(Self {
+            classification,
+            name,
+        })
+    }
+
     pub(crate) fn name(&self) -> &str {
         &self.name
     }
@@ -1,6 +1,6 @@
 use redb::{
-    Database, MultimapTableDefinition, RangeIter, ReadableTable, RedbKey, RedbValue,
-    TableDefinition, TypeName,
+    Compression, Database, Error, MultimapTableDefinition, RangeIter, ReadableTable, RedbKey,
+    RedbValue, TableDefinition, TypeName,
 };
 use std::cmp::Ordering;
 use std::sync;
@@ -1268,3 +1268,128 @@ fn iter() {
         assert_eq!(i, v.value());
     }
 }
+
+#[test]
+fn in_memory() {
+    let db = Database::builder().create_in_memory().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(STR_TABLE).unwrap();
+        table.insert("hello", "aborted").unwrap();
+    }
+    write_txn.abort().unwrap();
+
+    // Insert enough data to grow the database
+    let value = vec![0u8; 1024];
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000 {
+            table.insert(&i, &i).unwrap();
+        }
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..5_000u64 {
+            table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..5_000u64 {
+            table.remove(i.to_le_bytes().as_slice()).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    assert!(read_txn.open_table(STR_TABLE).is_err());
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 10_000);
+    for i in 0..10_000 {
+        assert_eq!(i, table.get(&i).unwrap().unwrap().value());
+    }
+    let table = read_txn.open_table(SLICE_TABLE).unwrap();
+    assert!(table.is_empty().unwrap());
+}
+
+#[test]
+fn compression() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let compressed: TableDefinition<u64, &str> =
+        TableDefinition::new("compressed").with_compression(Compression::Lz);
+    let uncompressed: TableDefinition<u64, &str> = TableDefinition::new("compressed");
+
+    let json = r#"{"id": 1, "tags": ["a", "b", "c"], "description": "some text"}"#.repeat(50);
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(compressed).unwrap();
+        for i in 0..100 {
+            table.insert(&i, json.as_str()).unwrap();
+        }
+        table.insert(&100, "small").unwrap();
+        let old = table.insert(&0, "replaced").unwrap().unwrap();
+        assert_eq!(old.value(), json);
+        drop(old);
+        let old = table.remove(&1).unwrap().unwrap();
+        assert_eq!(old.value(), json);
+        drop(old);
+        table
+            .insert_reserve(&1, 3)
+            .unwrap()
+            .as_mut()
+            .copy_from_slice(b"abc");
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(compressed).unwrap();
+        // Predicates see the decompressed values
+        let drained: Vec<u64> = table
+            .drain_filter(0.., |_, v| v.len() < 10)
+            .unwrap()
+            .map(|(k, v)| {
+                assert!(v.value().len() < 10);
+                k.value()
+            })
+            .collect();
+        assert_eq!(drained, vec![0, 1, 100]);
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    assert!(write_txn.stats().unwrap().stored_bytes() < 98 * json.len() / 10);
+    write_txn.abort().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(compressed).unwrap();
+    assert_eq!(table.len().unwrap(), 98);
+    assert_eq!(table.get(&2).unwrap().unwrap().value(), json);
+    for (key, value) in table.iter().unwrap() {
+        assert!(key.value() >= 2);
+        assert_eq!(value.value(), json);
+    }
+    assert!(matches!(
+        read_txn.open_table(uncompressed).err().unwrap(),
+        Error::TableTypeMismatch(_)
+    ));
+
+    // Fixed width values can't be compressed
+    let fixed: TableDefinition<u64, u64> =
+        TableDefinition::new("fixed").with_compression(Compression::Lz);
+    let write_txn = db.begin_write().unwrap();
+    assert!(matches!(
+        write_txn.open_table(uncompressed).err().unwrap(),
+        Error::TableTypeMismatch(_)
+    ));
+    assert!(matches!(
+        write_txn.open_table(fixed).err().unwrap(),
+        Error::TableTypeMismatch(_)
+    ));
+}
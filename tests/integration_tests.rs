@@ -1,13 +1,21 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, Bound};
 use std::fs;
-use std::io::ErrorKind;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use std::{panic, thread};
 use tempfile::NamedTempFile;
 
 use rand::prelude::SliceRandom;
 use rand::Rng;
 use redb::ReadableMultimapTable;
 use redb::{
-    Builder, Database, Durability, Error, MultimapTableDefinition, ReadableTable, TableDefinition,
-    WriteStrategy,
+    Builder, ChangeSet, Compression, Database, Durability, Error, IndexDefinition,
+    MultimapTableDefinition, OptimisticRangeIter, ReadableTable, ReplicatedTable, StorageBackend,
+    TableDefinition, WriteStrategy,
 };
 
 const ELEMENTS: usize = 100;
@@ -776,6 +784,111 @@ fn regression18() {
     drop(savepoint4);
 }
 
+#[test]
+fn persistent_savepoint() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+    let value = vec![0u8; 10_000];
+
+    let write = |db: &Database, key: u64, value: &[u8]| {
+        let txn = db.begin_write().unwrap();
+        txn.open_table(definition)
+            .unwrap()
+            .insert(&key, &value)
+            .unwrap();
+        txn.commit().unwrap();
+    };
+    let read = |db: &Database, key: u64| -> Option<Vec<u8>> {
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(definition).unwrap();
+        let result = table.get(&key).unwrap().map(|x| x.value().to_vec());
+        result
+    };
+
+    write(&db, 0, &value);
+    let txn = db.begin_write().unwrap();
+    let savepoint = txn.persistent_savepoint().unwrap();
+    txn.commit().unwrap();
+    // Overwrite the values many times, so that their old pages would be reused if not protected
+    for i in 1..20 {
+        write(&db, 0, &[i; 10_000]);
+        write(&db, i.into(), &value);
+    }
+
+    // The savepoint is kept when the database is repaired
+    let copy: NamedTempFile = NamedTempFile::new().unwrap();
+    fs::copy(tmpfile.path(), copy.path()).unwrap();
+    let repaired = Database::open(copy.path()).unwrap();
+    for i in 0..20 {
+        write(&repaired, i, &[3; 10_000]);
+    }
+    let mut txn = repaired.begin_write().unwrap();
+    txn.restore_persistent_savepoint(savepoint).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(read(&repaired, 0).unwrap(), value);
+    assert!(read(&repaired, 1).is_none());
+    drop(repaired);
+
+    // The savepoint survives a restart
+    drop(db);
+    let db = Database::open(tmpfile.path()).unwrap();
+    let mut txn = db.begin_write().unwrap();
+    assert_eq!(
+        txn.list_persistent_savepoints()
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec![savepoint]
+    );
+    txn.restore_persistent_savepoint(savepoint).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(read(&db, 0).unwrap(), value);
+    assert!(read(&db, 1).is_none());
+
+    // Restoring a savepoint deletes the persistent savepoints created after it
+    let txn = db.begin_write().unwrap();
+    let savepoint2 = txn.persistent_savepoint().unwrap();
+    txn.commit().unwrap();
+    assert!(savepoint2 > savepoint);
+    write(&db, 1, &value);
+    let mut txn = db.begin_write().unwrap();
+    txn.restore_persistent_savepoint(savepoint).unwrap();
+    assert_eq!(
+        txn.list_persistent_savepoints()
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec![savepoint]
+    );
+    assert!(matches!(
+        txn.restore_persistent_savepoint(savepoint2).err().unwrap(),
+        Error::InvalidSavepoint
+    ));
+    txn.commit().unwrap();
+
+    // A savepoint created in an aborted transaction is not stored
+    let txn = db.begin_write().unwrap();
+    txn.persistent_savepoint().unwrap();
+    txn.abort().unwrap();
+    let txn = db.begin_write().unwrap();
+    assert_eq!(txn.list_persistent_savepoints().unwrap().count(), 1);
+    txn.abort().unwrap();
+
+    let mut db = db;
+    assert!(matches!(
+        db.compact().err().unwrap(),
+        Error::PersistentSavepointExists
+    ));
+    let txn = db.begin_write().unwrap();
+    assert!(txn.delete_persistent_savepoint(savepoint).unwrap());
+    assert!(!txn.delete_persistent_savepoint(savepoint).unwrap());
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    assert_eq!(txn.list_persistent_savepoints().unwrap().count(), 0);
+    txn.abort().unwrap();
+    db.compact().unwrap();
+    assert_eq!(read(&db, 0).unwrap(), value);
+}
+
 #[test]
 fn regression19() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
@@ -1234,3 +1347,1918 @@ fn savepoint() {
     txn.restore_savepoint(&savepoint).unwrap();
     txn.commit().unwrap();
 }
+
+#[test]
+fn read_at_savepoint() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u32, &str> = TableDefinition::new("x");
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.insert(&0, "hello").unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    let savepoint = txn.savepoint().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.insert(&0, "world").unwrap();
+        table.insert(&1, "new").unwrap();
+    }
+    txn.commit().unwrap();
+
+    let old_txn = db.begin_read_at(&savepoint).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap().value(), "world");
+    assert_eq!(table.len().unwrap(), 2);
+
+    // The snapshot remains readable after the savepoint is dropped, and its pages are reused
+    drop(savepoint);
+    for i in 0..100 {
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(definition).unwrap();
+            table.insert(&0, "overwritten").unwrap();
+            table.insert(&(i + 2), "more").unwrap();
+        }
+        txn.commit().unwrap();
+    }
+    let table = old_txn.open_table(definition).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap().value(), "hello");
+    assert_eq!(table.len().unwrap(), 1);
+
+    // Savepoints which were invalidated by restoring an older one can't be read
+    let txn = db.begin_write().unwrap();
+    let savepoint = txn.savepoint().unwrap();
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    let savepoint2 = txn.savepoint().unwrap();
+    txn.commit().unwrap();
+    let mut txn = db.begin_write().unwrap();
+    txn.restore_savepoint(&savepoint).unwrap();
+    txn.commit().unwrap();
+    assert!(matches!(
+        db.begin_read_at(&savepoint2).err().unwrap(),
+        Error::InvalidSavepoint
+    ));
+    assert!(db.begin_read_at(&savepoint).is_ok());
+}
+
+#[test]
+fn cache_stats() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let reports = Arc::new(AtomicU64::new(0));
+    let reports2 = reports.clone();
+    let db = Builder::new()
+        .set_cache_stats_callback(move |stats| {
+            assert!(stats.fsyncs() > 0);
+            reports2.fetch_add(1, Ordering::Relaxed);
+        })
+        .create(tmpfile.path())
+        .unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    assert_eq!(reports.load(Ordering::Relaxed), 1);
+    let stats = db.cache_stats();
+    assert!(stats.fsyncs() > 0);
+    assert!(stats.fsync_time() >= stats.max_fsync_time());
+    assert!(stats.bytes_written() > 0);
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let read = || {
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(U64_TABLE).unwrap();
+        assert_eq!(table.get(&7).unwrap().unwrap().value(), 7);
+    };
+    read();
+    let stats = db.cache_stats();
+    assert!(stats.read_misses() > 0);
+    assert!(stats.bytes_read() > 0);
+    assert!(stats.read_cache_bytes() > 0);
+    // The same pages are read again, from the cache
+    read();
+    let stats2 = db.cache_stats();
+    assert!(stats2.read_hits() > stats.read_hits());
+    assert_eq!(stats2.read_misses(), stats.read_misses());
+    assert_eq!(stats2.evictions(), 0);
+}
+
+#[test]
+fn range_uncached() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+    let value = [1u8; 100];
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        for i in 0..10_000u64 {
+            table.insert(&i, value.as_slice()).unwrap();
+        }
+        // Uncommitted pages are read too
+        let mut iter = table.range_uncached(5..).unwrap();
+        assert_eq!(iter.next().unwrap().0.value(), 5);
+        assert_eq!(iter.next_back().unwrap().0.value(), 9_999);
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    let before = db.cache_stats().read_cache_bytes();
+    let mut expected = 0;
+    for (key, entry) in table.range_uncached::<u64>(..).unwrap() {
+        assert_eq!(key.value(), expected);
+        assert_eq!(entry.value(), value);
+        expected += 1;
+    }
+    assert_eq!(expected, 10_000);
+    assert_eq!(table.range_uncached(100..200).unwrap().count(), 100);
+    let uncached_growth = db.cache_stats().read_cache_bytes() - before;
+
+    // A normal scan caches the leaves
+    let before = db.cache_stats().read_cache_bytes();
+    for _ in table.iter().unwrap() {}
+    let cached_growth = db.cache_stats().read_cache_bytes() - before;
+    assert!(uncached_growth * 10 < cached_growth);
+}
+
+#[test]
+fn backup() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::builder()
+        .set_write_strategy(WriteStrategy::TwoPhase)
+        .create(tmpfile.path())
+        .unwrap();
+    let multimap_def: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("mm");
+
+    let value = vec![7u8; 500];
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            table
+                .insert(&i.to_be_bytes().as_slice(), &value.as_slice())
+                .unwrap();
+        }
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&1, &2).unwrap();
+        txn.open_table(STR_TABLE).unwrap();
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        for i in 0..1000 {
+            multimap.insert(&0, &i).unwrap();
+        }
+        multimap.insert(&1, &1).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..9_000u64 {
+            table.remove(i.to_be_bytes().as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    // Writes made after the snapshot, or while the backup is running, are not included
+    let read_txn = db.begin_read().unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&1, &3).unwrap();
+    }
+    let backup_dir = tempfile::tempdir().unwrap();
+    let backup_path = backup_dir.path().join("backup.redb");
+    read_txn.backup_to(&backup_path).unwrap();
+    write_txn.commit().unwrap();
+
+    // Refuse to overwrite an existing file
+    assert!(matches!(
+        read_txn.backup_to(&backup_path).err().unwrap(),
+        Error::Io(_)
+    ));
+    drop(read_txn);
+    // The backup is written to a temporary file, which is renamed once it's complete
+    assert_eq!(fs::read_dir(backup_dir.path()).unwrap().count(), 1);
+
+    let backup_len = fs::metadata(&backup_path).unwrap().len();
+    assert!(backup_len < tmpfile.as_file().metadata().unwrap().len());
+
+    let backup = Database::builder()
+        .set_write_strategy(WriteStrategy::TwoPhase)
+        .open(&backup_path)
+        .unwrap();
+    let txn = backup.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 1000);
+    let mut iter = table.iter().unwrap();
+    for i in 9_000..10_000u64 {
+        let (key, entry_value) = iter.next().unwrap();
+        assert_eq!(key.value(), i.to_be_bytes());
+        assert_eq!(entry_value.value(), value);
+    }
+    assert!(iter.next().is_none());
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&1).unwrap().unwrap().value(), 2);
+    let table = txn.open_table(STR_TABLE).unwrap();
+    assert!(table.is_empty().unwrap());
+    let multimap = txn.open_multimap_table(multimap_def).unwrap();
+    let values: Vec<u64> = multimap.get(&0).unwrap().map(|x| x.value()).collect();
+    assert_eq!(values, (0..1000).collect::<Vec<u64>>());
+    let values: Vec<u64> = multimap.get(&1).unwrap().map(|x| x.value()).collect();
+    assert_eq!(values, vec![1]);
+}
+
+#[derive(Debug, Clone, Default)]
+struct FaultyBackend {
+    data: Arc<RwLock<Vec<u8>>>,
+    fail: Arc<AtomicBool>,
+}
+
+impl FaultyBackend {
+    fn check_failure(&self) -> Result<(), io::Error> {
+        if self.fail.load(Ordering::SeqCst) {
+            Err(io::Error::from(ErrorKind::Other))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl StorageBackend for FaultyBackend {
+    fn len(&self) -> Result<u64, io::Error> {
+        Ok(self.data.read().unwrap().len() as u64)
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, io::Error> {
+        self.check_failure()?;
+        let offset = offset as usize;
+        Ok(self.data.read().unwrap()[offset..(offset + len)].to_vec())
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), io::Error> {
+        self.check_failure()?;
+        let offset = offset as usize;
+        self.data.write().unwrap()[offset..(offset + data.len())].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), io::Error> {
+        self.check_failure()?;
+        self.data.write().unwrap().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn sync_data(&self, _eventual: bool) -> Result<(), io::Error> {
+        self.check_failure()
+    }
+}
+
+#[test]
+fn custom_backend() {
+    let backend = FaultyBackend::default();
+    let db = Builder::new().create_with_backend(backend.clone()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &0).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &1).unwrap();
+    }
+    backend.fail.store(true, Ordering::SeqCst);
+    assert!(matches!(txn.commit().err().unwrap(), Error::Io(_)));
+    backend.fail.store(false, Ordering::SeqCst);
+    drop(db);
+
+    // The failed commit is not visible after reopening
+    let db = Builder::new().create_with_backend(backend).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap().value(), 0);
+}
+
+// Records the ranges which redb asks to be read ahead
+#[derive(Debug, Clone, Default)]
+struct ReadaheadBackend {
+    inner: FaultyBackend,
+    readaheads: Arc<Mutex<Vec<(u64, usize)>>>,
+}
+
+impl StorageBackend for ReadaheadBackend {
+    fn len(&self) -> Result<u64, io::Error> {
+        self.inner.len()
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, io::Error> {
+        self.inner.read(offset, len)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), io::Error> {
+        self.inner.write(offset, data)
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), io::Error> {
+        self.inner.set_len(len)
+    }
+
+    fn sync_data(&self, eventual: bool) -> Result<(), io::Error> {
+        self.inner.sync_data(eventual)
+    }
+
+    fn readahead(&self, offset: u64, len: usize) {
+        self.readaheads.lock().unwrap().push((offset, len));
+    }
+}
+
+#[test]
+fn range_prefetch() {
+    let backend = ReadaheadBackend::default();
+    let db = Builder::new().create_with_backend(backend.clone()).unwrap();
+    let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+    let value = [1u8; 100];
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        for i in 0..10_000u64 {
+            table.insert(&i, value.as_slice()).unwrap();
+        }
+    }
+    let stats = txn.stats().unwrap();
+    // The prefetched leaves must continue below the following branches, rather than stopping at
+    // the end of each one
+    assert!(stats.tree_height() > 2);
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Builder::new().create_with_backend(backend.clone()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    backend.readaheads.lock().unwrap().clear();
+
+    // Lookups, and scans which stay within a single leaf, don't prefetch anything
+    assert_eq!(table.get(&5).unwrap().unwrap().value(), value);
+    assert_eq!(table.range(0..10).unwrap().count(), 10);
+    assert!(backend.readaheads.lock().unwrap().is_empty());
+
+    let mut expected = 0;
+    for (key, _) in table.iter().unwrap() {
+        assert_eq!(key.value(), expected);
+        expected += 1;
+    }
+    assert_eq!(expected, 10_000);
+    // Every leaf is prefetched exactly once, other than the first and last, which were read when
+    // the iterator was created, and the second, since prefetching starts once the scan leaves the
+    // first one
+    let prefetched: usize = backend
+        .readaheads
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, len)| len)
+        .sum();
+    assert_eq!(prefetched / stats.page_size(), stats.leaf_pages() - 3);
+
+    // Leaves which are already cached aren't prefetched again
+    backend.readaheads.lock().unwrap().clear();
+    assert_eq!(table.iter().unwrap().rev().count(), 10_000);
+    assert!(backend.readaheads.lock().unwrap().is_empty());
+
+    // Reverse scans prefetch in the same way
+    drop(table);
+    drop(txn);
+    drop(db);
+    let db = Builder::new().create_with_backend(backend.clone()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    backend.readaheads.lock().unwrap().clear();
+    let mut expected = 10_000;
+    for (key, _) in table.iter().unwrap().rev() {
+        expected -= 1;
+        assert_eq!(key.value(), expected);
+    }
+    assert_eq!(expected, 0);
+    let prefetched: usize = backend
+        .readaheads
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, len)| len)
+        .sum();
+    assert_eq!(prefetched / stats.page_size(), stats.leaf_pages() - 3);
+}
+
+#[test]
+fn read_only() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &1).unwrap();
+    }
+    txn.commit().unwrap();
+
+    // The file is locked while it's open for writing
+    assert!(matches!(
+        Builder::new().open_read_only(tmpfile.path()).err().unwrap(),
+        Error::DatabaseAlreadyOpen
+    ));
+
+    // A copy of a database that is open for writing is not clean, and can't be repaired
+    let copy: NamedTempFile = NamedTempFile::new().unwrap();
+    fs::copy(tmpfile.path(), copy.path()).unwrap();
+    assert!(matches!(
+        Builder::new().open_read_only(copy.path()).err().unwrap(),
+        Error::RepairRequired
+    ));
+    drop(db);
+
+    let db = Builder::new().open_read_only(tmpfile.path()).unwrap();
+    let db2 = Builder::new().open_read_only(tmpfile.path()).unwrap();
+    assert!(matches!(
+        db.begin_write().err().unwrap(),
+        Error::DatabaseReadOnly
+    ));
+    assert!(matches!(
+        Database::open(tmpfile.path()).err().unwrap(),
+        Error::DatabaseAlreadyOpen
+    ));
+    for db in [&db, &db2] {
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(U64_TABLE).unwrap();
+        assert_eq!(table.get(&0).unwrap().unwrap().value(), 1);
+    }
+    drop(db);
+    drop(db2);
+
+    // Opening read-only must not modify the file
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap().value(), 1);
+}
+
+#[test]
+fn overflow_values() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let mut db = Database::create(tmpfile.path()).unwrap();
+    let compressed_def: TableDefinition<&[u8], &[u8]> =
+        TableDefinition::new("compressed").with_compression(Compression::Lz);
+    let large = gen_data(1, 0, 5_000_000).pop().unwrap().1;
+    let small = vec![1u8; 100];
+
+    let txn = db.begin_write().unwrap();
+    txn.open_table(SLICE_TABLE).unwrap();
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    let baseline_pages = txn.stats().unwrap().allocated_pages();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..3u8 {
+            table.insert(&[i].as_slice(), &large.as_slice()).unwrap();
+        }
+        // Overwrite a value that is stored in uncommitted overflow pages
+        let old = table.insert(&[0].as_slice(), &small.as_slice()).unwrap();
+        assert_eq!(old.unwrap().value(), large);
+        let mut table = txn.open_table(compressed_def).unwrap();
+        table.insert(&[0].as_slice(), &large.as_slice()).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.get(&[0].as_slice()).unwrap().unwrap().value(), small);
+    let values: Vec<Vec<u8>> = table
+        .range::<&[u8]>([1].as_slice()..)
+        .unwrap()
+        .map(|(_, v)| v.value().to_vec())
+        .collect();
+    assert_eq!(values, vec![large.clone(), large.clone()]);
+    let table = txn.open_table(compressed_def).unwrap();
+    assert_eq!(table.get(&[0].as_slice()).unwrap().unwrap().value(), large);
+    drop(txn);
+
+    // The overflow pages are reachable after a repair
+    let copy: NamedTempFile = NamedTempFile::new().unwrap();
+    fs::copy(tmpfile.path(), copy.path()).unwrap();
+    let repaired = Database::open(copy.path()).unwrap();
+    let txn = repaired.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(&[3].as_slice(), &small.as_slice()).unwrap();
+    }
+    txn.commit().unwrap();
+    let txn = repaired.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.get(&[1].as_slice()).unwrap().unwrap().value(), large);
+    drop(txn);
+    drop(repaired);
+
+    assert!(db.compact().unwrap());
+    let backup_dir = tempfile::tempdir().unwrap();
+    let backup_path = backup_dir.path().join("backup.redb");
+    db.begin_read().unwrap().backup_to(&backup_path).unwrap();
+    let backup = Database::open(&backup_path).unwrap();
+    for db in [&db, &backup] {
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(SLICE_TABLE).unwrap();
+        assert_eq!(table.get(&[2].as_slice()).unwrap().unwrap().value(), large);
+        let table = txn.open_table(compressed_def).unwrap();
+        assert_eq!(table.get(&[0].as_slice()).unwrap().unwrap().value(), large);
+    }
+
+    // Removing the values frees their overflow pages
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        assert_eq!(
+            table.remove(&[1].as_slice()).unwrap().unwrap().value(),
+            large
+        );
+        let drained: Vec<Vec<u8>> = table
+            .drain::<&[u8]>(..)
+            .unwrap()
+            .map(|(_, v)| v.value().to_vec())
+            .collect();
+        assert_eq!(drained, vec![small.clone(), large.clone()]);
+    }
+    assert!(txn.delete_table(compressed_def).unwrap());
+    txn.commit().unwrap();
+    // Pages freed by the previous transaction are only reclaimed by the next one
+    let txn = db.begin_write().unwrap();
+    txn.open_table(SLICE_TABLE).unwrap();
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    assert!(txn.stats().unwrap().allocated_pages() <= baseline_pages + 2);
+    txn.abort().unwrap();
+}
+
+#[test]
+fn reference_shaped_values() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    // Raw values of the same shape as an overflow reference must round-trip unchanged
+    let mut value = vec![2u8; 33];
+    value[1..9].copy_from_slice(&u64::MAX.to_le_bytes());
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(&[0].as_slice(), &value.as_slice()).unwrap();
+        table
+            .insert_stream(&[1].as_slice(), value.as_slice(), value.len())
+            .unwrap();
+        table
+            .insert_reserve(&[2].as_slice(), value.len())
+            .unwrap()
+            .as_mut()
+            .copy_from_slice(&value);
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    for key in 0..3u8 {
+        assert_eq!(
+            table.get(&[key].as_slice()).unwrap().unwrap().value(),
+            value
+        );
+    }
+    let mut iter = table.iter().unwrap();
+    for (_, v) in iter.by_ref() {
+        assert_eq!(v.value(), value);
+    }
+    iter.check().unwrap();
+}
+
+#[test]
+fn stream_values() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let compressed_def: TableDefinition<&[u8], &[u8]> =
+        TableDefinition::new("compressed").with_compression(Compression::Lz);
+    let large = gen_data(1, 0, 3_000_000).pop().unwrap().1;
+    let small = vec![1u8; 100];
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table
+            .insert_stream(&[0].as_slice(), large.as_slice(), large.len())
+            .unwrap();
+        table
+            .insert_stream(&[1].as_slice(), small.as_slice(), small.len())
+            .unwrap();
+        assert_eq!(table.get(&[0].as_slice()).unwrap().unwrap().value(), large);
+        assert_eq!(table.get(&[1].as_slice()).unwrap().unwrap().value(), small);
+
+        // A failed read leaves the table unchanged
+        let allocated_pages = txn.stats().unwrap().allocated_pages();
+        assert!(matches!(
+            table
+                .insert_stream(&[2].as_slice(), large.as_slice(), large.len() + 1)
+                .err()
+                .unwrap(),
+            Error::Io(_)
+        ));
+        assert!(table.get(&[2].as_slice()).unwrap().is_none());
+        assert_eq!(txn.stats().unwrap().allocated_pages(), allocated_pages);
+
+        let mut table = txn.open_table(compressed_def).unwrap();
+        table.insert(&[0].as_slice(), &small.as_slice()).unwrap();
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table
+            .insert_stream(&0, 5u64.to_le_bytes().as_slice(), 8)
+            .unwrap();
+        // The length of a fixed width value must match its type
+        assert!(matches!(
+            table.insert_stream(&1, [0u8; 4].as_slice(), 4),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(table.insert_reserve(&1, 4), Err(Error::Io(_))));
+        assert!(table.get(&1).unwrap().is_none());
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    let mut reader = table.get_reader(&[0].as_slice()).unwrap().unwrap();
+    assert_eq!(reader.len(), large.len() as u64);
+    let mut value = vec![];
+    reader.read_to_end(&mut value).unwrap();
+    assert_eq!(value, large);
+    // Read across a chunk boundary
+    let mut buffer = vec![0; 100_000];
+    reader.seek(SeekFrom::Start(1_000_000)).unwrap();
+    reader.read_exact(&mut buffer).unwrap();
+    assert_eq!(buffer, large[1_000_000..1_100_000]);
+    reader.seek(SeekFrom::End(-10)).unwrap();
+    let mut tail = vec![];
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, large[(large.len() - 10)..]);
+    assert!(reader
+        .seek(SeekFrom::Current(-(large.len() as i64) - 1))
+        .is_err());
+    let mut value = vec![];
+    table
+        .get_reader(&[1].as_slice())
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut value)
+        .unwrap();
+    assert_eq!(value, small);
+    assert!(table.get_reader(&[2].as_slice()).unwrap().is_none());
+
+    let table = txn.open_table(compressed_def).unwrap();
+    let mut value = vec![];
+    let mut reader = table.get_reader(&[0].as_slice()).unwrap().unwrap();
+    assert_eq!(reader.len(), small.len() as u64);
+    reader.read_to_end(&mut value).unwrap();
+    assert_eq!(value, small);
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap().value(), 5);
+    let mut value = vec![];
+    table
+        .get_reader(&0)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut value)
+        .unwrap();
+    assert_eq!(value, 5u64.to_le_bytes());
+}
+
+#[test]
+fn table_length() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let multimap_def: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("multimap");
+    let large = vec![1u8; 100_000];
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10 {
+            table.insert(&i, &i).unwrap();
+        }
+        // Overwriting a key does not change the length
+        table.insert(&0, &1).unwrap();
+        assert_eq!(table.len().unwrap(), 10);
+        table.remove(&0).unwrap();
+        table.remove(&0).unwrap();
+        table.pop_first().unwrap();
+        table.pop_last().unwrap();
+        assert_eq!(table.len().unwrap(), 7);
+        drop(table.drain(2..4).unwrap());
+        assert_eq!(table.len().unwrap(), 5);
+        drop(table.drain_filter(0..10, |k, _| k % 2 == 0).unwrap());
+        assert_eq!(table.len().unwrap(), 2);
+
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table.insert_reserve(&[0].as_slice(), 10).unwrap();
+        table.insert_reserve(&[0].as_slice(), 20).unwrap();
+        table
+            .insert_stream(&[1].as_slice(), large.as_slice(), large.len())
+            .unwrap();
+        table
+            .insert_stream(&[1].as_slice(), large.as_slice(), large.len())
+            .unwrap();
+        assert_eq!(table.len().unwrap(), 2);
+
+        let mut table = txn.open_multimap_table(multimap_def).unwrap();
+        for i in 0..3 {
+            for j in 0..100 {
+                table.insert(&i, &j).unwrap();
+            }
+        }
+        table.insert(&0, &0).unwrap();
+        table.remove(&0, &0).unwrap();
+        table.remove(&0, &0).unwrap();
+        assert_eq!(table.len().unwrap(), 299);
+        table.remove_all(&1).unwrap();
+        assert_eq!(table.len().unwrap(), 199);
+    }
+    txn.commit().unwrap();
+
+    // Changes made by an aborted transaction are rolled back
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&100, &100).unwrap();
+        assert_eq!(table.len().unwrap(), 3);
+        let mut table = txn.open_multimap_table(multimap_def).unwrap();
+        table.remove_all(&0).unwrap();
+        assert_eq!(table.len().unwrap(), 100);
+    }
+    txn.abort().unwrap();
+
+    drop(db);
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    assert_eq!(txn.open_table(U64_TABLE).unwrap().len().unwrap(), 2);
+    assert_eq!(txn.open_table(SLICE_TABLE).unwrap().len().unwrap(), 2);
+    let table = txn.open_multimap_table(multimap_def).unwrap();
+    assert_eq!(table.len().unwrap(), 199);
+    assert!(!table.is_empty().unwrap());
+}
+
+fn check_order_statistics<T: ReadableTable<u64, &'static [u8]>>(
+    table: &T,
+    expected: &BTreeMap<u64, Vec<u8>>,
+) {
+    for (i, (key, value)) in expected.iter().enumerate().step_by(7) {
+        let (nth_key, nth_value) = table.nth(i).unwrap().unwrap();
+        assert_eq!(nth_key.value(), *key);
+        assert_eq!(nth_value.value(), value.as_slice());
+        assert_eq!(table.rank(key).unwrap(), i);
+    }
+    assert!(table.nth(expected.len()).unwrap().is_none());
+
+    for start in (0..5100).step_by(97) {
+        assert_eq!(table.rank(start).unwrap(), expected.range(..start).count());
+        for end in (start..5100).step_by(389) {
+            assert_eq!(
+                table.range(start..end).unwrap().count(),
+                expected.range(start..end).count()
+            );
+            assert_eq!(
+                table.range(start..=end).unwrap().count(),
+                expected.range(start..=end).count()
+            );
+            let bounds = (Bound::Excluded(start), Bound::Included(end));
+            assert_eq!(
+                table.range::<u64>(bounds).unwrap().count(),
+                expected.range(bounds).count()
+            );
+        }
+        assert_eq!(
+            table.range(start..).unwrap().count(),
+            expected.range(start..).count()
+        );
+    }
+
+    let mut iter = table.range(100..4000).unwrap();
+    let mut expected_iter = expected.range(100..4000);
+    iter.next();
+    iter.next_back();
+    expected_iter.next();
+    expected_iter.next_back();
+    assert_eq!(iter.count(), expected_iter.count());
+}
+
+#[test]
+fn order_statistics() {
+    for strategy in [WriteStrategy::Checksum, WriteStrategy::TwoPhase] {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let db = Builder::new()
+            .set_write_strategy(strategy)
+            .create(tmpfile.path())
+            .unwrap();
+        let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let mut expected = BTreeMap::new();
+        let mut rng = rand::thread_rng();
+
+        for round in 0..8 {
+            let txn = db.begin_write().unwrap();
+            {
+                let mut table = txn.open_table(definition).unwrap();
+                if round % 3 == 2 {
+                    let start = rng.gen_range(0..4500u64);
+                    drop(table.drain(start..(start + 300)).unwrap());
+                    expected.retain(|key, _| *key < start || *key >= start + 300);
+                }
+                for _ in 0..1500 {
+                    let key = rng.gen_range(0..5000u64);
+                    if rng.gen_bool(0.3) {
+                        table.remove(&key).unwrap();
+                        expected.remove(&key);
+                    } else {
+                        // Occasionally insert a value larger than a page
+                        let len = if rng.gen_bool(0.02) {
+                            5000
+                        } else {
+                            rng.gen_range(0..400)
+                        };
+                        let value = vec![key as u8; len];
+                        if rng.gen_bool(0.5) {
+                            table.insert(&key, &value.as_slice()).unwrap();
+                        } else {
+                            let mut guard = table.insert_reserve(&key, len).unwrap();
+                            guard.as_mut().copy_from_slice(&value);
+                        }
+                        expected.insert(key, value);
+                    }
+                }
+                check_order_statistics(&table, &expected);
+            }
+            txn.commit().unwrap();
+        }
+
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(definition).unwrap();
+        check_order_statistics(&table, &expected);
+        drop(table);
+        drop(txn);
+
+        // Relocated pages keep their entry counts
+        let mut db = db;
+        db.compact().unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(definition).unwrap();
+        check_order_statistics(&table, &expected);
+    }
+}
+
+const BY_NAME: IndexDefinition<u64, (&str, u64), &str> =
+    IndexDefinition::new("by_name", |person| person.value().0);
+const BY_AGE: IndexDefinition<u64, (&str, u64), u64> =
+    IndexDefinition::new("by_age", |person| person.value().1);
+const PEOPLE: TableDefinition<u64, (&str, u64)> =
+    TableDefinition::new("people").with_indexes(&[&BY_NAME, &BY_AGE]);
+
+fn check_indexes<T: ReadableTable<u64, (&'static str, u64)>>(
+    table: &T,
+    expected: &BTreeMap<u64, (&str, u64)>,
+) {
+    let by_name = table.index(BY_NAME).unwrap();
+    let by_age = table.index(BY_AGE).unwrap();
+    assert_eq!(by_name.len().unwrap(), expected.len());
+    assert_eq!(by_age.len().unwrap(), expected.len());
+    for (name, age) in expected.values() {
+        let ids: Vec<u64> = by_name.get(*name).unwrap().map(|id| id.value()).collect();
+        let expected_ids: Vec<u64> = expected
+            .iter()
+            .filter(|(_, value)| value.0 == *name)
+            .map(|(id, _)| *id)
+            .collect();
+        assert_eq!(ids, expected_ids);
+        let ids: Vec<u64> = by_age.get(age).unwrap().map(|id| id.value()).collect();
+        let expected_ids: Vec<u64> = expected
+            .iter()
+            .filter(|(_, value)| value.1 == *age)
+            .map(|(id, _)| *id)
+            .collect();
+        assert_eq!(ids, expected_ids);
+    }
+}
+
+#[test]
+fn secondary_indexes() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let names = ["alice", "bob", "carol", "dave", "eve"];
+    let mut expected = BTreeMap::new();
+
+    // Indexes which are added to an existing table are built when it is opened
+    let txn = db.begin_write().unwrap();
+    {
+        let unindexed: TableDefinition<u64, (&str, u64)> = TableDefinition::new("people");
+        let mut table = txn.open_table(unindexed).unwrap();
+        for id in 0..10u64 {
+            let value = (names[id as usize % names.len()], 20 + id % 3);
+            table.insert(&id, &value).unwrap();
+            expected.insert(id, value);
+        }
+    }
+    {
+        let table = txn.open_table(PEOPLE).unwrap();
+        check_indexes(&table, &expected);
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(PEOPLE).unwrap();
+        // Overwrite
+        table.insert(&3, &("zoe", 99)).unwrap();
+        expected.insert(3, ("zoe", 99));
+        table.insert(&4, &("alice", 20)).unwrap();
+        expected.insert(4, ("alice", 20));
+        check_indexes(&table, &expected);
+
+        table.remove(&0).unwrap();
+        expected.remove(&0);
+        table.pop_first().unwrap();
+        expected.remove(&1);
+        table.pop_last().unwrap();
+        expected.remove(&9);
+        check_indexes(&table, &expected);
+
+        drop(table.drain(5..7).unwrap());
+        expected.retain(|id, _| !(5..7).contains(id));
+        check_indexes(&table, &expected);
+
+        // The predicate is only called once per entry
+        let calls = Cell::new(0);
+        let len = table.len().unwrap();
+        drop(
+            table
+                .drain_filter(0.., |_, value| {
+                    calls.set(calls.get() + 1);
+                    value.0 == "alice"
+                })
+                .unwrap(),
+        );
+        assert_eq!(calls.get(), len);
+        expected.retain(|_, value| value.0 != "alice");
+        check_indexes(&table, &expected);
+
+        assert!(matches!(table.insert_reserve(&100, 10), Err(Error::Io(_))));
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(PEOPLE).unwrap();
+    check_indexes(&table, &expected);
+    assert!(txn.list_multimap_tables().unwrap().next().is_none());
+    drop(table);
+    drop(txn);
+
+    // Aborted writes are rolled back from the indexes too
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(PEOPLE).unwrap();
+        table.insert(&100, &("mallory", 50)).unwrap();
+        let index = table.index(BY_NAME).unwrap();
+        let mut ids = index.get("mallory").unwrap();
+        assert_eq!(ids.next().unwrap().value(), 100);
+    }
+    txn.abort().unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(PEOPLE).unwrap();
+    check_indexes(&table, &expected);
+    assert!(table
+        .index(BY_NAME)
+        .unwrap()
+        .get("mallory")
+        .unwrap()
+        .next()
+        .is_none());
+    drop(table);
+    drop(txn);
+
+    // Writes which are made without the indexes leave them stale, until they're rebuilt
+    let txn = db.begin_write().unwrap();
+    {
+        let unindexed: TableDefinition<u64, (&str, u64)> = TableDefinition::new("people");
+        let mut table = txn.open_table(unindexed).unwrap();
+        table.insert(&2, &("mallory", 50)).unwrap();
+        expected.insert(2, ("mallory", 50));
+        assert!(matches!(
+            table.index(BY_NAME).err().unwrap(),
+            Error::TableDoesNotExist(_)
+        ));
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(PEOPLE).unwrap();
+    assert!(matches!(
+        table.index(BY_NAME).err().unwrap(),
+        Error::TableDoesNotExist(_)
+    ));
+    drop(table);
+    drop(txn);
+    let txn = db.begin_write().unwrap();
+    {
+        let table = txn.open_table(PEOPLE).unwrap();
+        check_indexes(&table, &expected);
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(PEOPLE).unwrap();
+    check_indexes(&table, &expected);
+    drop(table);
+    drop(txn);
+
+    // Deleting the table deletes its indexes
+    let txn = db.begin_write().unwrap();
+    assert!(txn.delete_table(PEOPLE).unwrap());
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let unindexed: TableDefinition<u64, (&str, u64)> = TableDefinition::new("people");
+        let table = txn.open_table(unindexed).unwrap();
+        assert!(matches!(
+            table.index(BY_NAME).err().unwrap(),
+            Error::TableDoesNotExist(_)
+        ));
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(PEOPLE).unwrap();
+    assert!(matches!(
+        table.index(BY_NAME).err().unwrap(),
+        Error::TableDoesNotExist(_)
+    ));
+}
+
+// The table, is multimap, key, old value, and new value of a change
+type DescribedChange = (String, bool, String, Option<String>, Option<String>);
+
+fn describe(changes: &ChangeSet) -> Vec<DescribedChange> {
+    let to_string = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).unwrap();
+    changes
+        .changes()
+        .iter()
+        .map(|change| {
+            (
+                change.table().to_string(),
+                change.is_multimap(),
+                to_string(change.key()),
+                change.old_value().map(to_string),
+                change.new_value().map(to_string),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn change_subscription() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let table_def: TableDefinition<&str, &[u8]> = TableDefinition::new("x");
+    let multimap_def: MultimapTableDefinition<&str, &str> = MultimapTableDefinition::new("y");
+    let entry = |table: &str, multimap, key: &str, old: Option<&str>, new: Option<&str>| {
+        (
+            table.to_string(),
+            multimap,
+            key.to_string(),
+            old.map(|x| x.to_string()),
+            new.map(|x| x.to_string()),
+        )
+    };
+
+    // Transactions which began before subscribing are not recorded
+    let txn = db.begin_write().unwrap();
+    let changes = db.subscribe();
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.insert("a", b"1".as_slice()).unwrap();
+    }
+    txn.commit().unwrap();
+    assert!(changes.try_recv().is_err());
+
+    let mut txn = db.begin_write().unwrap();
+    txn.set_durability(Durability::None);
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.insert("b", b"2".as_slice()).unwrap();
+        table.insert("a", b"3".as_slice()).unwrap();
+        table.remove("b").unwrap();
+        table.insert_reserve("c", 1).unwrap().as_mut()[0] = b'4';
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        multimap.insert("a", "5").unwrap();
+        multimap.insert("a", "6").unwrap();
+        // Already present
+        multimap.insert("a", "6").unwrap();
+        multimap.remove("a", "5").unwrap();
+        // Not present
+        multimap.remove("a", "7").unwrap();
+    }
+    txn.commit().unwrap();
+    let received = changes.try_recv().unwrap();
+    assert!(!received.restored_savepoint());
+    assert_eq!(
+        describe(&received),
+        vec![
+            entry("x", false, "b", None, Some("2")),
+            entry("x", false, "a", Some("1"), Some("3")),
+            entry("x", false, "b", Some("2"), None),
+            entry("x", false, "c", None, Some("4")),
+            entry("y", true, "a", None, Some("5")),
+            entry("y", true, "a", None, Some("6")),
+            entry("y", true, "a", Some("5"), None),
+        ]
+    );
+    assert!(changes.try_recv().is_err());
+
+    // Aborted transactions, and those without changes, are not published
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.insert("d", b"7".as_slice()).unwrap();
+    }
+    txn.abort().unwrap();
+    let txn = db.begin_write().unwrap();
+    txn.open_table(table_def).unwrap();
+    txn.commit().unwrap();
+    assert!(changes.try_recv().is_err());
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.drain::<&str>("b"..).unwrap();
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        multimap.insert("b", "8").unwrap();
+        multimap.remove_all("a").unwrap();
+    }
+    assert!(txn.delete_multimap_table(multimap_def).unwrap());
+    // Changes to indexes are not recorded
+    {
+        let mut table = txn.open_table(PEOPLE).unwrap();
+        table.insert(&0, &("alice", 30)).unwrap();
+    }
+    txn.commit().unwrap();
+    let received = changes.try_recv().unwrap();
+    let transaction_id = received.transaction_id();
+    let mut expected = vec![
+        entry("x", false, "c", Some("4"), None),
+        entry("y", true, "b", None, Some("8")),
+        entry("y", true, "a", Some("6"), None),
+        entry("y", true, "b", Some("8"), None),
+    ];
+    let described = describe(&received);
+    assert_eq!(&described[..expected.len()], &expected[..]);
+    assert_eq!(described.len(), expected.len() + 1);
+    assert_eq!(received.changes().last().unwrap().table(), "people");
+
+    // Restoring a savepoint discards the changes made before it
+    let txn = db.begin_write().unwrap();
+    let savepoint = txn.savepoint().unwrap();
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.insert("e", b"9".as_slice()).unwrap();
+    }
+    txn.commit().unwrap();
+    let received = changes.try_recv().unwrap();
+    assert!(received.transaction_id() > transaction_id);
+    expected = vec![entry("x", false, "e", None, Some("9"))];
+    assert_eq!(describe(&received), expected);
+    let mut txn = db.begin_write().unwrap();
+    txn.restore_savepoint(&savepoint).unwrap();
+    txn.commit().unwrap();
+    let received = changes.try_recv().unwrap();
+    assert!(received.restored_savepoint());
+    assert!(received.changes().is_empty());
+
+    // Dropping the receiver unsubscribes
+    drop(changes);
+    let changes = db.subscribe();
+    drop(changes);
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.insert("f", b"10".as_slice()).unwrap();
+    }
+    txn.commit().unwrap();
+    let changes = db.subscribe();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.remove("f").unwrap();
+    }
+    txn.commit().unwrap();
+    assert_eq!(
+        describe(&changes.try_recv().unwrap()),
+        vec![entry("x", false, "f", Some("10"), None)]
+    );
+}
+
+#[test]
+fn replication() {
+    let leader_file: NamedTempFile = NamedTempFile::new().unwrap();
+    let leader = Database::create(leader_file.path()).unwrap();
+    let follower_file: NamedTempFile = NamedTempFile::new().unwrap();
+    let follower = Database::create(follower_file.path()).unwrap();
+    let table_def: TableDefinition<u64, &str> = TableDefinition::new("x");
+    let multimap_def: MultimapTableDefinition<&str, u64> = MultimapTableDefinition::new("y");
+    let tables: &[&dyn ReplicatedTable] = &[&table_def, &multimap_def, &PEOPLE];
+
+    let changes = leader.subscribe();
+    let mut txn = leader.begin_write().unwrap();
+    txn.set_durability(Durability::None);
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        for i in 0..10 {
+            table.insert(&i, "a").unwrap();
+        }
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        multimap.insert("a", &1).unwrap();
+        multimap.insert("a", &2).unwrap();
+        let mut people = txn.open_table(PEOPLE).unwrap();
+        people.insert(&0, &("alice", 30)).unwrap();
+        people.insert(&1, &("bob", 40)).unwrap();
+    }
+    txn.commit().unwrap();
+    let txn = leader.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.insert(&3, "b").unwrap();
+        table.drain(5..).unwrap();
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        multimap.remove("a", &1).unwrap();
+        multimap.insert("b", &3).unwrap();
+        let mut people = txn.open_table(PEOPLE).unwrap();
+        people.insert(&0, &("carol", 31)).unwrap();
+    }
+    txn.commit().unwrap();
+
+    // Ship the changesets through a byte stream
+    let mut stream = vec![];
+    while let Ok(changeset) = changes.try_recv() {
+        changeset.write_to(&mut stream).unwrap();
+    }
+    let mut reader = stream.as_slice();
+    let first = ChangeSet::read_from(&mut reader).unwrap();
+    let second = ChangeSet::read_from(&mut reader).unwrap();
+    assert!(reader.is_empty());
+    assert!(matches!(
+        ChangeSet::read_from(&mut reader).err().unwrap(),
+        Error::Io(_)
+    ));
+
+    assert_eq!(follower.last_applied_transaction().unwrap(), None);
+    assert!(follower.apply_changeset(&first, tables).unwrap());
+    assert_eq!(
+        follower.last_applied_transaction().unwrap(),
+        Some(first.transaction_id())
+    );
+    // Changesets which were already applied are skipped, so that followers can resume
+    assert!(!follower.apply_changeset(&first, tables).unwrap());
+    assert!(follower.apply_changeset(&second, tables).unwrap());
+    assert!(!follower.apply_changeset(&first, tables).unwrap());
+    assert_eq!(
+        follower.last_applied_transaction().unwrap(),
+        Some(second.transaction_id())
+    );
+
+    let leader_txn = leader.begin_read().unwrap();
+    let follower_txn = follower.begin_read().unwrap();
+    let collect = |txn: &redb::ReadTransaction| {
+        let table = txn.open_table(table_def).unwrap();
+        let entries: Vec<(u64, String)> = table
+            .iter()
+            .unwrap()
+            .map(|(k, v)| (k.value(), v.value().to_string()))
+            .collect();
+        let multimap = txn.open_multimap_table(multimap_def).unwrap();
+        let values: Vec<(String, u64)> = multimap
+            .iter()
+            .unwrap()
+            .flat_map(|(k, v)| {
+                let key = k.value().to_string();
+                v.map(move |v| (key.clone(), v.value()))
+            })
+            .collect();
+        (entries, values)
+    };
+    assert_eq!(collect(&leader_txn), collect(&follower_txn));
+    assert_eq!(
+        collect(&follower_txn).0,
+        vec![
+            (0, "a".to_string()),
+            (1, "a".to_string()),
+            (2, "a".to_string()),
+            (3, "b".to_string()),
+            (4, "a".to_string())
+        ]
+    );
+    // The follower maintains its own indexes
+    let people = follower_txn.open_table(PEOPLE).unwrap();
+    let by_name = people.index(BY_NAME).unwrap();
+    assert_eq!(by_name.get("carol").unwrap().next().unwrap().value(), 0);
+    assert!(by_name.get("alice").unwrap().next().is_none());
+    assert_eq!(
+        follower_txn.list_tables().unwrap().collect::<Vec<_>>(),
+        vec!["people", "x"]
+    );
+    assert_eq!(
+        follower_txn
+            .list_multimap_tables()
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec!["y"]
+    );
+
+    // Every changed table must have a definition, of the right type
+    let txn = leader.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.insert(&20, "c").unwrap();
+    }
+    txn.commit().unwrap();
+    let third = changes.try_recv().unwrap();
+    assert!(matches!(
+        follower
+            .apply_changeset(&third, &[&multimap_def])
+            .err()
+            .unwrap(),
+        Error::InvalidChangeSet(_)
+    ));
+    let wrong_type: TableDefinition<u64, u64> = TableDefinition::new("x");
+    assert!(matches!(
+        follower
+            .apply_changeset(&third, &[&wrong_type])
+            .err()
+            .unwrap(),
+        Error::TableTypeMismatch(_)
+    ));
+    assert_eq!(
+        follower.last_applied_transaction().unwrap(),
+        Some(second.transaction_id())
+    );
+
+    // Changesets can't be applied after a missed one
+    let txn = leader.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_def).unwrap();
+        table.insert(&21, "d").unwrap();
+    }
+    txn.commit().unwrap();
+    let fourth = changes.try_recv().unwrap();
+    assert_eq!(second.transaction_id(), third.previous_transaction_id());
+    assert_eq!(third.transaction_id(), fourth.previous_transaction_id());
+    assert!(matches!(
+        follower.apply_changeset(&fourth, tables).err().unwrap(),
+        Error::InvalidChangeSet(_)
+    ));
+    assert!(follower.apply_changeset(&third, tables).unwrap());
+
+    // Malformed changesets are rejected
+    let mut data = vec![];
+    fourth.write_to(&mut data).unwrap();
+    let key = [&8u32.to_le_bytes()[..], &21u64.to_le_bytes()[..]].concat();
+    let position = data.windows(key.len()).position(|x| x == key).unwrap();
+    data.splice(position..(position + key.len()), [4, 0, 0, 0, 21, 0, 0, 0]);
+    let truncated_key = ChangeSet::read_from(&mut data.as_slice()).unwrap();
+    assert!(matches!(
+        follower
+            .apply_changeset(&truncated_key, tables)
+            .err()
+            .unwrap(),
+        Error::InvalidChangeSet(_)
+    ));
+    assert!(follower.apply_changeset(&fourth, tables).unwrap());
+    data[4] = 0xFF;
+    assert!(matches!(
+        ChangeSet::read_from(&mut data.as_slice()).err().unwrap(),
+        Error::InvalidChangeSet(_)
+    ));
+    assert!(matches!(
+        ChangeSet::read_from(&mut b"garbage".as_slice())
+            .err()
+            .unwrap(),
+        Error::InvalidChangeSet(_)
+    ));
+
+    // Restoring a savepoint on the leader can't be replicated
+    let txn = leader.begin_write().unwrap();
+    let savepoint = txn.savepoint().unwrap();
+    txn.abort().unwrap();
+    let mut txn = leader.begin_write().unwrap();
+    txn.restore_savepoint(&savepoint).unwrap();
+    txn.commit().unwrap();
+    assert!(matches!(
+        follower
+            .apply_changeset(&changes.try_recv().unwrap(), tables)
+            .err()
+            .unwrap(),
+        Error::InvalidChangeSet(_)
+    ));
+}
+
+#[test]
+fn optimistic_transactions() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u64, u64> = TableDefinition::new("x");
+
+    // Transactions which access disjoint keys both commit
+    let txn1 = db.begin_optimistic_write().unwrap();
+    let txn2 = db.begin_optimistic_write().unwrap();
+    {
+        let mut table1 = txn1.open_table(definition).unwrap();
+        let mut table2 = txn2.open_table(definition).unwrap();
+        assert!(table1.insert(&1, &1).unwrap().is_none());
+        assert!(table2.insert(&2, &2).unwrap().is_none());
+        // Writes are visible within the transaction, but not outside of it
+        assert_eq!(table1.get(&1).unwrap().unwrap().value(), 1);
+        assert!(table1.get(&2).unwrap().is_none());
+        assert_eq!(table1.insert(&1, &10).unwrap().unwrap().value(), 1);
+    }
+    txn1.commit().unwrap();
+    txn2.commit().unwrap();
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(definition).unwrap();
+    assert_eq!(table.get(&1).unwrap().unwrap().value(), 10);
+    assert_eq!(table.get(&2).unwrap().unwrap().value(), 2);
+    drop(table);
+    drop(read_txn);
+
+    // Writing a key which another transaction wrote after this one began conflicts
+    let txn1 = db.begin_optimistic_write().unwrap();
+    let txn2 = db.begin_optimistic_write().unwrap();
+    txn1.open_table(definition).unwrap().insert(&3, &3).unwrap();
+    txn2.open_table(definition).unwrap().insert(&3, &4).unwrap();
+    txn1.commit().unwrap();
+    assert!(matches!(txn2.commit().err().unwrap(), Error::Conflict));
+
+    // As does reading one, even by a regular write transaction
+    let txn1 = db.begin_optimistic_write().unwrap();
+    {
+        let mut table = txn1.open_table(definition).unwrap();
+        let value = table.get(&1).unwrap().unwrap().value();
+        table.insert(&4, &(value + 1)).unwrap();
+    }
+    let txn2 = db.begin_write().unwrap();
+    txn2.open_table(definition)
+        .unwrap()
+        .insert(&1, &20)
+        .unwrap();
+    txn2.commit().unwrap();
+    assert!(matches!(txn1.commit().err().unwrap(), Error::Conflict));
+
+    // Retrying sees the new value
+    let txn = db.begin_optimistic_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        let value = table.get(&1).unwrap().unwrap().value();
+        table.insert(&4, &(value + 1)).unwrap();
+        assert_eq!(table.remove(&2).unwrap().unwrap().value(), 2);
+        assert!(table.get(&2).unwrap().is_none());
+    }
+    txn.commit().unwrap();
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(definition).unwrap();
+    assert_eq!(table.get(&3).unwrap().unwrap().value(), 3);
+    assert_eq!(table.get(&4).unwrap().unwrap().value(), 21);
+    assert!(table.get(&2).unwrap().is_none());
+    assert_eq!(table.len().unwrap(), 3);
+    drop(table);
+    drop(read_txn);
+
+    // Transactions which began after a commit don't conflict with it, and aborted transactions
+    // don't conflict with anything
+    let txn1 = db.begin_optimistic_write().unwrap();
+    txn1.open_table(definition).unwrap().insert(&5, &5).unwrap();
+    txn1.abort().unwrap();
+    let txn2 = db.begin_optimistic_write().unwrap();
+    txn2.open_table(definition).unwrap().insert(&5, &6).unwrap();
+    txn2.commit().unwrap();
+    let txn3 = db.begin_optimistic_write().unwrap();
+    txn3.open_table(definition).unwrap().insert(&5, &7).unwrap();
+    txn3.commit().unwrap();
+
+    // Iterators merge the writes of the transaction over its snapshot
+    let txn = db.begin_optimistic_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.insert(&0, &0).unwrap();
+        table.insert(&4, &40).unwrap();
+        table.remove(&3).unwrap();
+        table.remove(&100).unwrap();
+        table.insert(&9, &9).unwrap();
+        let entries = |iter: OptimisticRangeIter<u64, u64>| -> Vec<(u64, u64)> {
+            iter.map(|(k, v)| (k.value(), v.value())).collect()
+        };
+        let expected = vec![(0, 0), (1, 20), (4, 40), (5, 7), (9, 9)];
+        assert_eq!(entries(table.iter().unwrap()), expected);
+        let mut reversed: Vec<(u64, u64)> = table
+            .iter()
+            .unwrap()
+            .rev()
+            .map(|(k, v)| (k.value(), v.value()))
+            .collect();
+        reversed.reverse();
+        assert_eq!(reversed, expected);
+        assert_eq!(entries(table.range(1..5).unwrap()), vec![(1, 20), (4, 40)]);
+        let mut iter = table.iter().unwrap();
+        assert_eq!(iter.next().unwrap().0.value(), 0);
+        assert_eq!(iter.next_back().unwrap().0.value(), 9);
+        assert_eq!(iter.next_back().unwrap().0.value(), 5);
+        assert_eq!(iter.next().unwrap().0.value(), 1);
+        assert_eq!(iter.next().unwrap().0.value(), 4);
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+    }
+    txn.abort().unwrap();
+
+    // Iterating over a range conflicts with a change to any key in it, but not outside of it
+    let txn1 = db.begin_optimistic_write().unwrap();
+    let txn2 = db.begin_optimistic_write().unwrap();
+    drop(txn1.open_table(definition).unwrap().range(10..20).unwrap());
+    drop(txn2.open_table(definition).unwrap().range(20..).unwrap());
+    let txn = db.begin_write().unwrap();
+    txn.open_table(definition)
+        .unwrap()
+        .insert(&15, &15)
+        .unwrap();
+    txn.commit().unwrap();
+    assert!(matches!(txn1.commit().err().unwrap(), Error::Conflict));
+    txn2.commit().unwrap();
+
+    // Restoring a savepoint may change any key
+    let txn = db.begin_write().unwrap();
+    let savepoint = txn.savepoint().unwrap();
+    txn.abort().unwrap();
+    let txn1 = db.begin_optimistic_write().unwrap();
+    txn1.open_table(definition).unwrap().insert(&6, &6).unwrap();
+    let mut txn2 = db.begin_write().unwrap();
+    txn2.restore_savepoint(&savepoint).unwrap();
+    txn2.commit().unwrap();
+    assert!(matches!(txn1.commit().err().unwrap(), Error::Conflict));
+
+    // Beginning doesn't wait for a regular write transaction. Since that one began first, its
+    // changes aren't recorded, so its commit may conflict with any key
+    let txn = db.begin_write().unwrap();
+    let txn1 = db.begin_optimistic_write().unwrap();
+    txn1.open_table(definition).unwrap().insert(&7, &7).unwrap();
+    txn.open_table(definition).unwrap().insert(&8, &8).unwrap();
+    txn.commit().unwrap();
+    assert!(matches!(txn1.commit().err().unwrap(), Error::Conflict));
+    let txn = db.begin_optimistic_write().unwrap();
+    txn.open_table(definition).unwrap().insert(&7, &7).unwrap();
+    txn.commit().unwrap();
+}
+
+#[test]
+fn read_transaction_limits() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    write_txn
+        .open_table(U64_TABLE)
+        .unwrap()
+        .insert(&0, &0)
+        .unwrap();
+    write_txn.commit().unwrap();
+
+    let line = line!() + 1;
+    let txn1 = db.begin_read().unwrap();
+    thread::sleep(Duration::from_millis(10));
+    let txn2 = db.begin_read().unwrap();
+    let live = db.live_read_transactions();
+    assert_eq!(live.len(), 2);
+    assert!(live[0].age() > live[1].age());
+    assert!(live[0].location().file().ends_with("integration_tests.rs"));
+    assert_eq!(live[0].location().line(), line);
+    let age = live[0].age();
+    thread::sleep(Duration::from_millis(10));
+    assert!(db.live_read_transactions()[0].age() > age);
+    drop(txn1);
+    assert_eq!(db.live_read_transactions().len(), 1);
+    drop(txn2);
+    assert!(db.live_read_transactions().is_empty());
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Builder::new()
+        .set_read_transaction_timeout(Duration::from_millis(100))
+        .create(tmpfile.path())
+        .unwrap();
+    let multimap_def: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("mm");
+    let write_txn = db.begin_write().unwrap();
+    write_txn
+        .open_table(U64_TABLE)
+        .unwrap()
+        .insert(&0, &0)
+        .unwrap();
+    write_txn
+        .open_multimap_table(multimap_def)
+        .unwrap()
+        .insert(&0, &0)
+        .unwrap();
+    write_txn.commit().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    let mut iter = table.iter().unwrap();
+    let multimap = txn.open_multimap_table(multimap_def).unwrap();
+    let mut multimap_iter = multimap.iter().unwrap();
+    let mut values = multimap.get(&0).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap().value(), 0);
+    thread::sleep(Duration::from_millis(150));
+    assert!(matches!(
+        table.get(&0).err().unwrap(),
+        Error::ReadTransactionExpired
+    ));
+    assert!(matches!(
+        txn.open_table(U64_TABLE).err().unwrap(),
+        Error::ReadTransactionExpired
+    ));
+    assert!(iter.next().is_none());
+    assert!(matches!(
+        iter.check().err().unwrap(),
+        Error::ReadTransactionExpired
+    ));
+    assert!(multimap_iter.next().is_none());
+    assert!(matches!(
+        multimap_iter.check().err().unwrap(),
+        Error::ReadTransactionExpired
+    ));
+    assert!(values.next().is_none());
+    assert!(matches!(
+        values.check().err().unwrap(),
+        Error::ReadTransactionExpired
+    ));
+
+    // The snapshot of an expired transaction is released by the next commit
+    assert_eq!(db.live_read_transactions().len(), 1);
+    let write_txn = db.begin_write().unwrap();
+    write_txn
+        .open_table(U64_TABLE)
+        .unwrap()
+        .insert(&0, &1)
+        .unwrap();
+    write_txn.commit().unwrap();
+    assert!(db.live_read_transactions().is_empty());
+    drop(multimap);
+    drop(table);
+    drop(txn);
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap().value(), 1);
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encryption() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let key = [7u8; 32];
+    let secret = "plaintext which must not appear in the file".repeat(10);
+
+    let db = Builder::new()
+        .set_encryption_key(key)
+        .create(tmpfile.path())
+        .unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(STR_TABLE).unwrap();
+        for i in 0..1000 {
+            table
+                .insert(format!("{i}").as_str(), secret.as_str())
+                .unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let contents = fs::read(tmpfile.path()).unwrap();
+    assert!(!contents
+        .windows(secret.len())
+        .any(|x| x == secret.as_bytes()));
+
+    assert!(matches!(
+        Builder::new()
+            .set_encryption_key([8u8; 32])
+            .open(tmpfile.path())
+            .err()
+            .unwrap(),
+        Error::InvalidEncryptionKey
+    ));
+    assert!(matches!(
+        Database::open(tmpfile.path()).err().unwrap(),
+        Error::InvalidEncryptionKey
+    ));
+
+    let db = Builder::new()
+        .set_encryption_key(key)
+        .open(tmpfile.path())
+        .unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(STR_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 1000);
+    assert_eq!(table.get("999").unwrap().unwrap().value(), secret);
+    drop(table);
+    drop(txn);
+    drop(db);
+
+    // Tampering with any page, other than the header, is detected
+    let mut contents = fs::read(tmpfile.path()).unwrap();
+    for byte in contents[4096..].iter_mut() {
+        *byte ^= 0xFF;
+    }
+    fs::write(tmpfile.path(), contents).unwrap();
+    let result = Builder::new()
+        .set_encryption_key(key)
+        .open(tmpfile.path())
+        .and_then(|db| {
+            let txn = db.begin_read()?;
+            let table = txn.open_table(STR_TABLE)?;
+            for entry in table.iter()? {
+                entry.0.value();
+            }
+            Ok(())
+        });
+    assert!(result.is_err());
+
+    // Replaying an older version of the pages is detected
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let read_all = || -> Result<usize, Error> {
+        let db = Builder::new()
+            .set_encryption_key(key)
+            .open(tmpfile.path())?;
+        let txn = db.begin_read()?;
+        let table = txn.open_table(STR_TABLE)?;
+        let mut count = 0;
+        let mut iter = table.iter()?;
+        for (_, value) in iter.by_ref() {
+            value.value();
+            count += 1;
+        }
+        iter.check()?;
+        Ok(count)
+    };
+    for i in 0..2 {
+        let db = Builder::new()
+            .set_encryption_key(key)
+            .create(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(STR_TABLE).unwrap();
+            table.insert(format!("{i}").as_str(), "value").unwrap();
+        }
+        txn.commit().unwrap();
+    }
+    let snapshot = fs::read(tmpfile.path()).unwrap();
+    {
+        let db = Builder::new()
+            .set_encryption_key(key)
+            .open(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(STR_TABLE).unwrap();
+            table.insert("2", "value").unwrap();
+        }
+        txn.commit().unwrap();
+    }
+    assert_eq!(read_all().unwrap(), 3);
+    let current = fs::read(tmpfile.path()).unwrap();
+    let mut contents = current.clone();
+    // The block table follows the data, so only replay the first half of the file
+    let end = snapshot.len().min(contents.len()) / 2;
+    contents[4096..end].copy_from_slice(&snapshot[4096..end]);
+    assert_ne!(contents, current);
+    fs::write(tmpfile.path(), &contents).unwrap();
+    assert!(read_all().is_err());
+
+    // As is erasing a page which has been written
+    let mut contents = current;
+    for byte in contents[4096..(2 * 4096)].iter_mut() {
+        *byte = 0;
+    }
+    fs::write(tmpfile.path(), &contents).unwrap();
+    assert!(read_all().is_err());
+
+    // A key can't be added to a database which isn't encrypted
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    drop(Database::create(tmpfile.path()).unwrap());
+    assert!(matches!(
+        Builder::new()
+            .set_encryption_key(key)
+            .open(tmpfile.path())
+            .err()
+            .unwrap(),
+        Error::InvalidEncryptionKey
+    ));
+
+    // Nor is encryption supported by the mmap backend
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    assert!(matches!(
+        unsafe {
+            Builder::new()
+                .set_encryption_key(key)
+                .create_mmapped(tmpfile.path())
+        }
+        .err()
+        .unwrap(),
+        Error::Io(_)
+    ));
+
+    // An unknown cipher in the header is reported as corruption
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    drop(Database::create(tmpfile.path()).unwrap());
+    let mut contents = fs::read(tmpfile.path()).unwrap();
+    // The cipher follows the magic number, god byte and page size, and region layout fields
+    contents[24] = 0xFF;
+    fs::write(tmpfile.path(), contents).unwrap();
+    assert!(matches!(
+        Database::open(tmpfile.path()).err().unwrap(),
+        Error::Corrupted(_)
+    ));
+}
+
+// Only exercises io_uring if the kernel supports it. Otherwise, the file is accessed in the usual way
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+#[test]
+fn io_uring() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+    let value = |key: u64, generation: u64| vec![(key + generation) as u8; 1000];
+
+    // Each commit writes many more dirty pages than are submitted to the ring at once
+    let db = Database::create(tmpfile.path()).unwrap();
+    for generation in 0..3 {
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(definition).unwrap();
+            for key in (generation * 1000)..10_000 {
+                table
+                    .insert(&key, value(key, generation).as_slice())
+                    .unwrap();
+            }
+        }
+        txn.commit().unwrap();
+    }
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    let mut expected = 0;
+    for (key, data) in table.iter().unwrap() {
+        assert_eq!(key.value(), expected);
+        let generation = (expected / 1000).min(2);
+        assert_eq!(data.value(), value(expected, generation).as_slice());
+        expected += 1;
+    }
+    assert_eq!(expected, 10_000);
+    // Scanning again reads the pages that were prefetched in the background
+    for (key, data) in table.iter().unwrap().rev() {
+        let generation = (key.value() / 1000).min(2);
+        assert_eq!(data.value(), value(key.value(), generation).as_slice());
+    }
+    drop(table);
+    drop(txn);
+
+    // Pages freed by the earlier commits are reused once the database is written again
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        for key in 0..10_000 {
+            table.insert(&key, value(key, 3).as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    for key in 0..10_000 {
+        assert_eq!(
+            table.get(&key).unwrap().unwrap().value(),
+            value(key, 3).as_slice()
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use redb::{Database, ReadableTable, TableDefinition};
+use redb::{Database, Error, ReadableTable, TableDefinition};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 use tempfile::NamedTempFile;
 
 const TABLE: TableDefinition<&str, &str> = TableDefinition::new("x");
@@ -62,3 +63,83 @@ fn multithreaded_insert() {
     let table = read_txn.open_table(DEF2).unwrap();
     assert_eq!(table.len().unwrap(), 2);
 }
+
+#[test]
+fn optimistic_insert() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    const COUNTERS: TableDefinition<u64, u64> = TableDefinition::new("counters");
+    const THREADS: u64 = 4;
+    const INCREMENTS: u64 = 20;
+    thread::scope(|s| {
+        for i in 0..THREADS {
+            let db = &db;
+            s.spawn(move || {
+                for _ in 0..INCREMENTS {
+                    // Every thread increments its own counter, and a shared one, retrying on conflict
+                    for key in [i, THREADS] {
+                        loop {
+                            let txn = db.begin_optimistic_write().unwrap();
+                            {
+                                let mut table = txn.open_table(COUNTERS).unwrap();
+                                let value = table.get(&key).unwrap().map_or(0, |x| x.value());
+                                table.insert(&key, &(value + 1)).unwrap();
+                            }
+                            match txn.commit() {
+                                Ok(()) => break,
+                                Err(Error::Conflict) => {}
+                                Err(err) => panic!("{err}"),
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(COUNTERS).unwrap();
+    for i in 0..THREADS {
+        assert_eq!(table.get(&i).unwrap().unwrap().value(), INCREMENTS);
+    }
+    assert_eq!(
+        table.get(&THREADS).unwrap().unwrap().value(),
+        THREADS * INCREMENTS
+    );
+}
+
+#[test]
+fn begin_write_timeout() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    assert!(db.try_begin_write().unwrap().is_none());
+    let start = Instant::now();
+    assert!(db
+        .begin_write_timeout(Duration::from_millis(10))
+        .unwrap()
+        .is_none());
+    assert!(start.elapsed() >= Duration::from_millis(10));
+
+    // Waiters are woken up when the transaction in progress completes
+    thread::scope(|s| {
+        s.spawn(|| {
+            let write_txn = db
+                .begin_write_timeout(Duration::from_secs(60))
+                .unwrap()
+                .unwrap();
+            let mut table = write_txn.open_table(TABLE).unwrap();
+            table.insert("hello", "world").unwrap();
+            drop(table);
+            write_txn.commit().unwrap();
+        });
+        thread::sleep(Duration::from_millis(10));
+        write_txn.abort().unwrap();
+    });
+
+    let write_txn = db.try_begin_write().unwrap().unwrap();
+    let table = write_txn.open_table(TABLE).unwrap();
+    assert_eq!(table.get("hello").unwrap().unwrap().value(), "world");
+}